@@ -0,0 +1,114 @@
+//! Throughput of `hash_to_curve` over large batches, against the BigUint
+//! implementation it replaced.
+//!
+//! Run with `cargo bench --bench hash_to_curve`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use leaklens_api::crypto::ecc_cipher::ECCommutativeCipher;
+use num_bigint::BigUint;
+use num_traits::{One, Zero};
+use p256::elliptic_curve::sec1::{FromEncodedPoint, ToEncodedPoint};
+use p256::{EncodedPoint, ProjectivePoint};
+use sha2::{Digest, Sha256};
+
+const BATCH_SIZES: &[usize] = &[100, 1_000, 10_000];
+
+/// Deterministic stand-ins for scrypt lookup hashes.
+fn lookup_hashes(count: usize) -> Vec<[u8; 32]> {
+    (0..count as u64)
+        .map(|i| Sha256::digest(i.to_be_bytes()).into())
+        .collect()
+}
+
+/// The pre-field-element implementation, kept here only as a baseline.
+mod legacy {
+    use super::*;
+
+    fn p() -> BigUint {
+        BigUint::parse_bytes(b"ffffffff00000001000000000000000000000000ffffffffffffffffffffffff", 16).unwrap()
+    }
+
+    fn random_oracle(input: &[u8], p: &BigUint) -> BigUint {
+        let mut output = BigUint::zero();
+        for i in 1u8..=2 {
+            output <<= 256;
+            let digest = Sha256::new().chain_update([i]).chain_update(input).finalize();
+            output |= BigUint::from_bytes_be(&digest);
+        }
+        output % p
+    }
+
+    fn pad(bytes: &[u8]) -> [u8; 32] {
+        let mut out = [0u8; 32];
+        out[32 - bytes.len()..].copy_from_slice(bytes);
+        out
+    }
+
+    pub fn hash_to_curve(data: &[u8]) -> ProjectivePoint {
+        let data = match data.iter().position(|&b| b == 0) {
+            Some(pos) => &data[..pos],
+            None => data,
+        };
+        let p = p();
+        let a = &p - BigUint::from(3u8);
+        let b = BigUint::parse_bytes(b"5ac635d8aa3a93e7b3ebbd55769886bc651d06b0cc53b0f63bce3c3e27d2604b", 16).unwrap();
+        let exp = (&p + BigUint::one()) / BigUint::from(4u8);
+
+        let mut x = random_oracle(data, &p);
+        loop {
+            let y_squared = (&x * &x * &x + &a * &x + &b) % &p;
+            let sqrt = y_squared.modpow(&exp, &p);
+            if (&sqrt * &sqrt) % &p == y_squared {
+                let y = if sqrt.bit(0) { &p - &sqrt } else { sqrt };
+                let encoded = EncodedPoint::from_affine_coordinates(
+                    &pad(&x.to_bytes_be()).into(),
+                    &pad(&y.to_bytes_be()).into(),
+                    false,
+                );
+                return ProjectivePoint::from_encoded_point(&encoded).unwrap();
+            }
+            x = random_oracle(&x.to_bytes_be(), &p);
+        }
+    }
+}
+
+fn bench_hash_to_curve(c: &mut Criterion) {
+    let cipher = ECCommutativeCipher::new(None);
+
+    // Sanity check that both sides compute the same thing before timing them.
+    for hash in lookup_hashes(16) {
+        assert_eq!(
+            cipher.hash_to_curve(&hash).unwrap().to_encoded_point(true),
+            legacy::hash_to_curve(&hash).to_encoded_point(true)
+        );
+    }
+
+    let mut group = c.benchmark_group("hash_to_curve_batch");
+    group.sample_size(10);
+
+    for &size in BATCH_SIZES {
+        let hashes = lookup_hashes(size);
+        group.throughput(Throughput::Elements(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("field_element", size), &hashes, |b, hashes| {
+            b.iter(|| {
+                for hash in hashes {
+                    cipher.hash_to_curve(hash).unwrap();
+                }
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("biguint_legacy", size), &hashes, |b, hashes| {
+            b.iter(|| {
+                for hash in hashes {
+                    legacy::hash_to_curve(hash);
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_hash_to_curve);
+criterion_main!(benches);
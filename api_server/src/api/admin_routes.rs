@@ -0,0 +1,96 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use chrono::{Duration, Utc};
+
+use crate::api::AppState;
+use crate::models::{
+    request_models::CreateApiKeyRequest,
+    response_models::{ApiKeyListResponse, IssuedApiKeyResponse},
+};
+use crate::services::api_keys::{self, ApiKey, IssuedKey};
+use crate::utils::error::ApiError;
+
+pub async fn create_api_key(
+    State(state): State<AppState>,
+    Json(request): Json<CreateApiKeyRequest>,
+) -> Result<Json<IssuedApiKeyResponse>, ApiError> {
+    if request.name.trim().is_empty() {
+        return Err(ApiError::InvalidInput("name is required".to_string()));
+    }
+    if request.scopes.is_empty() {
+        return Err(ApiError::InvalidInput("At least one scope is required".to_string()));
+    }
+
+    let lifetime = match request.expires_in_days {
+        Some(0) => return Err(ApiError::InvalidInput("expires_in_days must be positive".to_string())),
+        Some(days) => Some(Duration::days(days.into())),
+        None => None,
+    };
+
+    let issued = api_keys::issue(state.api_keys.as_ref(), &request.name, request.scopes, lifetime)
+        .await
+        .map_err(|e| ApiError::Internal(format!("Failed to issue API key: {}", e)))?;
+
+    tracing::info!("Issued API key {} ({})", issued.key.id, issued.key.name);
+    Ok(Json(issued_response(issued)))
+}
+
+pub async fn list_api_keys(State(state): State<AppState>) -> Result<Json<ApiKeyListResponse>, ApiError> {
+    let keys = state.api_keys
+        .list()
+        .await
+        .map_err(|e| ApiError::Internal(format!("Failed to list API keys: {}", e)))?;
+
+    Ok(Json(ApiKeyListResponse { keys }))
+}
+
+/// Issues a replacement with the same name, scopes and lifetime, and revokes
+/// the key right away.
+pub async fn rotate_api_key(
+    State(state): State<AppState>,
+    Path(key_id): Path<String>,
+) -> Result<Json<IssuedApiKeyResponse>, ApiError> {
+    let key = find_key(&state, &key_id).await?;
+    if key.revoked_at.is_some() {
+        return Err(ApiError::Conflict(format!("API key {} has been revoked", key_id)));
+    }
+
+    let issued = api_keys::rotate(state.api_keys.as_ref(), &key)
+        .await
+        .map_err(|e| ApiError::Internal(format!("Failed to rotate API key: {}", e)))?;
+
+    tracing::info!("Rotated API key {} to {}", key_id, issued.key.id);
+    Ok(Json(issued_response(issued)))
+}
+
+pub async fn revoke_api_key(
+    State(state): State<AppState>,
+    Path(key_id): Path<String>,
+) -> Result<Json<ApiKey>, ApiError> {
+    find_key(&state, &key_id).await?;
+
+    state.api_keys
+        .revoke(&key_id, Utc::now())
+        .await
+        .map_err(|e| ApiError::Internal(format!("Failed to revoke API key: {}", e)))?;
+
+    tracing::info!("Revoked API key {}", key_id);
+    find_key(&state, &key_id).await.map(Json)
+}
+
+async fn find_key(state: &AppState, key_id: &str) -> Result<ApiKey, ApiError> {
+    state.api_keys
+        .get(key_id)
+        .await
+        .map_err(|e| ApiError::Internal(format!("Failed to look up API key: {}", e)))?
+        .ok_or_else(|| ApiError::NotFound(format!("API key {} not found", key_id)))
+}
+
+fn issued_response(issued: IssuedKey) -> IssuedApiKeyResponse {
+    IssuedApiKeyResponse {
+        secret: issued.secret,
+        key: issued.key,
+    }
+}
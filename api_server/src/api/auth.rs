@@ -0,0 +1,209 @@
+//! Authentication for the router, with LeakLens API keys or, when
+//! `AUTH_MODE=jwt`, bearer tokens from an OIDC identity provider.
+//!
+//! `authenticate` runs in front of every protected route group, puts the
+//! resulting `Caller` into the request extensions and writes an audit record
+//! for each request; `authorize_job` then keeps callers away from batch jobs
+//! uploaded by someone else.
+
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, Request},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+
+use crate::api::AppState;
+use crate::services::api_keys::{self, hash_secret, ApiKey, ApiKeyStore, Scope};
+use crate::services::jwt::{AuthMode, JwtValidator, Principal, TokenError};
+use crate::utils::{config, error::ApiError};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Tracing target of the audit records.
+pub const AUDIT_TARGET: &str = "audit";
+
+/// Who is making a request.
+#[derive(Debug, Clone)]
+pub enum Caller {
+    /// Authentication is disabled.
+    Anonymous,
+    Key(ApiKey),
+    Token(Principal),
+}
+
+impl Caller {
+    /// Owner of the caller's key or subject of its token, recorded as the
+    /// owner of jobs it uploads. A key's owner survives rotation.
+    pub fn id(&self) -> Option<&str> {
+        match self {
+            Caller::Anonymous => None,
+            Caller::Key(key) => Some(&key.owner_id),
+            Caller::Token(principal) => Some(&principal.subject),
+        }
+    }
+
+    pub fn allows(&self, scope: Scope) -> bool {
+        match self {
+            Caller::Anonymous => true,
+            Caller::Key(key) => key.allows(scope),
+            Caller::Token(principal) => principal.allows(scope),
+        }
+    }
+
+    /// Whether the caller may see and control a job owned by `owner`.
+    pub fn can_access(&self, owner: Option<&str>) -> bool {
+        match self {
+            Caller::Anonymous => true,
+            _ => self.allows(Scope::Admin) || owner == self.id(),
+        }
+    }
+}
+
+/// State of one `authenticate` layer: the scope its routes need.
+#[derive(Clone)]
+pub struct Guard {
+    api_keys: Arc<dyn ApiKeyStore>,
+    jwt: Option<Arc<JwtValidator>>,
+    scope: Scope,
+}
+
+impl Guard {
+    pub fn new(state: &AppState, scope: Scope) -> Self {
+        Guard { api_keys: state.api_keys.clone(), jwt: state.jwt.clone(), scope }
+    }
+}
+
+/// Identifies the caller and checks it has the guard's scope. With
+/// `AUTH_ENABLED=false` only the admin routes ask for credentials.
+pub async fn authenticate<B>(
+    State(guard): State<Guard>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, ApiError> {
+    if !config::get().auth.enabled && guard.scope != Scope::Admin {
+        request.extensions_mut().insert(Caller::Anonymous);
+        return Ok(next.run(request).await);
+    }
+
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+
+    let caller = match identify(&guard, request.headers()).await {
+        Ok(caller) => caller,
+        Err(e) => {
+            tracing::info!(target: AUDIT_TARGET, "{} {} rejected: {}", method, path, e);
+            return Err(e);
+        }
+    };
+
+    let id = caller.id().unwrap_or_default().to_string();
+    request.extensions_mut().insert(caller);
+    let response = next.run(request).await;
+
+    tracing::info!(target: AUDIT_TARGET, "{} {} by {} -> {}", method, path, id, response.status().as_u16());
+    Ok(response)
+}
+
+async fn identify(guard: &Guard, headers: &HeaderMap) -> Result<Caller, ApiError> {
+    let auth = &config::get().auth;
+
+    let credential = presented_key(headers).ok_or_else(|| {
+        ApiError::Authentication(match auth.mode {
+            AuthMode::ApiKey => "Missing API key".to_string(),
+            AuthMode::Jwt => "Missing bearer token".to_string(),
+        })
+    })?;
+
+    let caller = match admin_key(auth.admin_key.as_deref(), credential) {
+        Some(key) => Caller::Key(key),
+        None => match auth.mode {
+            AuthMode::ApiKey => Caller::Key(
+                api_keys::authenticate(guard.api_keys.as_ref(), credential)
+                    .await
+                    .map_err(|e| ApiError::Internal(format!("Failed to look up API key: {}", e)))?
+                    .ok_or_else(|| ApiError::Authentication("Invalid, expired or revoked API key".to_string()))?,
+            ),
+            AuthMode::Jwt => {
+                let validator = guard.jwt.as_ref()
+                    .ok_or_else(|| ApiError::Internal("AUTH_MODE is jwt but JWT validation is not configured".to_string()))?;
+                Caller::Token(validator.validate(credential).await.map_err(|e| match e {
+                    TokenError::Invalid(reason) => ApiError::Authentication(reason),
+                    TokenError::KeysUnavailable(_) => ApiError::ExternalService(e.to_string()),
+                })?)
+            }
+        },
+    };
+
+    if !caller.allows(guard.scope) {
+        let what = match caller {
+            Caller::Token(_) => "Token",
+            _ => "API key",
+        };
+        return Err(ApiError::Authorization(format!("{} lacks the '{}' scope", what, guard.scope)));
+    }
+    Ok(caller)
+}
+
+/// Answers `404` for jobs the caller may not access, as if they did not exist.
+pub async fn authorize_job<B>(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, ApiError> {
+    let caller = request
+        .extensions()
+        .get::<Caller>()
+        .ok_or_else(|| ApiError::Internal("Job route mounted without authentication".to_string()))?;
+
+    // Missing jobs are reported by the handler itself.
+    let hidden = state.job_storage
+        .read()
+        .await
+        .get(&job_id)
+        .is_some_and(|job| !caller.can_access(job.owner.as_deref()));
+    if hidden {
+        return Err(ApiError::NotFound(format!("Job ID {} not found", job_id)));
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// The key or token from `X-API-Key` or an `Authorization: Bearer` header.
+fn presented_key(headers: &HeaderMap) -> Option<&str> {
+    if let Some(key) = headers.get(API_KEY_HEADER).and_then(|v| v.to_str().ok()) {
+        return Some(key.trim());
+    }
+
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// The `ADMIN_API_KEY` from config, if `secret` matches it. Hashes are
+/// compared rather than the keys themselves to keep the time taken independent
+/// of where they differ.
+fn admin_key(configured: Option<&str>, secret: &str) -> Option<ApiKey> {
+    let configured = configured?;
+    if hash_secret(configured.trim()) != hash_secret(secret) {
+        return None;
+    }
+
+    Some(ApiKey {
+        id: "admin".to_string(),
+        name: "ADMIN_API_KEY".to_string(),
+        prefix: String::new(),
+        key_hash: String::new(),
+        scopes: vec![Scope::Admin],
+        created_at: DateTime::<Utc>::UNIX_EPOCH,
+        expires_at: None,
+        revoked_at: None,
+        rotated_from: None,
+        owner_id: "admin".to_string(),
+    })
+}
@@ -1,39 +1,74 @@
 use axum::{
-    extract::{Multipart, Path, State},
+    extract::{Extension, Multipart, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
-use std::io::{BufReader, Cursor, BufRead};
 use tokio::spawn;
+use tracing::Instrument;
 use uuid::Uuid;
-use std::time::Instant;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::api::AppState;
+use crate::api::{auth::Caller, AppState};
 use crate::models::{
-    request_models::{BatchCheckMetadata, BatchProcessingJob, CredentialCheckResult, SingleCheckRequest},
-    response_models::{BatchCheckResponse, BatchCheckResultsResponse, BatchCheckSummary, SingleCheckResponse},
+    request_models::{
+        BatchCheckMetadata, BatchProcessingJob, BatchStatusQuery, BlindedCheckRequest, CredentialCheckResult,
+        JobState, SingleCheckRequest, DEFAULT_ABANDON_TIMEOUT, MAX_RESULTS_PAGE,
+    },
+    response_models::{
+        BatchCheckResponse, BatchCheckResultsResponse, BlindedCheckResponse, SingleCheckResponse,
+        WebhookDeliveriesResponse,
+    },
 };
+use crate::crypto::check_google_api::{validate_lookup_request, USERNAME_HASH_PREFIX_LENGTH};
+use crate::proto::LookupSingleLeakRequest;
+use crate::services::backends::LeakBackend;
+use crate::services::batch_input::{
+    self, BatchEntry, ColumnMapping, InputFormat, MasterPassword, ParseOptions, RejectedEntry,
+};
+use crate::services::job_store::NewJob;
+use crate::services::webhooks::{WebhookEvent, WebhookPayload, WebhookTarget};
 use crate::utils::{
+    config,
     error::ApiError,
-    rate_limiter::get_rate_limiter,
+    metrics,
+    upload,
 };
 
+/// How long a finished job stays available before it is cleaned up, and how
+/// long an abandoned job waits for a client before it is cancelled.
+const JOB_RETENTION: Duration = Duration::from_secs(3600);
+
+const ABANDONED_MESSAGE: &str = "Job abandoned - client stopped requesting updates";
 
 pub async fn check_single(
     State(state): State<AppState>, 
     Json(request): Json<SingleCheckRequest>
 ) -> Result<Json<SingleCheckResponse>, ApiError> {
-    if !get_rate_limiter().check_single_credential_limit().await {
-        return Err(ApiError::RateLimited("Rate limit exceeded for single credential checks".to_string()));
-    }
-
     if request.username.trim().is_empty() || request.password.trim().is_empty() {
         return Err(ApiError::InvalidInput("Username and password are required".to_string()));
     }
 
-    let is_leaked = state.leak_check_service
-        .check_single_credential(&request.username, &request.password)
-        .await
-        .map_err(|e| ApiError::Internal(format!("Credential check failed: {}", e)))?;
+    let backend = state.leak_check_service
+        .backend(request.backend.as_deref())
+        .map_err(|e| ApiError::InvalidInput(e.to_string()))?;
+
+    let started = Instant::now();
+    let outcome = backend
+        .check_credential(&request.username, &request.password)
+        .instrument(tracing::info_span!("check_credential", backend = backend.name()))
+        .await;
+    let label = match &outcome {
+        Ok(outcome) if outcome.is_leaked => "leaked",
+        Ok(_) => "not_leaked",
+        Err(_) => "error",
+    };
+    metrics::CREDENTIAL_CHECKS.inc(&["single", label]);
+    metrics::SINGLE_CHECK_DURATION.observe_since(&[label], started);
+
+    let outcome = outcome.map_err(|e| ApiError::Internal(format!("Credential check failed: {}", e)))?;
+    let is_leaked = outcome.is_leaked;
 
     let message = if is_leaked {
         "Credential found in a known data breach"
@@ -45,20 +80,53 @@ pub async fn check_single(
         username: request.username,
         is_leaked,
         message: message.to_string(),
+        backend: backend.name().to_string(),
+        occurrences: outcome.occurrences,
+        cached: outcome.cached,
+    }))
+}
+
+
+pub async fn check_blinded(
+    State(state): State<AppState>,
+    Json(request): Json<BlindedCheckRequest>,
+) -> Result<Json<BlindedCheckResponse>, ApiError> {
+    let username_hash_prefix = hex::decode(request.username_hash_prefix.trim())
+        .map_err(|_| ApiError::InvalidInput("username_hash_prefix must be hex encoded".to_string()))?;
+    let encrypted_lookup_hash = hex::decode(request.encrypted_lookup_hash.trim())
+        .map_err(|_| ApiError::InvalidInput("encrypted_lookup_hash must be hex encoded".to_string()))?;
+
+    let lookup_request = LookupSingleLeakRequest {
+        username_hash_prefix,
+        username_hash_prefix_length: USERNAME_HASH_PREFIX_LENGTH,
+        encrypted_lookup_hash,
+    };
+
+    validate_lookup_request(&lookup_request)
+        .map_err(|e| ApiError::InvalidInput(e.to_string()))?;
+
+    let response = state.leak_check_service
+        .relay_blinded_lookup(lookup_request)
+        .await
+        .map_err(|e| ApiError::ExternalService(format!("Blinded lookup failed: {}", e)))?;
+
+    Ok(Json(BlindedCheckResponse {
+        reencrypted_lookup_hash: hex::encode(&response.reencrypted_lookup_hash),
+        encrypted_leak_match_prefix: response.encrypted_leak_match_prefix.iter().map(hex::encode).collect(),
     }))
 }
 
 
 pub async fn check_batch(
     State(state): State<AppState>,
+    Extension(caller): Extension<Caller>,
     mut multipart: Multipart,
 ) -> Result<Json<BatchCheckResponse>, ApiError> {
-    if !get_rate_limiter().check_batch_credentials_limit().await {
-        return Err(ApiError::RateLimited("Rate limit exceeded for batch credential checks".to_string()));
-    }
+    let limits = &config::get().rate_limits;
 
     let mut file_bytes = None;
-    let mut metadata = BatchCheckMetadata { input_type: None };
+    let mut master_password = None;
+    let mut metadata = BatchCheckMetadata::default();
 
     while let Some(field) = multipart.next_field().await
         .map_err(|e| ApiError::InvalidInput(format!("Error reading multipart form: {}", e)))? {
@@ -66,60 +134,125 @@ pub async fn check_batch(
         let name = field.name().unwrap_or("").to_string();
 
         if name == "file" {
-            file_bytes = Some(field.bytes().await
-                .map_err(|e| ApiError::InvalidInput(format!("Failed to read file: {}", e)))?);
-        } else if name == "input_type" {
-            let input_type = field.text().await
-                .map_err(|e| ApiError::InvalidInput(format!("Invalid input type: {}", e)))?;
-            metadata.input_type = Some(input_type);
+            file_bytes = Some(upload::read_field(field, limits.max_upload_bytes).await?);
+            continue;
+        }
+
+        if name == "master_password" {
+            let password = field.text().await
+                .map_err(|e| ApiError::InvalidInput(format!("Invalid master password: {}", e)))?;
+            master_password = Some(MasterPassword::new(password));
+            continue;
         }
+
+        let target = match name.as_str() {
+            "input_type" => &mut metadata.input_type,
+            "backend" => &mut metadata.backend,
+            "delimiter" => &mut metadata.delimiter,
+            "username_column" => &mut metadata.username_column,
+            "password_column" => &mut metadata.password_column,
+            "url_column" => &mut metadata.url_column,
+            "title_column" => &mut metadata.title_column,
+            "abandon_timeout" => &mut metadata.abandon_timeout,
+            "detached" => &mut metadata.detached,
+            "callback_url" => &mut metadata.callback_url,
+            "callback_secret" => &mut metadata.callback_secret,
+            "callback_include_results" => &mut metadata.callback_include_results,
+            _ => continue,
+        };
+        let value = field.text().await
+            .map_err(|e| ApiError::InvalidInput(format!("Invalid {}: {}", name, e)))?;
+        *target = Some(value);
     }
 
+    let backend = state.leak_check_service
+        .backend(metadata.backend.as_deref())
+        .map_err(|e| ApiError::InvalidInput(e.to_string()))?;
+
+    let abandon_timeout = parse_abandon_timeout(metadata.abandon_timeout.as_deref(), metadata.detached.as_deref())?;
+
+    let callback = match metadata.callback_url.as_deref().map(str::trim).filter(|url| !url.is_empty()) {
+        Some(url) => {
+            let include_results = parse_flag("callback_include_results", metadata.callback_include_results.as_deref())?;
+            let target = WebhookTarget::new(url, metadata.callback_secret.take(), include_results, &config::get().webhooks)
+                .map_err(|e| ApiError::InvalidInput(e.to_string()))?;
+            Some(target)
+        }
+        None => None,
+    };
+
     let file_bytes = file_bytes.ok_or_else(|| {
         ApiError::InvalidInput("No file provided".to_string())
     })?;
 
-    let cursor = Cursor::new(&file_bytes);
-    let reader = BufReader::new(cursor);
-    let lines: Vec<String> = reader.lines()
-        .filter_map(|line| line.ok())
-        .filter(|line| !line.trim().is_empty())
-        .collect();
+    let columns = ColumnMapping {
+        username: metadata.username_column,
+        password: metadata.password_column,
+        url: metadata.url_column,
+        title: metadata.title_column,
+    };
+    let mut options = ParseOptions::from_fields(metadata.input_type.as_deref(), metadata.delimiter.as_deref(), columns)
+        .map_err(|e| ApiError::InvalidInput(e.to_string()))?;
+    options.master_password = master_password;
+    options.max_entries = Some(limits.max_batch_size);
+
+    // Unpacking and KeePass key derivation are CPU bound, so parse off the runtime.
+    let max_upload_bytes = limits.max_upload_bytes;
+    let batch = tokio::task::spawn_blocking(move || {
+        let bytes = upload::decompress(file_bytes, max_upload_bytes)?;
+        batch_input::parse(&bytes, &options)
+    })
+        .await
+        .map_err(|e| ApiError::Internal(format!("Failed to parse upload: {}", e)))?
+        .map_err(|e| ApiError::InvalidInput(e.to_string()))?;
 
-    if lines.is_empty() {
+    if batch.is_empty() {
         return Err(ApiError::InvalidInput("File is empty".to_string()));
     }
 
-    if lines.len() > 10000 {
-        return Err(ApiError::InvalidInput("File contains more than 10,000 lines".to_string()));
-    }
+    tracing::info!("Parsed batch upload as {} with {} entries", batch.format.name(), batch.len());
+    metrics::BATCH_UPLOAD_ENTRIES.observe(&[], batch.len() as f64);
 
     let job_id = Uuid::new_v4().to_string();
+    let total = batch.len();
+    // Passwords decrypted from a KeePass database stay in memory only.
+    let resumable = batch.format != InputFormat::Kdbx;
+    let entries = batch.entries;
+    let rejected_results: Vec<CredentialCheckResult> = batch.rejected.into_iter().map(rejected_result).collect();
 
-    let job = BatchProcessingJob {
+    let new_job = NewJob {
         id: job_id.clone(),
-        total: lines.len(),
-        processed: 0,
-        results: Vec::new(),
-        completed: false,
-        error: None,
-        last_heartbeat: Instant::now(),
-        is_abandoned: false,
+        total,
+        backend: backend.name().to_string(),
+        abandon_timeout: abandon_timeout.map(|timeout| timeout.as_secs()),
+        callback: callback.clone(),
+        owner: caller.id().map(str::to_string),
+        resumable,
     };
+    state.job_store
+        .create_job(&new_job, &entries)
+        .await
+        .map_err(|e| ApiError::Internal(format!("Failed to save batch job: {}", e)))?;
+
+    let mut job = BatchProcessingJob::new(job_id.clone(), total);
+    job.abandon_timeout = abandon_timeout;
+    job.callback = callback;
+    job.owner = new_job.owner;
+    if !rejected_results.is_empty() {
+        job.record_results(rejected_results);
+        if let Err(e) = state.job_store.append_results(&job_id, &job.results, 0, job.version).await {
+            tracing::warn!("Failed to save results of job {}: {}", job_id, e);
+        }
+    }
 
     {
         let mut jobs = state.job_storage.write().await;
         jobs.insert(job_id.clone(), job);
     }
 
-    let leak_check_service = state.leak_check_service.clone();
-    let job_storage = state.job_storage.clone();
-    
-    let job_id_clone = job_id.clone();
-    
-    spawn(async move {
-        process_batch_job(job_id_clone, lines, leak_check_service, job_storage, metadata.input_type).await;
-    });
+    // The job's log lines keep the request ID of the upload that started it.
+    let span = tracing::info_span!("batch_job", job_id = %job_id);
+    spawn(process_batch_job(state, job_id.clone(), entries, 0, backend).instrument(span));
 
     Ok(Json(BatchCheckResponse {
         job_id,
@@ -131,212 +264,545 @@ pub async fn check_batch(
 pub async fn get_batch_status(
     State(state): State<AppState>,
     Path(job_id): Path<String>,
-) -> Result<Json<BatchCheckResultsResponse>, ApiError> {
+    Query(query): Query<BatchStatusQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
     let mut jobs = state.job_storage.write().await; // Change to write lock to update heartbeat
     
     let job = jobs.get_mut(&job_id).ok_or_else(|| {
         ApiError::NotFound(format!("Job ID {} not found", job_id))
     })?;
 
-    job.last_heartbeat = std::time::Instant::now();
-    if job.is_abandoned {
-        job.is_abandoned = false;
-    }
-
-    let total_leaked = job.results.iter().filter(|r| r.is_leaked == Some(true)).count();
-    let total_not_leaked = job.results.iter().filter(|r| r.is_leaked == Some(false)).count();
-    let total_errors = job.results.iter().filter(|r| r.status == "error" || r.is_leaked.is_none()).count();
+    let resumed = job.touch().then_some((job.state, job.version));
 
-    let progress_percentage = if job.total == 0 {
-        100
+    // Without any paging parameters the full result list is returned, as
+    // existing pollers expect.
+    let paged = query.since.is_some() || query.cursor.is_some() || query.limit.is_some();
+    let cursor = query.since.or(query.cursor).unwrap_or(0);
+    let limit = if paged {
+        query.limit.unwrap_or(MAX_RESULTS_PAGE).clamp(1, MAX_RESULTS_PAGE)
     } else {
-        ((job.processed as f32 / job.total as f32) * 100.0) as u8
+        usize::MAX
     };
 
-    let summary = BatchCheckSummary {
-        total_processed: job.processed,
-        total_leaked,
-        total_not_leaked,
-        total_errors,
-        completed: job.completed,
-        progress_percentage,
-    };
+    // Each page has its own ETag, so one page's cannot answer for another.
+    let etag = job.etag(paged.then_some((cursor, limit)));
+    if etag_matches(&headers, &etag) {
+        drop(jobs);
+        if let Some((job_state, version)) = resumed {
+            save_job_state(&state, &job_id, job_state, version).await;
+        }
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
 
-    let results = job.results.clone();
+    let summary = job.summary();
+    let results = job.results_page(cursor, limit).to_vec();
+    let next_cursor = results.last().map_or(cursor.min(job.results.len()), |r| r.index + 1);
+    let has_more = next_cursor < job.results.len();
+    let version = job.version;
 
     drop(jobs); // Release the write lock
+    if let Some((job_state, version)) = resumed {
+        save_job_state(&state, &job_id, job_state, version).await;
+    }
 
-    Ok(Json(BatchCheckResultsResponse {
+    let body = Json(BatchCheckResultsResponse {
         summary,
         results,
-    }))
+        next_cursor,
+        has_more,
+        version,
+    });
+    Ok(([(header::ETAG, etag)], body).into_response())
 }
 
 
-async fn process_batch_job(
-    job_id: String,
-    lines: Vec<String>,
-    leak_check_service: crate::services::leak_check_service::LeakCheckService,
-    job_storage: crate::models::request_models::JobStorage,
-    input_type: Option<String>,
-) {
-    let is_email_only = input_type.as_deref() == Some("email_only");
-    let mut credentials = Vec::new();
-    let mut invalid_lines = Vec::new();
+/// Whether an `If-None-Match` header names `etag` (or is `*`).
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|candidate| candidate.trim().trim_start_matches("W/"))
+        .any(|candidate| candidate == etag || candidate == "*")
+}
 
-    for line in lines.iter() {
-        let line = line.trim();
-        
-        if is_email_only {
-            let result = CredentialCheckResult {
-                credential: line.to_string(),
-                is_leaked: None,
-                status: "skipped".to_string(),
-                message: Some("Email-only format not supported yet".to_string()),
-            };
-            
-            invalid_lines.push(result);
-        } else {
-            let parts: Vec<&str> = line.split(':').collect();
-            
-            if parts.len() == 2 {
-                let username = parts[0].trim();
-                let password = parts[1].trim();
-                
-                if !username.is_empty() && !password.is_empty() {
-                    credentials.push((username.to_string(), password.to_string()));
-                    continue;
-                }
-            }
-            
-            let result = CredentialCheckResult {
-                credential: line.to_string(),
-                is_leaked: None,
-                status: "error".to_string(),
-                message: Some("Invalid format. Expected username:password".to_string()),
-            };
-            
-            invalid_lines.push(result);
-        }
+
+/// Reads the `abandon_timeout` (seconds) and `detached` form fields. `None`
+/// means the job is never abandoned.
+fn parse_abandon_timeout(timeout: Option<&str>, detached: Option<&str>) -> Result<Option<Duration>, ApiError> {
+    if parse_flag("detached", detached)? {
+        return Ok(None);
     }
 
-    if !invalid_lines.is_empty() {
-        let invalid_count = invalid_lines.len();
-        
-        let mut jobs = job_storage.write().await;
-        if let Some(job) = jobs.get_mut(&job_id) {
-            job.results.extend(invalid_lines);
-            job.processed += invalid_count;
-        }
+    match timeout {
+        None => Ok(Some(DEFAULT_ABANDON_TIMEOUT)),
+        Some(value) => value
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .filter(|secs| *secs > 0)
+            .map(|secs| Some(Duration::from_secs(secs)))
+            .ok_or_else(|| ApiError::InvalidInput("abandon_timeout must be a positive number of seconds".to_string())),
     }
+}
+
+
+/// Reads a boolean form field; absent means false.
+fn parse_flag(name: &str, value: Option<&str>) -> Result<bool, ApiError> {
+    match value.map(|value| value.trim().to_lowercase()).as_deref() {
+        None | Some("") | Some("false") | Some("0") => Ok(false),
+        Some("true") | Some("1") => Ok(true),
+        Some(_) => Err(ApiError::InvalidInput(format!("{} must be true or false", name))),
+    }
+}
+
+
+fn rejected_result(entry: RejectedEntry) -> CredentialCheckResult {
+    CredentialCheckResult {
+        index: 0,
+        line: entry.line,
+        credential: entry.label,
+        is_leaked: None,
+        status: entry.status.to_string(),
+        message: Some(entry.reason),
+        backend: None,
+        occurrences: None,
+        cached: false,
+        url: entry.url,
+        title: entry.title,
+        group: entry.group,
+    }
+}
+
+
+/// Checks `entries`, the part of a job's entries starting at `offset`; a
+/// resumed job passes the entries it had not checked yet.
+async fn process_batch_job(
+    state: AppState,
+    job_id: String,
+    entries: Vec<BatchEntry>,
+    offset: usize,
+    backend: Arc<dyn LeakBackend>,
+) {
+    let leak_check_service = &state.leak_check_service;
+    let job_storage = &state.job_storage;
 
-    let mut batch_size = 10; 
     let mut processed_count = 0;
-    let abandon_timeout = std::time::Duration::from_secs(15); 
     
-    for (chunk_index, chunk) in credentials.chunks(batch_size).enumerate() {
-        if chunk_index == 1 && batch_size < 25 {
-            batch_size = 25;
-        } else if chunk_index == 5 && batch_size < 50 {
-            batch_size = 50;
+    for chunk_index in 0.. {
+        let chunk = &entries[processed_count..entries.len().min(processed_count + chunk_size(chunk_index))];
+        if chunk.is_empty() {
+            break;
         }
         
-        let is_abandoned = {
-            let jobs = job_storage.read().await;
-            if let Some(job) = jobs.get(&job_id) {
-                if job.completed {
-                    return;
-                }
-                
-                let elapsed = job.last_heartbeat.elapsed();
-                if elapsed > abandon_timeout {
-                    tracing::warn!("Job {} has no heartbeat for {:?}, marking as abandoned", job_id, elapsed);
-                    true
-                } else {
-                    false
-                }
-            } else {
-                true
-            }
-        };
-        
-        if is_abandoned {
-            let mut jobs = job_storage.write().await;
-            if let Some(job) = jobs.get_mut(&job_id) {
-                job.is_abandoned = true;
-                job.error = Some("Job abandoned - client stopped requesting updates".to_string());
-                job.completed = true;
-                
-                tracing::warn!("Job {} abandoned after processing {} credentials. Stopping.", job_id, processed_count);
-            }
+        if !wait_until_running(&state, &job_id).await {
+            tracing::info!("Job {} stopped after processing {} credentials", job_id, processed_count);
             return;
         }
         
-        match leak_check_service.check_batch_credentials(chunk.to_vec()).await {
+        let credentials = chunk
+            .iter()
+            .map(|entry| (entry.username.clone(), entry.password.clone()))
+            .collect();
+
+        let chunk_span = tracing::info_span!("batch_chunk", chunk = chunk_index, entries = chunk.len());
+        match leak_check_service.check_batch_credentials(credentials, &backend).instrument(chunk_span).await {
             Ok(results) => {
                 let formatted_results: Vec<CredentialCheckResult> = results
                     .into_iter()
-                    .map(|(username, _, is_leaked, error)| {
+                    .zip(chunk)
+                    .map(|((username, _, outcome, error), entry)| {
                         let status = if error.is_some() { "error" } else { "checked" };
+                        let label = match (&error, outcome.is_leaked) {
+                            (Some(_), _) => "error",
+                            (None, true) => "leaked",
+                            (None, false) => "not_leaked",
+                        };
+                        metrics::CREDENTIAL_CHECKS.inc(&["batch", label]);
                         CredentialCheckResult {
+                            index: 0,
+                            line: entry.line,
                             credential: format!("{}:••••••••", username),
-                            is_leaked: if error.is_some() { None } else { Some(is_leaked) },
+                            is_leaked: if error.is_some() { None } else { Some(outcome.is_leaked) },
                             status: status.to_string(),
                             message: error,
+                            backend: Some(backend.name().to_string()),
+                            occurrences: outcome.occurrences,
+                            cached: outcome.cached,
+                            url: entry.url.clone(),
+                            title: entry.title.clone(),
+                            group: entry.group.clone(),
                         }
                     })
                     .collect();
 
-                let mut jobs = job_storage.write().await;
-                if let Some(job) = jobs.get_mut(&job_id) {
-                    job.results.extend(formatted_results);
-                    job.processed += chunk.len();
-                    processed_count += chunk.len();
-                }
+                processed_count += chunk.len();
+                record_results(&state, &job_id, formatted_results, offset + processed_count).await;
             }
             Err(e) => {
                 let error_msg = format!("Error checking batch: {}", e);
+                metrics::CREDENTIAL_CHECKS.inc_by(&["batch", "error"], chunk.len() as u64);
                 
-                let mut jobs = job_storage.write().await;
-                if let Some(job) = jobs.get_mut(&job_id) {
-                    job.error = Some(error_msg);
-                    job.completed = true;
+                {
+                    let mut jobs = job_storage.write().await;
+                    if let Some(job) = jobs.get_mut(&job_id) {
+                        job.finish(Some(error_msg));
+                    }
                 }
-                
+                publish_finish(&state, &job_id).await;
+                schedule_cleanup(state, job_id, JOB_RETENTION);
                 return;
             }
         }
-        
-        let delay = if batch_size <= 10 { 25 } else { 50 };
-        tokio::time::sleep(tokio::time::Duration::from_millis(delay)).await;
     }
 
     {
         let mut jobs = job_storage.write().await;
-        if let Some(job) = jobs.get_mut(&job_id) {
-            job.completed = true;
-            job.is_abandoned = false; // Clear abandoned flag on completion
-            tracing::info!("Completed job {} with {} credentials processed", job_id, processed_count);
+        match jobs.get_mut(&job_id) {
+            Some(job) if !job.completed => {
+                job.finish(None);
+                tracing::info!("Completed job {} with {} credentials processed", job_id, processed_count);
+            }
+            // Cancelled or deleted while the last chunk was being checked.
+            _ => return,
         }
     }
+    publish_finish(&state, &job_id).await;
+
+    schedule_cleanup(state, job_id, JOB_RETENTION);
+}
+
+/// Entries checked in chunk `chunk_index` of a job. The first chunks are small
+/// so that a client sees results early; later ones are larger so that more
+/// lookups run concurrently.
+fn chunk_size(chunk_index: usize) -> usize {
+    match chunk_index {
+        0 => 10,
+        1..=4 => 25,
+        _ => 50,
+    }
+}
+
+/// Holds the worker while its job is paused or abandoned, first marking the
+/// job abandoned if no client is listening. Returns false once the worker
+/// should stop: the job finished, was cancelled or deleted, or stayed
+/// abandoned for `JOB_RETENTION`.
+async fn wait_until_running(state: &AppState, job_id: &str) -> bool {
+    loop {
+        let (job_state, wake, abandoned) = {
+            let mut jobs = state.job_storage.write().await;
+            let Some(job) = jobs.get_mut(job_id) else { return false };
+            let abandoned = job.state == JobState::Running && !job.has_listeners();
+            if abandoned {
+                tracing::warn!(
+                    "Job {} has no heartbeat for {:?}, waiting for a client to return",
+                    job_id,
+                    job.last_heartbeat.elapsed()
+                );
+                job.abandon();
+                metrics::BATCH_JOBS_ABANDONED.inc(&[]);
+                send_webhook(state, job, WebhookEvent::Abandoned);
+            }
+            (job.state, job.wake.clone(), abandoned.then_some(job.version))
+        };
+        if let Some(version) = abandoned {
+            save_job_state(state, job_id, JobState::Abandoned, version).await;
+        }
+
+        match job_state {
+            JobState::Running => return true,
+            JobState::Cancelled | JobState::Completed => return false,
+            JobState::Paused => wake.notified().await,
+            JobState::Abandoned => {
+                if tokio::time::timeout(JOB_RETENTION, wake.notified()).await.is_ok() {
+                    continue;
+                }
 
-    let job_storage_clone = job_storage.clone();
+                let expired = {
+                    let mut jobs = state.job_storage.write().await;
+                    match jobs.get_mut(job_id) {
+                        Some(job) if job.state == JobState::Abandoned => {
+                            job.cancel(ABANDONED_MESSAGE);
+                            true
+                        }
+                        _ => false,
+                    }
+                };
+                if expired {
+                    tracing::warn!("Job {} stayed abandoned for {:?}, cancelling it", job_id, JOB_RETENTION);
+                    publish_finish(state, job_id).await;
+                    schedule_cleanup(state.clone(), job_id.to_string(), JOB_RETENTION);
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+/// Records results on the live job and writes them through to the job store
+/// together with the number of entries checked so far.
+async fn record_results(state: &AppState, job_id: &str, results: Vec<CredentialCheckResult>, entries_checked: usize) {
+    let (new_results, version) = {
+        let mut jobs = state.job_storage.write().await;
+        let Some(job) = jobs.get_mut(job_id) else { return };
+        if job.completed {
+            return;
+        }
+        let start = job.results.len();
+        job.record_results(results);
+        (job.results[start..].to_vec(), job.version)
+    };
+
+    if let Err(e) = state.job_store.append_results(job_id, &new_results, entries_checked, version).await {
+        tracing::warn!("Failed to save results of job {}: {}", job_id, e);
+    }
+}
+
+/// Writes the live job's completion through to the job store and notifies
+/// its webhook.
+async fn publish_finish(state: &AppState, job_id: &str) {
+    let finished = {
+        let jobs = state.job_storage.read().await;
+        jobs.get(job_id).map(|job| {
+            let (event, outcome) = match (job.state, &job.error) {
+                (JobState::Cancelled, _) => (WebhookEvent::Cancelled, "cancelled"),
+                (_, Some(_)) => (WebhookEvent::Failed, "failed"),
+                (_, None) => (WebhookEvent::Completed, "completed"),
+            };
+            metrics::BATCH_JOBS_FINISHED.inc(&[outcome]);
+            send_webhook(state, job, event);
+            (job.state, job.error.clone(), job.version)
+        })
+    };
+    let Some((job_state, error, version)) = finished else { return };
+
+    if let Err(e) = state.job_store.finish_job(job_id, job_state, error.as_deref(), version).await {
+        tracing::warn!("Failed to save completion of job {}: {}", job_id, e);
+    }
+}
+
+/// Delivers `event` to the job's callback URL, if it has one, in the background.
+fn send_webhook(state: &AppState, job: &BatchProcessingJob, event: WebhookEvent) {
+    let Some(target) = job.callback.clone() else { return };
+
+    let payload = WebhookPayload {
+        event,
+        job_id: job.id.clone(),
+        timestamp: chrono::Utc::now(),
+        summary: job.summary(),
+        error: job.error.clone(),
+        results: target.include_results.then(|| job.results.clone()),
+    };
+    let sender = state.webhooks.clone();
+    let log = job.webhook_log.clone();
+
+    spawn(
+        async move {
+            sender.deliver(&target, &payload, &log).await;
+        }
+        .in_current_span(),
+    );
+}
+
+/// Removes a finished job from memory and from the job store after `delay`.
+fn schedule_cleanup(state: AppState, job_id: String, delay: Duration) {
     spawn(async move {
-        tokio::time::sleep(tokio::time::Duration::from_secs(3600)).await;
-        let mut jobs = job_storage_clone.write().await;
-        if let Some(_job) = jobs.remove(&job_id) {
-            tracing::info!("Cleaned up job {} after 1 hour", job_id);
+        tokio::time::sleep(delay).await;
+        let removed = state.job_storage.write().await.remove(&job_id).is_some();
+        if let Err(e) = state.job_store.delete_job(&job_id).await {
+            tracing::warn!("Failed to delete job {} from the job store: {}", job_id, e);
+        }
+        if removed {
+            tracing::info!("Cleaned up job {} after {:?}", job_id, delay);
         }
     });
 }
 
+/// Loads the jobs kept in the job store into memory, resuming unfinished
+/// ones from the first entry they had not checked. Paused jobs stay paused.
+pub async fn restore_jobs(state: AppState) {
+    let stored_jobs = match state.job_store.load_jobs().await {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            tracing::error!("Failed to load jobs from the {} job store: {}", state.job_store.name(), e);
+            return;
+        }
+    };
+
+    for stored in stored_jobs {
+        let retention = match stored.finished_at {
+            Some(finished_at) => {
+                let age = (chrono::Utc::now() - finished_at).to_std().unwrap_or_default();
+                Some(JOB_RETENTION.saturating_sub(age))
+            }
+            None => None,
+        };
+
+        if retention == Some(Duration::ZERO) {
+            if let Err(e) = state.job_store.delete_job(&stored.id).await {
+                tracing::warn!("Failed to delete expired job {}: {}", stored.id, e);
+            }
+            continue;
+        }
+
+        let mut job = BatchProcessingJob::restore(
+            stored.id.clone(),
+            stored.total,
+            stored.results,
+            stored.state,
+            stored.error,
+            stored.abandon_timeout.map(Duration::from_secs),
+            stored.version,
+        );
+        job.callback = stored.callback;
+        job.owner = stored.owner;
+        let completed = job.completed;
+        state.job_storage.write().await.insert(stored.id.clone(), job);
+
+        if completed {
+            schedule_cleanup(state.clone(), stored.id, retention.unwrap_or(JOB_RETENTION));
+            continue;
+        }
+
+        let backend = if stored.resumable {
+            state.leak_check_service.backend(Some(&stored.backend))
+        } else {
+            Err(anyhow::anyhow!("its KeePass entries were not stored, upload the database again"))
+        };
+        match backend {
+            Ok(backend) => {
+                tracing::info!(
+                    "Resuming job {} at entry {} of {}",
+                    stored.id,
+                    stored.entries_checked,
+                    stored.entries_checked + stored.pending.len()
+                );
+                let span = tracing::info_span!("batch_job", job_id = %stored.id);
+                spawn(process_batch_job(state.clone(), stored.id, stored.pending, stored.entries_checked, backend).instrument(span));
+            }
+            Err(e) => {
+                {
+                    let mut jobs = state.job_storage.write().await;
+                    if let Some(job) = jobs.get_mut(&stored.id) {
+                        job.finish(Some(format!("Could not resume job: {}", e)));
+                    }
+                }
+                publish_finish(&state, &stored.id).await;
+                schedule_cleanup(state.clone(), stored.id, JOB_RETENTION);
+            }
+        }
+    }
+}
+
+/// Stops checking a job after the chunk in progress.
+pub async fn pause_batch_job(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<Json<BatchCheckResponse>, ApiError> {
+    set_job_running(&state, job_id, false).await
+}
+
+/// Continues a paused or abandoned job.
+pub async fn resume_batch_job(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<Json<BatchCheckResponse>, ApiError> {
+    set_job_running(&state, job_id, true).await
+}
+
+async fn set_job_running(state: &AppState, job_id: String, running: bool) -> Result<Json<BatchCheckResponse>, ApiError> {
+    let (job_state, version) = {
+        let mut jobs = state.job_storage.write().await;
+        let job = jobs.get_mut(&job_id).ok_or_else(|| {
+            ApiError::NotFound(format!("Job ID {} not found", job_id))
+        })?;
+
+        if !job.set_running(running) {
+            return Err(ApiError::Conflict(format!("Job {} has already finished", job_id)));
+        }
+        (job.state, job.version)
+    };
+
+    save_job_state(state, &job_id, job_state, version).await;
+
+    let message = if running { "Job resumed" } else { "Job paused" };
+    tracing::info!("{} {}", message, job_id);
+
+    Ok(Json(BatchCheckResponse {
+        job_id,
+        message: message.to_string(),
+    }))
+}
+
+/// Writes a job's new state through to the job store, so a restart finds it
+/// paused, abandoned or running as it was.
+pub(crate) async fn save_job_state(state: &AppState, job_id: &str, job_state: JobState, version: u64) {
+    if let Err(e) = state.job_store.update_state(job_id, job_state, version).await {
+        tracing::warn!("Failed to save state of job {}: {}", job_id, e);
+    }
+}
+
+/// Stops a job for good. Results so far stay available until the job is
+/// cleaned up; its unchecked entries are dropped.
+pub async fn cancel_batch_job(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<Json<BatchCheckResponse>, ApiError> {
+    {
+        let mut jobs = state.job_storage.write().await;
+        let job = jobs.get_mut(&job_id).ok_or_else(|| {
+            ApiError::NotFound(format!("Job ID {} not found", job_id))
+        })?;
+
+        if job.completed {
+            return Err(ApiError::Conflict(format!("Job {} has already finished", job_id)));
+        }
+        job.cancel("Job cancelled");
+    }
+
+    publish_finish(&state, &job_id).await;
+    schedule_cleanup(state.clone(), job_id.clone(), JOB_RETENTION);
+    tracing::info!("Job {} cancelled", job_id);
+
+    Ok(Json(BatchCheckResponse {
+        job_id,
+        message: "Job cancelled".to_string(),
+    }))
+}
+
+/// Every attempt to deliver the job's webhook callbacks so far.
+pub async fn get_webhook_deliveries(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<Json<WebhookDeliveriesResponse>, ApiError> {
+    let jobs = state.job_storage.read().await;
+    let job = jobs.get(&job_id).ok_or_else(|| {
+        ApiError::NotFound(format!("Job ID {} not found", job_id))
+    })?;
+
+    let deliveries = job.webhook_log.lock().unwrap().clone();
+    Ok(Json(WebhookDeliveriesResponse {
+        job_id,
+        callback_url: job.callback.as_ref().map(|target| target.url.clone()),
+        deliveries,
+    }))
+}
+
 pub async fn delete_batch_job(
     State(state): State<AppState>,
     Path(job_id): Path<String>,
 ) -> Result<Json<BatchCheckResponse>, ApiError> {
     let mut jobs = state.job_storage.write().await;
     
-    if let Some(_job) = jobs.remove(&job_id) {
+    if let Some(job) = jobs.remove(&job_id) {
+        drop(jobs);
+        // Lets a paused or abandoned worker see that the job is gone.
+        job.wake.notify_one();
+        if let Err(e) = state.job_store.delete_job(&job_id).await {
+            tracing::warn!("Failed to delete job {} from the job store: {}", job_id, e);
+        }
         tracing::info!("Job {} manually deleted", job_id);
         
         Ok(Json(BatchCheckResponse {
@@ -0,0 +1,279 @@
+use axum::{
+    body::StreamBody,
+    extract::{Path, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use futures::{stream, StreamExt};
+use std::borrow::Cow;
+use std::convert::Infallible;
+use std::str::FromStr;
+
+use crate::api::AppState;
+use crate::models::{
+    request_models::{CredentialCheckResult, ExportQuery},
+    response_models::ExportRow,
+};
+use crate::utils::error::ApiError;
+
+/// Rows serialized into each chunk of the response body.
+const ROWS_PER_CHUNK: usize = 256;
+
+const CSV_HEADER: [&str; 9] = ["line", "credential", "status", "is_leaked", "message", "occurrences", "url", "title", "group"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExportFormat {
+    Csv,
+    Json,
+    Ndjson,
+}
+
+impl ExportFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Json => "application/json",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = ApiError;
+
+    fn from_str(s: &str) -> Result<Self, ApiError> {
+        match s.trim().to_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            "ndjson" | "jsonl" => Ok(ExportFormat::Ndjson),
+            other => Err(ApiError::InvalidInput(format!("Unsupported export format '{}'", other))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExportFilter {
+    All,
+    Leaked,
+    NotLeaked,
+    Errors,
+}
+
+impl ExportFilter {
+    fn matches(self, result: &CredentialCheckResult) -> bool {
+        match self {
+            ExportFilter::All => true,
+            ExportFilter::Leaked => result.is_leaked == Some(true),
+            ExportFilter::NotLeaked => result.is_leaked == Some(false),
+            ExportFilter::Errors => result.is_leaked.is_none(),
+        }
+    }
+}
+
+impl FromStr for ExportFilter {
+    type Err = ApiError;
+
+    fn from_str(s: &str) -> Result<Self, ApiError> {
+        match s.trim().to_lowercase().as_str() {
+            "all" => Ok(ExportFilter::All),
+            "leaked" => Ok(ExportFilter::Leaked),
+            "not_leaked" | "clean" => Ok(ExportFilter::NotLeaked),
+            "error" | "errors" => Ok(ExportFilter::Errors),
+            other => Err(ApiError::InvalidInput(format!("Unsupported status filter '{}'", other))),
+        }
+    }
+}
+
+
+/// Downloads a job's results as CSV, a JSON array or NDJSON, ordered by line
+/// number. The matching rows are copied out under the read lock and then
+/// serialized chunk by chunk as the body is sent.
+pub async fn export_batch_results(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, ApiError> {
+    let format = query.format.as_deref().unwrap_or("csv").parse::<ExportFormat>()?;
+    let filter = query.status.as_deref().unwrap_or("all").parse::<ExportFilter>()?;
+
+    let mut rows: Vec<ExportRow> = {
+        let jobs = state.job_storage.read().await;
+        let job = jobs.get(&job_id).ok_or_else(|| {
+            ApiError::NotFound(format!("Job ID {} not found", job_id))
+        })?;
+
+        job.results
+            .iter()
+            .filter(|result| filter.matches(result))
+            .map(ExportRow::from)
+            .collect()
+    };
+    rows.sort_by_key(|row| row.line);
+
+    let row_count = rows.len();
+    let chunks: Vec<Vec<ExportRow>> = rows
+        .chunks(ROWS_PER_CHUNK)
+        .map(<[ExportRow]>::to_vec)
+        .collect();
+
+    let opening = match format {
+        ExportFormat::Csv => Some(encode_csv([CSV_HEADER.map(Cow::Borrowed)])),
+        ExportFormat::Json => Some(Bytes::from_static(b"[")),
+        ExportFormat::Ndjson => None,
+    };
+    let closing = match format {
+        ExportFormat::Json => Some(Bytes::from_static(b"]\n")),
+        _ => None,
+    };
+
+    let body = stream::iter(opening)
+        .chain(stream::iter(chunks.into_iter().enumerate()).map(move |(i, chunk)| {
+            encode_rows(format, &chunk, i == 0)
+        }))
+        .chain(stream::iter(closing))
+        .map(Ok::<_, Infallible>);
+
+    tracing::info!("Exporting {} rows of job {} as {}", row_count, job_id, format.extension());
+
+    let disposition = format!("attachment; filename=\"batch-{}.{}\"", job_id, format.extension());
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        StreamBody::new(body),
+    )
+        .into_response())
+}
+
+fn encode_rows(format: ExportFormat, rows: &[ExportRow], first_chunk: bool) -> Bytes {
+    let mut out = String::new();
+
+    match format {
+        ExportFormat::Csv => return encode_csv(rows.iter().map(csv_record)),
+        ExportFormat::Json => {
+            for (i, row) in rows.iter().enumerate() {
+                if !(first_chunk && i == 0) {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::to_string(row).unwrap_or_default());
+            }
+        }
+        ExportFormat::Ndjson => {
+            for row in rows {
+                out.push_str(&serde_json::to_string(row).unwrap_or_default());
+                out.push('\n');
+            }
+        }
+    }
+
+    Bytes::from(out)
+}
+
+/// The CSV fields of a row, text fields neutralised.
+fn csv_record(row: &ExportRow) -> [Cow<'_, str>; 9] {
+    [
+        Cow::Owned(row.line.to_string()),
+        neutralise_formula(&row.credential),
+        neutralise_formula(&row.status),
+        Cow::Owned(row.is_leaked.map(|l| l.to_string()).unwrap_or_default()),
+        row.message.as_deref().map(neutralise_formula).unwrap_or_default(),
+        Cow::Owned(row.occurrences.map(|o| o.to_string()).unwrap_or_default()),
+        row.url.as_deref().map(neutralise_formula).unwrap_or_default(),
+        row.title.as_deref().map(neutralise_formula).unwrap_or_default(),
+        row.group.as_deref().map(neutralise_formula).unwrap_or_default(),
+    ]
+}
+
+/// Writes `records` as CSV lines, quoting fields where needed.
+fn encode_csv<'a>(records: impl IntoIterator<Item = [Cow<'a, str>; 9]>) -> Bytes {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for record in records {
+        // Records of a fixed length cannot fail to write to memory.
+        let _ = writer.write_record(record.iter().map(|field| field.as_bytes()));
+    }
+    Bytes::from(writer.into_inner().unwrap_or_default())
+}
+
+/// Prefixes text starting with a formula character with `'`, so spreadsheets
+/// do not evaluate user-supplied text.
+fn neutralise_formula(value: &str) -> Cow<'_, str> {
+    if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        Cow::Owned(format!("'{}", value))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(line: usize, credential: &str, message: Option<&str>) -> ExportRow {
+        ExportRow {
+            line,
+            credential: credential.to_string(),
+            status: "checked".to_string(),
+            is_leaked: Some(true),
+            message: message.map(str::to_string),
+            occurrences: None,
+            url: None,
+            title: None,
+            group: None,
+        }
+    }
+
+    #[test]
+    fn test_csv_field_escaping() {
+        assert_eq!(neutralise_formula("plain"), "plain");
+        assert_eq!(neutralise_formula("-1"), "'-1");
+
+        let mut quoted = row(2, "a,b", Some("say \"hi\""));
+        quoted.title = Some("=HYPERLINK(\"x\")".to_string());
+        let csv = encode_rows(ExportFormat::Csv, &[quoted], true);
+        assert_eq!(
+            std::str::from_utf8(&csv).unwrap(),
+            "2,\"a,b\",checked,true,\"say \"\"hi\"\"\",,,\"'=HYPERLINK(\"\"x\"\")\",\n"
+        );
+        let header = encode_csv([CSV_HEADER.map(Cow::Borrowed)]);
+        assert_eq!(&header[..], b"line,credential,status,is_leaked,message,occurrences,url,title,group\n");
+    }
+
+    #[test]
+    fn test_encode_rows() {
+        let rows = [row(1, "alice:••••••••", None), row(3, "bob:••••••••", Some("Error: timeout, retry"))];
+
+        let csv = encode_rows(ExportFormat::Csv, &rows, true);
+        assert_eq!(
+            std::str::from_utf8(&csv).unwrap(),
+            "1,alice:••••••••,checked,true,,,,,\n3,bob:••••••••,checked,true,\"Error: timeout, retry\",,,,\n"
+        );
+
+        let first = encode_rows(ExportFormat::Json, &rows[..1], true);
+        let second = encode_rows(ExportFormat::Json, &rows[1..], false);
+        let json = format!("[{}{}]", std::str::from_utf8(&first).unwrap(), std::str::from_utf8(&second).unwrap());
+        let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.as_array().unwrap().len(), 2);
+        assert_eq!(parsed[1]["line"], 3);
+
+        let ndjson = encode_rows(ExportFormat::Ndjson, &rows, true);
+        assert_eq!(std::str::from_utf8(&ndjson).unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn test_query_parsing() {
+        assert_eq!("NDJSON".parse::<ExportFormat>().unwrap(), ExportFormat::Ndjson);
+        assert!("xml".parse::<ExportFormat>().is_err());
+        assert_eq!("errors".parse::<ExportFilter>().unwrap(), ExportFilter::Errors);
+        assert!("maybe".parse::<ExportFilter>().is_err());
+    }
+}
@@ -0,0 +1,56 @@
+use axum::{extract::State, http::header, response::IntoResponse};
+
+use crate::api::AppState;
+use crate::models::request_models::JobState;
+use crate::utils::metrics;
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Serves every metric in the Prometheus text format. Job counts are taken
+/// from the live jobs at scrape time: running jobs are active, paused and
+/// abandoned ones are queued until they resume. Result cache entries and the
+/// adaptive upstream concurrency are read at scrape time as well.
+pub async fn get_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let mut out = metrics::render();
+
+    let states = [JobState::Running, JobState::Paused, JobState::Abandoned];
+    let counts: Vec<(&str, u64)> = {
+        let jobs = state.job_storage.read().await;
+        states
+            .iter()
+            .map(|job_state| {
+                let count = jobs.values().filter(|job| job.state == *job_state).count();
+                (job_state.as_str(), count as u64)
+            })
+            .collect()
+    };
+    metrics::render_gauge(
+        &mut out,
+        "leaklens_batch_jobs",
+        "Unfinished batch jobs in memory, by state.",
+        "state",
+        &counts,
+    );
+
+    let (limit, in_flight) = state.leak_check_service.api_client.limiter().snapshot();
+    metrics::render_gauge(
+        &mut out,
+        "leaklens_upstream_concurrency",
+        "Lookups to Google currently allowed in flight (limit) and sent but not answered (in_flight).",
+        "kind",
+        &[("limit", limit as u64), ("in_flight", in_flight as u64)],
+    );
+
+    if let Some(cache) = state.leak_check_service.result_cache() {
+        let (leaked, not_leaked) = cache.counts();
+        metrics::render_gauge(
+            &mut out,
+            "leaklens_result_cache_entries",
+            "Verdicts held in the result cache, by verdict.",
+            "verdict",
+            &[("leaked", leaked as u64), ("not_leaked", not_leaked as u64)],
+        );
+    }
+
+    ([(header::CONTENT_TYPE, CONTENT_TYPE)], out)
+}
@@ -1,11 +1,19 @@
 //! API routes for the LeakLens API service
 
+pub mod admin_routes;
+pub mod auth;
 pub mod check_routes;
 pub mod docs;
+pub mod export_routes;
+pub mod metrics_routes;
+pub mod request_id;
 pub mod status_routes;
+pub mod stream_routes;
 pub mod health;
 
 use axum::{
+    extract::DefaultBodyLimit,
+    middleware,
     routing::{get, post, delete},
     Router,
 };
@@ -13,25 +21,49 @@ use tower_http::{
     cors::{Any, CorsLayer},
     trace::TraceLayer,
 };
-use http::HeaderValue;
-use tracing;
+use http::{header, HeaderName, HeaderValue};
+use std::sync::Arc;
 
+use crate::services::api_keys::{ApiKeyStore, Scope};
+use crate::services::job_store::JobStore;
+use crate::services::jwt::JwtValidator;
 use crate::services::leak_check_service::LeakCheckService;
 use crate::services::token_manager::TokenManager;
+use crate::services::webhooks::WebhookSender;
 use crate::models::request_models::JobStorage;
-use crate::utils::config;
+use crate::utils::{
+    config,
+    rate_limiter::{self, LimitedRoute},
+    upload::MULTIPART_OVERHEAD,
+};
 
-pub fn create_router(job_storage: JobStorage) -> Router {
+/// Builds the router and resumes any unfinished jobs found in `job_store`.
+/// Fails if a configured leak backend cannot be set up.
+pub fn create_router(
+    job_storage: JobStorage,
+    job_store: Arc<dyn JobStore>,
+    api_keys: Arc<dyn ApiKeyStore>,
+) -> anyhow::Result<Router> {
     let token_manager = TokenManager::new();
-    let leak_check_service = LeakCheckService::new(token_manager.clone());
+    let leak_check_service = LeakCheckService::new(token_manager.clone())?;
 
     let config = config::get();
     
+    // Lets browser clients read the rate limit and request ID headers.
+    let exposed_headers = [
+        HeaderName::from_static(rate_limiter::LIMIT_HEADER),
+        HeaderName::from_static(rate_limiter::REMAINING_HEADER),
+        HeaderName::from_static(rate_limiter::RESET_HEADER),
+        header::RETRY_AFTER,
+        HeaderName::from_static(request_id::REQUEST_ID_HEADER),
+    ];
+
     let cors = if config.server.cors_allowed_origins.contains(&"*".to_string()) {
         CorsLayer::new()
             .allow_origin(Any)
             .allow_methods(Any)
             .allow_headers(Any)
+            .expose_headers(exposed_headers)
     } else {
         let mut cors_layer = CorsLayer::new();
         
@@ -45,25 +77,84 @@ pub fn create_router(job_storage: JobStorage) -> Router {
         cors_layer
             .allow_methods(Any)
             .allow_headers(Any)
+            .expose_headers(exposed_headers)
     };
 
     let app_state = AppState {
         leak_check_service,
         token_manager,
         job_storage,
+        job_store,
+        webhooks: WebhookSender::new(&config.webhooks),
+        api_keys,
+        jwt: config.auth.jwt.clone().map(|jwt| Arc::new(JwtValidator::new(jwt))),
     };
 
-    Router::<AppState>::new()
-        .route("/health", get(health::health_check))
-        .route("/api/v1/status", get(status_routes::get_api_status))
-        .route("/api/v1/check/single", post(check_routes::check_single))
-        .route("/api/v1/check/batch", post(check_routes::check_batch))
+    if let Some(jwt) = app_state.jwt.clone() {
+        tokio::spawn(async move {
+            if let Err(e) = jwt.preload().await {
+                tracing::warn!("Failed to load the JWKS, retrying on the first token: {:#}", e);
+            }
+        });
+    }
+
+    tokio::spawn(check_routes::restore_jobs(app_state.clone()));
+
+    let single_routes = Router::<AppState>::new()
+        .route(
+            "/api/v1/check/single",
+            post(check_routes::check_single)
+                .layer(middleware::from_fn_with_state(LimitedRoute::Single, rate_limiter::rate_limit)),
+        )
+        .route(
+            "/api/v1/check/blinded",
+            post(check_routes::check_blinded)
+                .layer(middleware::from_fn_with_state(LimitedRoute::Blinded, rate_limiter::rate_limit)),
+        )
+        .route_layer(middleware::from_fn_with_state(auth::Guard::new(&app_state, Scope::Single), auth::authenticate));
+
+    let upload_routes = Router::<AppState>::new()
+        .route(
+            "/api/v1/check/batch",
+            post(check_routes::check_batch)
+                .layer(DefaultBodyLimit::max(config.rate_limits.max_upload_bytes + MULTIPART_OVERHEAD))
+                .layer(middleware::from_fn_with_state(LimitedRoute::Batch, rate_limiter::rate_limit)),
+        )
+        .route_layer(middleware::from_fn_with_state(auth::Guard::new(&app_state, Scope::Batch), auth::authenticate));
+
+    let job_routes = Router::<AppState>::new()
         .route("/api/v1/check/batch/:job_id/status", get(check_routes::get_batch_status))
+        .route("/api/v1/check/batch/:job_id/export", get(export_routes::export_batch_results))
+        .route("/api/v1/check/batch/:job_id/events", get(stream_routes::batch_events_sse))
+        .route("/api/v1/check/batch/:job_id/ws", get(stream_routes::batch_events_ws))
+        .route("/api/v1/check/batch/:job_id/pause", post(check_routes::pause_batch_job))
+        .route("/api/v1/check/batch/:job_id/resume", post(check_routes::resume_batch_job))
+        .route("/api/v1/check/batch/:job_id/cancel", post(check_routes::cancel_batch_job))
+        .route("/api/v1/check/batch/:job_id/webhooks", get(check_routes::get_webhook_deliveries))
         .route("/api/v1/check/batch/:job_id", delete(check_routes::delete_batch_job))
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), auth::authorize_job))
+        .route_layer(middleware::from_fn_with_state(auth::Guard::new(&app_state, Scope::Batch), auth::authenticate));
+
+    let admin_routes = Router::<AppState>::new()
+        .route("/api/v1/admin/keys", post(admin_routes::create_api_key).get(admin_routes::list_api_keys))
+        .route("/api/v1/admin/keys/:key_id/rotate", post(admin_routes::rotate_api_key))
+        .route("/api/v1/admin/keys/:key_id", delete(admin_routes::revoke_api_key))
+        .route_layer(middleware::from_fn_with_state(auth::Guard::new(&app_state, Scope::Admin), auth::authenticate));
+
+    Ok(Router::<AppState>::new()
+        .route("/health", get(health::health_check))
+        .route("/metrics", get(metrics_routes::get_metrics))
+        .route("/api/v1/status", get(status_routes::get_api_status))
+        .merge(single_routes)
+        .merge(upload_routes)
+        .merge(job_routes)
+        .merge(admin_routes)
         .merge(docs::docs_routes())
         .with_state(app_state)
+        .layer(middleware::from_fn(rate_limiter::limit_address))
         .layer(cors)
         .layer(TraceLayer::new_for_http())
+        .layer(middleware::from_fn(request_id::request_id)))
 }
 
 #[derive(Clone)]
@@ -71,4 +162,10 @@ pub struct AppState {
     pub leak_check_service: LeakCheckService,
     pub token_manager: TokenManager,
     pub job_storage: JobStorage,
+    /// Durable copy of `job_storage`, used to resume jobs after a restart.
+    pub job_store: Arc<dyn JobStore>,
+    pub webhooks: WebhookSender,
+    pub api_keys: Arc<dyn ApiKeyStore>,
+    /// Set when `AUTH_MODE` is `jwt`.
+    pub jwt: Option<Arc<JwtValidator>>,
 }
\ No newline at end of file
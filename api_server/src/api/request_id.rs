@@ -0,0 +1,55 @@
+//! Request IDs tie together every log line written while serving a request,
+//! including those of background work it starts, such as batch jobs. The same
+//! span is the root of the request's trace, continuing the caller's trace
+//! when it sends a `traceparent` header.
+
+use axum::{
+    http::{HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use tracing::Instrument;
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Longest request ID accepted from a client or proxy.
+const MAX_LENGTH: usize = 128;
+
+/// Runs the request inside an `http` span carrying its `request_id` and
+/// response status, and echoes the ID in `X-Request-Id`. A well formed ID
+/// sent by the client or a proxy is kept, otherwise a new one is generated.
+pub async fn request_id<B>(request: Request<B>, next: Next<B>) -> Response {
+    let id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let traceparent = request.headers().get(TRACEPARENT_HEADER).and_then(|value| value.to_str().ok());
+    let span = tracing::info_span!(
+        "http",
+        otel.kind = "server",
+        traceparent,
+        request_id = %id,
+        method = %request.method(),
+        path = %request.uri().path(),
+        http.status_code = tracing::field::Empty,
+    );
+    let mut response = next.run(request).instrument(span.clone()).await;
+    span.record("http.status_code", response.status().as_u16());
+
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_LENGTH
+        && id.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b':'))
+}
@@ -0,0 +1,149 @@
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, State,
+    },
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Response,
+    },
+};
+use futures::{stream, Stream, StreamExt};
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{debug, warn};
+
+use crate::api::{check_routes::save_job_state, AppState};
+use crate::models::{request_models::JobStorage, response_models::BatchEvent};
+use crate::utils::error::ApiError;
+
+
+/// Server-Sent Events feed of a batch job: every result so far, a summary,
+/// then live events until the job completes.
+pub async fn batch_events_sse(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let events = subscribe(&state, &job_id).await?;
+
+    let stream = events.map(|event| {
+        let sse_event = Event::default()
+            .event(event.kind())
+            .json_data(&event)
+            .unwrap_or_else(|_| Event::default().event("error"));
+        Ok(sse_event)
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+
+/// Same feed as `batch_events_sse`, one JSON text message per event.
+pub async fn batch_events_ws(
+    State(state): State<AppState>,
+    Path(job_id): Path<String>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    let events = subscribe(&state, &job_id).await?;
+    Ok(ws.on_upgrade(move |socket| forward_events(socket, events)))
+}
+
+
+async fn forward_events(mut socket: WebSocket, events: impl Stream<Item = BatchEvent>) {
+    let mut events = Box::pin(events);
+
+    loop {
+        tokio::select! {
+            event = events.next() => {
+                let Some(event) = event else { break };
+                let Ok(payload) = serde_json::to_string(&event) else { continue };
+                if socket.send(Message::Text(payload)).await.is_err() {
+                    return;
+                }
+            }
+            message = socket.recv() => {
+                match message {
+                    Some(Ok(Message::Close(_))) | None | Some(Err(_)) => return,
+                    Some(Ok(_)) => {}
+                }
+            }
+        }
+    }
+
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+
+struct Feed {
+    backlog: VecDeque<BatchEvent>,
+    receiver: Option<broadcast::Receiver<BatchEvent>>,
+    job_storage: JobStorage,
+    job_id: String,
+}
+
+/// Snapshots the job and subscribes to its events under one lock, so the
+/// replayed backlog and the live events neither overlap nor leave a gap.
+async fn subscribe(state: &AppState, job_id: &str) -> Result<impl Stream<Item = BatchEvent>, ApiError> {
+    let mut jobs = state.job_storage.write().await;
+    let job = jobs.get_mut(job_id).ok_or_else(|| {
+        ApiError::NotFound(format!("Job ID {} not found", job_id))
+    })?;
+
+    let resumed = job.touch().then_some((job.state, job.version));
+
+    let mut backlog: VecDeque<BatchEvent> = job.results
+        .iter()
+        .map(|result| BatchEvent::Result { result: result.clone() })
+        .collect();
+    backlog.push_back(BatchEvent::Summary { summary: job.summary() });
+
+    let receiver = if job.completed {
+        backlog.push_back(BatchEvent::Completed { summary: job.summary(), error: job.error.clone() });
+        None
+    } else {
+        Some(job.events.subscribe())
+    };
+
+    debug!("Streaming job {} with {} buffered events", job_id, backlog.len());
+
+    let feed = Feed {
+        backlog,
+        receiver,
+        job_storage: state.job_storage.clone(),
+        job_id: job_id.to_string(),
+    };
+    drop(jobs);
+
+    if let Some((job_state, version)) = resumed {
+        save_job_state(state, job_id, job_state, version).await;
+    }
+
+    Ok(stream::unfold(feed, next_event))
+}
+
+async fn next_event(mut feed: Feed) -> Option<(BatchEvent, Feed)> {
+    if let Some(event) = feed.backlog.pop_front() {
+        return Some((event, feed));
+    }
+
+    let receiver = feed.receiver.as_mut()?;
+    let event = match receiver.recv().await {
+        Ok(event) => event,
+        Err(RecvError::Lagged(skipped)) => {
+            // Catch up with a fresh summary; the skipped results stay
+            // available from the status endpoint.
+            warn!("Stream for job {} lagged by {} events", feed.job_id, skipped);
+            let jobs = feed.job_storage.read().await;
+            let summary = jobs.get(&feed.job_id)?.summary();
+            BatchEvent::Summary { summary }
+        }
+        Err(RecvError::Closed) => return None,
+    };
+
+    if matches!(event, BatchEvent::Completed { .. }) {
+        feed.receiver = None;
+    }
+
+    Some((event, feed))
+}
@@ -0,0 +1,96 @@
+//! Manages the API keys in the SQLite key store (`API_KEY_STORE_PATH`) without
+//! going through the server, e.g. to issue the first admin key.
+
+use chrono::Duration;
+use leaklens_api::services::api_keys::{self, parse_scopes, sqlite::SqliteApiKeyStore, ApiKey, ApiKeyStore};
+use std::error::Error;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    dotenvy::dotenv().ok();
+
+    let args: Vec<String> = std::env::args().collect();
+    let path = std::env::var("API_KEY_STORE_PATH").unwrap_or_else(|_| "leaklens-keys.sqlite3".to_string());
+    let store = SqliteApiKeyStore::open(&path)?;
+
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        [_, "issue", name, "--scopes", scopes] => issue(&store, name, scopes, None).await,
+        [_, "issue", name, "--scopes", scopes, "--expires-days", days] => {
+            issue(&store, name, scopes, Some(days.parse()?)).await
+        }
+        [_, "list"] => list(&store).await,
+        [_, "rotate", key_id] => rotate(&store, key_id).await,
+        [_, "revoke", key_id] => revoke(&store, key_id).await,
+        _ => {
+            eprintln!("Usage: {} issue <name> --scopes <single,batch,admin> [--expires-days <days>]", args[0]);
+            eprintln!("       {} list", args[0]);
+            eprintln!("       {} rotate <key_id>", args[0]);
+            eprintln!("       {} revoke <key_id>", args[0]);
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn issue(
+    store: &dyn ApiKeyStore,
+    name: &str,
+    scopes: &str,
+    expires_days: Option<u32>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let scopes = parse_scopes(scopes)?;
+    if scopes.is_empty() {
+        return Err("At least one scope is required".into());
+    }
+
+    let lifetime = expires_days.map(|days| Duration::days(days.into()));
+    let issued = api_keys::issue(store, name, scopes, lifetime).await?;
+    print_secret(&issued.key, &issued.secret);
+    Ok(())
+}
+
+async fn list(store: &dyn ApiKeyStore) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let now = chrono::Utc::now();
+    for key in store.list().await? {
+        let status = match (key.revoked_at, key.expires_at) {
+            (Some(revoked_at), _) => format!("revoked {}", revoked_at.to_rfc3339()),
+            (None, Some(expires_at)) if expires_at <= now => format!("expired {}", expires_at.to_rfc3339()),
+            (None, Some(expires_at)) => format!("expires {}", expires_at.to_rfc3339()),
+            (None, None) => "active".to_string(),
+        };
+        let scopes: Vec<&str> = key.scopes.iter().map(|scope| scope.as_str()).collect();
+        println!("{}  {}…  {}  [{}]  {}", key.id, key.prefix, key.name, scopes.join(","), status);
+    }
+    Ok(())
+}
+
+async fn rotate(store: &dyn ApiKeyStore, key_id: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let key = find_key(store, key_id).await?;
+    if key.revoked_at.is_some() {
+        return Err(format!("API key {} has been revoked", key_id).into());
+    }
+
+    let issued = api_keys::rotate(store, &key).await?;
+    println!("Revoked {}", key_id);
+    print_secret(&issued.key, &issued.secret);
+    Ok(())
+}
+
+async fn revoke(store: &dyn ApiKeyStore, key_id: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    find_key(store, key_id).await?;
+    store.revoke(key_id, chrono::Utc::now()).await?;
+    println!("Revoked {}", key_id);
+    Ok(())
+}
+
+async fn find_key(store: &dyn ApiKeyStore, key_id: &str) -> Result<ApiKey, Box<dyn Error + Send + Sync>> {
+    store
+        .get(key_id)
+        .await?
+        .ok_or_else(|| format!("API key {} not found", key_id).into())
+}
+
+fn print_secret(key: &ApiKey, secret: &str) {
+    println!("Issued {} ({})", key.id, key.name);
+    println!("{}", secret);
+    println!("Store this key now; it cannot be shown again.");
+}
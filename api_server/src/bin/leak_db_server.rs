@@ -0,0 +1,133 @@
+//! Self-hosted breach database speaking Google's `PasswordLeakCheckService`
+//! protocol. Point `GOOGLE_API_URL` at `/v1/leaks:lookupSingle` and
+//! `GOOGLE_TOKEN_URL` at `/token` to check against a private corpus.
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Form, Json, Router,
+};
+use leaklens_api::crypto::ecc_cipher::ECCommutativeCipher;
+use leaklens_api::proto::LookupSingleLeakRequest;
+use leaklens_api::services::leak_database::LeakDatabase;
+use prost::Message;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::{info, warn, Level};
+use tracing_subscriber::FmtSubscriber;
+
+const TOKEN_LIFETIME_SECS: u64 = 3600;
+
+#[derive(Clone)]
+struct AppState {
+    database: Arc<LeakDatabase>,
+    access_token: Arc<String>,
+    client_secret: Option<Arc<String>>,
+}
+
+#[derive(Deserialize)]
+struct TokenRequest {
+    client_secret: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let subscriber = FmtSubscriber::builder()
+        .with_max_level(Level::INFO)
+        .finish();
+    tracing::subscriber::set_global_default(subscriber)?;
+    dotenvy::dotenv().ok();
+
+    let args: Vec<String> = std::env::args().collect();
+    let (corpus_path, listen) = match args.as_slice() {
+        [_, corpus] => (corpus, "127.0.0.1:4000"),
+        [_, corpus, flag, listen] if flag == "--listen" => (corpus, listen.as_str()),
+        _ => {
+            eprintln!("Usage: {} <corpus_file> [--listen <addr>]", args[0]);
+            eprintln!("The corpus holds one username:password pair per line.");
+            std::process::exit(1);
+        }
+    };
+
+    let credentials = read_corpus(&std::fs::read_to_string(corpus_path)?);
+    info!("Ingesting {} credentials from {}", credentials.len(), corpus_path);
+
+    let mut database = LeakDatabase::new(ECCommutativeCipher::new(None));
+    database.ingest(&credentials)?;
+
+    let access_token = std::env::var("LEAK_DB_ACCESS_TOKEN").unwrap_or_else(|_| {
+        warn!("LEAK_DB_ACCESS_TOKEN not set, issuing a random token");
+        uuid::Uuid::new_v4().to_string()
+    });
+
+    let state = AppState {
+        database: Arc::new(database),
+        access_token: Arc::new(access_token),
+        client_secret: std::env::var("LEAK_DB_CLIENT_SECRET").ok().map(Arc::new),
+    };
+
+    let app = Router::new()
+        .route("/v1/leaks:lookupSingle", post(lookup_single_leak))
+        .route("/token", post(issue_token))
+        .with_state(state);
+
+    let addr: SocketAddr = listen.parse()?;
+    info!("Leak database listening on http://{}", addr);
+    axum::Server::bind(&addr).serve(app.into_make_service()).await?;
+
+    Ok(())
+}
+
+/// Parses `username:password` lines, skipping blanks and `#` comments.
+fn read_corpus(contents: &str) -> Vec<(String, String)> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(username, password)| (username.to_string(), password.to_string()))
+        .collect()
+}
+
+/// Minimal OAuth token endpoint so `TokenManager` works unchanged.
+async fn issue_token(State(state): State<AppState>, Form(form): Form<TokenRequest>) -> Response {
+    if let Some(expected) = &state.client_secret {
+        if form.client_secret.as_deref() != Some(expected.as_str()) {
+            return (StatusCode::UNAUTHORIZED, "invalid client_secret").into_response();
+        }
+    }
+
+    let body: HashMap<&str, serde_json::Value> = HashMap::from([
+        ("access_token", state.access_token.as_str().into()),
+        ("expires_in", TOKEN_LIFETIME_SECS.into()),
+        ("token_type", "Bearer".into()),
+    ]);
+    Json(body).into_response()
+}
+
+async fn lookup_single_leak(State(state): State<AppState>, headers: HeaderMap, body: Bytes) -> Response {
+    let expected = format!("Bearer {}", state.access_token);
+    if headers.get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()) != Some(expected.as_str()) {
+        return (StatusCode::UNAUTHORIZED, "missing or invalid bearer token").into_response();
+    }
+
+    let request = match LookupSingleLeakRequest::decode(body) {
+        Ok(request) => request,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("invalid request: {}", e)).into_response(),
+    };
+
+    match state.database.lookup(&request) {
+        Ok(response) => (
+            [(header::CONTENT_TYPE, "application/x-protobuf")],
+            response.encode_to_vec(),
+        )
+            .into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
@@ -1,30 +1,47 @@
+use leaklens_api::crypto::check_google_api::{create_lookup_request, unblind_and_check};
+use leaklens_api::crypto::ecc_cipher::ECCommutativeCipher;
+use leaklens_api::proto::LookupSingleLeakResponse;
+use leaklens_api::services::batch_input::{self, InputFormat, MasterPassword, ParseOptions};
 use leaklens_api::services::leak_check_service::LeakCheckService;
 use leaklens_api::services::token_manager::TokenManager;
 use leaklens_api::utils::config;
+use serde::Deserialize;
 use std::error::Error;
 
+#[derive(Deserialize)]
+struct BlindedCheckResponse {
+    reencrypted_lookup_hash: String,
+    encrypted_leak_match_prefix: Vec<String>,
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
-    // Initialize configuration
-    config::init()?;
-    
     let args: Vec<String> = std::env::args().collect();
-    
-    if args.len() != 3 {
-        eprintln!("Usage: {} <username> <password>", args[0]);
-        std::process::exit(1);
+
+    if let [_, flag, database_path] = args.as_slice() {
+        if flag == "--kdbx" {
+            return audit_kdbx(database_path).await;
+        }
     }
-    
-    let username = &args[1];
-    let password = &args[2];
-    
-    // Create a token manager
-    let token_manager = TokenManager::new();
-    let leak_check_service = LeakCheckService::new(token_manager);
-    
+
+    let (server_url, username, password) = match args.as_slice() {
+        [_, username, password] => (None, username, password),
+        [_, flag, server_url, username, password] if flag == "--blinded" => (Some(server_url), username, password),
+        _ => {
+            eprintln!("Usage: {} [--blinded <server_url>] <username> <password>", args[0]);
+            eprintln!("       {} --kdbx <database.kdbx>", args[0]);
+            std::process::exit(1);
+        }
+    };
+
     println!("Checking if credential is leaked: {}:{}", username, "*".repeat(password.len()));
-    
-    match leak_check_service.check_single_credential(username, password).await {
+
+    let result = match server_url {
+        Some(server_url) => check_blinded(server_url, username, password).await,
+        None => check_direct(username, password).await,
+    };
+
+    match result {
         Ok(is_leaked) => {
             if is_leaked {
                 println!("❌ CREDENTIAL FOUND IN BREACH DATABASE");
@@ -35,7 +52,103 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         },
         Err(e) => {
             eprintln!("Error checking credential: {}", e);
-            Err(e.into())
+            Err(e)
         }
     }
-} 
\ No newline at end of file
+}
+
+async fn check_direct(username: &str, password: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    // Initialize configuration
+    config::init()?;
+
+    // Create a token manager
+    let token_manager = TokenManager::new();
+    let leak_check_service = LeakCheckService::new(token_manager)?;
+
+    Ok(leak_check_service.check_single_credential(username, password).await?)
+}
+
+/// Runs the blinded flow against a LeakLens server: hashing and blinding happen
+/// here, the server only relays the request to Google.
+async fn check_blinded(server_url: &str, username: &str, password: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let cipher = ECCommutativeCipher::new(None);
+    let request = create_lookup_request(username, password, &cipher)?;
+
+    let body = serde_json::json!({
+        "username_hash_prefix": hex::encode(&request.username_hash_prefix),
+        "encrypted_lookup_hash": hex::encode(&request.encrypted_lookup_hash),
+    });
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/v1/check/blinded", server_url.trim_end_matches('/')))
+        .json(&body)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<BlindedCheckResponse>()
+        .await?;
+
+    let response = LookupSingleLeakResponse {
+        reencrypted_lookup_hash: hex::decode(&response.reencrypted_lookup_hash)?,
+        encrypted_leak_match_prefix: response.encrypted_leak_match_prefix
+            .iter()
+            .map(hex::decode)
+            .collect::<Result<_, _>>()?,
+    };
+
+    Ok(unblind_and_check(&response, &cipher)?)
+}
+
+/// Checks every entry of a KeePass database. The master password comes from
+/// `KDBX_PASSWORD` or a prompt; the vault is only ever decrypted in memory.
+async fn audit_kdbx(database_path: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let master_password = match std::env::var("KDBX_PASSWORD") {
+        Ok(password) => password,
+        Err(_) => rpassword::prompt_password("Master password: ")?,
+    };
+
+    let bytes = std::fs::read(database_path)?;
+    let options = ParseOptions {
+        format: Some(InputFormat::Kdbx),
+        master_password: Some(MasterPassword::new(master_password)),
+        ..ParseOptions::default()
+    };
+    let batch = batch_input::parse(&bytes, &options)?;
+
+    println!("Checking {} entries from {}", batch.entries.len(), database_path);
+    for rejected in &batch.rejected {
+        println!("⏭  {} — {}", rejected.label, rejected.reason);
+    }
+
+    config::init()?;
+    let leak_check_service = LeakCheckService::new(TokenManager::new())?;
+    let backend = leak_check_service.backend(None)?;
+
+    let credentials = batch.entries
+        .iter()
+        .map(|entry| (entry.username.clone(), entry.password.clone()))
+        .collect();
+    let results = leak_check_service.check_batch_credentials(credentials, &backend).await?;
+
+    let mut leaked = 0;
+    for ((_, _, outcome, error), entry) in results.into_iter().zip(&batch.entries) {
+        let name = format!(
+            "{} / {} ({})",
+            entry.group.as_deref().unwrap_or("-"),
+            entry.title.as_deref().unwrap_or("untitled"),
+            entry.url.as_deref().unwrap_or("no URL"),
+        );
+
+        match error {
+            Some(error) => println!("⚠️  {} — {}", name, error),
+            None if outcome.is_leaked => {
+                leaked += 1;
+                println!("❌ {} — FOUND IN BREACH DATABASE", name);
+            }
+            None => println!("✅ {}", name),
+        }
+    }
+
+    println!("{} of {} entries found in breach database", leaked, batch.entries.len());
+    Ok(())
+}
@@ -1,17 +1,20 @@
-use anyhow::Result;
-use hex;
+use anyhow::{anyhow, Context, Result};
+use p256::elliptic_curve::sec1::FromEncodedPoint;
 use sha2::{Digest, Sha256};
 use tracing::{debug, info};
 
 use crate::crypto::ecc_cipher::ECCommutativeCipher;
 use crate::crypto::hashing::{extract_username_from_email, scrypt_hash_username_and_password, username_hash_prefix};
 use crate::proto::{LookupSingleLeakRequest, LookupSingleLeakResponse};
+use crate::utils::redact;
+
+pub const USERNAME_HASH_PREFIX_LENGTH: u32 = 26;
 
 
 #[allow(dead_code)]
 pub fn process_credential(raw_username: &str, password: &str) -> (String, String) {
     let username = extract_username_from_email(raw_username);
-    debug!("Processed username '{}' from '{}'", username, raw_username);
+    debug!("Processed username {} from {}", redact::username(&username), redact::username(raw_username));
     (username, password.to_string())
 }
 
@@ -21,24 +24,22 @@ pub fn get_reference_lookup(username: &str, password: &str) -> Result<(LookupSin
     let (processed_username, processed_password) = process_credential(username, password);
     
     let prefix = username_hash_prefix(&processed_username);
-    debug!("Username hash prefix: {}", hex::encode(&prefix));
+    debug!("Username hash prefix: {}", redact::hash(&prefix));
     
     let lookup_hash = scrypt_hash_username_and_password(&processed_username, &processed_password)?;
     
-    let lookup_hash = match lookup_hash.iter().position(|&b| b == 0) {
-        Some(pos) => &lookup_hash[..pos],
-        None => &lookup_hash[..],
-    };
-    debug!("Scrypt hash: {}", hex::encode(lookup_hash));
+    let lookup_hash = blinding_input(&lookup_hash);
+    debug!("Scrypt hash: {}", redact::hash(lookup_hash));
     
     let cipher = ECCommutativeCipher::new(None);
     let encrypted_lookup_hash = cipher.encrypt(lookup_hash)?;
-    debug!("Encrypted lookup hash: {}", hex::encode(&encrypted_lookup_hash));
+    debug!("Encrypted lookup hash: {}", redact::hash(&encrypted_lookup_hash));
     
-    let mut request = LookupSingleLeakRequest::default();
-    request.username_hash_prefix = prefix;
-    request.username_hash_prefix_length = 26; // Fixed value per protocol
-    request.encrypted_lookup_hash = encrypted_lookup_hash;
+    let request = LookupSingleLeakRequest {
+        username_hash_prefix: prefix,
+        username_hash_prefix_length: USERNAME_HASH_PREFIX_LENGTH,
+        encrypted_lookup_hash,
+    };
     
     let expected_is_leaked = true;
     
@@ -58,22 +59,67 @@ pub fn create_lookup_request(
     
     let lookup_hash = scrypt_hash_username_and_password(&processed_username, &processed_password)?;
     
-    let lookup_hash = match lookup_hash.iter().position(|&b| b == 0) {
-        Some(pos) => &lookup_hash[..pos],
-        None => &lookup_hash[..],
-    };
+    let lookup_hash = blinding_input(&lookup_hash);
     
     let encrypted_lookup_hash = cipher.encrypt(lookup_hash)?;
     
-    let mut request = LookupSingleLeakRequest::default();
-    request.username_hash_prefix = prefix;
-    request.username_hash_prefix_length = 26; // Fixed value per protocol
-    request.encrypted_lookup_hash = encrypted_lookup_hash;
+    let request = LookupSingleLeakRequest {
+        username_hash_prefix: prefix,
+        username_hash_prefix_length: USERNAME_HASH_PREFIX_LENGTH,
+        encrypted_lookup_hash,
+    };
     
     Ok(request)
 }
 
 
+/// The part of a scrypt lookup hash that gets hashed to the curve: everything
+/// up to the first zero byte. Servers building their own breach database must
+/// use the same input or their entries will never match.
+pub fn blinding_input(lookup_hash: &[u8]) -> &[u8] {
+    match lookup_hash.iter().position(|&b| b == 0) {
+        Some(pos) => &lookup_hash[..pos],
+        None => lookup_hash,
+    }
+}
+
+
+/// Checks that a client-built lookup request is well formed before it is
+/// relayed: a 26-bit username prefix and a SEC1-encoded P-256 point.
+pub fn validate_lookup_request(request: &LookupSingleLeakRequest) -> Result<()> {
+    if request.username_hash_prefix_length != USERNAME_HASH_PREFIX_LENGTH {
+        return Err(anyhow!(
+            "username_hash_prefix_length must be {}",
+            USERNAME_HASH_PREFIX_LENGTH
+        ));
+    }
+    
+    if request.username_hash_prefix.len() != 4 || request.username_hash_prefix[3] & 0b00111111 != 0 {
+        return Err(anyhow!("username_hash_prefix must be a 26-bit prefix encoded in 4 bytes"));
+    }
+    
+    let encoded_point = p256::EncodedPoint::from_bytes(&request.encrypted_lookup_hash)
+        .map_err(|_| anyhow!("encrypted_lookup_hash is not an encoded curve point"))?;
+    if !encoded_point.is_compressed()
+        || bool::from(p256::AffinePoint::from_encoded_point(&encoded_point).is_none())
+    {
+        return Err(anyhow!("encrypted_lookup_hash is not a compressed P-256 point"));
+    }
+    
+    Ok(())
+}
+
+
+/// Client side of the blinded flow: removes our key from the relayed response
+/// and matches the result against the returned leak prefixes.
+pub fn unblind_and_check(response: &LookupSingleLeakResponse, cipher: &ECCommutativeCipher) -> Result<bool> {
+    let decrypted_hash = cipher
+        .decrypt(&response.reencrypted_lookup_hash)
+        .context("Failed to decrypt reencrypted lookup hash")?;
+    check_credential_leaked(response, &decrypted_hash)
+}
+
+
 pub fn check_credential_leaked(
     response: &LookupSingleLeakResponse,
     decrypted_hash: &[u8]
@@ -84,26 +130,26 @@ pub fn check_credential_leaked(
     }
     
     debug!("Checking if credential is leaked with {} prefixes", response.encrypted_leak_match_prefix.len());
-    debug!("Decrypted hash: {}", hex::encode(decrypted_hash));
+    debug!("Decrypted hash: {}", redact::hash(decrypted_hash));
     
 
     
     let mut hasher1 = Sha256::new();
-    hasher1.update(&[0x02]);
+    hasher1.update([0x02]);
     hasher1.update(&decrypted_hash[1..]);
     let hash1 = hasher1.finalize().to_vec();
     
     let mut hasher2 = Sha256::new();
-    hasher2.update(&[0x03]);
+    hasher2.update([0x03]);
     hasher2.update(&decrypted_hash[1..]);
     let hash2 = hasher2.finalize().to_vec();
     
-    debug!("Hash1: {}", hex::encode(&hash1));
-    debug!("Hash2: {}", hex::encode(&hash2));
+    debug!("Hash1: {}", redact::hash(&hash1));
+    debug!("Hash2: {}", redact::hash(&hash2));
     
 
     for (i, prefix) in response.encrypted_leak_match_prefix.iter().enumerate() {
-        debug!("Checking prefix {}: {}", i, hex::encode(prefix));
+        debug!("Checking prefix {}: {}", i, redact::hash(prefix));
         
         if prefix.len() <= hash1.len() && hash1.starts_with(prefix) {
             debug!("Found match with hash1 variant");
@@ -124,7 +170,7 @@ pub fn debug_response_check(
     response: &LookupSingleLeakResponse,
     decrypted_hash: &[u8]
 ) -> Result<bool> {
-    info!("Debug response check - decrypted hash: {}", hex::encode(decrypted_hash));
+    info!("Debug response check - decrypted hash: {}", redact::hash(decrypted_hash));
     info!("Prefixes count: {}", response.encrypted_leak_match_prefix.len());
     
     if response.encrypted_leak_match_prefix.is_empty() {
@@ -135,19 +181,19 @@ pub fn debug_response_check(
 
     
     let mut hasher1 = Sha256::new();
-    hasher1.update(&[0x02]);
+    hasher1.update([0x02]);
     hasher1.update(&decrypted_hash[1..]);
     let hash1 = hasher1.finalize().to_vec();
-    info!("Hash1: {}", hex::encode(&hash1));
+    info!("Hash1: {}", redact::hash(&hash1));
 
     let mut hasher2 = Sha256::new();
-    hasher2.update(&[0x03]);
+    hasher2.update([0x03]);
     hasher2.update(&decrypted_hash[1..]);
     let hash2 = hasher2.finalize().to_vec();
-    info!("Hash2: {}", hex::encode(&hash2));
+    info!("Hash2: {}", redact::hash(&hash2));
     
     for (i, prefix) in response.encrypted_leak_match_prefix.iter().enumerate() {
-        info!("Prefix {}: {}", i, hex::encode(prefix));
+        info!("Prefix {}: {}", i, redact::hash(prefix));
         
         let hash1_matches = prefix.len() <= hash1.len() && hash1.starts_with(prefix);
         info!("Hash1 match: {}", hash1_matches);
@@ -163,4 +209,50 @@ pub fn debug_response_check(
     
     info!("No matches found");
     Ok(false)
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid_request() -> LookupSingleLeakRequest {
+        let cipher = ECCommutativeCipher::new(None);
+        LookupSingleLeakRequest {
+            username_hash_prefix: username_hash_prefix("user"),
+            username_hash_prefix_length: USERNAME_HASH_PREFIX_LENGTH,
+            encrypted_lookup_hash: cipher.encrypt(b"lookup hash").unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_validate_lookup_request_accepts_client_request() {
+        assert!(validate_lookup_request(&valid_request()).is_ok());
+    }
+
+    #[test]
+    fn test_validate_lookup_request_rejects_bad_prefix() {
+        let mut request = valid_request();
+        request.username_hash_prefix[3] |= 0x01;
+        assert!(validate_lookup_request(&request).is_err());
+
+        let mut request = valid_request();
+        request.username_hash_prefix.push(0);
+        assert!(validate_lookup_request(&request).is_err());
+
+        let mut request = valid_request();
+        request.username_hash_prefix_length = 32;
+        assert!(validate_lookup_request(&request).is_err());
+    }
+
+    #[test]
+    fn test_validate_lookup_request_rejects_bad_point() {
+        let mut request = valid_request();
+        // x = 2^256 - 1 is larger than the field modulus
+        request.encrypted_lookup_hash = [vec![0x02], vec![0xff; 32]].concat();
+        assert!(validate_lookup_request(&request).is_err());
+
+        let mut request = valid_request();
+        request.encrypted_lookup_hash.truncate(20);
+        assert!(validate_lookup_request(&request).is_err());
+    }
+}
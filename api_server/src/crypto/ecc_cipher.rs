@@ -1,21 +1,30 @@
 use anyhow::{anyhow, Result};
 use p256::{
     elliptic_curve::{
+        bigint::{Limb, U256},
         sec1::{FromEncodedPoint, ToEncodedPoint},
+        subtle::{Choice, ConditionallySelectable},
         Field, Group,
     },
-    NistP256, ProjectivePoint, Scalar,
+    EncodedPoint, FieldElement, NistP256, ProjectivePoint, Scalar,
 };
-use rand::rngs::OsRng;
+use rand::{rngs::OsRng, CryptoRng, RngCore};
 use sha2::{Digest, Sha256};
-use std::cmp::min;
-use num_bigint::BigUint;
-use num_traits::{One, Zero, Num};
 use p256::elliptic_curve::scalar::ScalarPrimitive;
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::utils::metrics;
+
+const FIELD_MODULUS: U256 = U256::from_be_hex("ffffffff00000001000000000000000000000000ffffffffffffffffffffffff");
 
 lazy_static::lazy_static! {
-    static ref CURVE_P_BIGUINT: BigUint = BigUint::from_str_radix("ffffffff00000001000000000000000000000000ffffffffffffffffffffffff", 16).unwrap();
-    static ref CURVE_ORDER_BIGUINT: BigUint = BigUint::from_str_radix("ffffffff00000000ffffffffffffffffbce6faada7179e84f3b9cac2fc632551", 16).unwrap();
+    static ref CURVE_A: FieldElement = -FieldElement::from_u64(3);
+    static ref CURVE_B: FieldElement = FieldElement::from_uint(
+        U256::from_be_hex("5ac635d8aa3a93e7b3ebbd55769886bc651d06b0cc53b0f63bce3c3e27d2604b")
+    ).unwrap();
+    static ref TWO_POW_256_MOD_P: FieldElement = FieldElement::from_uint(
+        U256::from_be_hex("00000000fffffffeffffffffffffffffffffffff000000000000000000000001")
+    ).unwrap();
 }
 
 
@@ -40,102 +49,30 @@ impl ECCommutativeCipher {
     }
     
 
-    pub fn get_private_key_bytes(&self) -> [u8; 32] {
-        self.private_key.to_bytes().into()
+    pub fn from_rng<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+        ECCommutativeCipher { private_key: Scalar::random(rng) }
     }
     
 
-    fn random_oracle(&self, input_bytes: &[u8], max_value: &BigUint) -> Result<BigUint> {
-        let hash_output_length = 256; // SHA-256 output length in bits
-        let output_bit_length = max_value.bits() as usize + hash_output_length;
-        let iter_count = (output_bit_length + hash_output_length - 1) / hash_output_length;
-        
-        if iter_count * hash_output_length >= 130048 {
-            return Err(anyhow!("Too many iterations required for random oracle"));
-        }
-        
-        let excess_bit_count = (iter_count * hash_output_length) - output_bit_length;
-        
-        let mut hash_output = BigUint::zero();
-        
-        for i in 1..=iter_count {
-            hash_output = hash_output << hash_output_length;
-            
-
-            let i_biguint = BigUint::from(i as u64);
-            let i_minimal_bytes_vec = i_biguint.to_bytes_be();
-            
-            let mut hash_input = Vec::with_capacity(i_minimal_bytes_vec.len() + input_bytes.len());
-            hash_input.extend_from_slice(&i_minimal_bytes_vec);
-            hash_input.extend_from_slice(input_bytes);
-            
-            let mut hasher = Sha256::new();
-            hasher.update(&hash_input);
-            let hash_bytes = hasher.finalize();
-            
-            let hash_value = BigUint::from_bytes_be(hash_bytes.as_slice());
-            hash_output = hash_output | hash_value;
-        }
-        
-        let result = (hash_output >> excess_bit_count) % max_value;
-        Ok(result)
+    pub fn get_private_key_bytes(&self) -> Zeroizing<[u8; 32]> {
+        Zeroizing::new(self.private_key.to_bytes().into())
     }
     
 
+    /// Maps `data` to a P-256 point with the try-and-increment construction
+    /// used by Chromium's leak detection. All arithmetic happens on
+    /// `FieldElement`s, so the square root and candidate construction run in
+    /// constant time; only the number of retries depends on the input.
     pub fn hash_to_curve(&self, data: &[u8]) -> Result<ProjectivePoint> {
-        let data = match data.iter().position(|&b| b == 0) {
-            Some(pos) => &data[..pos],
-            None => data,
-        };
-        
-        let p = BigUint::from_bytes_be(&hex::decode("ffffffff00000001000000000000000000000000ffffffffffffffffffffffff").unwrap());
-        let a = BigUint::from_bytes_be(&hex::decode("ffffffff00000001000000000000000000000000fffffffffffffffffffffffc").unwrap());
-        let b = BigUint::from_bytes_be(&hex::decode("5ac635d8aa3a93e7b3ebbd55769886bc651d06b0cc53b0f63bce3c3e27d2604b").unwrap());
-        
-        let mut x = self.random_oracle(data, &p)?;
-        
-        loop {
-            let mod_x = &x % &p;
-            
-            let x_squared = (&mod_x * &mod_x) % &p;
-            let x_cubed = (&x_squared * &mod_x) % &p;
-            let ax = (&a * &mod_x) % &p;
-            let y_squared = (&x_cubed + &ax + &b) % &p;
-            
-            if let Some(sqrt) = mod_sqrt(&y_squared, &p) {
-                let point_x = mod_x.to_bytes_be();
-                let point_y = if &sqrt % 2u8 == BigUint::one() {
-                    (&p - &sqrt).to_bytes_be()
-                } else {
-                    sqrt.to_bytes_be()
-                };
-                
-                let mut encoded = Vec::with_capacity(65);
-                encoded.push(0x04);
-                encoded.extend_from_slice(&pad_to_32_bytes(&point_x));
-                encoded.extend_from_slice(&pad_to_32_bytes(&point_y));
-                
-                if let Some(encoded_point) = p256::EncodedPoint::from_bytes(encoded).ok() {
-                    let point_option = ProjectivePoint::from_encoded_point(&encoded_point);
-                    if bool::from(point_option.is_some()) {
-                        let point = point_option.unwrap();
-                        if !bool::from(point.is_identity()) {
-                            return Ok(point);
-                        }
-                    }
-                }
-            }
-            
-            let x_bytes = x.to_bytes_be();
-            x = self.random_oracle(&x_bytes, &p)?;
-        }
+        let _span = tracing::info_span!("hash_to_curve").entered();
+        Ok(metrics::HASHING_DURATION.time(&["hash_to_curve"], || try_and_increment(data)))
     }
     
 
     pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
         let point = self.hash_to_curve(data)?;
         
-        let encrypted_point = point * &self.private_key;
+        let encrypted_point = point * self.private_key;
         
         let encoded_point = encrypted_point.to_encoded_point(true);
         
@@ -143,15 +80,17 @@ impl ECCommutativeCipher {
     }
     
 
-    pub fn decrypt(&self, encrypted_data: &[u8]) -> Result<Vec<u8>> {
-        let encoded_point = p256::EncodedPoint::from_bytes(encrypted_data)
-            .map_err(|_| anyhow!("Invalid encoded point"))?;
+    pub fn re_encrypt(&self, encrypted_data: &[u8]) -> Result<Vec<u8>> {
+        let encrypted_point = decode_point(encrypted_data)?;
         
-        let encrypted_point_option = ProjectivePoint::from_encoded_point(&encoded_point);
-        if !bool::from(encrypted_point_option.is_some()) {
-            return Err(anyhow!("Invalid curve point"));
-        }
-        let encrypted_point = encrypted_point_option.unwrap();
+        let reencrypted_point = encrypted_point * self.private_key;
+        
+        Ok(reencrypted_point.to_encoded_point(true).as_bytes().to_vec())
+    }
+    
+
+    pub fn decrypt(&self, encrypted_data: &[u8]) -> Result<Vec<u8>> {
+        let encrypted_point = decode_point(encrypted_data)?;
         
         let inverse_scalar_option = self.private_key.invert();
         if !bool::from(inverse_scalar_option.is_some()) {
@@ -167,59 +106,111 @@ impl ECCommutativeCipher {
     }
 }
 
-fn pad_to_32_bytes(input: &[u8]) -> [u8; 32] {
-    let mut result = [0u8; 32];
-    let start = 32 - min(32, input.len());
-    result[start..].copy_from_slice(&input[input.len().saturating_sub(32)..]);
-    result
+impl Drop for ECCommutativeCipher {
+    fn drop(&mut self) {
+        self.private_key.zeroize();
+    }
 }
 
-fn mod_sqrt(y_squared: &BigUint, p: &BigUint) -> Option<BigUint> {
-    let four = BigUint::from(4u32);
-    let three = BigUint::from(3u32);
-    let remainder = p % &four;
+/// Try-and-increment from `hash_to_curve`, without the timing.
+fn try_and_increment(data: &[u8]) -> ProjectivePoint {
+    let data = match data.iter().position(|&b| b == 0) {
+        Some(pos) => &data[..pos],
+        None => data,
+    };
     
-    if remainder == three {
-        let one = BigUint::from(1u32);
-        let exp = (p + &one) / &four;
-        let sqrt = mod_pow(y_squared, &exp, p);
+    let mut x = random_oracle(data);
+    
+    loop {
+        let y_squared = x.square() * x + *CURVE_A * x + *CURVE_B;
         
-        let sqrt_squared = (&sqrt * &sqrt) % p;
-        if sqrt_squared == *y_squared {
-            return Some(sqrt);
+        let sqrt = y_squared.sqrt();
+        if bool::from(sqrt.is_some()) {
+            let root = sqrt.unwrap();
+            let y = FieldElement::conditional_select(&root, &-root, root.is_odd());
+            
+            let encoded_point = EncodedPoint::from_affine_coordinates(&x.to_bytes(), &y.to_bytes(), false);
+            let point_option = ProjectivePoint::from_encoded_point(&encoded_point);
+            if bool::from(point_option.is_some()) {
+                let point = point_option.unwrap();
+                if !bool::from(point.is_identity()) {
+                    return point;
+                }
+            }
         }
-    } else {
+        
+        let x_bytes = x.to_bytes();
+        x = random_oracle(minimal_be_bytes(&x_bytes));
     }
-    
-    None
 }
 
-fn mod_pow(base: &BigUint, exp: &BigUint, modulus: &BigUint) -> BigUint {
-    if *modulus == BigUint::one() {
-        return BigUint::zero();
-    }
-    
-    let mut result = BigUint::one();
-    let mut base_val = base % modulus;
-    let mut exp_val = exp.clone();
-    let zero = BigUint::zero();
-    let two = BigUint::from(2u32);
+fn decode_point(encoded: &[u8]) -> Result<ProjectivePoint> {
+    let encoded_point = p256::EncodedPoint::from_bytes(encoded)
+        .map_err(|_| anyhow!("Invalid encoded point"))?;
     
-    while exp_val > zero {
-        if &exp_val % &two == BigUint::one() {
-            result = (&result * &base_val) % modulus;
-        }
-        exp_val >>= 1;
-        base_val = (&base_val * &base_val) % modulus;
+    let point_option = ProjectivePoint::from_encoded_point(&encoded_point);
+    if !bool::from(point_option.is_some()) {
+        return Err(anyhow!("Invalid curve point"));
     }
     
-    result
+    Ok(point_option.unwrap())
+}
+
+/// Expands `input` into a field element: two counter-prefixed SHA-256 blocks
+/// read as a 512-bit big-endian integer, reduced modulo p.
+fn random_oracle(input: &[u8]) -> FieldElement {
+    let high = reduce_u256(oracle_block(1, input));
+    let low = reduce_u256(oracle_block(2, input));
+    
+    high * *TWO_POW_256_MOD_P + low
+}
+
+fn oracle_block(counter: u8, input: &[u8]) -> U256 {
+    let digest = Sha256::new()
+        .chain_update([counter])
+        .chain_update(input)
+        .finalize();
+    U256::from_be_slice(&digest)
+}
+
+/// Reduces a 256-bit integer (always < 2p) into the field without branching.
+fn reduce_u256(value: U256) -> FieldElement {
+    let (reduced, borrow) = value.sbb(&FIELD_MODULUS, Limb::ZERO);
+    let canonical = U256::conditional_select(&reduced, &value, Choice::from((borrow.0 & 1) as u8));
+    FieldElement::from_uint(canonical).unwrap()
+}
+
+/// Big-endian bytes without leading zeros, matching how the candidate x
+/// coordinate is fed back into the oracle on a retry.
+fn minimal_be_bytes(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|&b| b != 0).unwrap_or(bytes.len() - 1);
+    &bytes[start..]
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     
+    // (input, compressed point) pairs produced by the original BigUint
+    // implementation. hash_to_curve must stay byte-for-byte compatible.
+    const HASH_TO_CURVE_VECTORS: &[(&str, &str)] = &[
+        ("", "026b51b9d722460e1f8073e65a824ebe2282f2d7d945491f150c1c04e6e553bb23"),
+        ("61", "02a2584c304a4a67a0ce0e30fa6788f41bc4ff173d354eded0c89575b5c0a099c7"),
+        ("746573742064617461", "02f5355b99233e16c1aaaf91a365d86e8af730393a5182d6768d10f8e688ecc881"),
+        ("70617373776f7264313233", "02790cccbcef9ff3a4b2e05912b780c07827745f0f4da2e903671fd42e631db2df"),
+        ("61626300646566", "024a7bcb50c5b44beff90f58600a36aab7c4f606587ed715496be543139bf899a5"),
+        ("ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff", "02513ba66b5b90cb774123f76e549275b7494563965d0523481f5f79d945ba0fd5"),
+        ("01264b7095badf04294e7398bde2072c51769bc0e50a2f54799ec3e80d32577c", "022ae868ad605e921683c24022b5aaee40afdc8775f965fd5aa748157d81f0bd7e"),
+        ("ceede953f1339bf598844bb86e5ec172a9cf58ab32e0f988869132e1dd3ff60e", "02f60ca29a4ed02c4dde57f44782785040e20c660c591758f5585ab36964e08ed8"),
+        ("1e93334aeed6eddd350e39812783d7697cf0d4cb8022183ac10c7658aa9963d9", "025f4eacbdf34ee4948a3a603e49ee6e19d50f4e2bfffca2f9297cc4ece3f78cad"),
+        ("72657472792d70726f62652d30", "0275303c0f22b55fac30583735f5e391dc12ffe54650359b88eb49332bc2182ba1"),
+        ("72657472792d70726f62652d31", "0281853982b3dc17eb6d2d1d7f69fedcc7f62a5e6cfb75bec21fe60fff90d548db"),
+        ("72657472792d70726f62652d32", "02a34250cab169c35ada2d08497de1c52d3aaf6ffb1c222fea7c3a81948b81acbe"),
+        ("72657472792d70726f62652d33", "02bd3d1b9432ef194f0c20d9ff675f52b4c668ba15179f027e3e46674542b5c1a5"),
+        ("72657472792d70726f62652d34", "02823a797a80505f959f9181063ee95f8b23eee38228984c4c799dd26a1a008e15"),
+        ("72657472792d70726f62652d35", "02937f77ac6b4495aa3de635b1593561f4648230ce5821da92ed4c026ffec99c19"),
+    ];
+    
     #[test]
     fn test_new_random_key() {
         let cipher = ECCommutativeCipher::new(None);
@@ -259,7 +250,7 @@ mod tests {
         
         // Since we're working with points on the curve, not the original data,
         // we need to verify that encrypting the decrypted value gives us the same encrypted value
-        let re_encrypted = cipher.encrypt(&decrypted).expect("Re-encryption failed");
+        let re_encrypted = cipher.re_encrypt(&decrypted).expect("Re-encryption failed");
         assert_eq!(encrypted, re_encrypted);
     }
     
@@ -273,11 +264,11 @@ mod tests {
         
         // First encryption path: cipher1 -> cipher2
         let enc1 = cipher1.encrypt(data).expect("First encryption failed");
-        let enc1_2 = cipher2.encrypt(&enc1).expect("Second encryption failed");
+        let enc1_2 = cipher2.re_encrypt(&enc1).expect("Second encryption failed");
         
         // Second encryption path: cipher2 -> cipher1
         let enc2 = cipher2.encrypt(data).expect("First encryption failed");
-        let enc2_1 = cipher1.encrypt(&enc2).expect("Second encryption failed");
+        let enc2_1 = cipher1.re_encrypt(&enc2).expect("Second encryption failed");
         
         // The results should be the same regardless of encryption order
         assert_eq!(enc1_2, enc2_1);
@@ -292,4 +283,27 @@ mod tests {
         
         assert_eq!(dec2, expected_bytes);
     }
+    
+    #[test]
+    fn test_hash_to_curve_regression_vectors() {
+        let cipher = ECCommutativeCipher::new(None);
+        
+        for (input, expected) in HASH_TO_CURVE_VECTORS {
+            let data = hex::decode(input).unwrap();
+            let point = cipher.hash_to_curve(&data).expect("Hash to curve failed");
+            assert_eq!(
+                hex::encode(point.to_encoded_point(true).as_bytes()),
+                *expected,
+                "hash_to_curve mismatch for input {}",
+                input
+            );
+        }
+    }
+    
+    #[test]
+    fn test_minimal_be_bytes() {
+        assert_eq!(minimal_be_bytes(&[0, 0, 1, 2]), &[1, 2]);
+        assert_eq!(minimal_be_bytes(&[0, 0, 0]), &[0]);
+        assert_eq!(minimal_be_bytes(&[7]), &[7]);
+    }
 }
\ No newline at end of file
@@ -2,6 +2,8 @@ use sha2::{Digest, Sha256};
 use scrypt::{scrypt, Params};
 use anyhow::{Context, Result};
 
+use crate::utils::metrics;
+
 pub const USERNAME_SALT: [u8; 32] = [
     0xC4, 0x94, 0xA3, 0x95, 0xF8, 0xC0, 0xE2, 0x3E,
     0xA9, 0x23, 0x04, 0x78, 0x70, 0x2C, 0x72, 0x18,
@@ -28,7 +30,7 @@ pub fn extract_username_from_email(email: &str) -> String {
 pub fn username_hash_prefix(username: &str) -> Vec<u8> {
     let mut hasher = Sha256::new();
     hasher.update(username.as_bytes());
-    hasher.update(&USERNAME_SALT);
+    hasher.update(USERNAME_SALT);
     
     let hash = hasher.finalize();
     
@@ -49,7 +51,8 @@ pub fn scrypt_hash_username_and_password(username: &str, password: &str) -> Resu
     let params = Params::new(12, 8, 1, 32).context("Failed to create scrypt parameters")?;
     
     let mut output = vec![0u8; 32];
-    scrypt(&username_password, &salt, &params, &mut output)
+    metrics::HASHING_DURATION
+        .time(&["scrypt"], || scrypt(&username_password, &salt, &params, &mut output))
         .context("Failed to compute scrypt hash")?;
     
     Ok(output)
@@ -0,0 +1,136 @@
+use anyhow::{anyhow, Result};
+use rand::{rngs::{OsRng, StdRng}, SeedableRng};
+use serde::Deserialize;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use crate::crypto::ecc_cipher::ECCommutativeCipher;
+
+/// How long a client blinding key lives before a fresh one is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyScope {
+    /// Every credential check gets its own key.
+    Request,
+    /// All credentials in one `check_batch_credentials` call share a key.
+    Batch,
+}
+
+/// Key management mode, selected with `KEY_MODE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyMode {
+    PerRequest,
+    PerBatch,
+    /// Deterministic keys drawn from a seeded RNG. Only meant for tests.
+    Seeded(u64),
+}
+
+impl FromStr for KeyMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "per_request" | "request" => Ok(KeyMode::PerRequest),
+            "per_batch" | "batch" => Ok(KeyMode::PerBatch),
+            "seeded" => Err(anyhow!("Seeded key mode requires KEY_SEED to be set")),
+            other => Err(anyhow!("Unknown key mode '{}'", other)),
+        }
+    }
+}
+
+/// Source of the client-side keys used to blind lookup hashes.
+pub trait KeyProvider: Send + Sync {
+    /// Draws a new cipher with fresh key material.
+    fn next_cipher(&self) -> ECCommutativeCipher;
+
+    /// Whether a cipher should be reused across a batch or drawn per credential.
+    fn scope(&self) -> KeyScope;
+}
+
+/// Draws every key from the operating system RNG.
+pub struct RandomKeyProvider {
+    scope: KeyScope,
+}
+
+impl RandomKeyProvider {
+    pub fn new(scope: KeyScope) -> Self {
+        RandomKeyProvider { scope }
+    }
+}
+
+impl KeyProvider for RandomKeyProvider {
+    fn next_cipher(&self) -> ECCommutativeCipher {
+        ECCommutativeCipher::from_rng(&mut OsRng)
+    }
+
+    fn scope(&self) -> KeyScope {
+        self.scope
+    }
+}
+
+/// Draws keys from an RNG seeded with a fixed value, so the sequence of keys
+/// (and therefore every encrypted lookup hash) is reproducible.
+pub struct SeededKeyProvider {
+    rng: Mutex<StdRng>,
+}
+
+impl SeededKeyProvider {
+    pub fn new(seed: u64) -> Self {
+        SeededKeyProvider {
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+}
+
+impl KeyProvider for SeededKeyProvider {
+    fn next_cipher(&self) -> ECCommutativeCipher {
+        let mut rng = self.rng.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        ECCommutativeCipher::from_rng(&mut *rng)
+    }
+
+    fn scope(&self) -> KeyScope {
+        KeyScope::Request
+    }
+}
+
+pub fn key_provider_for_mode(mode: KeyMode) -> Box<dyn KeyProvider> {
+    match mode {
+        KeyMode::PerRequest => Box::new(RandomKeyProvider::new(KeyScope::Request)),
+        KeyMode::PerBatch => Box::new(RandomKeyProvider::new(KeyScope::Batch)),
+        KeyMode::Seeded(seed) => Box::new(SeededKeyProvider::new(seed)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_keys_differ() {
+        let provider = RandomKeyProvider::new(KeyScope::Request);
+        let first = provider.next_cipher();
+        let second = provider.next_cipher();
+        assert_ne!(*first.get_private_key_bytes(), *second.get_private_key_bytes());
+    }
+
+    #[test]
+    fn test_seeded_keys_are_reproducible() {
+        let a = SeededKeyProvider::new(42);
+        let b = SeededKeyProvider::new(42);
+        for _ in 0..3 {
+            assert_eq!(*a.next_cipher().get_private_key_bytes(), *b.next_cipher().get_private_key_bytes());
+        }
+
+        let c = SeededKeyProvider::new(43);
+        assert_ne!(*SeededKeyProvider::new(42).next_cipher().get_private_key_bytes(), *c.next_cipher().get_private_key_bytes());
+    }
+
+    #[test]
+    fn test_key_mode_parsing() {
+        assert_eq!("per_request".parse::<KeyMode>().unwrap(), KeyMode::PerRequest);
+        assert_eq!("PER_BATCH".parse::<KeyMode>().unwrap(), KeyMode::PerBatch);
+        assert!("seeded".parse::<KeyMode>().is_err());
+        assert!("fixed".parse::<KeyMode>().is_err());
+        assert_eq!(key_provider_for_mode(KeyMode::PerBatch).scope(), KeyScope::Batch);
+    }
+}
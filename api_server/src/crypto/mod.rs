@@ -1,3 +1,4 @@
 pub mod ecc_cipher;
 pub mod hashing;
-pub mod check_google_api;
\ No newline at end of file
+pub mod check_google_api;
+pub mod key_provider;
\ No newline at end of file
@@ -9,37 +9,52 @@ use std::net::SocketAddr;
 use std::str::FromStr;
 
 use tokio::signal;
-use tracing::{info, Level};
-use tracing_subscriber::FmtSubscriber;
+use tracing::{info, warn};
 
 use crate::models::request_models::create_job_storage;
+use crate::services::{api_keys, job_store, jwt::AuthMode};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(Level::INFO)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber)?;
-    
-    info!("Initializing configuration...");
     utils::config::init()?;
     let config = utils::config::get();
+    let traces = utils::telemetry::init(&config.telemetry);
+    let (trace_layer, trace_exporter) = traces.unzip();
+    utils::logging::init(&config.logging, trace_layer)?;
+    info!("Configuration loaded, logging {:?} with {:?} redaction", config.logging.format, config.logging.redaction);
+    if let Some(url) = config.telemetry.traces_url() {
+        info!("Exporting traces to {}", url);
+    }
     
     let job_storage = create_job_storage();
+    let job_store = job_store::open(&config.jobs)?;
+    info!("Using the {} job store", job_store.name());
+
+    let api_keys = api_keys::open(&config.auth)?;
+    match (config.auth.enabled, &config.auth.jwt) {
+        (false, _) => warn!("AUTH_ENABLED is false: check and batch routes accept requests without credentials"),
+        (true, Some(jwt)) if config.auth.mode == AuthMode::Jwt => {
+            info!("JWT authentication enabled for tokens issued by {}", jwt.issuer)
+        }
+        (true, _) => info!("API key authentication enabled, using the {} key store", api_keys.name()),
+    }
     
-    let app = api::create_router(job_storage);
+    let app = api::create_router(job_storage, job_store, api_keys)?;
     
     let addr = SocketAddr::from_str(&format!("{}:{}", config.server.host, config.server.port))?;
     
     info!("LeakLens API starting on http://{}", addr);
     
     let server = axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .with_graceful_shutdown(shutdown_signal());
         
     server.await?;
     
     info!("Server shutting down");
+    if let Some(exporter) = trace_exporter {
+        exporter.flush().await;
+    }
     Ok(())
 }
 
@@ -100,7 +115,7 @@ fn demo_crypto_operations() -> Result<(), Box<dyn std::error::Error>> {
     println!("Google private key: {}", hex::encode(&google_cipher.get_private_key_bytes()[0..5]));
     println!("  (abbreviated for display)\n");
     
-    let reencrypted_lookup_hash = google_cipher.encrypt(&encrypted_lookup_hash)?;
+    let reencrypted_lookup_hash = google_cipher.re_encrypt(&encrypted_lookup_hash)?;
     println!("Re-encrypted lookup hash: {}", hex::encode(&reencrypted_lookup_hash[0..10]));
     println!("  This is returned to the client\n");
     
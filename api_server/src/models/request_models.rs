@@ -1,25 +1,180 @@
+use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::RwLock;
-use std::time::Instant;
+use std::{collections::HashMap, fmt, str::FromStr, sync::Arc};
+use tokio::sync::{broadcast, Notify, RwLock};
+use std::time::{Duration, Instant};
 
-#[derive(Debug, Deserialize)]
+use super::response_models::{BatchCheckSummary, BatchEvent};
+use crate::services::api_keys::Scope;
+use crate::services::webhooks::{DeliveryLog, WebhookTarget};
+
+/// Events buffered per job before slow subscribers start lagging.
+pub const BATCH_EVENT_CAPACITY: usize = 1024;
+
+/// Largest page of results returned by one paged status request.
+pub const MAX_RESULTS_PAGE: usize = 1000;
+
+/// How long a job keeps running without a client polling or streaming it,
+/// unless the upload sets its own `abandon_timeout`.
+pub const DEFAULT_ABANDON_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Deserialize)]
 pub struct SingleCheckRequest {
     pub username: String,
     pub password: String,
+    /// Leak backend to check against; the configured default when absent.
+    pub backend: Option<String>,
 }
 
+impl fmt::Debug for SingleCheckRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SingleCheckRequest")
+            .field("username", &crate::utils::redact::username(&self.username))
+            .field("password", &"<redacted>")
+            .field("backend", &self.backend)
+            .finish()
+    }
+}
+
+/// Phase two of the blinded flow. Both fields are hex encoded and computed by
+/// the client; the password never leaves the client.
 #[derive(Debug, Deserialize)]
+pub struct BlindedCheckRequest {
+    pub username_hash_prefix: String,
+    pub encrypted_lookup_hash: String,
+}
+
+#[derive(Default, Deserialize)]
 pub struct BatchCheckMetadata {
     pub input_type: Option<String>,
+    pub backend: Option<String>,
+    /// Field delimiter for delimited lines and CSV: one character or `tab`.
+    pub delimiter: Option<String>,
+    /// CSV columns by header name or 1-based position.
+    pub username_column: Option<String>,
+    pub password_column: Option<String>,
+    pub url_column: Option<String>,
+    pub title_column: Option<String>,
+    /// Seconds without a poll or stream before the job is abandoned.
+    pub abandon_timeout: Option<String>,
+    /// `true` for a job that keeps running with no client attached.
+    pub detached: Option<String>,
+    /// URL notified when the job finishes or is abandoned.
+    pub callback_url: Option<String>,
+    /// Signing key for the callbacks; `WEBHOOK_SECRET` when absent.
+    pub callback_secret: Option<String>,
+    /// `true` to send every result along with the summary.
+    pub callback_include_results: Option<String>,
+}
+
+impl fmt::Debug for BatchCheckMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BatchCheckMetadata")
+            .field("input_type", &self.input_type)
+            .field("backend", &self.backend)
+            .field("delimiter", &self.delimiter)
+            .field("username_column", &self.username_column)
+            .field("password_column", &self.password_column)
+            .field("url_column", &self.url_column)
+            .field("title_column", &self.title_column)
+            .field("abandon_timeout", &self.abandon_timeout)
+            .field("detached", &self.detached)
+            .field("callback_url", &self.callback_url)
+            .field("callback_secret", &self.callback_secret.as_ref().map(|_| "<redacted>"))
+            .field("callback_include_results", &self.callback_include_results)
+            .finish()
+    }
 }
 
-#[derive(Debug, Clone, Serialize)]
+/// Body of the admin endpoint that issues API keys.
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// Days until the key expires; never when absent.
+    pub expires_in_days: Option<u32>,
+}
+
+/// Query parameters of the batch status endpoint. `since` (or its alias
+/// `cursor`) is the `next_cursor` of a previous response; only results
+/// recorded after it are returned.
+#[derive(Debug, Default, Deserialize)]
+pub struct BatchStatusQuery {
+    pub since: Option<usize>,
+    pub cursor: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+/// Query parameters of the batch export endpoint: `format` is `csv` (default),
+/// `json` or `ndjson`; `status` is `all` (default), `leaked`, `not_leaked` or `errors`.
+#[derive(Debug, Default, Deserialize)]
+pub struct ExportQuery {
+    pub format: Option<String>,
+    pub status: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CredentialCheckResult {
+    /// Stable position in the job's results, assigned when recorded.
+    pub index: usize,
+    /// 1-based line number in the uploaded file.
+    pub line: usize,
     pub credential: String,      
     pub is_leaked: Option<bool>, 
     pub status: String,          
     pub message: Option<String>, 
+    pub backend: Option<String>,
+    pub occurrences: Option<u64>,
+    /// The verdict came from the result cache.
+    #[serde(default)]
+    pub cached: bool,
+    /// Site of the entry, when the upload format records one.
+    pub url: Option<String>,
+    /// Entry name from a password manager export.
+    pub title: Option<String>,
+    /// Group path of a KeePass entry.
+    pub group: Option<String>,
+}
+
+/// Where a job is in its lifecycle. `Cancelled` and `Completed` are final.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Running,
+    /// Paused through the API; waits for an explicit resume.
+    Paused,
+    /// No client polled or streamed the job within its abandon timeout.
+    /// Checking stops until a client comes back or resumes it.
+    Abandoned,
+    Cancelled,
+    Completed,
+}
+
+impl JobState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobState::Running => "running",
+            JobState::Paused => "paused",
+            JobState::Abandoned => "abandoned",
+            JobState::Cancelled => "cancelled",
+            JobState::Completed => "completed",
+        }
+    }
+}
+
+impl FromStr for JobState {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "running" => Ok(JobState::Running),
+            "paused" => Ok(JobState::Paused),
+            "abandoned" => Ok(JobState::Abandoned),
+            "cancelled" => Ok(JobState::Cancelled),
+            "completed" => Ok(JobState::Completed),
+            other => Err(anyhow!("Unknown job state '{}'", other)),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -29,10 +184,184 @@ pub struct BatchProcessingJob {
     pub total: usize,
     pub processed: usize,
     pub results: Vec<CredentialCheckResult>,
+    /// Set once the job reaches a final state.
     pub completed: bool,
     pub error: Option<String>,
+    pub state: JobState,
     pub last_heartbeat: Instant,  
-    pub is_abandoned: bool,      
+    /// `None` for a detached job, which is never abandoned.
+    pub abandon_timeout: Option<Duration>,
+    /// Wakes the worker of a paused or abandoned job.
+    pub wake: Arc<Notify>,
+    pub callback: Option<WebhookTarget>,
+    /// ID of the API key or token subject that uploaded the job; `None` when
+    /// authentication was disabled.
+    pub owner: Option<String>,
+    /// Every attempt to deliver a callback of this job.
+    pub webhook_log: DeliveryLog,
+    pub events: broadcast::Sender<BatchEvent>,
+    /// Bumped on every change visible through the status endpoint; used as its ETag.
+    pub version: u64,
+}
+
+impl BatchProcessingJob {
+    pub fn new(id: String, total: usize) -> Self {
+        let (events, _) = broadcast::channel(BATCH_EVENT_CAPACITY);
+        BatchProcessingJob {
+            id,
+            total,
+            processed: 0,
+            results: Vec::new(),
+            completed: false,
+            error: None,
+            state: JobState::Running,
+            last_heartbeat: Instant::now(),
+            abandon_timeout: Some(DEFAULT_ABANDON_TIMEOUT),
+            wake: Arc::new(Notify::new()),
+            callback: None,
+            owner: None,
+            webhook_log: DeliveryLog::default(),
+            events,
+            version: 0,
+        }
+    }
+
+    pub fn summary(&self) -> BatchCheckSummary {
+        let total_leaked = self.results.iter().filter(|r| r.is_leaked == Some(true)).count();
+        let total_not_leaked = self.results.iter().filter(|r| r.is_leaked == Some(false)).count();
+        let total_errors = self.results.iter().filter(|r| r.status == "error" || r.is_leaked.is_none()).count();
+
+        let progress_percentage = if self.total == 0 {
+            100
+        } else {
+            ((self.processed as f32 / self.total as f32) * 100.0) as u8
+        };
+
+        BatchCheckSummary {
+            total_processed: self.processed,
+            total_leaked,
+            total_not_leaked,
+            total_errors,
+            completed: self.completed,
+            state: self.state,
+            progress_percentage,
+        }
+    }
+
+    /// Rebuilds a job loaded from a `JobStore` after a restart.
+    pub fn restore(
+        id: String,
+        total: usize,
+        results: Vec<CredentialCheckResult>,
+        state: JobState,
+        error: Option<String>,
+        abandon_timeout: Option<Duration>,
+        version: u64,
+    ) -> Self {
+        let mut job = BatchProcessingJob::new(id, total);
+        job.processed = results.len();
+        job.results = results;
+        job.completed = matches!(state, JobState::Cancelled | JobState::Completed);
+        job.state = state;
+        job.error = error;
+        job.abandon_timeout = abandon_timeout;
+        job.version = version;
+        job
+    }
+
+    /// Appends results and pushes them, followed by the new summary, to any
+    /// open progress streams.
+    pub fn record_results(&mut self, results: Vec<CredentialCheckResult>) {
+        self.processed += results.len();
+        for mut result in results {
+            result.index = self.results.len();
+            let _ = self.events.send(BatchEvent::Result { result: result.clone() });
+            self.results.push(result);
+        }
+        self.version += 1;
+        let _ = self.events.send(BatchEvent::Summary { summary: self.summary() });
+    }
+
+    /// Results recorded at or after `cursor`, at most `limit` of them.
+    pub fn results_page(&self, cursor: usize, limit: usize) -> &[CredentialCheckResult] {
+        let start = cursor.min(self.results.len());
+        let end = start.saturating_add(limit).min(self.results.len());
+        &self.results[start..end]
+    }
+
+    /// ETag of the status response for the page starting at `cursor` with at
+    /// most `limit` results, or for the full list when `page` is `None`.
+    pub fn etag(&self, page: Option<(usize, usize)>) -> String {
+        match page {
+            Some((cursor, limit)) => format!("\"{}-{}-{}-{}\"", self.id, self.version, cursor, limit),
+            None => format!("\"{}-{}\"", self.id, self.version),
+        }
+    }
+
+    /// Marks the job as finished and sends the final event.
+    pub fn finish(&mut self, error: Option<String>) {
+        self.completed = true;
+        if self.state != JobState::Cancelled {
+            self.state = JobState::Completed;
+        }
+        if error.is_some() {
+            self.error = error;
+        }
+        self.version += 1;
+        let _ = self.events.send(BatchEvent::Completed {
+            summary: self.summary(),
+            error: self.error.clone(),
+        });
+    }
+
+    /// Stops the job for good, keeping the results it has so far.
+    pub fn cancel(&mut self, reason: &str) {
+        self.state = JobState::Cancelled;
+        self.finish(Some(reason.to_string()));
+        self.wake.notify_one();
+    }
+
+    /// Moves an unfinished job to `Running` or `Paused` and wakes its worker.
+    /// Returns false if the job already finished.
+    pub fn set_running(&mut self, running: bool) -> bool {
+        if self.completed {
+            return false;
+        }
+
+        let state = if running { JobState::Running } else { JobState::Paused };
+        if self.state != state {
+            self.state = state;
+            self.last_heartbeat = Instant::now();
+            self.version += 1;
+            let _ = self.events.send(BatchEvent::Summary { summary: self.summary() });
+            self.wake.notify_one();
+        }
+        true
+    }
+
+    /// Marks the job abandoned; its worker stops until a client returns.
+    pub fn abandon(&mut self) {
+        self.state = JobState::Abandoned;
+        self.version += 1;
+        let _ = self.events.send(BatchEvent::Summary { summary: self.summary() });
+    }
+
+    /// Records that a client polled or subscribed, resuming the job if it
+    /// had been abandoned. Returns true when it did, so the caller can save
+    /// the new state.
+    pub fn touch(&mut self) -> bool {
+        self.last_heartbeat = Instant::now();
+        self.state == JobState::Abandoned && self.set_running(true)
+    }
+
+    /// Whether a client is still interested in this job: it polled within the
+    /// abandon timeout, holds an open progress stream, or the job is detached.
+    pub fn has_listeners(&self) -> bool {
+        match self.abandon_timeout {
+            Some(timeout) => self.last_heartbeat.elapsed() <= timeout || self.events.receiver_count() > 0,
+            None => true,
+        }
+    }
 }
 
 pub type JobStorage = Arc<RwLock<HashMap<String, BatchProcessingJob>>>;
@@ -1,10 +1,24 @@
 use serde::Serialize;
 
+use crate::services::api_keys::ApiKey;
+
 #[derive(Debug, Serialize)]
 pub struct SingleCheckResponse {
     pub username: String,
     pub is_leaked: bool,
     pub message: String,
+    pub backend: String,
+    pub occurrences: Option<u64>,
+    /// The verdict came from the result cache.
+    pub cached: bool,
+}
+
+/// Raw Google response for a blinded lookup, hex encoded. The client removes
+/// its own key from `reencrypted_lookup_hash` and matches the prefixes locally.
+#[derive(Debug, Serialize)]
+pub struct BlindedCheckResponse {
+    pub reencrypted_lookup_hash: String,
+    pub encrypted_leak_match_prefix: Vec<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -13,13 +27,14 @@ pub struct BatchCheckResponse {
     pub message: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct BatchCheckSummary {
     pub total_processed: usize,
     pub total_leaked: usize,
     pub total_not_leaked: usize,
     pub total_errors: usize,
     pub completed: bool,
+    pub state: super::request_models::JobState,
     pub progress_percentage: u8, 
 }
 
@@ -27,6 +42,30 @@ pub struct BatchCheckSummary {
 pub struct BatchCheckResultsResponse {
     pub summary: BatchCheckSummary,
     pub results: Vec<super::request_models::CredentialCheckResult>,
+    /// Pass back as `since` to receive only results recorded after this page.
+    pub next_cursor: usize,
+    pub has_more: bool,
+    pub version: u64,
+}
+
+/// Delivery log of a job's webhook callbacks.
+#[derive(Debug, Serialize)]
+pub struct WebhookDeliveriesResponse {
+    pub job_id: String,
+    pub callback_url: Option<String>,
+    pub deliveries: Vec<crate::services::webhooks::WebhookDelivery>,
+}
+
+/// A newly issued or rotated API key. `secret` is never shown again.
+#[derive(Debug, Serialize)]
+pub struct IssuedApiKeyResponse {
+    pub secret: String,
+    pub key: ApiKey,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiKeyListResponse {
+    pub keys: Vec<ApiKey>,
 }
 
 #[derive(Debug, Serialize)]
@@ -40,4 +79,62 @@ pub struct ApiStatusResponse {
 pub struct ErrorResponse {
     pub error: String,
     pub code: Option<String>,
-}
\ No newline at end of file
+}
+/// Pushed to batch progress subscribers (SSE and WebSocket) as
+/// `process_batch_job` produces results.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BatchEvent {
+    Result {
+        result: super::request_models::CredentialCheckResult,
+    },
+    Summary {
+        summary: BatchCheckSummary,
+    },
+    /// Last event of a job; `error` is set when it stopped early.
+    Completed {
+        summary: BatchCheckSummary,
+        error: Option<String>,
+    },
+}
+
+impl BatchEvent {
+    /// Event name used on the SSE stream.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BatchEvent::Result { .. } => "result",
+            BatchEvent::Summary { .. } => "summary",
+            BatchEvent::Completed { .. } => "completed",
+        }
+    }
+}
+
+/// One row of a batch results export.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportRow {
+    pub line: usize,
+    pub credential: String,
+    pub status: String,
+    pub is_leaked: Option<bool>,
+    pub message: Option<String>,
+    pub occurrences: Option<u64>,
+    pub url: Option<String>,
+    pub title: Option<String>,
+    pub group: Option<String>,
+}
+
+impl From<&super::request_models::CredentialCheckResult> for ExportRow {
+    fn from(result: &super::request_models::CredentialCheckResult) -> Self {
+        ExportRow {
+            line: result.line,
+            credential: result.credential.clone(),
+            status: result.status.clone(),
+            is_leaked: result.is_leaked,
+            message: result.message.clone(),
+            occurrences: result.occurrences,
+            url: result.url.clone(),
+            title: result.title.clone(),
+            group: result.group.clone(),
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::sync::Mutex;
+
+use crate::services::api_keys::{ApiKey, ApiKeyStore};
+
+/// Keeps keys for the lifetime of the process only; keys issued through the
+/// admin API are lost on restart.
+#[derive(Default)]
+pub struct MemoryApiKeyStore {
+    keys: Mutex<Vec<ApiKey>>,
+}
+
+#[async_trait]
+impl ApiKeyStore for MemoryApiKeyStore {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn insert(&self, key: &ApiKey) -> Result<()> {
+        self.keys.lock().unwrap().push(key.clone());
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<ApiKey>> {
+        Ok(self.keys.lock().unwrap().iter().find(|key| key.id == id).cloned())
+    }
+
+    async fn find_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>> {
+        Ok(self.keys.lock().unwrap().iter().find(|key| key.key_hash == key_hash).cloned())
+    }
+
+    async fn list(&self) -> Result<Vec<ApiKey>> {
+        Ok(self.keys.lock().unwrap().clone())
+    }
+
+    async fn revoke(&self, id: &str, at: DateTime<Utc>) -> Result<bool> {
+        let mut keys = self.keys.lock().unwrap();
+        match keys.iter_mut().find(|key| key.id == id) {
+            Some(key) => {
+                key.revoked_at.get_or_insert(at);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::api_keys::tests::exercise_store;
+
+    #[tokio::test]
+    async fn test_memory_store() {
+        exercise_store(&MemoryApiKeyStore::default()).await;
+    }
+}
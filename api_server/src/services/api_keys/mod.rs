@@ -0,0 +1,308 @@
+//! API keys for authenticating clients.
+//!
+//! A key is shown to its holder once, when issued; stores only keep its
+//! SHA-256. Keys are random 256-bit values, so a plain hash is enough to make
+//! a leaked store useless for calling the API. Each key carries the scopes it
+//! may use and can expire or be revoked; rotating a key issues a replacement
+//! with the same name, scopes and owner and revokes the original.
+
+pub mod memory;
+pub mod sqlite;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::utils::config::AuthConfig;
+
+/// Prefix of every issued key, so leaked keys are easy to spot in scanners.
+pub const KEY_PREFIX: &str = "llk_";
+
+/// Characters of a key kept in the clear to tell keys apart in listings.
+const DISPLAY_PREFIX_LEN: usize = KEY_PREFIX.len() + 8;
+
+/// What a key may be used for. `Admin` implies every other scope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// Single and blinded credential checks.
+    Single,
+    /// Batch uploads and everything done with the resulting jobs.
+    Batch,
+    /// Key management.
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Single => "single",
+            Scope::Batch => "batch",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "single" => Ok(Scope::Single),
+            "batch" => Ok(Scope::Batch),
+            "admin" => Ok(Scope::Admin),
+            other => Err(anyhow!("Unknown scope '{}'", other)),
+        }
+    }
+}
+
+/// Parses a comma separated scope list such as `single,batch`.
+pub fn parse_scopes(list: &str) -> Result<Vec<Scope>> {
+    let mut scopes = Vec::new();
+    for scope in list.split(',').filter(|s| !s.trim().is_empty()) {
+        let scope = scope.parse()?;
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+    Ok(scopes)
+}
+
+/// Where keys are kept, selected with `API_KEY_STORE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiKeyStoreKind {
+    Memory,
+    Sqlite,
+}
+
+impl FromStr for ApiKeyStoreKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "memory" => Ok(ApiKeyStoreKind::Memory),
+            "sqlite" => Ok(ApiKeyStoreKind::Sqlite),
+            other => Err(anyhow!("Unknown API key store '{}'", other)),
+        }
+    }
+}
+
+/// A key as stored, without its secret.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    /// Start of the key, e.g. `llk_1a2b3c4d`.
+    pub prefix: String,
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    /// The key this one replaced, when it was issued by a rotation.
+    pub rotated_from: Option<String>,
+    /// Holder of the key: the id of the first key in its rotation chain. Jobs
+    /// are owned by this id so they stay reachable after a rotation.
+    pub owner_id: String,
+}
+
+impl ApiKey {
+    /// Whether the key may call routes that need `scope`.
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin)
+    }
+
+    /// Neither revoked nor expired at `now`.
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.revoked_at.is_none() && self.expires_at.map_or(true, |expires_at| expires_at > now)
+    }
+}
+
+/// A freshly issued key together with its secret, which is not stored.
+#[derive(Clone)]
+pub struct IssuedKey {
+    pub key: ApiKey,
+    pub secret: String,
+}
+
+impl fmt::Debug for IssuedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IssuedKey").field("key", &self.key).field("secret", &"<redacted>").finish()
+    }
+}
+
+#[async_trait]
+pub trait ApiKeyStore: Send + Sync {
+    /// Identifier used in config and logs.
+    fn name(&self) -> &'static str;
+
+    async fn insert(&self, key: &ApiKey) -> Result<()>;
+
+    async fn get(&self, id: &str) -> Result<Option<ApiKey>>;
+
+    async fn find_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>>;
+
+    /// Every key, revoked and expired ones included, oldest first.
+    async fn list(&self) -> Result<Vec<ApiKey>>;
+
+    /// Marks a key revoked. Returns false if there is no such key; revoking a
+    /// revoked key keeps its original revocation time.
+    async fn revoke(&self, id: &str, at: DateTime<Utc>) -> Result<bool>;
+}
+
+/// Opens the store selected in config.
+pub fn open(config: &AuthConfig) -> Result<Arc<dyn ApiKeyStore>> {
+    match config.store {
+        ApiKeyStoreKind::Memory => Ok(Arc::new(memory::MemoryApiKeyStore::default())),
+        ApiKeyStoreKind::Sqlite => Ok(Arc::new(sqlite::SqliteApiKeyStore::open(&config.sqlite_path)?)),
+    }
+}
+
+pub fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("{}{}", KEY_PREFIX, hex::encode(bytes))
+}
+
+/// Creates and stores a new key. `lifetime` of `None` never expires.
+pub async fn issue(
+    store: &dyn ApiKeyStore,
+    name: &str,
+    scopes: Vec<Scope>,
+    lifetime: Option<Duration>,
+) -> Result<IssuedKey> {
+    let issued = new_key(name, scopes, lifetime);
+    store.insert(&issued.key).await?;
+    Ok(issued)
+}
+
+fn new_key(name: &str, scopes: Vec<Scope>, lifetime: Option<Duration>) -> IssuedKey {
+    let secret = generate_secret();
+    let created_at = Utc::now();
+    let id = uuid::Uuid::new_v4().to_string();
+    let key = ApiKey {
+        owner_id: id.clone(),
+        id,
+        name: name.trim().to_string(),
+        prefix: secret[..DISPLAY_PREFIX_LEN].to_string(),
+        key_hash: hash_secret(&secret),
+        scopes,
+        created_at,
+        expires_at: lifetime.map(|lifetime| created_at + lifetime),
+        revoked_at: None,
+        rotated_from: None,
+    };
+
+    IssuedKey { key, secret }
+}
+
+/// Replaces `key` with a new key of the same name, scopes, lifetime and owner,
+/// then revokes `key`.
+pub async fn rotate(store: &dyn ApiKeyStore, key: &ApiKey) -> Result<IssuedKey> {
+    let lifetime = key.expires_at.map(|expires_at| expires_at - key.created_at);
+    let mut issued = new_key(&key.name, key.scopes.clone(), lifetime);
+    issued.key.rotated_from = Some(key.id.clone());
+    issued.key.owner_id = key.owner_id.clone();
+    store.insert(&issued.key).await?;
+    store.revoke(&key.id, Utc::now()).await?;
+    Ok(issued)
+}
+
+/// Looks up the active key matching `secret`.
+pub async fn authenticate(store: &dyn ApiKeyStore, secret: &str) -> Result<Option<ApiKey>> {
+    let key = store.find_by_hash(&hash_secret(secret.trim())).await?;
+    Ok(key.filter(|key| key.is_active(Utc::now())))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Runs a store through issuing, rotating, revoking and expiring keys.
+    pub async fn exercise_store(store: &dyn ApiKeyStore) {
+        let issued = issue(store, "ci", vec![Scope::Single, Scope::Batch], Some(Duration::days(30)))
+            .await
+            .unwrap();
+        assert!(issued.secret.starts_with(KEY_PREFIX));
+        assert!(issued.secret.starts_with(&issued.key.prefix));
+
+        let found = authenticate(store, &issued.secret).await.unwrap().unwrap();
+        assert_eq!(found.id, issued.key.id);
+        assert_eq!(found.name, "ci");
+        assert_eq!(found.scopes, [Scope::Single, Scope::Batch]);
+        assert_eq!(found.owner_id, found.id);
+        assert!(found.allows(Scope::Batch));
+        assert!(!found.allows(Scope::Admin));
+        assert!(authenticate(store, "llk_wrong").await.unwrap().is_none());
+
+        let rotated = rotate(store, &found).await.unwrap();
+        assert_eq!(rotated.key.rotated_from.as_deref(), Some(found.id.as_str()));
+        assert_eq!(rotated.key.scopes, found.scopes);
+        assert_eq!(rotated.key.expires_at.unwrap() - rotated.key.created_at, Duration::days(30));
+        assert!(authenticate(store, &issued.secret).await.unwrap().is_none());
+        let stored = store.get(&rotated.key.id).await.unwrap().unwrap();
+        assert_eq!(stored.rotated_from.as_deref(), Some(found.id.as_str()));
+        assert_eq!(stored.owner_id, found.id);
+        assert!(authenticate(store, &rotated.secret).await.unwrap().is_some());
+        // The owner is the first key of the chain, however often it rotates.
+        let rotated = rotate(store, &stored).await.unwrap();
+        assert_eq!(rotated.key.owner_id, found.id);
+        assert!(authenticate(store, &rotated.secret).await.unwrap().is_some());
+
+        assert!(store.revoke(&rotated.key.id, Utc::now()).await.unwrap());
+        assert!(authenticate(store, &rotated.secret).await.unwrap().is_none());
+        assert!(!store.revoke("missing", Utc::now()).await.unwrap());
+
+        let expired = issue(store, "expired", vec![Scope::Admin], Some(Duration::seconds(-1))).await.unwrap();
+        assert!(authenticate(store, &expired.secret).await.unwrap().is_none());
+
+        let keys = store.list().await.unwrap();
+        let names: Vec<_> = keys.iter().map(|key| key.name.as_str()).collect();
+        assert_eq!(names, ["ci", "ci", "ci", "expired"]);
+        assert!(keys[0].revoked_at.is_some());
+        assert!(keys.iter().all(|key| key.key_hash.len() == 64));
+    }
+
+    #[test]
+    fn test_parse_scopes() {
+        assert_eq!(parse_scopes("single, BATCH,single").unwrap(), [Scope::Single, Scope::Batch]);
+        assert!(parse_scopes("").unwrap().is_empty());
+        assert!(parse_scopes("single,root").is_err());
+    }
+
+    #[test]
+    fn test_admin_implies_every_scope() {
+        let key = ApiKey {
+            id: "id".to_string(),
+            name: "admin".to_string(),
+            prefix: "llk_".to_string(),
+            key_hash: String::new(),
+            scopes: vec![Scope::Admin],
+            created_at: Utc::now(),
+            expires_at: None,
+            revoked_at: None,
+            rotated_from: None,
+            owner_id: "id".to_string(),
+        };
+        assert!(key.allows(Scope::Single) && key.allows(Scope::Batch));
+        assert!(key.is_active(Utc::now()));
+    }
+}
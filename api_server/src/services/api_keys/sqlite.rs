@@ -0,0 +1,257 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+use crate::services::api_keys::{parse_scopes, ApiKey, ApiKeyStore};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS api_keys (
+        id           TEXT PRIMARY KEY,
+        name         TEXT NOT NULL,
+        prefix       TEXT NOT NULL,
+        key_hash     TEXT NOT NULL UNIQUE,
+        scopes       TEXT NOT NULL,
+        created_at   TEXT NOT NULL,
+        expires_at   TEXT,
+        revoked_at   TEXT,
+        rotated_from TEXT,
+        owner_id     TEXT
+    );
+";
+
+/// Fills `owner_id` of keys stored before it existed with the first key of
+/// their rotation chain.
+const BACKFILL_OWNERS: &str = "
+    WITH RECURSIVE chain(id, owner_id) AS (
+        SELECT id, id FROM api_keys WHERE rotated_from IS NULL
+        UNION ALL
+        SELECT api_keys.id, chain.owner_id FROM api_keys JOIN chain ON api_keys.rotated_from = chain.id
+    )
+    UPDATE api_keys
+    SET owner_id = COALESCE((SELECT owner_id FROM chain WHERE chain.id = api_keys.id), id)
+    WHERE owner_id IS NULL;
+";
+
+const COLUMNS: &str = "id, name, prefix, key_hash, scopes, created_at, expires_at, revoked_at, rotated_from, owner_id";
+
+/// Persists keys in a SQLite file shared by the server and the `api_keys` CLI.
+#[derive(Clone)]
+pub struct SqliteApiKeyStore {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteApiKeyStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let connection = Connection::open(path)
+            .with_context(|| format!("Failed to open API key database {}", path.display()))?;
+
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.execute_batch(SCHEMA).context("Failed to create API key table")?;
+        add_owner_column(&connection).context("Failed to migrate API key table")?;
+
+        info!("Reading API keys from {}", path.display());
+        Ok(SqliteApiKeyStore { connection: Arc::new(Mutex::new(connection)) })
+    }
+
+    /// Runs `f` on the connection from the blocking pool.
+    async fn with_connection<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            let connection = connection.lock().map_err(|_| anyhow!("API key database lock poisoned"))?;
+            f(&connection)
+        })
+        .await?
+    }
+}
+
+fn add_owner_column(connection: &Connection) -> Result<()> {
+    let existing = connection
+        .prepare("SELECT name FROM pragma_table_info('api_keys')")?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    if !existing.iter().any(|name| name == "owner_id") {
+        connection.execute_batch("ALTER TABLE api_keys ADD COLUMN owner_id TEXT")?;
+    }
+    connection.execute_batch(BACKFILL_OWNERS)?;
+    Ok(())
+}
+
+/// Columns of one row in `COLUMNS` order, converted once the query is done.
+type KeyRow = (
+    String,
+    String,
+    String,
+    String,
+    String,
+    String,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    String,
+);
+
+fn read_row(row: &Row) -> rusqlite::Result<KeyRow> {
+    Ok((
+        row.get(0)?,
+        row.get(1)?,
+        row.get(2)?,
+        row.get(3)?,
+        row.get(4)?,
+        row.get(5)?,
+        row.get(6)?,
+        row.get(7)?,
+        row.get(8)?,
+        row.get(9)?,
+    ))
+}
+
+fn to_key(row: KeyRow) -> Result<ApiKey> {
+    let (id, name, prefix, key_hash, scopes, created_at, expires_at, revoked_at, rotated_from, owner_id) = row;
+    Ok(ApiKey {
+        id,
+        name,
+        prefix,
+        key_hash,
+        scopes: parse_scopes(&scopes)?,
+        created_at: parse_time(&created_at)?,
+        expires_at: expires_at.as_deref().map(parse_time).transpose()?,
+        revoked_at: revoked_at.as_deref().map(parse_time).transpose()?,
+        rotated_from,
+        owner_id,
+    })
+}
+
+fn parse_time(value: &str) -> Result<DateTime<Utc>> {
+    Ok(DateTime::parse_from_rfc3339(value)?.with_timezone(&Utc))
+}
+
+fn query_one(connection: &Connection, filter: &str, value: String) -> Result<Option<ApiKey>> {
+    let sql = format!("SELECT {} FROM api_keys WHERE {} = ?1", COLUMNS, filter);
+    connection
+        .query_row(&sql, params![value], read_row)
+        .optional()?
+        .map(to_key)
+        .transpose()
+}
+
+#[async_trait]
+impl ApiKeyStore for SqliteApiKeyStore {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    async fn insert(&self, key: &ApiKey) -> Result<()> {
+        let key = key.clone();
+        self.with_connection(move |connection| {
+            let scopes: Vec<&str> = key.scopes.iter().map(|scope| scope.as_str()).collect();
+            connection.execute(
+                &format!("INSERT INTO api_keys ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)", COLUMNS),
+                params![
+                    key.id,
+                    key.name,
+                    key.prefix,
+                    key.key_hash,
+                    scopes.join(","),
+                    key.created_at.to_rfc3339(),
+                    key.expires_at.map(|t| t.to_rfc3339()),
+                    key.revoked_at.map(|t| t.to_rfc3339()),
+                    key.rotated_from,
+                    key.owner_id
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<ApiKey>> {
+        let id = id.to_string();
+        self.with_connection(move |connection| query_one(connection, "id", id)).await
+    }
+
+    async fn find_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>> {
+        let key_hash = key_hash.to_string();
+        self.with_connection(move |connection| query_one(connection, "key_hash", key_hash)).await
+    }
+
+    async fn list(&self) -> Result<Vec<ApiKey>> {
+        self.with_connection(|connection| {
+            let rows = connection
+                .prepare(&format!("SELECT {} FROM api_keys ORDER BY created_at, rowid", COLUMNS))?
+                .query_map([], read_row)?
+                .collect::<Result<Vec<_>, _>>()?;
+            rows.into_iter().map(to_key).collect()
+        })
+        .await
+    }
+
+    async fn revoke(&self, id: &str, at: DateTime<Utc>) -> Result<bool> {
+        let id = id.to_string();
+        self.with_connection(move |connection| {
+            let updated = connection.execute(
+                "UPDATE api_keys SET revoked_at = COALESCE(revoked_at, ?2) WHERE id = ?1",
+                params![id, at.to_rfc3339()],
+            )?;
+            Ok(updated > 0)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::api_keys::{issue, tests::exercise_store, Scope};
+    use crate::utils::test_support::TempDatabase;
+
+    #[tokio::test]
+    async fn test_sqlite_store() {
+        let database = TempDatabase::new("keys");
+        exercise_store(&SqliteApiKeyStore::open(&database.0).unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn test_keys_survive_reopening() {
+        let database = TempDatabase::new("keys");
+        let store = SqliteApiKeyStore::open(&database.0).unwrap();
+        let issued = issue(&store, "ops", vec![Scope::Admin], None).await.unwrap();
+        drop(store);
+
+        let store = SqliteApiKeyStore::open(&database.0).unwrap();
+        let key = store.get(&issued.key.id).await.unwrap().unwrap();
+        assert_eq!(key.scopes, [Scope::Admin]);
+        assert_eq!(key.expires_at, None);
+        assert_eq!(key.created_at, issued.key.created_at);
+    }
+
+    #[tokio::test]
+    async fn test_owners_are_backfilled() {
+        let database = TempDatabase::new("keys");
+        let connection = Connection::open(&database.0).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE api_keys (
+                    id TEXT PRIMARY KEY, name TEXT NOT NULL, prefix TEXT NOT NULL, key_hash TEXT NOT NULL UNIQUE,
+                    scopes TEXT NOT NULL, created_at TEXT NOT NULL, expires_at TEXT, revoked_at TEXT, rotated_from TEXT
+                );
+                INSERT INTO api_keys VALUES ('a', 'ci', 'llk_', 'h1', 'batch', '2024-01-01T00:00:00+00:00', NULL, NULL, NULL);
+                INSERT INTO api_keys VALUES ('b', 'ci', 'llk_', 'h2', 'batch', '2024-02-01T00:00:00+00:00', NULL, NULL, 'a');
+                INSERT INTO api_keys VALUES ('c', 'ci', 'llk_', 'h3', 'batch', '2024-03-01T00:00:00+00:00', NULL, NULL, 'b');",
+            )
+            .unwrap();
+        drop(connection);
+
+        let store = SqliteApiKeyStore::open(&database.0).unwrap();
+        let owners: Vec<_> = store.list().await.unwrap().into_iter().map(|key| key.owner_id).collect();
+        assert_eq!(owners, ["a", "a", "a"]);
+    }
+}
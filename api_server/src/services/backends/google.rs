@@ -0,0 +1,108 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::sync::Arc;
+use tracing::{debug, info};
+
+use crate::crypto::ecc_cipher::ECCommutativeCipher;
+use crate::crypto::hashing::scrypt_hash_username_and_password;
+use crate::crypto::key_provider::{KeyProvider, KeyScope};
+use crate::services::backends::{LeakBackend, LeakCheckOutcome};
+use crate::services::google_api_client::GoogleApiClient;
+use crate::services::result_cache::ResultCache;
+use crate::utils::redact;
+
+pub const BACKEND_NAME: &str = "google";
+
+/// Google's password leak detection API, reached through the commutative
+/// cipher protocol. Verdicts are reused from `result_cache` when it has one
+/// for the credential's lookup hash.
+#[derive(Clone)]
+pub struct GoogleLeakBackend {
+    api_client: GoogleApiClient,
+    key_provider: Arc<dyn KeyProvider>,
+    batch_cipher: Option<Arc<ECCommutativeCipher>>,
+    result_cache: Option<Arc<ResultCache>>,
+}
+
+impl GoogleLeakBackend {
+    pub fn new(
+        api_client: GoogleApiClient,
+        key_provider: Arc<dyn KeyProvider>,
+        result_cache: Option<Arc<ResultCache>>,
+    ) -> Self {
+        GoogleLeakBackend {
+            api_client,
+            key_provider,
+            batch_cipher: None,
+            result_cache,
+        }
+    }
+    
+    async fn check_credential_with_cipher(
+        &self,
+        username: &str,
+        password: &str,
+        cipher: &ECCommutativeCipher,
+    ) -> Result<LeakCheckOutcome> {
+        debug!("Checking credential for {}", redact::username(username));
+        
+        let lookup_hash = tracing::info_span!("scrypt")
+            .in_scope(|| scrypt_hash_username_and_password(username, password))
+            .context("Failed to hash username and password")?;
+        debug!("Scrypt hash: {}", redact::hash(&lookup_hash));
+        
+        let lookup_hash = match lookup_hash.iter().position(|&b| b == 0) {
+            Some(pos) => &lookup_hash[..pos],
+            None => &lookup_hash[..],
+        };
+        debug!("Lookup hash after null strip: {}", redact::hash(lookup_hash));
+        
+        let cache_key = self.result_cache.as_ref().map(|cache| cache.key(lookup_hash));
+        if let (Some(cache), Some(key)) = (&self.result_cache, &cache_key) {
+            if let Some(is_leaked) = cache.get(key) {
+                debug!("Using the cached verdict for {}", redact::username(username));
+                return Ok(LeakCheckOutcome { is_leaked, occurrences: None, cached: true });
+            }
+        }
+        
+        let encrypted_lookup_hash = tracing::info_span!("encrypt")
+            .in_scope(|| cipher.encrypt(lookup_hash))
+            .context("Failed to encrypt lookup hash")?;
+        debug!("Encrypted lookup hash: {}", redact::hash(&encrypted_lookup_hash));
+        
+        info!("Sending credential check request to Google API for {}", redact::username(username));
+        let is_leaked = self.api_client.check_credential(username, &encrypted_lookup_hash, cipher).await?;
+        
+        if let (Some(cache), Some(key)) = (&self.result_cache, cache_key) {
+            cache.insert(key, is_leaked).await;
+        }
+        Ok(LeakCheckOutcome { is_leaked, occurrences: None, cached: false })
+    }
+}
+
+#[async_trait]
+impl LeakBackend for GoogleLeakBackend {
+    fn name(&self) -> &'static str {
+        BACKEND_NAME
+    }
+    
+    async fn check_credential(&self, username: &str, password: &str) -> Result<LeakCheckOutcome> {
+        match &self.batch_cipher {
+            Some(cipher) => self.check_credential_with_cipher(username, password, cipher).await,
+            None => {
+                let cipher = self.key_provider.next_cipher();
+                self.check_credential_with_cipher(username, password, &cipher).await
+            }
+        }
+    }
+    
+    fn for_batch(&self) -> Option<Arc<dyn LeakBackend>> {
+        match self.key_provider.scope() {
+            KeyScope::Batch => Some(Arc::new(GoogleLeakBackend {
+                batch_cipher: Some(Arc::new(self.key_provider.next_cipher())),
+                ..self.clone()
+            })),
+            KeyScope::Request => None,
+        }
+    }
+}
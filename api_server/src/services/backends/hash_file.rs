@@ -0,0 +1,243 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use md4::Md4;
+use memmap2::Mmap;
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use std::cmp::Ordering;
+use std::fs::File;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::info;
+
+use crate::services::backends::{LeakBackend, LeakCheckOutcome};
+
+pub const BACKEND_NAME: &str = "hash_file";
+
+/// Hash used by a Pwned Passwords dump, selected with `HASH_FILE_FORMAT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashFileFormat {
+    Sha1,
+    Ntlm,
+}
+
+impl HashFileFormat {
+    /// Length of a hex encoded hash in this format.
+    fn hex_len(self) -> usize {
+        match self {
+            HashFileFormat::Sha1 => 40,
+            HashFileFormat::Ntlm => 32,
+        }
+    }
+
+    fn hash_password(self, password: &str) -> String {
+        match self {
+            HashFileFormat::Sha1 => hex::encode_upper(Sha1::digest(password.as_bytes())),
+            HashFileFormat::Ntlm => {
+                let utf16: Vec<u8> = password.encode_utf16().flat_map(u16::to_le_bytes).collect();
+                hex::encode_upper(Md4::digest(&utf16))
+            }
+        }
+    }
+}
+
+impl FromStr for HashFileFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "sha1" => Ok(HashFileFormat::Sha1),
+            "ntlm" => Ok(HashFileFormat::Ntlm),
+            other => Err(anyhow!("Unknown hash file format '{}'", other)),
+        }
+    }
+}
+
+/// Answers password-only checks from a locally downloaded Pwned Passwords
+/// dump of `HASH:COUNT` lines sorted by hash, as produced by the official
+/// downloader. The file is memory-mapped and binary searched, so nothing is
+/// loaded up front and no network access is needed. Usernames are ignored.
+#[derive(Clone)]
+pub struct HashFileBackend {
+    mmap: Arc<Mmap>,
+    format: HashFileFormat,
+}
+
+impl HashFileBackend {
+    pub fn open(path: impl AsRef<Path>, format: HashFileFormat) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open hash file {}", path.display()))?;
+        // SAFETY: the dump is treated as read-only for the lifetime of the
+        // server; truncating it underneath a running process is not supported.
+        let mmap = unsafe { Mmap::map(&file) }
+            .with_context(|| format!("Failed to memory-map hash file {}", path.display()))?;
+
+        let first_line = mmap.split(|&b| b == b'\n').next().unwrap_or_default();
+        let first_hash = first_line.split(|&b| b == b':').next().unwrap_or_default();
+        if first_hash.len() != format.hex_len() || !first_hash.iter().all(u8::is_ascii_hexdigit) {
+            return Err(anyhow!(
+                "Hash file {} does not look like a sorted {:?} dump of HASH:COUNT lines",
+                path.display(),
+                format
+            ));
+        }
+
+        info!("Loaded {:?} hash file {} ({} bytes)", format, path.display(), mmap.len());
+
+        Ok(HashFileBackend {
+            mmap: Arc::new(mmap),
+            format,
+        })
+    }
+}
+
+#[async_trait]
+impl LeakBackend for HashFileBackend {
+    fn name(&self) -> &'static str {
+        BACKEND_NAME
+    }
+
+    async fn check_credential(&self, _username: &str, password: &str) -> Result<LeakCheckOutcome> {
+        let digest = self.format.hash_password(password);
+        let mmap = self.mmap.clone();
+
+        // Cold pages fault in from disk, so keep the search off the async workers.
+        let occurrences = tokio::task::spawn_blocking(move || find_hash_count(&mmap, digest.as_bytes()))
+            .await?
+            .unwrap_or(0);
+
+        Ok(LeakCheckOutcome {
+            is_leaked: occurrences > 0,
+            occurrences: Some(occurrences),
+            cached: false,
+        })
+    }
+}
+
+/// Binary searches `data`, a sorted list of `HASH:COUNT` lines, for the
+/// uppercase hex `hash`. Works on byte offsets: each probe backs up to the
+/// start of the line it landed in and narrows the range to one side of it.
+pub(crate) fn find_hash_count(data: &[u8], hash: &[u8]) -> Option<u64> {
+    let mut low = 0;
+    let mut high = data.len();
+
+    while low < high {
+        let mid = low + (high - low) / 2;
+        let start = data[..mid].iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        let end = data[mid..].iter().position(|&b| b == b'\n').map_or(data.len(), |i| mid + i);
+        let line = &data[start..end];
+
+        let (candidate, count) = match line.iter().position(|&b| b == b':') {
+            Some(colon) => (&line[..colon], &line[colon + 1..]),
+            None => (line, &[][..]),
+        };
+
+        match compare_hex(candidate, hash) {
+            Ordering::Equal => {
+                return std::str::from_utf8(count).ok().and_then(|c| c.trim().parse().ok());
+            }
+            Ordering::Less => low = end + 1,
+            Ordering::Greater => high = start,
+        }
+    }
+
+    None
+}
+
+fn compare_hex(candidate: &[u8], hash: &[u8]) -> Ordering {
+    candidate
+        .iter()
+        .map(u8::to_ascii_uppercase)
+        .cmp(hash.iter().copied())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // SHA-1("password") = 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8
+    // NTLM("password")  = 8846F7EAEE8FB117AD06BDD830B7586C
+    const SHA1_DUMP: &str = "000000005AD76BD555C1D6D771DE417A4B87E4B4:10\r\n\
+        5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:9659365\r\n\
+        7c4a8d09ca3762af61e59520943dc26494f8941b:37359195\r\n\
+        FFFFFFFEE791CBAC0F6305CAF0CEE06BBE131160:2\r\n";
+
+    const NTLM_DUMP: &str = "32ED87BDB5FDC5E9CBA88547376818D4:37359195\n\
+        8846F7EAEE8FB117AD06BDD830B7586C:9659365\n";
+
+    struct TempDump(PathBuf);
+
+    impl TempDump {
+        fn new(contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("leaklens-hashes-{}.txt", uuid::Uuid::new_v4()));
+            std::fs::write(&path, contents).unwrap();
+            TempDump(path)
+        }
+    }
+
+    impl Drop for TempDump {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_find_hash_count() {
+        let data = SHA1_DUMP.as_bytes();
+        assert_eq!(find_hash_count(data, b"5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8"), Some(9659365));
+        assert_eq!(find_hash_count(data, b"000000005AD76BD555C1D6D771DE417A4B87E4B4"), Some(10));
+        assert_eq!(find_hash_count(data, b"FFFFFFFEE791CBAC0F6305CAF0CEE06BBE131160"), Some(2));
+        assert_eq!(find_hash_count(data, b"7C4A8D09CA3762AF61E59520943DC26494F8941B"), Some(37359195));
+        assert_eq!(find_hash_count(data, b"5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD9"), None);
+        assert_eq!(find_hash_count(data, b"0000000000000000000000000000000000000000"), None);
+        assert_eq!(find_hash_count(data, b"FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF"), None);
+        assert_eq!(find_hash_count(b"", b"5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8"), None);
+    }
+
+    #[test]
+    fn test_find_hash_count_on_every_line() {
+        let hashes: Vec<String> = (0..500u32).map(|i| format!("{:040X}", i as u64 * 7919)).collect();
+        let dump: String = hashes.iter().enumerate().map(|(i, h)| format!("{}:{}\n", h, i + 1)).collect();
+
+        for (i, hash) in hashes.iter().enumerate() {
+            assert_eq!(find_hash_count(dump.as_bytes(), hash.as_bytes()), Some(i as u64 + 1));
+        }
+        assert_eq!(find_hash_count(dump.as_bytes(), format!("{:040X}", 1).as_bytes()), None);
+    }
+
+    #[tokio::test]
+    async fn test_sha1_dump_backend() {
+        let dump = TempDump::new(SHA1_DUMP);
+        let backend = HashFileBackend::open(&dump.0, HashFileFormat::Sha1).unwrap();
+
+        let leaked = backend.check_credential("ignored", "password").await.unwrap();
+        assert_eq!(leaked, LeakCheckOutcome { is_leaked: true, occurrences: Some(9659365), cached: false });
+
+        let clean = backend.check_credential("ignored", "a much longer unusual passphrase").await.unwrap();
+        assert_eq!(clean, LeakCheckOutcome { is_leaked: false, occurrences: Some(0), cached: false });
+    }
+
+    #[tokio::test]
+    async fn test_ntlm_dump_backend() {
+        let dump = TempDump::new(NTLM_DUMP);
+        let backend = HashFileBackend::open(&dump.0, HashFileFormat::Ntlm).unwrap();
+
+        let occurrences = |outcome: LeakCheckOutcome| outcome.occurrences;
+        assert_eq!(occurrences(backend.check_credential("", "password").await.unwrap()), Some(9659365));
+        assert_eq!(occurrences(backend.check_credential("", "123456").await.unwrap()), Some(37359195));
+        assert_eq!(occurrences(backend.check_credential("", "Password").await.unwrap()), Some(0));
+    }
+
+    #[test]
+    fn test_open_rejects_mismatched_format() {
+        let dump = TempDump::new(NTLM_DUMP);
+        assert!(HashFileBackend::open(&dump.0, HashFileFormat::Sha1).is_err());
+        assert!(HashFileBackend::open(dump.0.with_extension("missing"), HashFileFormat::Ntlm).is_err());
+        assert_eq!("NTLM".parse::<HashFileFormat>().unwrap(), HashFileFormat::Ntlm);
+        assert!("md5".parse::<HashFileFormat>().is_err());
+    }
+}
@@ -0,0 +1,37 @@
+//! Leak data sources that `LeakCheckService` can dispatch a check to.
+
+pub mod google;
+pub mod hash_file;
+pub mod pwned_passwords;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Names `LEAK_BACKEND` and the per-request `backend` field may take.
+pub const BACKEND_NAMES: [&str; 3] = [google::BACKEND_NAME, pwned_passwords::BACKEND_NAME, hash_file::BACKEND_NAME];
+
+/// What a backend learned about one credential.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LeakCheckOutcome {
+    pub is_leaked: bool,
+    /// How often the password appears in the breach corpus, for backends
+    /// that report it.
+    pub occurrences: Option<u64>,
+    /// The verdict came from the result cache rather than the backend.
+    pub cached: bool,
+}
+
+#[async_trait]
+pub trait LeakBackend: Send + Sync {
+    /// Stable identifier used in config, requests and results.
+    fn name(&self) -> &'static str;
+
+    async fn check_credential(&self, username: &str, password: &str) -> Result<LeakCheckOutcome>;
+
+    /// Returns a backend instance to use for a single batch, for backends that
+    /// keep per-batch state. `None` means the shared instance is used as is.
+    fn for_batch(&self) -> Option<Arc<dyn LeakBackend>> {
+        None
+    }
+}
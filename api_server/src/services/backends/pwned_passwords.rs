@@ -0,0 +1,118 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use sha1::{Digest, Sha1};
+use tracing::debug;
+
+use crate::services::backends::{LeakBackend, LeakCheckOutcome};
+use crate::utils::redact;
+
+pub const BACKEND_NAME: &str = "hibp";
+
+/// Have I Been Pwned's Pwned Passwords range API. Only the first five hex
+/// characters of the password's SHA-1 leave the server; the suffix is matched
+/// locally against the returned bucket. Usernames are not part of the lookup.
+#[derive(Debug, Clone)]
+pub struct PwnedPasswordsBackend {
+    client: Client,
+    base_url: String,
+}
+
+impl PwnedPasswordsBackend {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        PwnedPasswordsBackend {
+            client: Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl LeakBackend for PwnedPasswordsBackend {
+    fn name(&self) -> &'static str {
+        BACKEND_NAME
+    }
+    
+    async fn check_credential(&self, _username: &str, password: &str) -> Result<LeakCheckOutcome> {
+        let digest = hex::encode_upper(Sha1::digest(password.as_bytes()));
+        let (prefix, suffix) = digest.split_at(5);
+        
+        let url = format!("{}/range/{}", self.base_url, prefix);
+        debug!("Querying Pwned Passwords range {}", redact::hash(prefix.as_bytes()));
+        
+        let response = self.client
+            .get(&url)
+            .header("add-padding", "true")
+            .header("user-agent", "LeakLens")
+            .send()
+            .await
+            .map_err(|e| anyhow!("Pwned Passwords request failed: {}", e))?;
+        
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            return Err(anyhow!("Pwned Passwords request failed: {} - {}", status, body.chars().take(200).collect::<String>()));
+        }
+        
+        let occurrences = find_suffix_count(&body, suffix).unwrap_or(0);
+        
+        Ok(LeakCheckOutcome {
+            is_leaked: occurrences > 0,
+            occurrences: Some(occurrences),
+            cached: false,
+        })
+    }
+}
+
+/// Looks up `suffix` in a range response of `SUFFIX:COUNT` lines. Padding
+/// entries carry a count of zero and are treated as absent.
+pub(crate) fn find_suffix_count(body: &str, suffix: &str) -> Option<u64> {
+    body.lines()
+        .filter_map(|line| line.trim().split_once(':'))
+        .find(|(candidate, _)| candidate.eq_ignore_ascii_case(suffix))
+        .and_then(|(_, count)| count.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::Path, routing::get, Router};
+    use std::net::TcpListener;
+
+    // SHA-1("password") = 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8
+    const PASSWORD_RANGE: &str = "1E4C9B93F3F0682250B6CF8331B7EE68FD8:9659365\r\n\
+        011053FD0102E94D6AE2F8B83D76FAF94F6:1\r\n\
+        0000000000000000000000000000000000A:0";
+
+    fn spawn_range_server() -> String {
+        let app = Router::new().route(
+            "/range/:prefix",
+            get(|Path(prefix): Path<String>| async move {
+                if prefix == "5BAA6" { PASSWORD_RANGE.to_string() } else { String::new() }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+        format!("http://{}", addr)
+    }
+
+    #[test]
+    fn test_find_suffix_count() {
+        assert_eq!(find_suffix_count(PASSWORD_RANGE, "1E4C9B93F3F0682250B6CF8331B7EE68FD8"), Some(9659365));
+        assert_eq!(find_suffix_count(PASSWORD_RANGE, "1e4c9b93f3f0682250b6cf8331b7ee68fd8"), Some(9659365));
+        assert_eq!(find_suffix_count(PASSWORD_RANGE, "0000000000000000000000000000000000A"), Some(0));
+        assert_eq!(find_suffix_count(PASSWORD_RANGE, "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF"), None);
+    }
+
+    #[tokio::test]
+    async fn test_range_lookup_against_local_server() {
+        let backend = PwnedPasswordsBackend::new(spawn_range_server());
+
+        let leaked = backend.check_credential("anyone", "password").await.unwrap();
+        assert_eq!(leaked, LeakCheckOutcome { is_leaked: true, occurrences: Some(9659365), cached: false });
+
+        let clean = backend.check_credential("anyone", "a much longer unusual passphrase").await.unwrap();
+        assert_eq!(clean, LeakCheckOutcome { is_leaked: false, occurrences: Some(0), cached: false });
+    }
+}
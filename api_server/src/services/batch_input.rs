@@ -0,0 +1,1043 @@
+//! Turns an uploaded batch file into credential entries.
+//!
+//! Supported inputs are `username<delimiter>password` lines, CSV with a header
+//! row (or an explicit column mapping), JSON arrays, NDJSON, the export
+//! layouts of Chrome, Firefox, Bitwarden (CSV and JSON) and 1Password (CSV and
+//! `.1pif`), and KeePass KDBX databases, which are decrypted in memory. The
+//! format is taken from the `input_type` form field or sniffed from the
+//! content. Rejected records are reported by line number only, so raw file
+//! contents (which may hold a password) never end up in results.
+
+use anyhow::{anyhow, bail, Result};
+use keepass::db::{Group, Node};
+use keepass::{Database, DatabaseKey};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+use crate::utils::redact;
+
+/// Signature every KeePass database file starts with.
+const KDBX_SIGNATURE: [u8; 4] = [0x03, 0xd9, 0xa2, 0x9a];
+
+/// Record separator in 1Password's `.1pif` export.
+const ONE_PIF_SEPARATOR: &str = "***5642bee8-a5ff-11dc-8314-0800200c9a66***";
+
+/// Header names and JSON keys accepted for each field, in order of preference.
+const USERNAME_KEYS: &[&str] = &["username", "login_username", "user", "user_name", "email", "login"];
+const PASSWORD_KEYS: &[&str] = &["password", "login_password", "pass", "passwd"];
+const URL_KEYS: &[&str] = &["url", "login_uri", "uri", "website", "site", "origin", "location"];
+const TITLE_KEYS: &[&str] = &["title", "name"];
+
+/// Delimiters tried, in order, when sniffing a plain text file.
+const SNIFFED_DELIMITERS: &[char] = &[':', '\t', ';', '|', ','];
+/// Delimiters tried when looking for a CSV header row.
+const HEADER_DELIMITERS: &[char] = &[',', ';', '\t', '|'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    /// `username<delimiter>password` per line, split at the first delimiter.
+    Delimited(char),
+    /// One email address per line; accepted but not checked.
+    EmailOnly,
+    Csv,
+    Json,
+    Ndjson,
+    Chrome,
+    Firefox,
+    Bitwarden,
+    OnePassword,
+    /// KeePass database; needs the master password.
+    Kdbx,
+}
+
+impl InputFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            InputFormat::Delimited(_) => "delimited",
+            InputFormat::EmailOnly => "email_only",
+            InputFormat::Csv => "csv",
+            InputFormat::Json => "json",
+            InputFormat::Ndjson => "ndjson",
+            InputFormat::Chrome => "chrome",
+            InputFormat::Firefox => "firefox",
+            InputFormat::Bitwarden => "bitwarden",
+            InputFormat::OnePassword => "1password",
+            InputFormat::Kdbx => "kdbx",
+        }
+    }
+
+    /// Header columns that identify a password manager's CSV export.
+    fn required_headers(self) -> &'static [&'static str] {
+        match self {
+            InputFormat::Chrome => &["name", "url", "username", "password"],
+            InputFormat::Firefox => &["url", "username", "password", "httprealm"],
+            InputFormat::Bitwarden => &["login_uri", "login_username", "login_password"],
+            InputFormat::OnePassword => &["title", "username", "password"],
+            _ => &[],
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "email_pass" | "username_password" | "delimited" => Ok(InputFormat::Delimited(':')),
+            "email_only" => Ok(InputFormat::EmailOnly),
+            "csv" => Ok(InputFormat::Csv),
+            "json" => Ok(InputFormat::Json),
+            "ndjson" | "jsonl" => Ok(InputFormat::Ndjson),
+            "chrome" => Ok(InputFormat::Chrome),
+            "firefox" => Ok(InputFormat::Firefox),
+            "bitwarden" => Ok(InputFormat::Bitwarden),
+            "1password" | "onepassword" => Ok(InputFormat::OnePassword),
+            "kdbx" | "keepass" => Ok(InputFormat::Kdbx),
+            other => Err(anyhow!("Unsupported input type '{}'", other)),
+        }
+    }
+}
+
+/// CSV columns to read, by header name or 1-based position. Unset fields fall
+/// back to the usual header names.
+#[derive(Debug, Clone, Default)]
+pub struct ColumnMapping {
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub url: Option<String>,
+    pub title: Option<String>,
+}
+
+impl ColumnMapping {
+    fn is_empty(&self) -> bool {
+        self.selectors().all(|selector| selector.is_none())
+    }
+
+    /// A file is read without a header row only when every mapped column is
+    /// given by position.
+    fn is_positional(&self) -> bool {
+        !self.is_empty()
+            && self
+                .selectors()
+                .flatten()
+                .all(|selector| selector.trim().parse::<usize>().is_ok())
+    }
+
+    fn selectors(&self) -> impl Iterator<Item = Option<&String>> {
+        [&self.username, &self.password, &self.url, &self.title]
+            .into_iter()
+            .map(Option::as_ref)
+    }
+}
+
+/// Master password of an uploaded vault. Wiped on drop and kept out of
+/// `Debug` output.
+#[derive(Clone)]
+pub struct MasterPassword(Zeroizing<String>);
+
+impl MasterPassword {
+    pub fn new(password: String) -> Self {
+        MasterPassword(Zeroizing::new(password))
+    }
+
+    fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for MasterPassword {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MasterPassword(<redacted>)")
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Explicit format; sniffed from the content when `None`.
+    pub format: Option<InputFormat>,
+    pub delimiter: Option<char>,
+    pub columns: ColumnMapping,
+    pub master_password: Option<MasterPassword>,
+    /// Most records a file may hold. Parsing stops with an error as soon as
+    /// the file passes it, without reading the rest.
+    pub max_entries: Option<usize>,
+}
+
+impl ParseOptions {
+    /// Builds options from the batch form fields. An `input_type` of `auto`
+    /// (or none) means sniffing; `delimiter` is one character or `tab`.
+    pub fn from_fields(input_type: Option<&str>, delimiter: Option<&str>, columns: ColumnMapping) -> Result<Self> {
+        let format = match input_type.map(str::trim) {
+            None | Some("") => None,
+            Some(t) if t.eq_ignore_ascii_case("auto") => None,
+            Some(t) => Some(t.parse()?),
+        };
+
+        let delimiter = match delimiter {
+            None => None,
+            Some(d) if d.eq_ignore_ascii_case("tab") || d == "\\t" => Some('\t'),
+            Some(d) => {
+                let mut chars = d.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Some(c),
+                    _ => bail!("Delimiter must be a single character or 'tab'"),
+                }
+            }
+        };
+
+        Ok(ParseOptions { format, delimiter, columns, master_password: None, max_entries: None })
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BatchEntry {
+    /// 1-based line in the file (record number for JSON arrays and KeePass entries).
+    pub line: usize,
+    pub username: String,
+    pub password: String,
+    pub url: Option<String>,
+    pub title: Option<String>,
+    /// Group path of a KeePass entry, e.g. `Root/Email`.
+    pub group: Option<String>,
+}
+
+impl fmt::Debug for BatchEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BatchEntry")
+            .field("line", &self.line)
+            .field("username", &redact::username(&self.username))
+            .field("password", &"<redacted>")
+            .field("url", &self.url)
+            .field("title", &self.title)
+            .field("group", &self.group)
+            .finish()
+    }
+}
+
+/// A record that will not be checked. `label` identifies it without
+/// revealing its contents: the username or title when one was found, else
+/// the line.
+#[derive(Debug, Clone)]
+pub struct RejectedEntry {
+    pub line: usize,
+    pub label: String,
+    /// `skipped` for records that are not credentials, `error` for malformed ones.
+    pub status: &'static str,
+    pub reason: String,
+    pub url: Option<String>,
+    pub title: Option<String>,
+    pub group: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct ParsedBatch {
+    pub format: InputFormat,
+    pub entries: Vec<BatchEntry>,
+    pub rejected: Vec<RejectedEntry>,
+    max_entries: usize,
+}
+
+impl ParsedBatch {
+    fn new(format: InputFormat, max_entries: usize) -> Self {
+        ParsedBatch { format, entries: Vec::new(), rejected: Vec::new(), max_entries }
+    }
+
+    /// Number of records found, checkable or not.
+    pub fn len(&self) -> usize {
+        self.entries.len() + self.rejected.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether more records were found than the file may hold; parsers stop
+    /// reading once this is true.
+    fn over_limit(&self) -> bool {
+        self.len() > self.max_entries
+    }
+
+    fn accept(&mut self, line: usize, fields: Fields) {
+        match (fields.username, fields.password) {
+            (Some(username), Some(password)) => self.entries.push(BatchEntry {
+                line,
+                username,
+                password,
+                url: fields.url,
+                title: fields.title,
+                group: fields.group,
+            }),
+            (username, _) => self.rejected.push(RejectedEntry {
+                line,
+                label: username
+                    .or_else(|| fields.title.clone())
+                    .unwrap_or_else(|| format!("line {}", line)),
+                status: "skipped",
+                reason: "Entry has no username and password".to_string(),
+                url: fields.url,
+                title: fields.title,
+                group: fields.group,
+            }),
+        }
+    }
+
+    fn reject(&mut self, line: usize, status: &'static str, reason: impl Into<String>) {
+        self.rejected.push(RejectedEntry {
+            line,
+            label: format!("line {}", line),
+            status,
+            reason: reason.into(),
+            url: None,
+            title: None,
+            group: None,
+        });
+    }
+}
+
+/// The fields found in one record, already trimmed and with blanks dropped.
+#[derive(Debug, Default)]
+struct Fields {
+    username: Option<String>,
+    password: Option<String>,
+    url: Option<String>,
+    title: Option<String>,
+    group: Option<String>,
+}
+
+/// Parses an uploaded file. Errors mean the file as a whole is unusable (bad
+/// JSON document, missing CSV columns, more than `max_entries` records); bad
+/// individual records are rejected.
+pub fn parse(bytes: &[u8], options: &ParseOptions) -> Result<ParsedBatch> {
+    let max_entries = options.max_entries.unwrap_or(usize::MAX);
+    let batch = parse_format(bytes, options, max_entries)?;
+
+    if batch.over_limit() {
+        bail!("File contains more than the limit of {} entries", max_entries);
+    }
+
+    Ok(batch)
+}
+
+fn parse_format(bytes: &[u8], options: &ParseOptions, max_entries: usize) -> Result<ParsedBatch> {
+    if options.format == Some(InputFormat::Kdbx) || bytes.starts_with(&KDBX_SIGNATURE) {
+        return parse_kdbx(bytes, options.master_password.as_ref(), max_entries);
+    }
+
+    let text = String::from_utf8_lossy(bytes);
+    let text = text.trim_start_matches('\u{feff}');
+
+    let format = match options.format {
+        Some(InputFormat::Delimited(default)) => InputFormat::Delimited(options.delimiter.unwrap_or(default)),
+        Some(format) => format,
+        None => sniff(text, options),
+    };
+
+    match format {
+        InputFormat::Delimited(delimiter) => Ok(parse_delimited(text, delimiter, max_entries)),
+        InputFormat::EmailOnly => Ok(parse_email_only(text, max_entries)),
+        InputFormat::Json => parse_json(text, max_entries),
+        InputFormat::Ndjson => Ok(parse_ndjson(text, max_entries)),
+        InputFormat::Bitwarden if looks_like_json(text) => {
+            let mut batch = parse_json(text, max_entries)?;
+            batch.format = format;
+            Ok(batch)
+        }
+        InputFormat::OnePassword if text.contains(ONE_PIF_SEPARATOR) => Ok(parse_one_pif(text, max_entries)),
+        InputFormat::Kdbx => unreachable!("KeePass databases are parsed from raw bytes"),
+        InputFormat::Csv | InputFormat::Chrome | InputFormat::Firefox | InputFormat::Bitwarden | InputFormat::OnePassword => {
+            let delimiter = options
+                .delimiter
+                .or_else(|| first_line(text).and_then(|line| header_delimiter(line, options.delimiter)))
+                .unwrap_or(',');
+            parse_csv(text, format, delimiter, &options.columns, max_entries)
+        }
+    }
+}
+
+fn looks_like_json(text: &str) -> bool {
+    text.trim_start().starts_with(['{', '['])
+}
+
+fn first_line(text: &str) -> Option<&str> {
+    text.lines().find(|line| !line.trim().is_empty())
+}
+
+fn sniff(text: &str, options: &ParseOptions) -> InputFormat {
+    if text.contains(ONE_PIF_SEPARATOR) {
+        return InputFormat::OnePassword;
+    }
+
+    let trimmed = text.trim();
+    if trimmed.starts_with('[') {
+        return InputFormat::Json;
+    }
+    if trimmed.starts_with('{') {
+        // One document (Bitwarden's export) or one object per line.
+        return match serde_json::from_str::<Value>(trimmed) {
+            Ok(Value::Object(map)) if map.contains_key("items") => InputFormat::Bitwarden,
+            Ok(_) => InputFormat::Json,
+            Err(_) => InputFormat::Ndjson,
+        };
+    }
+
+    let Some(line) = first_line(text) else {
+        return InputFormat::Delimited(options.delimiter.unwrap_or(':'));
+    };
+
+    if !options.columns.is_empty() {
+        return InputFormat::Csv;
+    }
+
+    if let Some(delimiter) = header_delimiter(line, options.delimiter) {
+        let headers = header_names(line, delimiter);
+        let layout = [InputFormat::Chrome, InputFormat::Firefox, InputFormat::Bitwarden, InputFormat::OnePassword]
+            .into_iter()
+            .find(|layout| has_headers(&headers, layout.required_headers()));
+        return layout.unwrap_or(InputFormat::Csv);
+    }
+
+    let delimiter = options.delimiter.unwrap_or_else(|| {
+        SNIFFED_DELIMITERS
+            .iter()
+            .copied()
+            .find(|d| line.contains(*d))
+            .unwrap_or(':')
+    });
+    InputFormat::Delimited(delimiter)
+}
+
+/// The delimiter under which `line` is a header naming both a username and
+/// a password column, if any.
+fn header_delimiter(line: &str, preferred: Option<char>) -> Option<char> {
+    let candidates = match preferred {
+        Some(delimiter) => vec![delimiter],
+        None => HEADER_DELIMITERS.to_vec(),
+    };
+
+    candidates.into_iter().find(|&delimiter| {
+        let headers = header_names(line, delimiter);
+        find_column(&headers, USERNAME_KEYS).is_some() && find_column(&headers, PASSWORD_KEYS).is_some()
+    })
+}
+
+fn header_names(line: &str, delimiter: char) -> Vec<String> {
+    line.split(delimiter)
+        .map(|name| name.trim().trim_matches('"').trim().to_lowercase())
+        .collect()
+}
+
+fn has_headers(headers: &[String], required: &[&str]) -> bool {
+    required.iter().all(|name| headers.iter().any(|h| h == name))
+}
+
+fn find_column(headers: &[String], keys: &[&str]) -> Option<usize> {
+    keys.iter().find_map(|key| headers.iter().position(|h| h == key))
+}
+
+fn non_blank(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Like `non_blank`, but keeps surrounding whitespace, which is part of a
+/// password stored in a structured format.
+fn secret(value: &str) -> Option<String> {
+    (!value.trim().is_empty()).then(|| value.to_string())
+}
+
+fn parse_delimited(text: &str, delimiter: char, max_entries: usize) -> ParsedBatch {
+    let mut batch = ParsedBatch::new(InputFormat::Delimited(delimiter), max_entries);
+
+    for (i, line) in text.lines().enumerate() {
+        if batch.over_limit() {
+            break;
+        }
+        let line_number = i + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        // Only the first delimiter separates the fields; passwords may contain it.
+        match line.trim().split_once(delimiter) {
+            Some((username, password)) => batch.accept(
+                line_number,
+                Fields {
+                    username: non_blank(username),
+                    password: non_blank(password),
+                    ..Fields::default()
+                },
+            ),
+            None => batch.reject(
+                line_number,
+                "error",
+                format!("Invalid format. Expected username{}password", delimiter.escape_default()),
+            ),
+        }
+    }
+
+    batch
+}
+
+fn parse_email_only(text: &str, max_entries: usize) -> ParsedBatch {
+    let mut batch = ParsedBatch::new(InputFormat::EmailOnly, max_entries);
+
+    for (i, line) in text.lines().enumerate() {
+        if batch.over_limit() {
+            break;
+        }
+        if let Some(email) = non_blank(line) {
+            batch.rejected.push(RejectedEntry {
+                line: i + 1,
+                label: email,
+                status: "skipped",
+                reason: "Email-only format not supported yet".to_string(),
+                url: None,
+                title: None,
+                group: None,
+            });
+        }
+    }
+
+    batch
+}
+
+fn parse_csv(
+    text: &str,
+    format: InputFormat,
+    delimiter: char,
+    columns: &ColumnMapping,
+    max_entries: usize,
+) -> Result<ParsedBatch> {
+    if !delimiter.is_ascii() {
+        bail!("CSV delimiter must be an ASCII character");
+    }
+
+    let positional = columns.is_positional();
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter as u8)
+        .has_headers(!positional)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+
+    let headers: Vec<String> = if positional {
+        Vec::new()
+    } else {
+        reader
+            .headers()?
+            .iter()
+            .map(|name| name.trim().to_lowercase())
+            .collect()
+    };
+
+    if !has_headers(&headers, format.required_headers()) {
+        bail!("File does not look like a {} export", format.name());
+    }
+
+    let column = |selector: &Option<String>, keys: &[&str], field: &str| -> Result<Option<usize>> {
+        match selector.as_deref().map(str::trim) {
+            Some(selector) => match selector.parse::<usize>() {
+                Ok(0) => bail!("Column positions start at 1"),
+                Ok(position) => Ok(Some(position - 1)),
+                Err(_) => headers
+                    .iter()
+                    .position(|h| h.eq_ignore_ascii_case(selector))
+                    .map(Some)
+                    .ok_or_else(|| anyhow!("No '{}' column for the {}", selector, field)),
+            },
+            None => Ok(find_column(&headers, keys)),
+        }
+    };
+
+    let username_column = column(&columns.username, USERNAME_KEYS, "username")?
+        .ok_or_else(|| anyhow!("No username column found; set username_column"))?;
+    let password_column = column(&columns.password, PASSWORD_KEYS, "password")?
+        .ok_or_else(|| anyhow!("No password column found; set password_column"))?;
+    let url_column = column(&columns.url, URL_KEYS, "URL")?;
+    let title_column = column(&columns.title, TITLE_KEYS, "title")?;
+    let type_column = (format == InputFormat::Bitwarden)
+        .then(|| headers.iter().position(|h| h == "type"))
+        .flatten();
+
+    let mut batch = ParsedBatch::new(format, max_entries);
+
+    for (i, record) in reader.records().enumerate() {
+        if batch.over_limit() {
+            break;
+        }
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                let line = e.position().map_or(i + 1, |p| p.line() as usize);
+                batch.reject(line, "error", "Malformed CSV record");
+                continue;
+            }
+        };
+        let line = record.position().map_or(i + 1, |p| p.line() as usize);
+
+        if record.iter().all(|value| value.trim().is_empty()) {
+            continue;
+        }
+
+        if let Some(kind) = type_column.and_then(|c| record.get(c)) {
+            if !kind.trim().is_empty() && !kind.trim().eq_ignore_ascii_case("login") {
+                batch.reject(line, "skipped", "Not a login item");
+                continue;
+            }
+        }
+
+        let get = |column: Option<usize>| column.and_then(|c| record.get(c)).and_then(non_blank);
+        batch.accept(
+            line,
+            Fields {
+                username: get(Some(username_column)),
+                password: record.get(password_column).and_then(secret),
+                url: get(url_column),
+                title: get(title_column),
+                ..Fields::default()
+            },
+        );
+    }
+
+    Ok(batch)
+}
+
+fn parse_json(text: &str, max_entries: usize) -> Result<ParsedBatch> {
+    let document: Value = serde_json::from_str(text).map_err(|e| anyhow!("Invalid JSON: {}", e))?;
+
+    let (format, items) = match document {
+        Value::Array(items) => (InputFormat::Json, items),
+        Value::Object(mut map) => match map.remove("items") {
+            Some(Value::Array(items)) => (InputFormat::Bitwarden, items),
+            _ => (InputFormat::Json, vec![Value::Object(map)]),
+        },
+        _ => bail!("JSON input must be an array of objects"),
+    };
+
+    let mut batch = ParsedBatch::new(format, max_entries);
+    for (i, item) in items.iter().enumerate() {
+        if batch.over_limit() {
+            break;
+        }
+        accept_json(&mut batch, i + 1, item);
+    }
+
+    Ok(batch)
+}
+
+fn parse_ndjson(text: &str, max_entries: usize) -> ParsedBatch {
+    let mut batch = ParsedBatch::new(InputFormat::Ndjson, max_entries);
+
+    for (i, line) in text.lines().enumerate() {
+        if batch.over_limit() {
+            break;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Value>(line) {
+            Ok(item) => accept_json(&mut batch, i + 1, &item),
+            Err(_) => batch.reject(i + 1, "error", "Invalid JSON"),
+        }
+    }
+
+    batch
+}
+
+fn accept_json(batch: &mut ParsedBatch, line: usize, item: &Value) {
+    let Value::Object(object) = item else {
+        batch.reject(line, "error", "Expected a JSON object");
+        return;
+    };
+
+    // Bitwarden items: `type` 1 is a login, with the credential under `login`.
+    if object.get("type").and_then(Value::as_u64).is_some_and(|kind| kind != 1) {
+        batch.reject(line, "skipped", "Not a login item");
+        return;
+    }
+
+    let login = object.get("login").and_then(Value::as_object);
+    let lookup = |keys: &[&str]| login.and_then(|l| json_field(l, keys)).or_else(|| json_field(object, keys));
+
+    let url = lookup(URL_KEYS).or_else(|| {
+        login
+            .and_then(|l| l.get("uris"))
+            .and_then(Value::as_array)
+            .and_then(|uris| uris.first())
+            .and_then(|uri| uri.get("uri"))
+            .and_then(json_string)
+    });
+
+    batch.accept(
+        line,
+        Fields {
+            username: lookup(USERNAME_KEYS),
+            password: lookup(PASSWORD_KEYS),
+            url,
+            title: json_field(object, TITLE_KEYS),
+            ..Fields::default()
+        },
+    );
+}
+
+fn json_field(object: &Map<String, Value>, keys: &[&str]) -> Option<String> {
+    keys.iter().find_map(|key| {
+        object
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .and_then(|(_, value)| json_string(value))
+    })
+}
+
+fn json_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => secret(s),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// 1Password's `.1pif`: one JSON record per line, separated by marker lines.
+/// Logins keep their credentials in `secureContents.fields` by designation.
+fn parse_one_pif(text: &str, max_entries: usize) -> ParsedBatch {
+    let mut batch = ParsedBatch::new(InputFormat::OnePassword, max_entries);
+
+    for (i, line) in text.lines().enumerate() {
+        if batch.over_limit() {
+            break;
+        }
+        let line_number = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line == ONE_PIF_SEPARATOR {
+            continue;
+        }
+
+        let Ok(Value::Object(record)) = serde_json::from_str::<Value>(line) else {
+            batch.reject(line_number, "error", "Invalid JSON");
+            continue;
+        };
+
+        let designated = |designation: &str| {
+            record
+                .get("secureContents")
+                .and_then(|c| c.get("fields"))
+                .and_then(Value::as_array)
+                .and_then(|fields| {
+                    fields
+                        .iter()
+                        .find(|f| f.get("designation").and_then(Value::as_str) == Some(designation))
+                })
+                .and_then(|f| f.get("value"))
+                .and_then(json_string)
+        };
+
+        batch.accept(
+            line_number,
+            Fields {
+                username: designated("username"),
+                password: designated("password"),
+                url: json_field(&record, &["location"]),
+                title: json_field(&record, &["title"]),
+                ..Fields::default()
+            },
+        );
+    }
+
+    batch
+}
+
+/// Decrypts a KeePass database in memory and walks its groups. Entries in
+/// the recycle bin are left out.
+fn parse_kdbx(bytes: &[u8], master_password: Option<&MasterPassword>, max_entries: usize) -> Result<ParsedBatch> {
+    let master_password = master_password
+        .ok_or_else(|| anyhow!("A master password is required to open a KeePass database"))?;
+
+    let key = DatabaseKey::new().with_password(master_password.expose());
+    let database = Database::parse(bytes, key)
+        .map_err(|e| anyhow!("Could not open KeePass database: {}", e))?;
+
+    let mut batch = ParsedBatch::new(InputFormat::Kdbx, max_entries);
+    let recycle_bin = database.meta.recyclebin_uuid;
+    collect_kdbx_group(&database.root, &database.root.name, recycle_bin, &mut batch);
+
+    Ok(batch)
+}
+
+fn collect_kdbx_group(group: &Group, path: &str, recycle_bin: Option<uuid::Uuid>, batch: &mut ParsedBatch) {
+    for node in &group.children {
+        if batch.over_limit() {
+            return;
+        }
+        match node {
+            Node::Entry(entry) => {
+                let line = batch.len() + 1;
+                batch.accept(
+                    line,
+                    Fields {
+                        username: entry.get_username().and_then(non_blank),
+                        password: entry.get_password().and_then(secret),
+                        url: entry.get_url().and_then(non_blank),
+                        title: entry.get_title().and_then(non_blank),
+                        group: Some(path.to_string()),
+                    },
+                );
+            }
+            Node::Group(child) if Some(child.uuid) == recycle_bin => {}
+            Node::Group(child) => {
+                collect_kdbx_group(child, &format!("{}/{}", path, child.name), recycle_bin, batch);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sniffed(text: &str) -> ParsedBatch {
+        parse(text.as_bytes(), &ParseOptions::default()).unwrap()
+    }
+
+    fn credentials(batch: &ParsedBatch) -> Vec<(usize, &str, &str)> {
+        batch
+            .entries
+            .iter()
+            .map(|e| (e.line, e.username.as_str(), e.password.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_delimited_lines_keep_colons_in_passwords() {
+        let batch = sniffed("alice:pa:ss\n\nbob:hunter2\nno delimiter here\n:missing\n");
+
+        assert_eq!(batch.format, InputFormat::Delimited(':'));
+        assert_eq!(credentials(&batch), vec![(1, "alice", "pa:ss"), (3, "bob", "hunter2")]);
+        assert_eq!(batch.rejected.len(), 2);
+        assert_eq!(batch.rejected[0].line, 4);
+        assert_eq!(batch.rejected[0].label, "line 4");
+        assert_eq!(batch.rejected[0].status, "error");
+        assert_eq!(batch.rejected[1].status, "skipped");
+    }
+
+    #[test]
+    fn test_custom_delimiters() {
+        let batch = sniffed("alice\tsecret;1\n");
+        assert_eq!(batch.format, InputFormat::Delimited('\t'));
+        assert_eq!(credentials(&batch), vec![(1, "alice", "secret;1")]);
+
+        let options = ParseOptions::from_fields(Some("delimited"), Some("|"), ColumnMapping::default()).unwrap();
+        let batch = parse(b"alice|a:b|c\n", &options).unwrap();
+        assert_eq!(credentials(&batch), vec![(1, "alice", "a:b|c")]);
+
+        assert!(ParseOptions::from_fields(None, Some("::"), ColumnMapping::default()).is_err());
+        assert!(ParseOptions::from_fields(Some("xml"), None, ColumnMapping::default()).is_err());
+    }
+
+    #[test]
+    fn test_csv_with_column_mapping() {
+        let text = "Site;Login;Secret\nexample.com;alice;\"p;w\"\n";
+        let columns = ColumnMapping {
+            username: Some("login".to_string()),
+            password: Some("Secret".to_string()),
+            url: Some("site".to_string()),
+            title: None,
+        };
+        let options = ParseOptions::from_fields(Some("csv"), Some(";"), columns).unwrap();
+        let batch = parse(text.as_bytes(), &options).unwrap();
+
+        assert_eq!(credentials(&batch), vec![(2, "alice", "p;w")]);
+        assert_eq!(batch.entries[0].url.as_deref(), Some("example.com"));
+
+        let columns = ColumnMapping {
+            username: Some("2".to_string()),
+            password: Some("3".to_string()),
+            ..ColumnMapping::default()
+        };
+        let options = ParseOptions::from_fields(Some("csv"), None, columns).unwrap();
+        let batch = parse(b"x,alice,secret\n", &options).unwrap();
+        assert_eq!(credentials(&batch), vec![(1, "alice", "secret")]);
+
+        let columns = ColumnMapping { password: Some("nope".to_string()), ..ColumnMapping::default() };
+        let options = ParseOptions::from_fields(Some("csv"), None, columns).unwrap();
+        assert!(parse(b"username,password\nalice,secret\n", &options).is_err());
+    }
+
+    #[test]
+    fn test_password_manager_csv_exports() {
+        let chrome = "name,url,username,password,note\nExample,https://example.com/,alice,\"a,b\",\n";
+        let batch = sniffed(chrome);
+        assert_eq!(batch.format, InputFormat::Chrome);
+        assert_eq!(credentials(&batch), vec![(2, "alice", "a,b")]);
+        assert_eq!(batch.entries[0].title.as_deref(), Some("Example"));
+        assert_eq!(batch.entries[0].url.as_deref(), Some("https://example.com/"));
+
+        let firefox = "\"url\",\"username\",\"password\",\"httpRealm\",\"formActionOrigin\",\"guid\"\n\
+                       \"https://example.org\",\"bob\",\"hunter2\",,\"https://example.org\",\"{x}\"\n";
+        let batch = sniffed(firefox);
+        assert_eq!(batch.format, InputFormat::Firefox);
+        assert_eq!(credentials(&batch), vec![(2, "bob", "hunter2")]);
+
+        let bitwarden = "folder,favorite,type,name,notes,fields,reprompt,login_uri,login_username,login_password,login_totp\n\
+                         ,,login,Mail,,,0,https://mail.example,carol,s3cret,\n\
+                         ,,note,Secret note,text,,0,,,,\n";
+        let batch = sniffed(bitwarden);
+        assert_eq!(batch.format, InputFormat::Bitwarden);
+        assert_eq!(credentials(&batch), vec![(2, "carol", "s3cret")]);
+        assert_eq!(batch.rejected[0].reason, "Not a login item");
+
+        let onepassword = "Title,Url,Username,Password,OTPAuth,Favorite,Archived,Tags,Notes\n\
+                           Bank,https://bank.example,dave,letmein,,false,false,,\n";
+        let batch = sniffed(onepassword);
+        assert_eq!(batch.format, InputFormat::OnePassword);
+        assert_eq!(batch.entries[0].title.as_deref(), Some("Bank"));
+
+        let options = ParseOptions::from_fields(Some("firefox"), None, ColumnMapping::default()).unwrap();
+        assert!(parse(chrome.as_bytes(), &options).is_err());
+    }
+
+    #[test]
+    fn test_json_inputs() {
+        let batch = sniffed(r#"[{"email": "alice@example.com", "password": "pw", "url": "https://a.example"}, {"username": "bob"}, 3]"#);
+        assert_eq!(batch.format, InputFormat::Json);
+        assert_eq!(credentials(&batch), vec![(1, "alice@example.com", "pw")]);
+        assert_eq!(batch.rejected.len(), 2);
+
+        let batch = sniffed("{\"username\": \"alice\", \"password\": 1234}\nnot json\n\n{\"username\": \"bob\", \"password\": \"x\"}\n");
+        assert_eq!(batch.format, InputFormat::Ndjson);
+        assert_eq!(credentials(&batch), vec![(1, "alice", "1234"), (4, "bob", "x")]);
+        assert_eq!(batch.rejected[0].line, 2);
+
+        let options = ParseOptions::from_fields(Some("json"), None, ColumnMapping::default()).unwrap();
+        assert!(parse(b"[{", &options).is_err());
+    }
+
+    #[test]
+    fn test_password_manager_json_exports() {
+        let bitwarden = r#"{"encrypted": false, "folders": [], "items": [
+            {"type": 1, "name": "Mail", "login": {"username": "carol", "password": "s3cret", "uris": [{"match": null, "uri": "https://mail.example"}]}},
+            {"type": 2, "name": "Note", "secureNote": {"type": 0}}
+        ]}"#;
+        let batch = sniffed(bitwarden);
+        assert_eq!(batch.format, InputFormat::Bitwarden);
+        assert_eq!(credentials(&batch), vec![(1, "carol", "s3cret")]);
+        assert_eq!(batch.entries[0].url.as_deref(), Some("https://mail.example"));
+        assert_eq!(batch.entries[0].title.as_deref(), Some("Mail"));
+        assert_eq!(batch.rejected[0].status, "skipped");
+
+        let one_pif = format!(
+            "{}\n{}\n{}\n",
+            r#"{"title": "Bank", "location": "https://bank.example", "secureContents": {"fields": [{"designation": "username", "value": "dave"}, {"designation": "password", "value": "letmein"}]}}"#,
+            ONE_PIF_SEPARATOR,
+            r#"{"title": "Wifi", "secureContents": {"notesPlain": "x"}}"#,
+        );
+        let batch = sniffed(&one_pif);
+        assert_eq!(batch.format, InputFormat::OnePassword);
+        assert_eq!(credentials(&batch), vec![(1, "dave", "letmein")]);
+        assert_eq!(batch.entries[0].url.as_deref(), Some("https://bank.example"));
+        assert_eq!(batch.rejected[0].line, 3);
+    }
+
+    fn kdbx_fixture(master_password: &str) -> Vec<u8> {
+        use keepass::config::{DatabaseConfig, KdfConfig};
+        use keepass::db::{Entry, Value as Field};
+
+        fn entry(title: &str, username: &str, password: &str, url: &str) -> Entry {
+            let mut entry = Entry::new();
+            for (key, value) in [("Title", title), ("UserName", username), ("URL", url)] {
+                entry.fields.insert(key.to_string(), Field::Unprotected(value.to_string()));
+            }
+            entry
+                .fields
+                .insert("Password".to_string(), Field::Protected(password.as_bytes().into()));
+            entry
+        }
+
+        let config = DatabaseConfig {
+            kdf_config: KdfConfig::Aes { rounds: 1 },
+            ..DatabaseConfig::default()
+        };
+        let mut database = Database::new(config);
+        database.root.name = "Team".to_string();
+        database.root.add_child(entry("Wiki", "alice", " pass word ", "https://wiki.example"));
+
+        let mut email = Group::new("Email");
+        email.add_child(entry("Mail", "bob", "hunter2", "https://mail.example"));
+        email.add_child(entry("Notes", "", "", ""));
+        database.root.add_child(email);
+
+        let mut trash = Group::new("Recycle Bin");
+        trash.add_child(entry("Old", "carol", "old", ""));
+        database.meta.recyclebin_uuid = Some(trash.uuid);
+        database.root.add_child(trash);
+
+        let mut bytes = Vec::new();
+        database
+            .save(&mut bytes, DatabaseKey::new().with_password(master_password))
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_kdbx_database() {
+        let bytes = kdbx_fixture("correct horse");
+
+        let options = ParseOptions {
+            master_password: Some(MasterPassword::new("correct horse".to_string())),
+            ..ParseOptions::default()
+        };
+        let batch = parse(&bytes, &options).unwrap();
+
+        assert_eq!(batch.format, InputFormat::Kdbx);
+        assert_eq!(credentials(&batch), vec![(1, "alice", " pass word "), (2, "bob", "hunter2")]);
+        assert_eq!(batch.entries[0].group.as_deref(), Some("Team"));
+        assert_eq!(batch.entries[1].group.as_deref(), Some("Team/Email"));
+        assert_eq!(batch.entries[1].title.as_deref(), Some("Mail"));
+        assert_eq!(batch.entries[1].url.as_deref(), Some("https://mail.example"));
+        assert_eq!(batch.rejected.len(), 1);
+        assert_eq!(batch.rejected[0].label, "Notes");
+
+        assert!(parse(&bytes, &ParseOptions::default()).is_err());
+        let options = ParseOptions {
+            master_password: Some(MasterPassword::new("wrong".to_string())),
+            ..ParseOptions::default()
+        };
+        assert!(parse(&bytes, &options).is_err());
+        assert_eq!(format!("{:?}", options.master_password.unwrap()), "MasterPassword(<redacted>)");
+    }
+
+    #[test]
+    fn test_email_only() {
+        let options = ParseOptions::from_fields(Some("email_only"), None, ColumnMapping::default()).unwrap();
+        let batch = parse(b"alice@example.com\n\nbob@example.com\n", &options).unwrap();
+
+        assert!(batch.entries.is_empty());
+        assert_eq!(batch.rejected[1].line, 3);
+        assert_eq!(batch.rejected[1].label, "bob@example.com");
+    }
+
+    #[test]
+    fn test_max_entries() {
+        let limited = |format: Option<&str>| ParseOptions {
+            max_entries: Some(2),
+            ..ParseOptions::from_fields(format, None, ColumnMapping::default()).unwrap()
+        };
+
+        let batch = parse(b"alice:a\nbob:b\n", &limited(None)).unwrap();
+        assert_eq!(batch.len(), 2);
+
+        // Rejected records count towards the limit too.
+        let error = parse(b"alice:a\nbob:b\nno delimiter\n", &limited(None)).unwrap_err();
+        assert_eq!(error.to_string(), "File contains more than the limit of 2 entries");
+        assert!(parse(b"username,password\na,1\nb,2\nc,3\n", &limited(Some("csv"))).is_err());
+        assert!(parse(b"[{}, {}, {}]", &limited(Some("json"))).is_err());
+
+        let options = ParseOptions {
+            master_password: Some(MasterPassword::new("correct horse".to_string())),
+            max_entries: Some(1),
+            ..ParseOptions::default()
+        };
+        assert!(parse(&kdbx_fixture("correct horse"), &options).is_err());
+    }
+}
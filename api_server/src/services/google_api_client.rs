@@ -1,20 +1,29 @@
 use anyhow::{anyhow, Context, Result};
 use bytes::Bytes;
 use reqwest::Client;
-use tracing::{debug, error, info, trace};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, trace, warn, Instrument};
 
 use crate::crypto::check_google_api;
 use crate::crypto::ecc_cipher::ECCommutativeCipher;
 use crate::crypto::hashing::{username_hash_prefix, extract_username_from_email};
 use crate::proto::{LookupSingleLeakRequest, LookupSingleLeakResponse};
 use crate::services::token_manager::TokenManager;
-use crate::utils::config;
+use crate::services::upstream::{self, AdaptiveLimiter, Signal, UpstreamError};
+use crate::utils::{config, metrics, redact};
 
+/// Client for Google's leak check API. Clones share one `AdaptiveLimiter`,
+/// so every lookup of the process counts against the same concurrency.
 #[derive(Debug, Clone)]
 pub struct GoogleApiClient {
     client: Client,
     token_manager: TokenManager,
     debug_mode: bool,
+    limiter: Arc<AdaptiveLimiter>,
+    max_attempts: u32,
+    retry_base_delay: Duration,
+    max_retry_delay: Duration,
 }
 
 impl GoogleApiClient {
@@ -26,13 +35,22 @@ impl GoogleApiClient {
         if debug_mode {
             info!("Google API client running in DEBUG mode");
         }
-            
+        
+        let config = &config::get().upstream;
         GoogleApiClient {
             client: Client::new(),
             token_manager,
             debug_mode,
+            limiter: Arc::new(AdaptiveLimiter::new(config)),
+            max_attempts: config.max_attempts.max(1),
+            retry_base_delay: Duration::from_millis(config.retry_base_delay_ms),
+            max_retry_delay: Duration::from_millis(config.max_retry_delay_ms),
         }
     }
+    
+    pub fn limiter(&self) -> &AdaptiveLimiter {
+        &self.limiter
+    }
 
 
     pub async fn check_credential(
@@ -42,24 +60,98 @@ impl GoogleApiClient {
         cipher: &ECCommutativeCipher,
     ) -> Result<bool> {
         let username_to_check = extract_username_from_email(username);
-        debug!("Extracted username '{}' from '{}'", username_to_check, username);
+        debug!("Extracted username {} from {}", redact::username(&username_to_check), redact::username(username));
 
         let prefix = username_hash_prefix(&username_to_check);
-        debug!("Username hash prefix calculated: {}", hex::encode(&prefix));
+        debug!("Username hash prefix calculated: {}", redact::hash(&prefix));
+
+        let request = LookupSingleLeakRequest {
+            username_hash_prefix: prefix,
+            username_hash_prefix_length: 26, // fixed value based on the protocol
+            encrypted_lookup_hash: encrypted_lookup_hash.to_vec(),
+        };
+
+        let response = self.lookup_single_leak(&request).await?;
+
+        let is_leaked = tracing::info_span!("unblind").in_scope(|| -> Result<bool> {
+            if !self.debug_mode {
+                return check_google_api::unblind_and_check(&response, cipher);
+            }
+
+            let decrypted_hash = cipher
+                .decrypt(&response.reencrypted_lookup_hash)
+                .context("Failed to decrypt reencrypted lookup hash")?;
+            debug!("Successfully decrypted re-encrypted hash");
+            check_google_api::debug_response_check(&response, &decrypted_hash)
+        })?;
+
+        info!(
+            "Credential check complete - is leaked: {} (with {} potential matches)",
+            is_leaked,
+            response.encrypted_leak_match_prefix.len()
+        );
+
+        Ok(is_leaked)
+    }
 
-        let mut request = LookupSingleLeakRequest::default();
-        request.username_hash_prefix = prefix;
-        request.username_hash_prefix_length = 26; // fixed value based on the protocol
-        request.encrypted_lookup_hash = encrypted_lookup_hash.to_vec();
 
-        let request_bytes = prost::Message::encode_to_vec(&request);
+    /// Sends an already blinded lookup to Google and returns the raw response.
+    /// The caller is responsible for unblinding `reencrypted_lookup_hash`.
+    ///
+    /// Waits for a slot from the limiter before every attempt. Transient
+    /// failures are retried after a jittered backoff, or after Google's
+    /// `Retry-After` when it is longer; a `Retry-After` beyond the longest
+    /// retry delay ends the retries.
+    pub async fn lookup_single_leak(&self, request: &LookupSingleLeakRequest) -> Result<LookupSingleLeakResponse> {
+        let request_bytes = Bytes::from(prost::Message::encode_to_vec(request));
         trace!("Serialized request size: {} bytes", request_bytes.len());
 
-        let token = self.token_manager.get_token().await?;
+        let token = self.token_manager.get_token().instrument(tracing::info_span!("get_token")).await?;
         debug!("Obtained valid OAuth token");
 
+        let mut attempt = 1;
+        loop {
+            let permit = self.limiter.acquire().await;
+            let e = match self.send_lookup(&token, request_bytes.clone()).await {
+                Ok(response_bytes) => {
+                    permit.finish(Signal::Answered);
+                    return self.decode_response(&response_bytes);
+                }
+                Err(e) => e,
+            };
+            permit.finish(if e.is_overload() { Signal::Overloaded } else { Signal::Failed });
+            if let Some(retry_after) = e.retry_after {
+                self.limiter.pause(retry_after.min(self.max_retry_delay));
+            }
+
+            let delay = upstream::retry_delay(self.retry_base_delay, self.max_retry_delay, attempt)
+                .max(e.retry_after.unwrap_or_default());
+            if attempt >= self.max_attempts || !e.is_transient() || delay > self.max_retry_delay {
+                error!("Giving up on the lookup after {} attempt(s): {}", attempt, e);
+                return Err(e.into());
+            }
+
+            let reason = e.status.map_or_else(|| "error".to_string(), |status| status.as_str().to_string());
+            metrics::UPSTREAM_RETRIES.inc(&[&reason]);
+            warn!("Lookup failed ({}), retrying in {:?}", e, delay);
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+
+    /// One attempt at a lookup, returning the body of a successful response.
+    async fn send_lookup(&self, token: &str, request_bytes: Bytes) -> Result<Bytes, UpstreamError> {
         let config = config::get();
         debug!("Sending request to Google API: {}", config.google_api.api_url);
+        let span = tracing::info_span!(
+            "POST leaks:lookupSingle",
+            otel.kind = "client",
+            http.method = "POST",
+            http.url = %config.google_api.api_url,
+            http.status_code = tracing::field::Empty,
+        );
+        let started = Instant::now();
         let response = self
             .client
             .post(&config.google_api.api_url)
@@ -68,46 +160,47 @@ impl GoogleApiClient {
             .header("user-agent", "Mozilla/5.0 (Rust Leak Checker)")
             .body(request_bytes)
             .send()
-            .await
-            .map_err(|e| anyhow!("API request failed: {}", e))?;
+            .instrument(span.clone())
+            .await;
+        let status_label = match &response {
+            Ok(response) => {
+                span.record("http.status_code", response.status().as_u16());
+                response.status().as_str().to_string()
+            }
+            Err(e) => {
+                span.in_scope(|| error!("Request to Google failed: {}", e));
+                "error".to_string()
+            }
+        };
+        metrics::UPSTREAM_REQUESTS.inc(&[&status_label]);
+        metrics::UPSTREAM_DURATION.observe_since(&[&status_label], started);
+        let response = response.map_err(|e| UpstreamError::new(None, None, e.to_string()))?;
 
         let status = response.status();
+        let retry_after = upstream::parse_retry_after(response.headers());
         let content_type = response.headers().get("content-type").map(|v| v.to_str().unwrap_or("")).unwrap_or("").to_string();
-        let response_bytes = response.bytes().await?;
+        let response_bytes = response
+            .bytes()
+            .await
+            .map_err(|e| UpstreamError::new(None, None, format!("Failed to read the response: {}", e)))?;
         debug!("Received response: {} bytes, status: {}, content-type: {}", response_bytes.len(), status, content_type);
         if !status.is_success() {
             let body_text = String::from_utf8_lossy(&response_bytes);
-            error!("API request failed: {} - {}", status, body_text);
-            return Err(anyhow!("API request failed: {} - {}", status, body_text));
+            return Err(UpstreamError::new(Some(status), retry_after, format!("{} - {}", status, body_text)));
         }
+        Ok(response_bytes)
+    }
 
-        let response = match self.parse_response(&response_bytes) {
-            Ok(r) => r,
+
+    fn decode_response(&self, response_bytes: &Bytes) -> Result<LookupSingleLeakResponse> {
+        match self.parse_response(response_bytes) {
+            Ok(r) => Ok(r),
             Err(e) => {
-                let body_text = String::from_utf8_lossy(&response_bytes);
+                let body_text = String::from_utf8_lossy(response_bytes);
                 error!("Failed to decode API protobuf response: {}. Raw body: {}", e, body_text);
-                return Err(anyhow!("Failed to decode API response: {}. Raw body: {}", e, body_text));
+                Err(anyhow!("Failed to decode API response: {}. Raw body: {}", e, body_text))
             }
-        };
-
-        let decrypted_hash = cipher
-            .decrypt(&response.reencrypted_lookup_hash)
-            .context("Failed to decrypt reencrypted lookup hash")?;
-        debug!("Successfully decrypted re-encrypted hash");
-
-        let is_leaked = if self.debug_mode {
-            check_google_api::debug_response_check(&response, &decrypted_hash)?
-        } else {
-            check_google_api::check_credential_leaked(&response, &decrypted_hash)?
-        };
-
-        info!(
-            "Credential check complete - is leaked: {} (with {} potential matches)",
-            is_leaked,
-            response.encrypted_leak_match_prefix.len()
-        );
-
-        Ok(is_leaked)
+        }
     }
 
 
@@ -0,0 +1,118 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::models::request_models::{CredentialCheckResult, JobState};
+use crate::services::batch_input::BatchEntry;
+use crate::services::job_store::{JobStore, NewJob, StoredJob};
+
+/// Keeps jobs for the lifetime of the process only; the default, matching
+/// the behaviour before jobs could be persisted.
+#[derive(Default)]
+pub struct MemoryJobStore {
+    jobs: Mutex<HashMap<String, MemoryJob>>,
+}
+
+struct MemoryJob {
+    job: StoredJob,
+    /// The entries not checked yet; `job.pending` is filled from them on load.
+    entries: Vec<BatchEntry>,
+}
+
+#[async_trait]
+impl JobStore for MemoryJobStore {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn create_job(&self, job: &NewJob, entries: &[BatchEntry]) -> Result<()> {
+        let stored = StoredJob {
+            id: job.id.clone(),
+            total: job.total,
+            backend: job.backend.clone(),
+            state: JobState::Running,
+            error: None,
+            abandon_timeout: job.abandon_timeout,
+            callback: job.callback.clone(),
+            owner: job.owner.clone(),
+            resumable: job.resumable,
+            version: 0,
+            finished_at: None,
+            results: Vec::new(),
+            entries_checked: 0,
+            pending: Vec::new(),
+        };
+
+        self.jobs.lock().unwrap().insert(
+            job.id.clone(),
+            MemoryJob { job: stored, entries: if job.resumable { entries.to_vec() } else { Vec::new() } },
+        );
+        Ok(())
+    }
+
+    async fn append_results(
+        &self,
+        job_id: &str,
+        results: &[CredentialCheckResult],
+        entries_checked: usize,
+        version: u64,
+    ) -> Result<()> {
+        if let Some(stored) = self.jobs.lock().unwrap().get_mut(job_id) {
+            stored.job.results.extend_from_slice(results);
+            let checked = entries_checked.saturating_sub(stored.job.entries_checked).min(stored.entries.len());
+            stored.entries.drain(..checked);
+            stored.job.entries_checked = entries_checked;
+            stored.job.version = version;
+        }
+        Ok(())
+    }
+
+    async fn update_state(&self, job_id: &str, state: JobState, version: u64) -> Result<()> {
+        if let Some(stored) = self.jobs.lock().unwrap().get_mut(job_id) {
+            stored.job.state = state;
+            stored.job.version = version;
+        }
+        Ok(())
+    }
+
+    async fn finish_job(&self, job_id: &str, state: JobState, error: Option<&str>, version: u64) -> Result<()> {
+        if let Some(stored) = self.jobs.lock().unwrap().get_mut(job_id) {
+            stored.job.state = state;
+            stored.job.error = error.map(str::to_string);
+            stored.job.version = version;
+            stored.job.finished_at = Some(Utc::now());
+            stored.entries.clear();
+        }
+        Ok(())
+    }
+
+    async fn delete_job(&self, job_id: &str) -> Result<()> {
+        self.jobs.lock().unwrap().remove(job_id);
+        Ok(())
+    }
+
+    async fn load_jobs(&self) -> Result<Vec<StoredJob>> {
+        let jobs = self.jobs.lock().unwrap();
+        Ok(jobs
+            .values()
+            .map(|stored| {
+                let mut job = stored.job.clone();
+                job.pending = stored.entries.clone();
+                job
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::job_store::tests::exercise_store;
+
+    #[tokio::test]
+    async fn test_memory_store() {
+        exercise_store(&MemoryJobStore::default()).await;
+    }
+}
@@ -0,0 +1,242 @@
+//! Durable copies of batch jobs.
+//!
+//! The routes work on the live `JobStorage` map, which also carries progress
+//! streams and heartbeats. Every change that has to survive a restart (a new
+//! job with its entries, recorded results, completion, deletion) is written
+//! through to a `JobStore` as well, and stored jobs are loaded back on startup.
+//! Pausing, resuming and abandonment are stored as well, so after a restart a
+//! job nobody was watching waits for a client to return before it runs again.
+//!
+//! Entries of KeePass databases are never stored: they must stay in memory,
+//! so a job checking one cannot resume after a restart and ends instead.
+
+pub mod memory;
+pub mod sqlite;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::models::request_models::{CredentialCheckResult, JobState};
+use crate::services::batch_input::BatchEntry;
+use crate::services::webhooks::WebhookTarget;
+use crate::utils::config::JobStoreConfig;
+
+/// Where jobs are kept, selected with `JOB_STORE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStoreKind {
+    Memory,
+    Sqlite,
+}
+
+impl FromStr for JobStoreKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "memory" => Ok(JobStoreKind::Memory),
+            "sqlite" => Ok(JobStoreKind::Sqlite),
+            other => Err(anyhow!("Unknown job store '{}'", other)),
+        }
+    }
+}
+
+/// A job as first written, before any entry has been checked.
+#[derive(Debug, Clone)]
+pub struct NewJob {
+    pub id: String,
+    pub total: usize,
+    /// Leak backend the job checks against; a resumed job uses it again.
+    pub backend: String,
+    /// Seconds without a client before the job is abandoned; `None` when detached.
+    pub abandon_timeout: Option<u64>,
+    /// Webhook, secret included, so a resumed job still reports back.
+    pub callback: Option<WebhookTarget>,
+    /// ID of the API key or token subject that uploaded the job.
+    pub owner: Option<String>,
+    /// Whether the entries may be stored so the job can resume after a
+    /// restart; false for KeePass databases.
+    pub resumable: bool,
+}
+
+/// A job read back from a store.
+#[derive(Debug, Clone)]
+pub struct StoredJob {
+    pub id: String,
+    pub total: usize,
+    pub backend: String,
+    /// `Running`, `Paused`, `Abandoned` or one of the final states.
+    pub state: JobState,
+    pub error: Option<String>,
+    pub abandon_timeout: Option<u64>,
+    pub callback: Option<WebhookTarget>,
+    pub owner: Option<String>,
+    /// False when the entries were not stored; such a job cannot resume.
+    pub resumable: bool,
+    pub version: u64,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub results: Vec<CredentialCheckResult>,
+    /// How many of the job's entries have been checked.
+    pub entries_checked: usize,
+    /// The entries after `entries_checked`, in order; empty once finished.
+    pub pending: Vec<BatchEntry>,
+}
+
+#[async_trait]
+pub trait JobStore: Send + Sync {
+    /// Identifier used in config and logs.
+    fn name(&self) -> &'static str;
+
+    /// Saves a new job with every entry it is going to check, unless the
+    /// job is not resumable.
+    async fn create_job(&self, job: &NewJob, entries: &[BatchEntry]) -> Result<()>;
+
+    /// Saves newly recorded results along with the job's progress, and drops
+    /// the entries checked so far.
+    async fn append_results(
+        &self,
+        job_id: &str,
+        results: &[CredentialCheckResult],
+        entries_checked: usize,
+        version: u64,
+    ) -> Result<()>;
+
+    /// Records a job being paused, resumed or abandoned.
+    async fn update_state(&self, job_id: &str, state: JobState, version: u64) -> Result<()>;
+
+    /// Moves a job to a final state and drops its unchecked entries, which
+    /// hold plaintext passwords.
+    async fn finish_job(&self, job_id: &str, state: JobState, error: Option<&str>, version: u64) -> Result<()>;
+
+    async fn delete_job(&self, job_id: &str) -> Result<()>;
+
+    async fn load_jobs(&self) -> Result<Vec<StoredJob>>;
+}
+
+/// Opens the store selected in config.
+pub fn open(config: &JobStoreConfig) -> Result<Arc<dyn JobStore>> {
+    match config.kind {
+        JobStoreKind::Memory => Ok(Arc::new(memory::MemoryJobStore::default())),
+        JobStoreKind::Sqlite => {
+            let key = config.key.as_deref().ok_or_else(|| anyhow!("A key is required to store jobs in SQLite"))?;
+            Ok(Arc::new(sqlite::SqliteJobStore::open(&config.sqlite_path, key)?))
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub fn entries(count: usize) -> Vec<BatchEntry> {
+        (0..count)
+            .map(|i| BatchEntry {
+                line: i + 1,
+                username: format!("user{}", i),
+                password: format!("secret{}", i),
+                url: None,
+                title: None,
+                group: None,
+            })
+            .collect()
+    }
+
+    fn result(index: usize) -> CredentialCheckResult {
+        CredentialCheckResult {
+            index,
+            line: index + 1,
+            credential: format!("user{}:••••••••", index),
+            is_leaked: Some(index % 2 == 0),
+            status: "checked".to_string(),
+            message: None,
+            backend: Some("google".to_string()),
+            occurrences: None,
+            cached: false,
+            url: None,
+            title: None,
+            group: None,
+        }
+    }
+
+    fn new_job(id: &str, total: usize) -> NewJob {
+        let callback = WebhookTarget {
+            url: "https://hooks.example.com/leaks".to_string(),
+            secret: "s3cret".to_string(),
+            include_results: false,
+        };
+        NewJob {
+            id: id.to_string(),
+            total,
+            backend: "google".to_string(),
+            abandon_timeout: Some(15),
+            callback: Some(callback),
+            owner: Some("key-1".to_string()),
+            resumable: true,
+        }
+    }
+
+    /// Runs a store through the lifecycle of a finished and an unfinished job.
+    pub async fn exercise_store(store: &dyn JobStore) {
+        store.create_job(&new_job("finished", 2), &entries(2)).await.unwrap();
+        store.create_job(&new_job("running", 3), &entries(3)).await.unwrap();
+
+        store.append_results("finished", &[result(0), result(1)], 2, 1).await.unwrap();
+        store.finish_job("finished", JobState::Completed, None, 2).await.unwrap();
+        store.append_results("running", &[result(0)], 1, 1).await.unwrap();
+        store.update_state("running", JobState::Paused, 2).await.unwrap();
+
+        let mut jobs = store.load_jobs().await.unwrap();
+        jobs.sort_by(|a, b| a.id.cmp(&b.id));
+        let [finished, running] = jobs.as_slice() else {
+            panic!("expected two jobs, found {}", jobs.len());
+        };
+
+        assert_eq!(finished.state, JobState::Completed);
+        assert!(finished.finished_at.is_some());
+        assert_eq!(finished.version, 2);
+        assert_eq!(finished.results.len(), 2);
+        assert_eq!(finished.results[1].credential, "user1:••••••••");
+        assert!(finished.pending.is_empty());
+
+        assert_eq!(running.state, JobState::Paused);
+        assert_eq!(running.version, 2);
+        assert_eq!(running.abandon_timeout, Some(15));
+        assert_eq!(running.callback.as_ref().map(|c| c.secret.as_str()), Some("s3cret"));
+        assert_eq!(running.backend, "google");
+        assert_eq!(running.owner.as_deref(), Some("key-1"));
+        assert_eq!(running.total, 3);
+        assert!(running.resumable);
+        assert_eq!(running.entries_checked, 1);
+        assert_eq!(running.results.len(), 1);
+        let pending: Vec<_> = running.pending.iter().map(|e| e.username.as_str()).collect();
+        assert_eq!(pending, ["user1", "user2"]);
+        assert_eq!(running.pending[0].password, "secret1");
+
+        store.finish_job("running", JobState::Cancelled, Some("Job cancelled"), 3).await.unwrap();
+        store.delete_job("finished").await.unwrap();
+        let jobs = store.load_jobs().await.unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].state, JobState::Cancelled);
+        assert_eq!(jobs[0].error.as_deref(), Some("Job cancelled"));
+        assert!(jobs[0].pending.is_empty());
+
+        let vault = NewJob { resumable: false, ..new_job("vault", 2) };
+        store.create_job(&vault, &entries(2)).await.unwrap();
+        let jobs = store.load_jobs().await.unwrap();
+        let vault = jobs.iter().find(|job| job.id == "vault").unwrap();
+        assert!(!vault.resumable);
+        assert!(vault.pending.is_empty());
+        store.delete_job("vault").await.unwrap();
+    }
+
+    #[test]
+    fn test_job_store_kind() {
+        assert_eq!("SQLite".parse::<JobStoreKind>().unwrap(), JobStoreKind::Sqlite);
+        assert_eq!("memory".parse::<JobStoreKind>().unwrap(), JobStoreKind::Memory);
+        assert!("redis".parse::<JobStoreKind>().is_err());
+    }
+}
@@ -0,0 +1,463 @@
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use rusqlite::{params, Connection};
+use sha2::Sha256;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+use zeroize::Zeroizing;
+
+use crate::models::request_models::{CredentialCheckResult, JobState};
+use crate::services::batch_input::BatchEntry;
+use crate::services::job_store::{JobStore, NewJob, StoredJob};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS jobs (
+        id              TEXT PRIMARY KEY,
+        total           INTEGER NOT NULL,
+        backend         TEXT NOT NULL,
+        completed       INTEGER NOT NULL DEFAULT 0,
+        state           TEXT NOT NULL DEFAULT 'running',
+        abandon_timeout INTEGER DEFAULT 15,
+        callback        TEXT,
+        owner           TEXT,
+        resumable       INTEGER NOT NULL DEFAULT 1,
+        error           TEXT,
+        version         INTEGER NOT NULL DEFAULT 0,
+        entries_checked INTEGER NOT NULL DEFAULT 0,
+        created_at      TEXT NOT NULL,
+        finished_at     TEXT
+    );
+    CREATE TABLE IF NOT EXISTS job_results (
+        job_id   TEXT NOT NULL REFERENCES jobs(id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        result   TEXT NOT NULL,
+        PRIMARY KEY (job_id, position)
+    );
+    CREATE TABLE IF NOT EXISTS job_entries (
+        job_id   TEXT NOT NULL REFERENCES jobs(id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        entry    BLOB NOT NULL,
+        PRIMARY KEY (job_id, position)
+    );
+";
+
+/// Columns added to `jobs` after its first release, added to older databases
+/// on open.
+const ADDED_COLUMNS: [(&str, &str); 4] = [
+    ("state", "TEXT NOT NULL DEFAULT 'running'"),
+    ("abandon_timeout", "INTEGER DEFAULT 15"),
+    ("callback", "TEXT"),
+    ("owner", "TEXT"),
+];
+
+const NONCE_LEN: usize = 12;
+
+/// Persists jobs in a SQLite file. Results are stored masked, exactly as the
+/// API returns them. Entries not yet checked are stored encrypted so that an
+/// interrupted job can resume, and are deleted once checked. Deleted rows are
+/// overwritten (`secure_delete`), so no password lingers in the file.
+#[derive(Clone)]
+pub struct SqliteJobStore {
+    connection: Arc<Mutex<Connection>>,
+    cipher: Arc<EntryCipher>,
+}
+
+/// Seals entries with AES-256-GCM under a key derived from `JOB_STORE_KEY`,
+/// bound to their job and position so rows cannot be swapped.
+struct EntryCipher(Aes256Gcm);
+
+impl EntryCipher {
+    fn new(key: &str) -> Self {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length");
+        mac.update(b"leaklens job entries");
+        EntryCipher(Aes256Gcm::new(&mac.finalize().into_bytes()))
+    }
+
+    fn seal(&self, job_id: &str, position: usize, entry: &BatchEntry) -> Result<Vec<u8>> {
+        let plaintext = Zeroizing::new(serde_json::to_vec(entry)?);
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let aad = format!("{}/{}", job_id, position);
+
+        let ciphertext = self
+            .0
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: &plaintext, aad: aad.as_bytes() })
+            .map_err(|_| anyhow!("Failed to encrypt an entry of job {}", job_id))?;
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    fn open(&self, job_id: &str, position: usize, sealed: &[u8]) -> Result<BatchEntry> {
+        if sealed.len() < NONCE_LEN {
+            return Err(anyhow!("Entry {} of job {} is truncated", position, job_id));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let aad = format!("{}/{}", job_id, position);
+
+        let plaintext = self
+            .0
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: aad.as_bytes() })
+            .map(Zeroizing::new)
+            .map_err(|_| anyhow!("Entry {} of job {} does not decrypt under JOB_STORE_KEY", position, job_id))?;
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+}
+
+impl SqliteJobStore {
+    /// Opens the database at `path`, encrypting entries under `key`.
+    pub fn open(path: impl AsRef<Path>, key: &str) -> Result<Self> {
+        let path = path.as_ref();
+        let connection = Connection::open(path)
+            .with_context(|| format!("Failed to open job database {}", path.display()))?;
+        let cipher = EntryCipher::new(key);
+
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.pragma_update(None, "foreign_keys", "ON")?;
+        connection.pragma_update(None, "secure_delete", "ON")?;
+        connection.execute_batch(SCHEMA).context("Failed to create job tables")?;
+        add_missing_columns(&connection).context("Failed to upgrade job tables")?;
+
+        info!("Persisting batch jobs in {}", path.display());
+        Ok(SqliteJobStore { connection: Arc::new(Mutex::new(connection)), cipher: Arc::new(cipher) })
+    }
+
+    /// Runs `f` on the connection from the blocking pool.
+    async fn with_connection<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection.lock().map_err(|_| anyhow!("Job database lock poisoned"))?;
+            f(&mut connection)
+        })
+        .await?
+    }
+}
+
+fn add_missing_columns(connection: &Connection) -> Result<()> {
+    let existing = connection
+        .prepare("SELECT name FROM pragma_table_info('jobs')")?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (column, definition) in ADDED_COLUMNS {
+        if !existing.iter().any(|name| name == column) {
+            connection.execute_batch(&format!("ALTER TABLE jobs ADD COLUMN {} {}", column, definition))?;
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl JobStore for SqliteJobStore {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    async fn create_job(&self, job: &NewJob, entries: &[BatchEntry]) -> Result<()> {
+        let job = job.clone();
+        let callback = job.callback.as_ref().map(serde_json::to_string).transpose()?;
+        let entries = if job.resumable {
+            entries
+                .iter()
+                .enumerate()
+                .map(|(position, entry)| self.cipher.seal(&job.id, position, entry))
+                .collect::<Result<Vec<_>>>()?
+        } else {
+            Vec::new()
+        };
+
+        self.with_connection(move |connection| {
+            let tx = connection.transaction()?;
+            tx.execute(
+                "INSERT INTO jobs (id, total, backend, abandon_timeout, callback, owner, resumable, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    job.id,
+                    job.total as i64,
+                    job.backend,
+                    job.abandon_timeout.map(|secs| secs as i64),
+                    callback,
+                    job.owner,
+                    job.resumable,
+                    Utc::now().to_rfc3339()
+                ],
+            )?;
+            {
+                let mut insert = tx.prepare("INSERT INTO job_entries (job_id, position, entry) VALUES (?1, ?2, ?3)")?;
+                for (position, entry) in entries.iter().enumerate() {
+                    insert.execute(params![job.id, position as i64, entry])?;
+                }
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn append_results(
+        &self,
+        job_id: &str,
+        results: &[CredentialCheckResult],
+        entries_checked: usize,
+        version: u64,
+    ) -> Result<()> {
+        let job_id = job_id.to_string();
+        let results = results
+            .iter()
+            .map(|result| Ok((result.index as i64, serde_json::to_string(result)?)))
+            .collect::<Result<Vec<_>>>()?;
+
+        self.with_connection(move |connection| {
+            let tx = connection.transaction()?;
+            {
+                let mut insert = tx.prepare(
+                    "INSERT OR REPLACE INTO job_results (job_id, position, result) VALUES (?1, ?2, ?3)",
+                )?;
+                for (position, result) in &results {
+                    insert.execute(params![job_id, position, result])?;
+                }
+            }
+            tx.execute(
+                "UPDATE jobs SET entries_checked = ?2, version = ?3 WHERE id = ?1",
+                params![job_id, entries_checked as i64, version as i64],
+            )?;
+            tx.execute(
+                "DELETE FROM job_entries WHERE job_id = ?1 AND position < ?2",
+                params![job_id, entries_checked as i64],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn update_state(&self, job_id: &str, state: JobState, version: u64) -> Result<()> {
+        let job_id = job_id.to_string();
+        self.with_connection(move |connection| {
+            connection.execute(
+                "UPDATE jobs SET state = ?2, version = ?3 WHERE id = ?1",
+                params![job_id, state.as_str(), version as i64],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn finish_job(&self, job_id: &str, state: JobState, error: Option<&str>, version: u64) -> Result<()> {
+        let job_id = job_id.to_string();
+        let error = error.map(str::to_string);
+
+        self.with_connection(move |connection| {
+            let tx = connection.transaction()?;
+            tx.execute(
+                "UPDATE jobs SET completed = 1, state = ?2, error = ?3, version = ?4, finished_at = ?5 WHERE id = ?1",
+                params![job_id, state.as_str(), error, version as i64, Utc::now().to_rfc3339()],
+            )?;
+            tx.execute("DELETE FROM job_entries WHERE job_id = ?1", params![job_id])?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_job(&self, job_id: &str) -> Result<()> {
+        let job_id = job_id.to_string();
+        self.with_connection(move |connection| {
+            connection.execute("DELETE FROM jobs WHERE id = ?1", params![job_id])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn load_jobs(&self) -> Result<Vec<StoredJob>> {
+        let cipher = self.cipher.clone();
+        self.with_connection(move |connection| {
+            let mut select = connection.prepare(
+                "SELECT id, total, backend, completed, state, error, abandon_timeout, version, entries_checked, finished_at,
+                        callback, owner, resumable
+                 FROM jobs",
+            )?;
+            let rows = select
+                .query_map([], |row| {
+                    let completed: bool = row.get(3)?;
+                    let state: String = row.get(4)?;
+                    let job = StoredJob {
+                        id: row.get(0)?,
+                        total: row.get::<_, i64>(1)? as usize,
+                        backend: row.get(2)?,
+                        state: JobState::Running,
+                        error: row.get(5)?,
+                        abandon_timeout: row.get::<_, Option<i64>>(6)?.map(|secs| secs as u64),
+                        version: row.get::<_, i64>(7)? as u64,
+                        entries_checked: row.get::<_, i64>(8)? as usize,
+                        finished_at: row
+                            .get::<_, Option<String>>(9)?
+                            .and_then(|t| DateTime::parse_from_rfc3339(&t).ok())
+                            .map(|t| t.with_timezone(&Utc)),
+                        callback: None,
+                        owner: row.get(11)?,
+                        resumable: row.get(12)?,
+                        results: Vec::new(),
+                        pending: Vec::new(),
+                    };
+                    Ok((job, completed, state, row.get::<_, Option<String>>(10)?))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let mut jobs = Vec::with_capacity(rows.len());
+            for (mut job, completed, state, callback) in rows {
+                job.state = state.parse()?;
+                job.callback = callback.map(|json| serde_json::from_str(&json)).transpose()?;
+                // Rows finished before states were stored still say `running`.
+                if completed && !matches!(job.state, JobState::Cancelled | JobState::Completed) {
+                    job.state = JobState::Completed;
+                }
+                jobs.push(job);
+            }
+
+            let mut results = connection.prepare("SELECT result FROM job_results WHERE job_id = ?1 ORDER BY position")?;
+            let mut entries = connection.prepare(
+                "SELECT position, entry FROM job_entries WHERE job_id = ?1 AND position >= ?2 ORDER BY position",
+            )?;
+
+            for job in &mut jobs {
+                job.results = results
+                    .query_map(params![job.id], |row| row.get::<_, String>(0))?
+                    .map(|json| Ok(serde_json::from_str(&json?)?))
+                    .collect::<Result<_>>()?;
+                let pending = entries
+                    .query_map(params![job.id, job.entries_checked as i64], |row| {
+                        Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?))
+                    })?
+                    .map(|row| {
+                        let (position, sealed) = row?;
+                        cipher.open(&job.id, position as usize, &sealed)
+                    })
+                    .collect::<Result<_>>();
+                match pending {
+                    Ok(pending) => job.pending = pending,
+                    Err(e) => {
+                        warn!("Job {} cannot resume: {:#}", job.id, e);
+                        job.resumable = false;
+                    }
+                }
+            }
+
+            Ok(jobs)
+        })
+        .await
+    }
+}
+
+impl SqliteJobStore {
+    /// Number of unchecked entries still stored for a job.
+    #[cfg(test)]
+    fn pending_count(&self, job_id: &str) -> usize {
+        let connection = self.connection.lock().unwrap();
+        connection
+            .query_row("SELECT COUNT(*) FROM job_entries WHERE job_id = ?1", params![job_id], |row| {
+                row.get::<_, i64>(0)
+            })
+            .unwrap() as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::job_store::tests::{entries, exercise_store};
+    use crate::utils::test_support::TempDatabase;
+
+    const KEY: &str = "job-key";
+
+    /// Raw `entry` column of every stored entry.
+    fn stored_entries(store: &SqliteJobStore) -> Vec<Vec<u8>> {
+        let connection = store.connection.lock().unwrap();
+        let mut select = connection.prepare("SELECT entry FROM job_entries").unwrap();
+        let rows = select.query_map([], |row| row.get::<_, Vec<u8>>(0)).unwrap();
+        rows.collect::<Result<_, _>>().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_store() {
+        let database = TempDatabase::new("jobs");
+        let store = SqliteJobStore::open(&database.0, KEY).unwrap();
+        exercise_store(&store).await;
+        assert_eq!(store.pending_count("finished"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_jobs_survive_reopening() {
+        let database = TempDatabase::new("jobs");
+        let store = SqliteJobStore::open(&database.0, KEY).unwrap();
+        let entries = entries(3);
+        let job = NewJob {
+            id: "job".to_string(),
+            total: 3,
+            backend: "google".to_string(),
+            abandon_timeout: None,
+            callback: None,
+            owner: None,
+            resumable: true,
+        };
+        store.create_job(&job, &entries).await.unwrap();
+        drop(store);
+
+        let store = SqliteJobStore::open(&database.0, KEY).unwrap();
+        let jobs = store.load_jobs().await.unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].pending.len(), 3);
+        assert_eq!(jobs[0].pending[2].username, "user2");
+        assert_eq!(jobs[0].pending[2].password, "secret2");
+        assert_eq!(jobs[0].abandon_timeout, None);
+        assert_eq!(store.pending_count("job"), 3);
+        assert!(stored_entries(&store).iter().all(|entry| !entry.windows(b"secret".len()).any(|window| window == b"secret")));
+
+        // Checked entries are dropped along with the results.
+        store.append_results("job", &[], 1, 1).await.unwrap();
+        assert_eq!(store.pending_count("job"), 2);
+        drop(store);
+
+        // Under another key the entries cannot be read, so the job cannot resume.
+        let store = SqliteJobStore::open(&database.0, "other-key").unwrap();
+        let jobs = store.load_jobs().await.unwrap();
+        assert!(!jobs[0].resumable);
+        assert!(jobs[0].pending.is_empty());
+
+        store.delete_job("job").await.unwrap();
+        assert!(store.load_jobs().await.unwrap().is_empty());
+        assert_eq!(store.pending_count("job"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_upgrades_older_databases() {
+        let database = TempDatabase::new("jobs");
+        {
+            let connection = Connection::open(&database.0).unwrap();
+            connection
+                .execute_batch(
+                    "CREATE TABLE jobs (
+                         id TEXT PRIMARY KEY, total INTEGER NOT NULL, backend TEXT NOT NULL,
+                         completed INTEGER NOT NULL DEFAULT 0, error TEXT, version INTEGER NOT NULL DEFAULT 0,
+                         resumable INTEGER NOT NULL DEFAULT 1, entries_checked INTEGER NOT NULL DEFAULT 0,
+                         created_at TEXT NOT NULL, finished_at TEXT
+                     );
+                     INSERT INTO jobs (id, total, backend, completed, created_at) VALUES ('old', 1, 'google', 1, '');",
+                )
+                .unwrap();
+        }
+
+        let store = SqliteJobStore::open(&database.0, KEY).unwrap();
+        let jobs = store.load_jobs().await.unwrap();
+        assert_eq!(jobs[0].state, JobState::Completed);
+        assert_eq!(jobs[0].abandon_timeout, Some(15));
+    }
+}
@@ -0,0 +1,286 @@
+//! Bearer JWTs issued by an external OIDC identity provider.
+//!
+//! Tokens must be signed with RS256 or ES256 by a key in the provider's JWKS,
+//! which is read from a file or URL and re-read when a token names a key ID it
+//! does not know, so key rotations at the provider are picked up without a
+//! restart. Besides the signature, the issuer, audience and expiry are checked;
+//! the `sub` claim identifies the caller and a configurable claim carries the
+//! roles that map to LeakLens scopes.
+
+use anyhow::{anyhow, Context, Result};
+use jsonwebtoken::{decode, decode_header, jwk::{Jwk, JwkSet}, Algorithm, DecodingKey, Validation};
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::RwLock;
+
+use crate::services::api_keys::Scope;
+use crate::utils::config::JwtConfig;
+
+/// Shortest time between two JWKS reloads triggered by unknown key IDs, so
+/// tokens with made up key IDs cannot hammer the identity provider.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How callers authenticate, selected with `AUTH_MODE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMode {
+    /// LeakLens API keys from the key store.
+    ApiKey,
+    /// Bearer JWTs from an OIDC identity provider.
+    Jwt,
+}
+
+impl FromStr for AuthMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "api_key" => Ok(AuthMode::ApiKey),
+            "jwt" => Ok(AuthMode::Jwt),
+            other => Err(anyhow!("Unknown auth mode '{}'", other)),
+        }
+    }
+}
+
+/// The caller a valid token speaks for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    /// The token's `sub` claim.
+    pub subject: String,
+    pub scopes: Vec<Scope>,
+}
+
+impl Principal {
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.contains(&Scope::Admin) || self.scopes.contains(&scope)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum TokenError {
+    /// The token is malformed, badly signed, expired or meant for someone else.
+    #[error("{0}")]
+    Invalid(String),
+    /// The JWKS could not be loaded, so no token can be checked.
+    #[error("Failed to load the JWKS: {0:#}")]
+    KeysUnavailable(anyhow::Error),
+}
+
+#[derive(Default)]
+struct KeyCache {
+    keys: Option<JwkSet>,
+    loaded_at: Option<Instant>,
+}
+
+pub struct JwtValidator {
+    config: JwtConfig,
+    client: Client,
+    cache: RwLock<KeyCache>,
+}
+
+impl JwtValidator {
+    pub fn new(config: JwtConfig) -> Self {
+        JwtValidator {
+            config,
+            client: Client::builder().timeout(FETCH_TIMEOUT).build().unwrap_or_default(),
+            cache: RwLock::new(KeyCache::default()),
+        }
+    }
+
+    /// Checks `token` and returns who it was issued to.
+    pub async fn validate(&self, token: &str) -> Result<Principal, TokenError> {
+        let header = decode_header(token).map_err(|e| TokenError::Invalid(format!("Malformed token: {}", e)))?;
+        if !matches!(header.alg, Algorithm::RS256 | Algorithm::ES256) {
+            return Err(TokenError::Invalid(format!("Unsupported token algorithm {:?}", header.alg)));
+        }
+
+        let jwk = self.key(header.kid.as_deref()).await?;
+        if jwk.common.key_algorithm.is_some_and(|alg| alg.to_string() != format!("{:?}", header.alg)) {
+            return Err(TokenError::Invalid("Token algorithm does not match its key".to_string()));
+        }
+        let key = DecodingKey::from_jwk(&jwk).map_err(|e| TokenError::Invalid(format!("Unusable JWKS key: {}", e)))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.config.issuer]);
+        validation.set_audience(&[&self.config.audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        validation.leeway = self.config.leeway_secs;
+
+        let claims = decode::<Value>(token, &key, &validation)
+            .map_err(|e| TokenError::Invalid(format!("Invalid token: {}", e)))?
+            .claims;
+        principal(&claims, &self.config)
+    }
+
+    /// Loads the JWKS now rather than on the first request.
+    pub async fn preload(&self) -> Result<()> {
+        let mut cache = self.cache.write().await;
+        self.reload(&mut cache).await
+    }
+
+    /// The key a token names. Tokens without a key ID are accepted only while
+    /// the JWKS holds a single key.
+    async fn key(&self, kid: Option<&str>) -> Result<Jwk, TokenError> {
+        if let Some(jwk) = self.cache.read().await.keys.as_ref().and_then(|keys| find_key(keys, kid)) {
+            return Ok(jwk);
+        }
+
+        let mut cache = self.cache.write().await;
+        // Another request may have reloaded the keys while this one waited.
+        if let Some(jwk) = cache.keys.as_ref().and_then(|keys| find_key(keys, kid)) {
+            return Ok(jwk);
+        }
+
+        let stale = cache.loaded_at.map_or(true, |at| at.elapsed() >= RELOAD_INTERVAL);
+        if stale {
+            self.reload(&mut cache).await.map_err(TokenError::KeysUnavailable)?;
+        }
+
+        cache
+            .keys
+            .as_ref()
+            .and_then(|keys| find_key(keys, kid))
+            .ok_or_else(|| TokenError::Invalid("Token is not signed by a known key".to_string()))
+    }
+
+    async fn reload(&self, cache: &mut KeyCache) -> Result<()> {
+        // Failed loads count too, so an unreachable provider is not retried on
+        // every request.
+        cache.loaded_at = Some(Instant::now());
+
+        let source = &self.config.jwks;
+        let body = if source.starts_with("http://") || source.starts_with("https://") {
+            self.client
+                .get(source)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .with_context(|| format!("Failed to fetch {}", source))?
+                .text()
+                .await
+                .with_context(|| format!("Failed to read {}", source))?
+        } else {
+            tokio::fs::read_to_string(source)
+                .await
+                .with_context(|| format!("Failed to read {}", source))?
+        };
+
+        let keys: JwkSet = serde_json::from_str(&body).with_context(|| format!("{} is not a valid JWKS", source))?;
+        tracing::info!("Loaded {} signing keys from {}", keys.keys.len(), source);
+        cache.keys = Some(keys);
+        Ok(())
+    }
+}
+
+fn find_key(keys: &JwkSet, kid: Option<&str>) -> Option<Jwk> {
+    match kid {
+        Some(kid) => keys.find(kid).cloned(),
+        None if keys.keys.len() == 1 => keys.keys.first().cloned(),
+        None => None,
+    }
+}
+
+fn principal(claims: &Value, config: &JwtConfig) -> Result<Principal, TokenError> {
+    let subject = claims
+        .get("sub")
+        .and_then(Value::as_str)
+        .filter(|sub| !sub.is_empty())
+        .ok_or_else(|| TokenError::Invalid("Token has no subject".to_string()))?
+        .to_string();
+
+    Ok(Principal { subject, scopes: scopes(claims, &config.scope_claim, &config.scope_map) })
+}
+
+/// The LeakLens scopes granted by the claim at `path`, a dotted path such as
+/// `realm_access.roles`. The claim may be a space separated string or an array
+/// of strings. Values missing from `scope_map` are ignored, unless the map is
+/// empty, in which case values named after a scope grant it.
+fn scopes(claims: &Value, path: &str, scope_map: &HashMap<String, Scope>) -> Vec<Scope> {
+    let claim = path.split('.').try_fold(claims, |value, field| value.get(field));
+    let values: Vec<&str> = match claim {
+        Some(Value::String(values)) => values.split_whitespace().collect(),
+        Some(Value::Array(values)) => values.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+
+    let mut scopes = Vec::new();
+    for value in values {
+        let scope = if scope_map.is_empty() {
+            value.parse().ok()
+        } else {
+            scope_map.get(value).copied()
+        };
+        if let Some(scope) = scope.filter(|scope| !scopes.contains(scope)) {
+            scopes.push(scope);
+        }
+    }
+    scopes
+}
+
+/// Parses a `JWT_SCOPE_MAP` such as `leaklens.check=single,leaklens.ops=admin`.
+pub fn parse_scope_map(list: &str) -> Result<HashMap<String, Scope>> {
+    let mut map = HashMap::new();
+    for entry in list.split(',').filter(|e| !e.trim().is_empty()) {
+        let (value, scope) = entry
+            .rsplit_once('=')
+            .ok_or_else(|| anyhow!("Expected <claim value>=<scope>, got '{}'", entry.trim()))?;
+        map.insert(value.trim().to_string(), scope.parse()?);
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_scopes_from_a_space_separated_claim() {
+        let claims = json!({"scope": "openid single profile batch single"});
+        assert_eq!(scopes(&claims, "scope", &HashMap::new()), vec![Scope::Single, Scope::Batch]);
+    }
+
+    #[test]
+    fn test_scopes_from_a_nested_array_through_the_map() {
+        let claims = json!({"realm_access": {"roles": ["leaklens-user", "offline_access", "admin"]}});
+        let map = parse_scope_map("leaklens-user=single, leaklens-batch=batch,leaklens-ops=admin").unwrap();
+
+        // With a map, scope names themselves grant nothing.
+        assert_eq!(scopes(&claims, "realm_access.roles", &map), vec![Scope::Single]);
+        assert!(scopes(&claims, "roles", &map).is_empty());
+    }
+
+    #[test]
+    fn test_parse_scope_map_rejects_bad_entries() {
+        assert!(parse_scope_map("").unwrap().is_empty());
+        assert!(parse_scope_map("group=root").is_err());
+        assert!(parse_scope_map("group").is_err());
+        assert_eq!(parse_scope_map("urn:idp=role=admin").unwrap()["urn:idp=role"], Scope::Admin);
+    }
+
+    #[test]
+    fn test_principal_requires_a_subject() {
+        let config = JwtConfig {
+            jwks: String::new(),
+            issuer: String::new(),
+            audience: String::new(),
+            scope_claim: "scope".to_string(),
+            scope_map: HashMap::new(),
+            leeway_secs: 0,
+        };
+
+        let caller = principal(&json!({"sub": "alice", "scope": "batch"}), &config).unwrap();
+        assert_eq!(caller.subject, "alice");
+        assert!(caller.allows(Scope::Batch));
+        assert!(!caller.allows(Scope::Single));
+
+        assert!(matches!(principal(&json!({"sub": ""}), &config), Err(TokenError::Invalid(_))));
+    }
+}
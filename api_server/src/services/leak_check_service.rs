@@ -1,108 +1,180 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use tracing::{debug, info, error};
-use hex;
-use futures::future;
+use tracing::{debug, error};
+use futures::{stream, StreamExt};
+use tracing::Instrument;
 
-use crate::crypto::ecc_cipher::ECCommutativeCipher;
-use crate::crypto::hashing::scrypt_hash_username_and_password;
+use crate::crypto::key_provider::{key_provider_for_mode, KeyProvider};
+use crate::proto::{LookupSingleLeakRequest, LookupSingleLeakResponse};
+use crate::services::backends::google::GoogleLeakBackend;
+use crate::services::backends::hash_file::HashFileBackend;
+use crate::services::backends::pwned_passwords::PwnedPasswordsBackend;
+use crate::services::backends::{LeakBackend, LeakCheckOutcome};
 use crate::services::google_api_client::GoogleApiClient;
+use crate::services::result_cache::ResultCache;
 use crate::services::token_manager::TokenManager;
+use crate::utils::config;
 
 #[derive(Clone)]
 pub struct LeakCheckService {
-    #[cfg_attr(test, allow(dead_code))]
-    pub(crate) cipher: Arc<Mutex<ECCommutativeCipher>>,
     #[cfg_attr(test, allow(dead_code))]
     pub(crate) api_client: GoogleApiClient,
+    backends: Arc<HashMap<&'static str, Arc<dyn LeakBackend>>>,
+    default_backend: &'static str,
+    result_cache: Option<Arc<ResultCache>>,
 }
 
 impl LeakCheckService {
-    pub fn new(token_manager: TokenManager) -> Self {
-        let fixed_key = [1u8; 32];
-        let cipher = ECCommutativeCipher::new(Some(&fixed_key));
-        let api_client = GoogleApiClient::new(token_manager);
-        
-        LeakCheckService {
-            cipher: Arc::new(Mutex::new(cipher)),
-            api_client,
-        }
+    pub fn new(token_manager: TokenManager) -> Result<Self> {
+        let key_provider = key_provider_for_mode(config::get().keys.mode);
+        Self::with_key_provider(token_manager, Arc::from(key_provider))
     }
     
-    pub async fn check_single_credential(&self, username: &str, password: &str) -> Result<bool> {
-        debug!("Checking credential for {}", username);
-        
-        let lookup_hash = scrypt_hash_username_and_password(username, password)
-            .context("Failed to hash username and password")?;
-        debug!("Scrypt hash: {}", hex::encode(&lookup_hash));
+    pub fn with_key_provider(token_manager: TokenManager, key_provider: Arc<dyn KeyProvider>) -> Result<Self> {
+        let config = config::get();
+        let api_client = GoogleApiClient::new(token_manager);
         
-        let lookup_hash = match lookup_hash.iter().position(|&b| b == 0) {
-            Some(pos) => &lookup_hash[..pos],
-            None => &lookup_hash[..],
-        };
-        debug!("Lookup hash after null strip: {}", hex::encode(lookup_hash));
+        let result_cache = ResultCache::open(&config.result_cache)
+            .context("Failed to open the result cache")?
+            .map(Arc::new);
         
-        let encrypted_lookup_hash = {
-            let cipher = self.cipher.lock().await;
-            cipher.encrypt(lookup_hash)
-                .context("Failed to encrypt lookup hash")?
-        };
-        debug!("Encrypted lookup hash: {}", hex::encode(&encrypted_lookup_hash));
+        let mut backends: Vec<Arc<dyn LeakBackend>> = vec![
+            Arc::new(GoogleLeakBackend::new(api_client.clone(), key_provider, result_cache.clone())),
+            Arc::new(PwnedPasswordsBackend::new(config.backends.hibp_api_url.clone())),
+        ];
         
-        let cipher_clone = {
-            let cipher = self.cipher.lock().await;
-            cipher.clone()
-        };
+        if let Some(path) = &config.backends.hash_file_path {
+            let backend = HashFileBackend::open(path, config.backends.hash_file_format)
+                .with_context(|| format!("Failed to open HASH_FILE_PATH {}", path))?;
+            backends.push(Arc::new(backend));
+        }
         
-        info!("Sending credential check request to Google API for {}", username);
-        self.api_client.check_credential(username, &encrypted_lookup_hash, &cipher_clone).await
+        let mut service = Self::with_backends(api_client, backends, &config.backends.default_backend)?;
+        service.result_cache = result_cache;
+        Ok(service)
     }
     
-    pub async fn check_batch_credentials(&self, credentials: Vec<(String, String)>)
-        -> Result<Vec<(String, String, bool, Option<String>)>> {
+    pub fn with_backends(
+        api_client: GoogleApiClient,
+        backends: Vec<Arc<dyn LeakBackend>>,
+        default_backend: &str,
+    ) -> Result<Self> {
+        let backends: HashMap<_, _> = backends.into_iter().map(|b| (b.name(), b)).collect();
+        let default_backend = backends
+            .get_key_value(default_backend)
+            .map(|(name, _)| *name)
+            .ok_or_else(|| anyhow!("Unknown leak backend '{}'", default_backend))?;
         
-        let mut results = Vec::with_capacity(credentials.len());
+        Ok(LeakCheckService {
+            api_client,
+            backends: Arc::new(backends),
+            default_backend,
+            result_cache: None,
+        })
+    }
+    
+    /// The cache of Google verdicts, unless disabled.
+    pub fn result_cache(&self) -> Option<&ResultCache> {
+        self.result_cache.as_deref()
+    }
+    
+    /// Resolves a backend by name, falling back to the configured default.
+    pub fn backend(&self, name: Option<&str>) -> Result<Arc<dyn LeakBackend>> {
+        let name = name.map(str::trim).filter(|n| !n.is_empty()).unwrap_or(self.default_backend);
+        self.backends
+            .get(name.to_lowercase().as_str())
+            .cloned()
+            .ok_or_else(|| anyhow!("Unknown leak backend '{}'", name))
+    }
+    
+    #[allow(dead_code)] // used by the test_credential_check binary
+    pub async fn check_single_credential(&self, username: &str, password: &str) -> Result<bool> {
+        let outcome = self.backend(None)?.check_credential(username, password).await?;
+        Ok(outcome.is_leaked)
+    }
+    
+    /// Relays a lookup that the client already blinded with its own key. The
+    /// server never sees the password or the lookup hash, and cannot unblind
+    /// the response. `request` must have passed `validate_lookup_request`.
+    pub async fn relay_blinded_lookup(&self, request: LookupSingleLeakRequest) -> Result<LookupSingleLeakResponse> {
+        debug!("Relaying blinded lookup request to Google API");
+        self.api_client.lookup_single_leak(&request).await
+    }
+    
+    /// Checks `credentials` concurrently, up to `UPSTREAM_MAX_CONCURRENCY` at
+    /// a time; Google lookups are paced further by the API client's limiter.
+    /// Results are in the order of `credentials`.
+    pub async fn check_batch_credentials(&self, credentials: Vec<(String, String)>, backend: &Arc<dyn LeakBackend>)
+        -> Result<Vec<(String, String, LeakCheckOutcome, Option<String>)>> {
         
-        let concurrency_limit = 5;
+        let concurrency_limit = config::get().upstream.max_concurrency.max(1);
         
-        let chunks: Vec<_> = credentials.chunks(concurrency_limit).collect();
+        let backend = backend.for_batch().unwrap_or_else(|| backend.clone());
         
-        for chunk in chunks {
-            let futures = chunk.iter().map(|(username, password)| {
-                let username = username.clone();
-                let password = password.clone();
-                let service = self.clone();
-                
-                async move {
-                    match service.check_single_credential(&username, &password).await {
-                        Ok(is_leaked) => {
-                            (
-                                username,
-                                "••••••••".to_string(),
-                                is_leaked,
-                                None
-                            )
-                        },
-                        Err(e) => {
-                            error!("Error checking credential: {}", e);
-                            (
-                                username,
-                                "••••••••".to_string(),
-                                false,
-                                Some(format!("Error: {}", e))
-                            )
-                        }
+        let checks = credentials.into_iter().map(|(username, password)| {
+            let backend = backend.clone();
+            let span = tracing::info_span!("check_credential", backend = backend.name());
+            
+            async move {
+                match backend.check_credential(&username, &password).await {
+                    Ok(outcome) => {
+                        (
+                            username,
+                            "••••••••".to_string(),
+                            outcome,
+                            None
+                        )
+                    },
+                    Err(e) => {
+                        error!("Error checking credential: {}", e);
+                        (
+                            username,
+                            "••••••••".to_string(),
+                            LeakCheckOutcome::default(),
+                            Some(format!("Error: {}", e))
+                        )
                     }
                 }
-            });
-            
-            let chunk_results = future::join_all(futures).await;
-            results.extend(chunk_results);
-            
-            tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
-        }
+            }
+            .instrument(span)
+        });
         
-        Ok(results)
+        Ok(stream::iter(checks).buffered(concurrency_limit).collect().await)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service_with_hibp_default() -> Result<LeakCheckService> {
+        config::init_for_tests();
+        LeakCheckService::with_backends(
+            GoogleApiClient::new(TokenManager::new()),
+            vec![Arc::new(PwnedPasswordsBackend::new("http://127.0.0.1:9"))],
+            "hibp",
+        )
+    }
+
+    #[test]
+    fn test_backend_selection() {
+        let service = service_with_hibp_default().unwrap();
+
+        assert_eq!(service.backend(None).unwrap().name(), "hibp");
+        assert_eq!(service.backend(Some("")).unwrap().name(), "hibp");
+        assert_eq!(service.backend(Some(" HIBP ")).unwrap().name(), "hibp");
+        assert!(service.backend(Some("google")).is_err());
+    }
+
+    #[test]
+    fn test_unknown_default_backend_is_rejected() {
+        config::init_for_tests();
+        let result = LeakCheckService::with_backends(
+            GoogleApiClient::new(TokenManager::new()),
+            vec![Arc::new(PwnedPasswordsBackend::new("http://127.0.0.1:9"))],
+            "nope",
+        );
+        assert!(result.is_err());
+    }
+}
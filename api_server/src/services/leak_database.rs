@@ -0,0 +1,176 @@
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::thread;
+use tracing::{debug, info};
+
+use crate::crypto::check_google_api::{blinding_input, process_credential, validate_lookup_request};
+use crate::crypto::ecc_cipher::ECCommutativeCipher;
+use crate::crypto::hashing::{scrypt_hash_username_and_password, username_hash_prefix};
+use crate::proto::{LookupSingleLeakRequest, LookupSingleLeakResponse};
+
+/// Number of bytes of SHA-256(encrypted lookup hash) returned per candidate.
+pub const LEAK_MATCH_PREFIX_LENGTH: usize = 20;
+
+/// Server side of `PasswordLeakCheckService`: a breach corpus bucketed by the
+/// 26-bit username hash prefix, with every entry already encrypted under the
+/// database's own key. Answers `LookupSingleLeakRequest`s the same way Google
+/// does, so `GoogleApiClient` can be pointed at it through `GOOGLE_API_URL`.
+#[allow(dead_code)]
+pub struct LeakDatabase {
+    cipher: ECCommutativeCipher,
+    buckets: HashMap<Vec<u8>, Vec<Vec<u8>>>,
+    entries: usize,
+}
+
+#[allow(dead_code)]
+impl LeakDatabase {
+    pub fn new(cipher: ECCommutativeCipher) -> Self {
+        LeakDatabase {
+            cipher,
+            buckets: HashMap::new(),
+            entries: 0,
+        }
+    }
+
+    /// Number of credentials in the database.
+    pub fn len(&self) -> usize {
+        self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries == 0
+    }
+
+    /// Adds one leaked credential.
+    pub fn insert(&mut self, username: &str, password: &str) -> Result<()> {
+        let (prefix, match_prefix) = self.precompute(username, password)?;
+        self.add_entry(prefix, match_prefix);
+        Ok(())
+    }
+
+    /// Adds a whole corpus. scrypt dominates ingestion, so the work is spread
+    /// over all available cores.
+    pub fn ingest(&mut self, credentials: &[(String, String)]) -> Result<()> {
+        let workers = thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk_size = credentials.len().div_ceil(workers).max(1);
+
+        let database = &*self;
+        let results = thread::scope(|scope| {
+            let handles: Vec<_> = credentials
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|(username, password)| database.precompute(username, password))
+                            .collect::<Result<Vec<_>>>()
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|handle| handle.join().map_err(|_| anyhow!("Ingestion worker panicked"))?)
+                .collect::<Result<Vec<_>>>()
+        })?;
+
+        for (prefix, match_prefix) in results.into_iter().flatten() {
+            self.add_entry(prefix, match_prefix);
+        }
+
+        info!("Leak database holds {} credentials in {} buckets", self.entries, self.buckets.len());
+        Ok(())
+    }
+
+    /// Re-encrypts the client's blinded lookup hash under our key and returns
+    /// the match prefixes of every credential sharing its username bucket.
+    pub fn lookup(&self, request: &LookupSingleLeakRequest) -> Result<LookupSingleLeakResponse> {
+        validate_lookup_request(request)?;
+
+        let reencrypted_lookup_hash = self.cipher.re_encrypt(&request.encrypted_lookup_hash)?;
+        let encrypted_leak_match_prefix = self
+            .buckets
+            .get(&request.username_hash_prefix)
+            .cloned()
+            .unwrap_or_default();
+
+        debug!(
+            "Lookup for bucket {} returned {} candidates",
+            hex::encode(&request.username_hash_prefix),
+            encrypted_leak_match_prefix.len()
+        );
+
+        Ok(LookupSingleLeakResponse {
+            encrypted_leak_match_prefix,
+            reencrypted_lookup_hash,
+        })
+    }
+
+    fn precompute(&self, username: &str, password: &str) -> Result<(Vec<u8>, Vec<u8>)> {
+        let (username, password) = process_credential(username, password);
+        let prefix = username_hash_prefix(&username);
+
+        let lookup_hash = scrypt_hash_username_and_password(&username, &password)?;
+        let encrypted = self.cipher.encrypt(blinding_input(&lookup_hash))?;
+
+        let digest = Sha256::digest(&encrypted);
+        Ok((prefix, digest[..LEAK_MATCH_PREFIX_LENGTH].to_vec()))
+    }
+
+    fn add_entry(&mut self, prefix: Vec<u8>, match_prefix: Vec<u8>) {
+        let bucket = self.buckets.entry(prefix).or_default();
+        if !bucket.contains(&match_prefix) {
+            bucket.push(match_prefix);
+            self.entries += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::check_google_api::{create_lookup_request, unblind_and_check};
+
+    fn database() -> LeakDatabase {
+        let mut database = LeakDatabase::new(ECCommutativeCipher::new(None));
+        database
+            .ingest(&[
+                ("alice@example.com".to_string(), "hunter2".to_string()),
+                ("bob".to_string(), "correct horse".to_string()),
+                ("bob@example.org".to_string(), "correct horse".to_string()),
+            ])
+            .unwrap();
+        database
+    }
+
+    fn check(database: &LeakDatabase, username: &str, password: &str) -> bool {
+        let client = ECCommutativeCipher::new(None);
+        let request = create_lookup_request(username, password, &client).unwrap();
+        let response = database.lookup(&request).unwrap();
+        unblind_and_check(&response, &client).unwrap()
+    }
+
+    #[test]
+    fn test_lookup_round_trip() {
+        let database = database();
+        // bob and bob@example.org canonicalise to the same credential
+        assert_eq!(database.len(), 2);
+
+        assert!(check(&database, "alice@example.com", "hunter2"));
+        assert!(check(&database, "alice", "hunter2"));
+        assert!(check(&database, "bob@example.net", "correct horse"));
+
+        assert!(!check(&database, "alice", "hunter3"));
+        assert!(!check(&database, "carol", "hunter2"));
+    }
+
+    #[test]
+    fn test_lookup_rejects_malformed_request() {
+        let database = database();
+        let client = ECCommutativeCipher::new(None);
+        let mut request = create_lookup_request("alice", "hunter2", &client).unwrap();
+        request.encrypted_lookup_hash.truncate(10);
+        assert!(database.lookup(&request).is_err());
+    }
+}
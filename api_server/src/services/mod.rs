@@ -1,3 +1,12 @@
+pub mod api_keys;
+pub mod backends;
+pub mod batch_input;
 pub mod google_api_client;
+pub mod job_store;
+pub mod jwt;
 pub mod leak_check_service;
-pub mod token_manager;
\ No newline at end of file
+pub mod leak_database;
+pub mod result_cache;
+pub mod token_manager;
+pub mod upstream;
+pub mod webhooks;
\ No newline at end of file
@@ -0,0 +1,299 @@
+//! Verdicts of recent Google lookups, so that a credential checked again
+//! within the TTL skips the round trip to Google.
+//!
+//! Entries are keyed by an HMAC of the scrypt lookup hash under
+//! `RESULT_CACHE_KEY`: neither the cache nor its database file holds anything
+//! that could be matched against a credential without that key. Leaked
+//! verdicts are kept for `ttl_secs`; not leaked ones for the usually shorter
+//! `negative_ttl_secs`, since a new breach can turn them. When full, the
+//! oldest entries are evicted first.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use rusqlite::{params, Connection};
+use sha2::Sha256;
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+use crate::utils::config::ResultCacheConfig;
+use crate::utils::metrics;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS cached_results (
+        key        BLOB PRIMARY KEY,
+        is_leaked  INTEGER NOT NULL,
+        expires_at INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS cached_results_expiry ON cached_results (expires_at);
+";
+
+/// HMAC-SHA256 of a lookup hash.
+pub type CacheKey = [u8; 32];
+
+struct Entry {
+    is_leaked: bool,
+    /// Unix time in seconds.
+    expires_at: i64,
+    /// Position in `State::order`.
+    seq: u64,
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<CacheKey, Entry>,
+    /// Keys in insertion order. A key inserted again is queued again; its
+    /// older position is skipped when it comes up for eviction.
+    order: VecDeque<(CacheKey, u64)>,
+    next_seq: u64,
+}
+
+pub struct ResultCache {
+    key: Vec<u8>,
+    ttl_secs: u64,
+    negative_ttl_secs: u64,
+    max_entries: usize,
+    state: Mutex<State>,
+    database: Option<Arc<Mutex<Connection>>>,
+}
+
+impl ResultCache {
+    /// An in-memory cache, or `None` when `max_entries` is 0.
+    pub fn new(config: &ResultCacheConfig) -> Option<Self> {
+        if config.max_entries == 0 {
+            return None;
+        }
+
+        let key = match &config.key {
+            Some(key) => key.as_bytes().to_vec(),
+            None => {
+                let mut key = vec![0; 32];
+                rand::thread_rng().fill_bytes(&mut key);
+                key
+            }
+        };
+        Some(ResultCache {
+            key,
+            ttl_secs: config.ttl_secs,
+            negative_ttl_secs: config.negative_ttl_secs,
+            max_entries: config.max_entries,
+            state: Mutex::new(State::default()),
+            database: None,
+        })
+    }
+
+    /// Like `new`, but keeps the entries in the SQLite file at
+    /// `config.sqlite_path`, when set, and loads the unexpired ones back.
+    pub fn open(config: &ResultCacheConfig) -> Result<Option<Self>> {
+        let Some(mut cache) = Self::new(config) else { return Ok(None) };
+        let Some(path) = &config.sqlite_path else { return Ok(Some(cache)) };
+        if config.key.is_none() {
+            return Err(anyhow!("A key is required to persist the result cache"));
+        }
+
+        let path = Path::new(path);
+        let connection = Connection::open(path)
+            .with_context(|| format!("Failed to open result cache database {}", path.display()))?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.execute_batch(SCHEMA).context("Failed to create the result cache table")?;
+        connection.execute("DELETE FROM cached_results WHERE expires_at <= ?1", params![Utc::now().timestamp()])?;
+
+        let rows = connection
+            .prepare("SELECT key, is_leaked, expires_at FROM cached_results ORDER BY rowid")?
+            .query_map([], |row| Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, bool>(1)?, row.get::<_, i64>(2)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+        {
+            let state = cache.state.get_mut().map_err(|_| anyhow!("Result cache lock poisoned"))?;
+            for (key, is_leaked, expires_at) in rows.into_iter().rev().take(cache.max_entries).rev() {
+                if let Ok(key) = CacheKey::try_from(key.as_slice()) {
+                    push(state, key, is_leaked, expires_at);
+                }
+            }
+            info!("Persisting the result cache in {}, {} entries loaded", path.display(), state.entries.len());
+        }
+
+        cache.database = Some(Arc::new(Mutex::new(connection)));
+        Ok(Some(cache))
+    }
+
+    /// The cache key of a lookup hash.
+    pub fn key(&self, lookup_hash: &[u8]) -> CacheKey {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(lookup_hash);
+        mac.finalize().into_bytes().into()
+    }
+
+    /// The cached verdict for `key`, if it has not expired.
+    pub fn get(&self, key: &CacheKey) -> Option<bool> {
+        let verdict = self.get_at(key, Utc::now().timestamp());
+        metrics::RESULT_CACHE_LOOKUPS.inc(&[if verdict.is_some() { "hit" } else { "miss" }]);
+        verdict
+    }
+
+    fn get_at(&self, key: &CacheKey, now: i64) -> Option<bool> {
+        let mut state = self.state.lock().unwrap();
+        let entry = state.entries.get(key)?;
+        if entry.expires_at > now {
+            return Some(entry.is_leaked);
+        }
+
+        state.entries.remove(key);
+        metrics::RESULT_CACHE_EVICTIONS.inc(&["expired"]);
+        None
+    }
+
+    /// Caches a verdict Google just returned, writing it through to the
+    /// database when there is one. Failing to persist it is only logged.
+    pub async fn insert(&self, key: CacheKey, is_leaked: bool) {
+        let now = Utc::now().timestamp();
+        let Some((expires_at, evicted)) = self.insert_at(key, is_leaked, now) else { return };
+        let Some(database) = self.database.clone() else { return };
+
+        let result = tokio::task::spawn_blocking(move || -> Result<()> {
+            let mut connection = database.lock().map_err(|_| anyhow!("Result cache database lock poisoned"))?;
+            let tx = connection.transaction()?;
+            tx.execute(
+                "INSERT OR REPLACE INTO cached_results (key, is_leaked, expires_at) VALUES (?1, ?2, ?3)",
+                params![key.as_slice(), is_leaked, expires_at],
+            )?;
+            for key in evicted {
+                tx.execute("DELETE FROM cached_results WHERE key = ?1", params![key.as_slice()])?;
+            }
+            tx.execute("DELETE FROM cached_results WHERE expires_at <= ?1", params![now])?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await;
+
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("Failed to persist a cached result: {:#}", e),
+            Err(e) => warn!("Failed to persist a cached result: {}", e),
+        }
+    }
+
+    /// Returns when the entry expires and the keys evicted to make room, or
+    /// `None` when verdicts like this one are not cached.
+    fn insert_at(&self, key: CacheKey, is_leaked: bool, now: i64) -> Option<(i64, Vec<CacheKey>)> {
+        let ttl = if is_leaked { self.ttl_secs } else { self.negative_ttl_secs };
+        if ttl == 0 {
+            return None;
+        }
+        let expires_at = now.saturating_add(ttl as i64);
+
+        let mut state = self.state.lock().unwrap();
+        let mut evicted = Vec::new();
+        while !state.entries.contains_key(&key) && state.entries.len() >= self.max_entries {
+            let Some((oldest, seq)) = state.order.pop_front() else { break };
+            if state.entries.get(&oldest).is_some_and(|entry| entry.seq == seq) {
+                state.entries.remove(&oldest);
+                metrics::RESULT_CACHE_EVICTIONS.inc(&["capacity"]);
+                evicted.push(oldest);
+            }
+        }
+        push(&mut state, key, is_leaked, expires_at);
+
+        if state.order.len() > 2 * self.max_entries {
+            let State { entries, order, .. } = &mut *state;
+            order.retain(|(key, seq)| entries.get(key).is_some_and(|entry| entry.seq == *seq));
+        }
+        Some((expires_at, evicted))
+    }
+
+    /// Entries held, leaked and not leaked, expired ones included until
+    /// they are next looked up.
+    pub fn counts(&self) -> (usize, usize) {
+        let state = self.state.lock().unwrap();
+        let leaked = state.entries.values().filter(|entry| entry.is_leaked).count();
+        (leaked, state.entries.len() - leaked)
+    }
+}
+
+fn push(state: &mut State, key: CacheKey, is_leaked: bool, expires_at: i64) {
+    let seq = state.next_seq;
+    state.next_seq += 1;
+    state.entries.insert(key, Entry { is_leaked, expires_at, seq });
+    state.order.push_back((key, seq));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_support::TempDatabase;
+
+    fn config(max_entries: usize) -> ResultCacheConfig {
+        ResultCacheConfig {
+            max_entries,
+            ttl_secs: 100,
+            negative_ttl_secs: 10,
+            key: Some("cache-key".to_string()),
+            sqlite_path: None,
+        }
+    }
+
+    #[test]
+    fn test_keys_are_keyed_hmacs() {
+        let cache = ResultCache::new(&config(10)).unwrap();
+        let key = cache.key(b"lookup hash");
+        assert_eq!(key, cache.key(b"lookup hash"));
+        assert_ne!(key, cache.key(b"other hash"));
+
+        let other = ResultCache::new(&ResultCacheConfig { key: Some("other-key".to_string()), ..config(10) }).unwrap();
+        assert_ne!(key, other.key(b"lookup hash"));
+        assert!(ResultCache::new(&config(0)).is_none());
+    }
+
+    #[test]
+    fn test_verdicts_expire_after_their_ttl() {
+        let cache = ResultCache::new(&config(10)).unwrap();
+        let (leaked, clean) = (cache.key(b"leaked"), cache.key(b"clean"));
+        cache.insert_at(leaked, true, 1000);
+        cache.insert_at(clean, false, 1000);
+
+        assert_eq!(cache.get_at(&leaked, 1009), Some(true));
+        assert_eq!(cache.get_at(&clean, 1009), Some(false));
+        assert_eq!(cache.get_at(&clean, 1010), None);
+        assert_eq!(cache.get_at(&leaked, 1099), Some(true));
+        assert_eq!(cache.get_at(&leaked, 1100), None);
+        assert_eq!(cache.counts(), (0, 0));
+
+        let no_negatives = ResultCache::new(&ResultCacheConfig { negative_ttl_secs: 0, ..config(10) }).unwrap();
+        assert!(no_negatives.insert_at(clean, false, 1000).is_none());
+        assert_eq!(no_negatives.get_at(&clean, 1000), None);
+    }
+
+    #[test]
+    fn test_oldest_entries_are_evicted_when_full() {
+        let cache = ResultCache::new(&config(2)).unwrap();
+        let keys: Vec<_> = (0u8..3).map(|i| cache.key(&[i])).collect();
+        cache.insert_at(keys[0], true, 0);
+        cache.insert_at(keys[1], false, 0);
+        let (_, evicted) = cache.insert_at(keys[2], true, 0).unwrap();
+
+        assert_eq!(evicted, vec![keys[0]]);
+        assert_eq!(cache.get_at(&keys[0], 1), None);
+        assert_eq!(cache.get_at(&keys[1], 1), Some(false));
+        assert_eq!(cache.get_at(&keys[2], 1), Some(true));
+        assert_eq!(cache.counts(), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_persisted_entries_survive_a_restart() {
+        let database = TempDatabase::new("cache");
+        let config = ResultCacheConfig { sqlite_path: Some(database.0.display().to_string()), ..config(10) };
+
+        let cache = ResultCache::open(&config).unwrap().unwrap();
+        let key = cache.key(b"lookup hash");
+        cache.insert(key, true).await;
+        drop(cache);
+
+        let reopened = ResultCache::open(&config).unwrap().unwrap();
+        assert_eq!(reopened.get(&key), Some(true));
+        assert!(ResultCache::open(&ResultCacheConfig { key: None, ..config.clone() }).is_err());
+        let unreachable = database.0.join("cache.sqlite3").display().to_string();
+        assert!(ResultCache::open(&ResultCacheConfig { sqlite_path: Some(unreachable), ..config }).is_err());
+    }
+}
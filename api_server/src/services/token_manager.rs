@@ -4,18 +4,19 @@ use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
-use tracing::{debug, info, warn, error};
+use tracing::{debug, info, warn, error, Instrument};
 
-use crate::utils::config;
+use crate::utils::{config, metrics};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// No `Debug`, so the access token cannot end up in a log line.
+#[derive(Clone, Serialize, Deserialize)]
 struct TokenResponse {
     access_token: String,
     expires_in: u64,
     token_type: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct TokenManager {
     client: Client,
     token_cache: Arc<Mutex<Option<(String, Instant)>>>,
@@ -23,10 +24,7 @@ pub struct TokenManager {
 
 impl TokenManager {
     pub fn new() -> Self {
-        TokenManager {
-            client: Client::new(),
-            token_cache: Arc::new(Mutex::new(None)),
-        }
+        Self::default()
     }
 
     pub async fn get_token(&self) -> Result<String> {
@@ -42,7 +40,12 @@ impl TokenManager {
         }
 
         debug!("Fetching new OAuth token");
-        
+        let result = self.fetch_token().instrument(tracing::info_span!("token_refresh")).await;
+        metrics::TOKEN_REFRESHES.inc(&[if result.is_ok() { "success" } else { "failure" }]);
+        result
+    }
+
+    async fn fetch_token(&self) -> Result<String> {
         let config = config::get();
         
         let client_id = config.google_api.client_id.clone();
@@ -100,14 +103,13 @@ impl TokenManager {
                 Ok(token_response_val.access_token)
             }
             Err(e) => {
-                let body_text = String::from_utf8_lossy(&response_body_bytes);
+                // A successful response may hold a token even when it does
+                // not parse, so its body is never logged.
                 error!(
-                    "Failed to parse TokenResponse JSON from Google (Status: {}). Body: '{}', Error: {}",
-                    response_status, body_text, e
+                    "Failed to parse TokenResponse JSON from Google (Status: {}, {} bytes). Error: {}",
+                    response_status, response_body_bytes.len(), e
                 );
-                Err(anyhow!(
-                    "Failed to parse token data from Google: {}. Response body snippet: '{}'", e, body_text.chars().take(500).collect::<String>()
-                ))
+                Err(anyhow!("Failed to parse token data from Google: {}", e))
             }
         }
     }
@@ -129,6 +131,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_token_manager_cache() {
+        crate::utils::config::init_for_tests();
         let manager = TokenManager::new();
         
         {
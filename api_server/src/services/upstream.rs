@@ -0,0 +1,292 @@
+//! Pacing of lookups sent to Google's leak check API.
+//!
+//! `AdaptiveLimiter` caps how many lookups are in flight, AIMD style: each
+//! lookup answered within the target latency raises the cap by `1 / cap`, so
+//! by about one per round of lookups at the cap. A 429 or 503 halves it and a
+//! slow answer lowers it by a tenth; answers to lookups sent before the last
+//! decrease are ignored, so one burst of rejections only counts once. A
+//! `Retry-After` from Google holds every new lookup until it has passed.
+//!
+//! Lookups that failed in a way that may not last, see
+//! [`UpstreamError::is_transient`], are retried after [`retry_delay`].
+
+use chrono::DateTime;
+use rand::Rng;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::StatusCode;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::Notify;
+
+use crate::utils::config::UpstreamConfig;
+
+/// Factor the cap is multiplied by when Google pushes back.
+const OVERLOAD_DECREASE: f64 = 0.5;
+
+/// Factor the cap is multiplied by after an answer slower than the target.
+const LATENCY_DECREASE: f64 = 0.9;
+
+/// A lookup that Google did not answer with a leak check response.
+#[derive(Debug, Error)]
+#[error("API request failed: {message}")]
+pub struct UpstreamError {
+    /// `None` when no response arrived.
+    pub status: Option<StatusCode>,
+    pub retry_after: Option<Duration>,
+    message: String,
+}
+
+impl UpstreamError {
+    pub fn new(status: Option<StatusCode>, retry_after: Option<Duration>, message: impl Into<String>) -> Self {
+        UpstreamError { status, retry_after, message: message.into() }
+    }
+
+    /// Google was unreachable, timed out, asked to slow down or failed on
+    /// its side.
+    pub fn is_transient(&self) -> bool {
+        match self.status {
+            None => true,
+            Some(status) => status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+
+    /// Google asked for fewer lookups.
+    pub fn is_overload(&self) -> bool {
+        matches!(self.status, Some(StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE))
+    }
+}
+
+/// `Retry-After` as a number of seconds or an HTTP date.
+pub fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let at = DateTime::parse_from_rfc2822(value).ok()?;
+    (at.timestamp_millis() - chrono::Utc::now().timestamp_millis())
+        .try_into()
+        .ok()
+        .map(Duration::from_millis)
+}
+
+/// Wait before retrying after failed attempt number `attempt`: between half
+/// and all of `base` doubled for every earlier attempt, at most `max`.
+pub fn retry_delay(base: Duration, max: Duration, attempt: u32) -> Duration {
+    let ceiling = base.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1))).min(max);
+    let half = ceiling / 2;
+    half + rand::thread_rng().gen_range(Duration::ZERO..=half)
+}
+
+/// What an attempt says about Google's capacity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    Answered,
+    Overloaded,
+    /// Failed for reasons unrelated to load.
+    Failed,
+}
+
+#[derive(Debug)]
+struct State {
+    limit: f64,
+    in_flight: usize,
+    paused_until: Option<Instant>,
+    last_decrease: Option<Instant>,
+}
+
+#[derive(Debug)]
+pub struct AdaptiveLimiter {
+    min: f64,
+    max: f64,
+    target_latency: Duration,
+    state: Mutex<State>,
+    released: Notify,
+}
+
+impl AdaptiveLimiter {
+    pub fn new(config: &UpstreamConfig) -> Self {
+        let max = config.max_concurrency.max(1) as f64;
+        AdaptiveLimiter {
+            min: 1.0,
+            max,
+            target_latency: Duration::from_millis(config.target_latency_ms),
+            state: Mutex::new(State {
+                limit: (config.initial_concurrency as f64).clamp(1.0, max),
+                in_flight: 0,
+                paused_until: None,
+                last_decrease: None,
+            }),
+            released: Notify::new(),
+        }
+    }
+
+    /// Waits for a free slot, and for any `Retry-After` pause to pass.
+    pub async fn acquire(&self) -> Permit<'_> {
+        loop {
+            let released = self.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+
+            let paused_until = {
+                let mut state = self.state.lock().unwrap();
+                match state.paused_until {
+                    Some(until) if until > Instant::now() => Some(until),
+                    _ => {
+                        state.paused_until = None;
+                        if state.in_flight < state.limit as usize {
+                            state.in_flight += 1;
+                            return Permit { limiter: self, started: Instant::now() };
+                        }
+                        None
+                    }
+                }
+            };
+
+            match paused_until {
+                Some(until) => tokio::time::sleep_until(until.into()).await,
+                None => released.await,
+            }
+        }
+    }
+
+    /// Holds every new lookup for `delay`, unless already held for longer.
+    pub fn pause(&self, delay: Duration) {
+        let until = Instant::now() + delay;
+        let mut state = self.state.lock().unwrap();
+        if state.paused_until.map_or(true, |current| current < until) {
+            state.paused_until = Some(until);
+        }
+    }
+
+    /// The current cap and the lookups in flight.
+    pub fn snapshot(&self) -> (usize, usize) {
+        let state = self.state.lock().unwrap();
+        (state.limit as usize, state.in_flight)
+    }
+
+    fn record(&self, started: Instant, signal: Signal) {
+        let mut state = self.state.lock().unwrap();
+        let factor = match signal {
+            Signal::Answered if started.elapsed() <= self.target_latency => {
+                state.limit = (state.limit + 1.0 / state.limit).min(self.max);
+                drop(state);
+                self.released.notify_waiters();
+                return;
+            }
+            Signal::Answered => LATENCY_DECREASE,
+            Signal::Overloaded => OVERLOAD_DECREASE,
+            Signal::Failed => return,
+        };
+
+        if state.last_decrease.is_some_and(|last| started < last) {
+            return;
+        }
+        state.limit = (state.limit * factor).max(self.min);
+        state.last_decrease = Some(Instant::now());
+    }
+}
+
+/// A slot for one lookup, given back when dropped.
+pub struct Permit<'a> {
+    limiter: &'a AdaptiveLimiter,
+    started: Instant,
+}
+
+impl Permit<'_> {
+    /// Adjusts the cap to how the lookup went, and gives the slot back.
+    pub fn finish(self, signal: Signal) {
+        self.limiter.record(self.started, signal);
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.limiter.state.lock().unwrap().in_flight -= 1;
+        self.limiter.released.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn limiter(initial: usize, max: usize) -> AdaptiveLimiter {
+        AdaptiveLimiter::new(&UpstreamConfig {
+            max_concurrency: max,
+            initial_concurrency: initial,
+            target_latency_ms: 1000,
+            max_attempts: 3,
+            retry_base_delay_ms: 100,
+            max_retry_delay_ms: 1000,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_limit_grows_additively_and_halves_on_overload() {
+        let limiter = limiter(2, 4);
+        for _ in 0..3 {
+            limiter.acquire().await.finish(Signal::Answered);
+        }
+        assert_eq!(limiter.snapshot(), (3, 0));
+        for _ in 0..20 {
+            limiter.acquire().await.finish(Signal::Answered);
+        }
+        assert_eq!(limiter.snapshot(), (4, 0));
+
+        // Rejections of lookups sent under the old cap only count once.
+        let permits = [limiter.acquire().await, limiter.acquire().await, limiter.acquire().await];
+        assert_eq!(limiter.snapshot(), (4, 3));
+        for permit in permits {
+            permit.finish(Signal::Overloaded);
+        }
+        assert_eq!(limiter.snapshot(), (2, 0));
+
+        limiter.acquire().await.finish(Signal::Overloaded);
+        limiter.acquire().await.finish(Signal::Failed);
+        assert_eq!(limiter.snapshot(), (1, 0));
+        limiter.acquire().await.finish(Signal::Overloaded);
+        assert_eq!(limiter.snapshot(), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn test_acquire_waits_for_a_free_slot_and_pauses() {
+        let limiter = limiter(1, 1);
+        let held = limiter.acquire().await;
+        assert!(tokio::time::timeout(Duration::from_millis(50), limiter.acquire()).await.is_err());
+        drop(held);
+        drop(tokio::time::timeout(Duration::from_millis(50), limiter.acquire()).await.unwrap());
+
+        limiter.pause(Duration::from_millis(100));
+        let started = Instant::now();
+        drop(limiter.acquire().await);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_retry_delays_and_classification() {
+        let (base, max) = (Duration::from_millis(100), Duration::from_millis(1000));
+        for attempt in 1..=6 {
+            let ceiling = (base * 2u32.pow(attempt - 1)).min(max);
+            let delay = retry_delay(base, max, attempt);
+            assert!(delay >= ceiling / 2 && delay <= ceiling, "{:?} for attempt {}", delay, attempt);
+        }
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(parse_retry_after(&headers), None);
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("3"));
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(3)));
+        let soon = (chrono::Utc::now() + chrono::Duration::seconds(30)).to_rfc2822();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(&soon).unwrap());
+        assert!(parse_retry_after(&headers).is_some_and(|wait| wait > Duration::from_secs(25)));
+
+        let overloaded = UpstreamError::new(Some(StatusCode::TOO_MANY_REQUESTS), None, "429");
+        assert!(overloaded.is_transient() && overloaded.is_overload());
+        assert!(UpstreamError::new(None, None, "timed out").is_transient());
+        assert!(UpstreamError::new(Some(StatusCode::BAD_GATEWAY), None, "502").is_transient());
+        assert!(!UpstreamError::new(Some(StatusCode::BAD_REQUEST), None, "400").is_transient());
+        assert!(!UpstreamError::new(Some(StatusCode::FORBIDDEN), None, "403").is_overload());
+    }
+}
@@ -0,0 +1,391 @@
+//! Signed callbacks sent when a batch job finishes or is abandoned.
+//!
+//! Each delivery is a JSON `POST` signed with HMAC-SHA256 over
+//! `"{timestamp}.{body}"`, sent as `X-LeakLens-Signature: t=<unix>,v1=<hex>`.
+//! Failed deliveries are retried with exponential backoff and every attempt is
+//! recorded in the job's delivery log.
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::{Client, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::models::request_models::CredentialCheckResult;
+use crate::models::response_models::BatchCheckSummary;
+use crate::utils::config::WebhookConfig;
+
+pub const SIGNATURE_HEADER: &str = "X-LeakLens-Signature";
+pub const EVENT_HEADER: &str = "X-LeakLens-Event";
+pub const DELIVERY_HEADER: &str = "X-LeakLens-Delivery";
+
+/// Longest wait between two attempts of one delivery.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// Where and how to notify a job's client.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WebhookTarget {
+    pub url: String,
+    /// HMAC key the receiver verifies signatures with.
+    pub secret: String,
+    /// Send every result along with the summary.
+    pub include_results: bool,
+}
+
+impl fmt::Debug for WebhookTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebhookTarget")
+            .field("url", &self.url)
+            .field("secret", &"<redacted>")
+            .field("include_results", &self.include_results)
+            .finish()
+    }
+}
+
+impl WebhookTarget {
+    /// Validates a callback URL given with an upload. Without a per-job
+    /// secret the configured `WEBHOOK_SECRET` signs the deliveries.
+    pub fn new(url: &str, secret: Option<String>, include_results: bool, config: &WebhookConfig) -> Result<Self> {
+        let parsed = Url::parse(url.trim()).map_err(|e| anyhow!("Invalid callback_url: {}", e))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            bail!("callback_url must be an http or https URL");
+        }
+        if !config.allow_private_targets && is_private_host(&parsed) {
+            bail!("callback_url must not point at a private or loopback address");
+        }
+
+        let secret = secret
+            .filter(|s| !s.is_empty())
+            .or_else(|| config.secret.clone())
+            .ok_or_else(|| anyhow!("callback_url needs a callback_secret, as no WEBHOOK_SECRET is configured"))?;
+
+        Ok(WebhookTarget {
+            url: parsed.to_string(),
+            secret,
+            include_results,
+        })
+    }
+}
+
+/// Catches literal addresses and `localhost` up front. Other hostnames are
+/// checked by `PublicResolver` each time a delivery connects.
+fn is_private_host(url: &Url) -> bool {
+    let Some(host) = url.host_str() else { return true };
+    let host = host.trim_end_matches('.');
+    if host.eq_ignore_ascii_case("localhost") || host.to_ascii_lowercase().ends_with(".localhost") {
+        return true;
+    }
+
+    match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        Ok(ip) => is_private_ip(ip),
+        Err(_) => false,
+    }
+}
+
+fn is_private_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // Carrier-grade NAT (100.64.0.0/10).
+                || (octets[0] == 100 && (octets[1] & 0xc0) == 64)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(mapped) = ip.to_ipv4_mapped() {
+                return is_private_ip(IpAddr::V4(mapped));
+            }
+            let segments = ip.segments();
+            ip.is_loopback()
+                || ip.is_unspecified()
+                // Unique local (fc00::/7) and link-local (fe80::/10) ranges.
+                || (segments[0] & 0xfe00) == 0xfc00
+                || (segments[0] & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+/// Looks up a callback host and refuses it if any of its addresses is
+/// private, so a public name cannot be pointed at internal services.
+async fn resolve_public(host: &str) -> Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, 0)).await?.collect();
+    if let Some(addr) = addrs.iter().find(|addr| is_private_ip(addr.ip())) {
+        bail!("{} resolves to the private address {}", host, addr.ip());
+    }
+    Ok(addrs)
+}
+
+/// DNS resolver of the webhook client unless `WEBHOOK_ALLOW_PRIVATE` is set.
+/// Resolving on every connection also covers records changed after the
+/// callback URL was accepted.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs = resolve_public(name.as_str()).await?;
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum WebhookEvent {
+    #[serde(rename = "job.completed")]
+    Completed,
+    /// The job stopped on an error.
+    #[serde(rename = "job.failed")]
+    Failed,
+    #[serde(rename = "job.cancelled")]
+    Cancelled,
+    /// No client is listening; the job waits until one polls or resumes it.
+    #[serde(rename = "job.abandoned")]
+    Abandoned,
+}
+
+impl WebhookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::Completed => "job.completed",
+            WebhookEvent::Failed => "job.failed",
+            WebhookEvent::Cancelled => "job.cancelled",
+            WebhookEvent::Abandoned => "job.abandoned",
+        }
+    }
+}
+
+/// Body of a delivery.
+#[derive(Debug, Serialize)]
+pub struct WebhookPayload {
+    pub event: WebhookEvent,
+    pub job_id: String,
+    pub timestamp: DateTime<Utc>,
+    pub summary: BatchCheckSummary,
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub results: Option<Vec<CredentialCheckResult>>,
+}
+
+/// One attempt to deliver an event.
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookDelivery {
+    /// Shared by all attempts of one event; sent as `X-LeakLens-Delivery`.
+    pub delivery_id: String,
+    pub event: WebhookEvent,
+    pub attempt: u32,
+    pub attempted_at: DateTime<Utc>,
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+    pub duration_ms: u64,
+    pub delivered: bool,
+}
+
+pub type DeliveryLog = Arc<Mutex<Vec<WebhookDelivery>>>;
+
+/// `t=<timestamp>,v1=<hex HMAC-SHA256 of "{timestamp}.{body}">`.
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("t={},v1={}", timestamp, hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Whether a failed attempt is worth repeating: the receiver was unreachable,
+/// timed out, asked to slow down or failed on its side.
+fn is_retryable(status: Option<StatusCode>) -> bool {
+    match status {
+        None => true,
+        Some(status) => {
+            status.is_server_error() || status == StatusCode::REQUEST_TIMEOUT || status == StatusCode::TOO_MANY_REQUESTS
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookSender {
+    client: Client,
+    max_attempts: u32,
+    retry_base_delay: Duration,
+}
+
+impl WebhookSender {
+    pub fn new(config: &WebhookConfig) -> Self {
+        let mut builder = Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .redirect(reqwest::redirect::Policy::none());
+        if !config.allow_private_targets {
+            builder = builder.dns_resolver(Arc::new(PublicResolver));
+        }
+        let client = builder.build().unwrap_or_default();
+
+        WebhookSender {
+            client,
+            max_attempts: config.max_attempts.max(1),
+            retry_base_delay: Duration::from_millis(config.retry_base_delay_ms),
+        }
+    }
+
+    /// Posts `payload` to `target` until it is accepted or the attempts run
+    /// out, waiting twice as long after each failure. Every attempt is
+    /// appended to `log`. Returns whether the event was delivered.
+    pub async fn deliver(&self, target: &WebhookTarget, payload: &WebhookPayload, log: &DeliveryLog) -> bool {
+        let body = match serde_json::to_vec(payload) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Failed to encode {} webhook of job {}: {}", payload.event.as_str(), payload.job_id, e);
+                return false;
+            }
+        };
+        let delivery_id = Uuid::new_v4().to_string();
+
+        for attempt in 1..=self.max_attempts {
+            let started = Instant::now();
+            let attempted_at = Utc::now();
+            let signature = sign(&target.secret, attempted_at.timestamp(), &body);
+
+            let response = self.client
+                .post(&target.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(SIGNATURE_HEADER, signature)
+                .header(EVENT_HEADER, payload.event.as_str())
+                .header(DELIVERY_HEADER, &delivery_id)
+                .body(body.clone())
+                .send()
+                .await;
+
+            let (status, error) = match response {
+                Ok(response) if response.status().is_success() => (Some(response.status()), None),
+                Ok(response) => (Some(response.status()), Some(format!("Receiver answered {}", response.status()))),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            let delivered = error.is_none();
+
+            log.lock().unwrap().push(WebhookDelivery {
+                delivery_id: delivery_id.clone(),
+                event: payload.event,
+                attempt,
+                attempted_at,
+                status_code: status.map(|s| s.as_u16()),
+                error: error.clone(),
+                duration_ms: started.elapsed().as_millis() as u64,
+                delivered,
+            });
+
+            if delivered {
+                info!("Delivered {} webhook of job {}", payload.event.as_str(), payload.job_id);
+                return true;
+            }
+
+            let error = error.unwrap_or_default();
+            if attempt == self.max_attempts || !is_retryable(status) {
+                warn!(
+                    "Giving up on {} webhook of job {} after {} attempt(s): {}",
+                    payload.event.as_str(),
+                    payload.job_id,
+                    attempt,
+                    error
+                );
+                return false;
+            }
+
+            let delay = self.retry_base_delay
+                .saturating_mul(2u32.saturating_pow(attempt - 1))
+                .min(MAX_RETRY_DELAY);
+            warn!(
+                "{} webhook of job {} failed ({}), retrying in {:?}",
+                payload.event.as_str(),
+                payload.job_id,
+                error,
+                delay
+            );
+            tokio::time::sleep(delay).await;
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(secret: Option<&str>, allow_private_targets: bool) -> WebhookConfig {
+        WebhookConfig {
+            secret: secret.map(str::to_string),
+            max_attempts: 3,
+            retry_base_delay_ms: 10,
+            timeout_secs: 5,
+            allow_private_targets,
+        }
+    }
+
+    #[test]
+    fn test_signature() {
+        let signature = sign("whsec_test", 1_700_000_000, br#"{"event":"job.completed"}"#);
+        assert_eq!(
+            signature,
+            "t=1700000000,v1=51be9920773f454007b9aaf2ef84578604f287a1ad8b1cf6918458c66aac6bd8"
+        );
+    }
+
+    #[test]
+    fn test_target_validation() {
+        let public = config(None, false);
+        let target = WebhookTarget::new("https://hooks.example.com/leaks", Some("s3cret".into()), true, &public).unwrap();
+        assert_eq!(target.url, "https://hooks.example.com/leaks");
+        assert!(!format!("{:?}", target).contains("s3cret"));
+
+        assert!(WebhookTarget::new("https://hooks.example.com", None, false, &public).is_err());
+        assert!(WebhookTarget::new("ftp://hooks.example.com", Some("s".into()), false, &public).is_err());
+        assert!(WebhookTarget::new("not a url", Some("s".into()), false, &public).is_err());
+        for private in [
+            "http://localhost:8080",
+            "http://localhost./",
+            "http://127.0.0.1/",
+            "http://10.1.2.3/",
+            "http://[::1]/",
+            "http://169.254.169.254/",
+            "http://100.64.0.1/",
+            "http://[::ffff:127.0.0.1]/",
+            "http://[::ffff:a9fe:a9fe]/",
+        ] {
+            assert!(WebhookTarget::new(private, Some("s".into()), false, &public).is_err(), "{}", private);
+        }
+        assert!(WebhookTarget::new("http://100.128.0.1/", Some("s".into()), false, &public).is_ok());
+
+        let fallback = WebhookTarget::new("http://127.0.0.1:9/hook", None, false, &config(Some("server"), true)).unwrap();
+        assert_eq!(fallback.secret, "server");
+    }
+
+    #[tokio::test]
+    async fn test_resolver_rejects_private_addresses() {
+        // No name is special-cased here: `localhost` is refused for resolving to loopback.
+        let error = resolve_public("localhost").await.unwrap_err();
+        assert!(error.to_string().contains("private address"), "{}", error);
+
+        let addrs = resolve_public("8.8.8.8").await.unwrap();
+        assert_eq!(addrs, vec!["8.8.8.8:0".parse().unwrap()]);
+    }
+
+    #[test]
+    fn test_retry_classification() {
+        assert!(is_retryable(None));
+        assert!(is_retryable(Some(StatusCode::BAD_GATEWAY)));
+        assert!(is_retryable(Some(StatusCode::TOO_MANY_REQUESTS)));
+        assert!(!is_retryable(Some(StatusCode::BAD_REQUEST)));
+        assert!(!is_retryable(Some(StatusCode::GONE)));
+    }
+}
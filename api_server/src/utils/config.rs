@@ -1,8 +1,19 @@
 use once_cell::sync::OnceCell;
 use serde::Deserialize;
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
 use std::env;
 
+use crate::crypto::key_provider::KeyMode;
+use crate::services::api_keys::{ApiKeyStoreKind, Scope};
+use crate::services::backends::{hash_file::{self, HashFileFormat}, BACKEND_NAMES};
+use crate::services::job_store::JobStoreKind;
+use crate::services::jwt::{self, AuthMode};
+use crate::utils::logging::LogFormat;
+use crate::utils::redact::Redaction;
+use crate::utils::telemetry;
+use crate::utils::rate_limiter::{self, IpNetwork};
+
 static CONFIG: OnceCell<AppConfig> = OnceCell::new();
 
 #[derive(Debug, Clone, Deserialize)]
@@ -10,7 +21,16 @@ static CONFIG: OnceCell<AppConfig> = OnceCell::new();
 pub struct AppConfig {
     pub server: ServerConfig,
     pub google_api: GoogleApiConfig,
+    pub upstream: UpstreamConfig,
     pub rate_limits: RateLimitConfig,
+    pub keys: KeyConfig,
+    pub backends: BackendConfig,
+    pub result_cache: ResultCacheConfig,
+    pub jobs: JobStoreConfig,
+    pub webhooks: WebhookConfig,
+    pub auth: AuthConfig,
+    pub logging: LoggingConfig,
+    pub telemetry: TelemetryConfig,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -21,7 +41,7 @@ pub struct ServerConfig {
     pub cors_allowed_origins: Vec<String>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Clone, Deserialize)]
 #[allow(dead_code)]
 pub struct GoogleApiConfig {
     pub client_id: String,
@@ -33,17 +53,345 @@ pub struct GoogleApiConfig {
     pub token_cache_duration: u64,
 }
 
+/// Pacing and retries of lookups sent to Google.
+#[derive(Debug, Clone, Deserialize)]
+#[allow(dead_code)]
+pub struct UpstreamConfig {
+    /// Most lookups in flight at once, however well Google keeps up.
+    pub max_concurrency: usize,
+    /// Lookups allowed in flight before any answer came back.
+    pub initial_concurrency: usize,
+    /// Answers slower than this lower the concurrency.
+    pub target_latency_ms: u64,
+    /// Attempts per lookup, the first included.
+    pub max_attempts: u32,
+    /// Wait before the first retry; doubled after every further failure.
+    pub retry_base_delay_ms: u64,
+    /// Longest wait between two attempts. A longer `Retry-After` ends the
+    /// retries instead.
+    pub max_retry_delay_ms: u64,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 #[allow(dead_code)]
 pub struct RateLimitConfig {
+    /// Requests per minute from one client IP, over every route.
+    pub ip_rpm: u32,
     pub single_credential_rpm: u32,
     pub batch_credential_rpm: u32,
+    pub blinded_credential_rpm: u32,
+    /// Daily quotas per client; 0 leaves only the per-minute quota.
+    pub single_credential_daily: u32,
+    pub blinded_credential_daily: u32,
+    pub batch_credential_daily: u32,
+    /// Most entries accepted in one batch upload.
     pub max_batch_size: usize,
+    /// Largest batch upload in bytes, before and after decompression.
+    pub max_upload_bytes: usize,
+    /// Proxies trusted to name the client in `X-Forwarded-For`.
+    pub trusted_proxies: Vec<IpNetwork>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[allow(dead_code)]
+pub struct KeyConfig {
+    pub mode: KeyMode,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[allow(dead_code)]
+pub struct BackendConfig {
+    pub default_backend: String,
+    pub hibp_api_url: String,
+    pub hash_file_path: Option<String>,
+    pub hash_file_format: HashFileFormat,
+}
+
+#[derive(Clone, Deserialize)]
+#[allow(dead_code)]
+pub struct ResultCacheConfig {
+    /// Most verdicts kept in memory; 0 disables the cache.
+    pub max_entries: usize,
+    /// How long a leaked verdict is reused; 0 never caches one.
+    pub ttl_secs: u64,
+    /// How long a not leaked verdict is reused; 0 never caches one.
+    pub negative_ttl_secs: u64,
+    /// HMAC key for cache keys; random per process when unset.
+    pub key: Option<String>,
+    /// Database file the cache is persisted in, which requires `key`.
+    pub sqlite_path: Option<String>,
+}
+
+#[derive(Clone, Deserialize)]
+#[allow(dead_code)]
+pub struct JobStoreConfig {
+    pub kind: JobStoreKind,
+    /// Database file used when `kind` is `sqlite`.
+    pub sqlite_path: String,
+    /// Encrypts the stored entries of unfinished jobs; required when `kind`
+    /// is `sqlite`.
+    pub key: Option<String>,
+}
+
+impl std::fmt::Debug for JobStoreConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JobStoreConfig")
+            .field("kind", &self.kind)
+            .field("sqlite_path", &self.sqlite_path)
+            .field("key", &self.key.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[allow(dead_code)]
+pub struct WebhookConfig {
+    /// Signs callbacks of jobs uploaded without their own `callback_secret`.
+    pub secret: Option<String>,
+    pub max_attempts: u32,
+    /// Wait before the first retry; doubled after every further failure.
+    pub retry_base_delay_ms: u64,
+    pub timeout_secs: u64,
+    /// Accept callback URLs on loopback and private networks.
+    pub allow_private_targets: bool,
+}
+
+#[derive(Clone, Deserialize)]
+#[allow(dead_code)]
+pub struct AuthConfig {
+    /// Require credentials on the check and batch routes; on unless
+    /// `AUTH_ENABLED=false`. Admin routes always require them.
+    pub enabled: bool,
+    pub mode: AuthMode,
+    pub store: ApiKeyStoreKind,
+    /// Database file used when `store` is `sqlite`.
+    pub sqlite_path: String,
+    /// Key with the `admin` scope that is always accepted, for issuing the
+    /// first keys through the admin API.
+    pub admin_key: Option<String>,
+    /// Set when `mode` is `jwt`.
+    pub jwt: Option<JwtConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[allow(dead_code)]
+pub struct JwtConfig {
+    /// Path or http(s) URL of the identity provider's JWKS.
+    pub jwks: String,
+    pub issuer: String,
+    pub audience: String,
+    /// Claim holding the caller's roles, as a dotted path into the token.
+    pub scope_claim: String,
+    /// Claim values and the LeakLens scope each grants. When empty, values
+    /// named after a scope grant it.
+    pub scope_map: HashMap<String, Scope>,
+    /// Clock skew tolerated on `exp` and `nbf`.
+    pub leeway_secs: u64,
+}
+
+#[derive(Clone, Deserialize)]
+#[allow(dead_code)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    /// `RUST_LOG` directives, e.g. `info,leaklens_api=debug`.
+    pub filter: String,
+    pub redaction: Redaction,
+    /// Key for username and hash pseudonyms; random per process when unset.
+    pub pseudonym_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[allow(dead_code)]
+pub struct TelemetryConfig {
+    /// Base URL of an OTLP/HTTP collector, e.g. `http://localhost:4318`.
+    pub otlp_endpoint: Option<String>,
+    /// Full URL for traces, used instead of `<otlp_endpoint>/v1/traces`.
+    pub otlp_traces_endpoint: Option<String>,
+    pub service_name: String,
+}
+
+impl TelemetryConfig {
+    /// Where spans are sent; `None` disables trace export.
+    pub fn traces_url(&self) -> Option<String> {
+        self.otlp_traces_endpoint.clone().or_else(|| {
+            self.otlp_endpoint
+                .as_ref()
+                .map(|endpoint| format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        })
+    }
+}
+
+impl std::fmt::Debug for GoogleApiConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GoogleApiConfig")
+            .field("client_id", &self.client_id)
+            .field("client_secret", &"<redacted>")
+            .field("refresh_token", &"<redacted>")
+            .field("api_url", &self.api_url)
+            .field("token_url", &self.token_url)
+            .field("scope", &self.scope)
+            .field("token_cache_duration", &self.token_cache_duration)
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for ResultCacheConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResultCacheConfig")
+            .field("max_entries", &self.max_entries)
+            .field("ttl_secs", &self.ttl_secs)
+            .field("negative_ttl_secs", &self.negative_ttl_secs)
+            .field("key", &self.key.as_ref().map(|_| "<redacted>"))
+            .field("sqlite_path", &self.sqlite_path)
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for WebhookConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebhookConfig")
+            .field("secret", &self.secret.as_ref().map(|_| "<redacted>"))
+            .field("max_attempts", &self.max_attempts)
+            .field("retry_base_delay_ms", &self.retry_base_delay_ms)
+            .field("timeout_secs", &self.timeout_secs)
+            .field("allow_private_targets", &self.allow_private_targets)
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for LoggingConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoggingConfig")
+            .field("format", &self.format)
+            .field("filter", &self.filter)
+            .field("redaction", &self.redaction)
+            .field("pseudonym_key", &self.pseudonym_key.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for AuthConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthConfig")
+            .field("enabled", &self.enabled)
+            .field("mode", &self.mode)
+            .field("store", &self.store)
+            .field("sqlite_path", &self.sqlite_path)
+            .field("admin_key", &self.admin_key.as_ref().map(|_| "<redacted>"))
+            .field("jwt", &self.jwt)
+            .finish()
+    }
+}
+
+impl AppConfig {
+    /// Configuration for running against local stand-ins of the Google
+    /// endpoints, with authentication off and every other setting at its
+    /// default.
+    #[allow(dead_code)]
+    pub fn local(google_api_url: &str, token_url: &str) -> Self {
+        AppConfig {
+            server: ServerConfig {
+                host: "127.0.0.1".to_string(),
+                port: 0,
+                cors_allowed_origins: vec!["*".to_string()],
+            },
+            google_api: GoogleApiConfig {
+                client_id: "test-client-id".to_string(),
+                client_secret: "test-client-secret".to_string(),
+                refresh_token: "test-refresh-token".to_string(),
+                api_url: google_api_url.to_string(),
+                token_url: token_url.to_string(),
+                scope: "https://www.googleapis.com/auth/identity.passwords.leak.check".to_string(),
+                token_cache_duration: 3000,
+            },
+            upstream: UpstreamConfig {
+                max_concurrency: 16,
+                initial_concurrency: 4,
+                target_latency_ms: 2000,
+                max_attempts: 4,
+                retry_base_delay_ms: 250,
+                max_retry_delay_ms: 30000,
+            },
+            rate_limits: RateLimitConfig {
+                ip_rpm: 600,
+                single_credential_rpm: 60,
+                batch_credential_rpm: 10,
+                blinded_credential_rpm: 60,
+                single_credential_daily: 0,
+                blinded_credential_daily: 0,
+                batch_credential_daily: 0,
+                max_batch_size: 10000,
+                max_upload_bytes: 10 * 1024 * 1024,
+                trusted_proxies: Vec::new(),
+            },
+            keys: KeyConfig {
+                mode: KeyMode::PerRequest,
+            },
+            backends: BackendConfig {
+                default_backend: "google".to_string(),
+                hibp_api_url: "http://127.0.0.1:9".to_string(),
+                hash_file_path: None,
+                hash_file_format: HashFileFormat::Sha1,
+            },
+            result_cache: ResultCacheConfig {
+                max_entries: 100_000,
+                ttl_secs: 86400,
+                negative_ttl_secs: 3600,
+                key: None,
+                sqlite_path: None,
+            },
+            jobs: JobStoreConfig {
+                kind: JobStoreKind::Memory,
+                sqlite_path: "leaklens-jobs.sqlite3".to_string(),
+                key: None,
+            },
+            webhooks: WebhookConfig {
+                secret: None,
+                max_attempts: 5,
+                retry_base_delay_ms: 1000,
+                timeout_secs: 10,
+                allow_private_targets: false,
+            },
+            auth: AuthConfig {
+                enabled: false,
+                mode: AuthMode::ApiKey,
+                store: ApiKeyStoreKind::Memory,
+                sqlite_path: "leaklens-keys.sqlite3".to_string(),
+                admin_key: None,
+                jwt: None,
+            },
+            logging: LoggingConfig {
+                format: LogFormat::Json,
+                filter: "info".to_string(),
+                redaction: Redaction::Pseudonymize,
+                pseudonym_key: None,
+            },
+            telemetry: TelemetryConfig {
+                otlp_endpoint: None,
+                otlp_traces_endpoint: None,
+                service_name: "leaklens-api".to_string(),
+            },
+        }
+    }
 }
 
 pub fn init() -> Result<()> {
     dotenvy::dotenv().ok();
     
+    let auth_mode: AuthMode = env::var("AUTH_MODE")
+        .unwrap_or_else(|_| "api_key".to_string())
+        .parse()
+        .context("Failed to parse AUTH_MODE")?;
+
+    if let Ok(protocol) = env::var("OTEL_EXPORTER_OTLP_PROTOCOL") {
+        telemetry::check_protocol(&protocol).context("Failed to parse OTEL_EXPORTER_OTLP_PROTOCOL")?;
+    }
+
+    let single_rpm = env::var("RATE_LIMIT_SINGLE_RPM")
+        .unwrap_or_else(|_| "60".to_string()) // 60 requests per minute
+        .parse()
+        .context("Failed to parse RATE_LIMIT_SINGLE_RPM")?;
+
     let config = AppConfig {
         server: ServerConfig {
             host: env::var("SERVER_HOST").unwrap_or_else(|_| "0.0.0.0".to_string()),
@@ -75,31 +423,241 @@ pub fn init() -> Result<()> {
                 .parse()
                 .context("Failed to parse TOKEN_CACHE_DURATION")?,
         },
+        upstream: UpstreamConfig {
+            max_concurrency: env::var("UPSTREAM_MAX_CONCURRENCY")
+                .unwrap_or_else(|_| "16".to_string())
+                .parse()
+                .context("Failed to parse UPSTREAM_MAX_CONCURRENCY")?,
+            initial_concurrency: env::var("UPSTREAM_INITIAL_CONCURRENCY")
+                .unwrap_or_else(|_| "4".to_string())
+                .parse()
+                .context("Failed to parse UPSTREAM_INITIAL_CONCURRENCY")?,
+            target_latency_ms: env::var("UPSTREAM_TARGET_LATENCY_MS")
+                .unwrap_or_else(|_| "2000".to_string())
+                .parse()
+                .context("Failed to parse UPSTREAM_TARGET_LATENCY_MS")?,
+            max_attempts: env::var("UPSTREAM_MAX_ATTEMPTS")
+                .unwrap_or_else(|_| "4".to_string())
+                .parse()
+                .context("Failed to parse UPSTREAM_MAX_ATTEMPTS")?,
+            retry_base_delay_ms: env::var("UPSTREAM_RETRY_BASE_MS")
+                .unwrap_or_else(|_| "250".to_string())
+                .parse()
+                .context("Failed to parse UPSTREAM_RETRY_BASE_MS")?,
+            max_retry_delay_ms: env::var("UPSTREAM_MAX_RETRY_DELAY_MS")
+                .unwrap_or_else(|_| "30000".to_string())
+                .parse()
+                .context("Failed to parse UPSTREAM_MAX_RETRY_DELAY_MS")?,
+        },
         rate_limits: RateLimitConfig {
-            single_credential_rpm: env::var("RATE_LIMIT_SINGLE_RPM")
-                .unwrap_or_else(|_| "60".to_string()) // 60 requests per minute
+            ip_rpm: env::var("RATE_LIMIT_IP_RPM")
+                .unwrap_or_else(|_| "600".to_string()) // 600 requests per minute
                 .parse()
-                .context("Failed to parse RATE_LIMIT_SINGLE_RPM")?,
+                .context("Failed to parse RATE_LIMIT_IP_RPM")?,
+            single_credential_rpm: single_rpm,
             batch_credential_rpm: env::var("RATE_LIMIT_BATCH_RPM")
                 .unwrap_or_else(|_| "10".to_string()) // 10 requests per minute
                 .parse()
                 .context("Failed to parse RATE_LIMIT_BATCH_RPM")?,
+            blinded_credential_rpm: match env::var("RATE_LIMIT_BLINDED_RPM") {
+                Ok(rpm) => rpm.parse().context("Failed to parse RATE_LIMIT_BLINDED_RPM")?,
+                Err(_) => single_rpm,
+            },
+            single_credential_daily: env::var("RATE_LIMIT_SINGLE_DAILY")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .context("Failed to parse RATE_LIMIT_SINGLE_DAILY")?,
+            blinded_credential_daily: env::var("RATE_LIMIT_BLINDED_DAILY")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .context("Failed to parse RATE_LIMIT_BLINDED_DAILY")?,
+            batch_credential_daily: env::var("RATE_LIMIT_BATCH_DAILY")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .context("Failed to parse RATE_LIMIT_BATCH_DAILY")?,
             max_batch_size: env::var("MAX_BATCH_SIZE")
                 .unwrap_or_else(|_| "10000".to_string()) // max 10k credentials per batch
                 .parse()
                 .context("Failed to parse MAX_BATCH_SIZE")?,
+            max_upload_bytes: env::var("MAX_UPLOAD_BYTES")
+                .unwrap_or_else(|_| "10485760".to_string()) // 10 MiB
+                .parse()
+                .context("Failed to parse MAX_UPLOAD_BYTES")?,
+            trusted_proxies: rate_limiter::parse_networks(&env::var("TRUSTED_PROXIES").unwrap_or_default())
+                .context("Failed to parse TRUSTED_PROXIES")?,
+        },
+        keys: KeyConfig {
+            mode: parse_key_mode()?,
+        },
+        backends: BackendConfig {
+            default_backend: env::var("LEAK_BACKEND")
+                .unwrap_or_else(|_| "google".to_string())
+                .trim()
+                .to_lowercase(),
+            hibp_api_url: env::var("HIBP_API_URL")
+                .unwrap_or_else(|_| "https://api.pwnedpasswords.com".to_string()),
+            hash_file_path: env::var("HASH_FILE_PATH").ok().filter(|p| !p.trim().is_empty()),
+            hash_file_format: env::var("HASH_FILE_FORMAT")
+                .unwrap_or_else(|_| "sha1".to_string())
+                .parse()
+                .context("Failed to parse HASH_FILE_FORMAT")?,
+        },
+        result_cache: ResultCacheConfig {
+            max_entries: env::var("RESULT_CACHE_MAX_ENTRIES")
+                .unwrap_or_else(|_| "100000".to_string())
+                .parse()
+                .context("Failed to parse RESULT_CACHE_MAX_ENTRIES")?,
+            ttl_secs: env::var("RESULT_CACHE_TTL_SECS")
+                .unwrap_or_else(|_| "86400".to_string()) // 1 day
+                .parse()
+                .context("Failed to parse RESULT_CACHE_TTL_SECS")?,
+            negative_ttl_secs: env::var("RESULT_CACHE_NEGATIVE_TTL_SECS")
+                .unwrap_or_else(|_| "3600".to_string()) // 1 hour
+                .parse()
+                .context("Failed to parse RESULT_CACHE_NEGATIVE_TTL_SECS")?,
+            key: env::var("RESULT_CACHE_KEY").ok().filter(|k| !k.is_empty()),
+            sqlite_path: env::var("RESULT_CACHE_PATH").ok().filter(|p| !p.trim().is_empty()),
+        },
+        jobs: JobStoreConfig {
+            kind: env::var("JOB_STORE")
+                .unwrap_or_else(|_| "memory".to_string())
+                .parse()
+                .context("Failed to parse JOB_STORE")?,
+            sqlite_path: env::var("JOB_STORE_PATH")
+                .unwrap_or_else(|_| "leaklens-jobs.sqlite3".to_string()),
+            key: env::var("JOB_STORE_KEY").ok().filter(|k| !k.is_empty()),
+        },
+        webhooks: WebhookConfig {
+            secret: env::var("WEBHOOK_SECRET").ok().filter(|s| !s.is_empty()),
+            max_attempts: env::var("WEBHOOK_MAX_ATTEMPTS")
+                .unwrap_or_else(|_| "5".to_string())
+                .parse()
+                .context("Failed to parse WEBHOOK_MAX_ATTEMPTS")?,
+            retry_base_delay_ms: env::var("WEBHOOK_RETRY_BASE_MS")
+                .unwrap_or_else(|_| "1000".to_string())
+                .parse()
+                .context("Failed to parse WEBHOOK_RETRY_BASE_MS")?,
+            timeout_secs: env::var("WEBHOOK_TIMEOUT_SECS")
+                .unwrap_or_else(|_| "10".to_string())
+                .parse()
+                .context("Failed to parse WEBHOOK_TIMEOUT_SECS")?,
+            allow_private_targets: env::var("WEBHOOK_ALLOW_PRIVATE")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .context("Failed to parse WEBHOOK_ALLOW_PRIVATE")?,
+        },
+        auth: AuthConfig {
+            enabled: env::var("AUTH_ENABLED")
+                .unwrap_or_else(|_| "true".to_string())
+                .parse()
+                .context("Failed to parse AUTH_ENABLED")?,
+            mode: auth_mode,
+            store: env::var("API_KEY_STORE")
+                .unwrap_or_else(|_| "memory".to_string())
+                .parse()
+                .context("Failed to parse API_KEY_STORE")?,
+            sqlite_path: env::var("API_KEY_STORE_PATH")
+                .unwrap_or_else(|_| "leaklens-keys.sqlite3".to_string()),
+            admin_key: env::var("ADMIN_API_KEY").ok().filter(|k| !k.trim().is_empty()),
+            jwt: match auth_mode {
+                AuthMode::Jwt => Some(parse_jwt_config()?),
+                AuthMode::ApiKey => None,
+            },
+        },
+        logging: LoggingConfig {
+            format: env::var("LOG_FORMAT")
+                .unwrap_or_else(|_| "json".to_string())
+                .parse()
+                .context("Failed to parse LOG_FORMAT")?,
+            filter: env::var("RUST_LOG").ok().filter(|f| !f.trim().is_empty()).unwrap_or_else(|| "info".to_string()),
+            redaction: env::var("LOG_REDACTION")
+                .unwrap_or_else(|_| "pseudonymize".to_string())
+                .parse()
+                .context("Failed to parse LOG_REDACTION")?,
+            pseudonym_key: env::var("LOG_PSEUDONYM_KEY").ok().filter(|k| !k.is_empty()),
+        },
+        telemetry: TelemetryConfig {
+            otlp_endpoint: env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok().filter(|e| !e.trim().is_empty()),
+            otlp_traces_endpoint: env::var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT").ok().filter(|e| !e.trim().is_empty()),
+            service_name: env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "leaklens-api".to_string()),
         },
     };
+
+    if !BACKEND_NAMES.contains(&config.backends.default_backend.as_str()) {
+        return Err(anyhow!(
+            "LEAK_BACKEND must be one of {}, got '{}'",
+            BACKEND_NAMES.join(", "),
+            config.backends.default_backend
+        ));
+    }
+
+    if config.backends.default_backend == hash_file::BACKEND_NAME && config.backends.hash_file_path.is_none() {
+        return Err(anyhow!("HASH_FILE_PATH environment variable must be set when LEAK_BACKEND=hash_file"));
+    }
+
+    // Unchecked entries hold plaintext passwords and are only stored encrypted.
+    if config.jobs.kind == JobStoreKind::Sqlite && config.jobs.key.is_none() {
+        return Err(anyhow!("JOB_STORE_KEY environment variable must be set when JOB_STORE=sqlite"));
+    }
+
+    // Entries persisted under a random key could never be found again.
+    if config.result_cache.sqlite_path.is_some() && config.result_cache.key.is_none() {
+        return Err(anyhow!("RESULT_CACHE_KEY environment variable must be set when RESULT_CACHE_PATH is set"));
+    }
     
-    CONFIG.set(config).expect("Failed to set global config");
+    init_with(config)
+}
+
+/// Installs an already built configuration, e.g. one pointing at local test
+/// doubles. Fails if a configuration has already been installed.
+pub fn init_with(config: AppConfig) -> Result<()> {
+    CONFIG.set(config).map_err(|_| anyhow!("Config already initialized"))
+}
+
+fn parse_key_mode() -> Result<KeyMode> {
+    let mode = env::var("KEY_MODE").unwrap_or_else(|_| "per_request".to_string());
+    
+    if mode.trim().eq_ignore_ascii_case("seeded") {
+        let seed = env::var("KEY_SEED")
+            .context("KEY_SEED environment variable must be set when KEY_MODE=seeded")?
+            .parse()
+            .context("Failed to parse KEY_SEED")?;
+        return Ok(KeyMode::Seeded(seed));
+    }
     
-    Ok(())
+    mode.parse().context("Failed to parse KEY_MODE")
+}
+
+fn parse_jwt_config() -> Result<JwtConfig> {
+    Ok(JwtConfig {
+        jwks: env::var("JWT_JWKS")
+            .context("JWT_JWKS environment variable must be set when AUTH_MODE=jwt")?,
+        issuer: env::var("JWT_ISSUER")
+            .context("JWT_ISSUER environment variable must be set when AUTH_MODE=jwt")?,
+        audience: env::var("JWT_AUDIENCE")
+            .context("JWT_AUDIENCE environment variable must be set when AUTH_MODE=jwt")?,
+        scope_claim: env::var("JWT_SCOPE_CLAIM").unwrap_or_else(|_| "scope".to_string()),
+        scope_map: jwt::parse_scope_map(&env::var("JWT_SCOPE_MAP").unwrap_or_default())
+            .context("Failed to parse JWT_SCOPE_MAP")?,
+        leeway_secs: env::var("JWT_LEEWAY_SECS")
+            .unwrap_or_else(|_| "60".to_string())
+            .parse()
+            .context("Failed to parse JWT_LEEWAY_SECS")?,
+    })
 }
 
 pub fn get() -> &'static AppConfig {
     CONFIG.get().expect("Config not initialized. Call init() first")
 }
 
+/// Installs a fixed configuration for unit tests that never talks to Google.
+#[cfg(test)]
+pub fn init_for_tests() {
+    CONFIG.get_or_init(|| {
+        AppConfig::local("http://127.0.0.1:9/v1/leaks:lookupSingle", "http://127.0.0.1:9/token")
+    });
+}
+
 /// example .env file
 #[allow(dead_code)]
 const ENV_EXAMPLE: &str = r#"
@@ -117,8 +675,89 @@ GOOGLE_TOKEN_URL=https://www.googleapis.com/oauth2/v4/token
 GOOGLE_API_SCOPE=https://www.googleapis.com/auth/identity.passwords.leak.check
 TOKEN_CACHE_DURATION=3000
 
+# Google lookups: concurrency adapts between 1 and the maximum, backing off
+# on 429/503 and slow answers; transient failures are retried
+UPSTREAM_MAX_CONCURRENCY=16
+UPSTREAM_INITIAL_CONCURRENCY=4
+UPSTREAM_TARGET_LATENCY_MS=2000
+UPSTREAM_MAX_ATTEMPTS=4
+UPSTREAM_RETRY_BASE_MS=250
+UPSTREAM_MAX_RETRY_DELAY_MS=30000
+
 # Rate Limiting
+# Requests per minute from one client IP over every route, checked before authentication
+RATE_LIMIT_IP_RPM=600
 RATE_LIMIT_SINGLE_RPM=60
 RATE_LIMIT_BATCH_RPM=10
+# RATE_LIMIT_BLINDED_RPM=60
+# Daily quotas per client (0 = none)
+RATE_LIMIT_SINGLE_DAILY=0
+RATE_LIMIT_BLINDED_DAILY=0
+RATE_LIMIT_BATCH_DAILY=0
+# Proxies allowed to set X-Forwarded-For (addresses or CIDR blocks)
+# TRUSTED_PROXIES=127.0.0.1,10.0.0.0/8
 MAX_BATCH_SIZE=10000
+MAX_UPLOAD_BYTES=10485760
+
+# Client Key Management (per_request, per_batch or seeded)
+KEY_MODE=per_request
+# KEY_SEED=42
+
+# Leak Backends (google, hibp or hash_file)
+LEAK_BACKEND=google
+HIBP_API_URL=https://api.pwnedpasswords.com
+# Sorted Pwned Passwords dump for offline checks (sha1 or ntlm)
+# HASH_FILE_PATH=/data/pwnedpasswords.txt
+HASH_FILE_FORMAT=sha1
+
+# Result Cache for Google lookups; 0 entries disables it. TTLs in seconds,
+# the negative TTL applies to credentials not found in a breach
+RESULT_CACHE_MAX_ENTRIES=100000
+RESULT_CACHE_TTL_SECS=86400
+RESULT_CACHE_NEGATIVE_TTL_SECS=3600
+# Keys cache entries; random per process when unset, required with a path
+# RESULT_CACHE_KEY=change-me
+# RESULT_CACHE_PATH=leaklens-cache.sqlite3
+
+# Batch Jobs (memory or sqlite); sqlite keeps jobs across restarts and
+# encrypts their unchecked entries under the required key
+JOB_STORE=memory
+JOB_STORE_PATH=leaklens-jobs.sqlite3
+# JOB_STORE_KEY=change-me
+
+# Batch Job Webhooks
+# WEBHOOK_SECRET=change-me
+WEBHOOK_MAX_ATTEMPTS=5
+WEBHOOK_RETRY_BASE_MS=1000
+WEBHOOK_TIMEOUT_SECS=10
+WEBHOOK_ALLOW_PRIVATE=false
+
+# API Keys (memory or sqlite); manage sqlite keys with the api_keys CLI.
+# Check and batch routes need credentials unless AUTH_ENABLED=false, which lets
+# anyone use them and only suits trusted networks
+AUTH_ENABLED=true
+API_KEY_STORE=memory
+API_KEY_STORE_PATH=leaklens-keys.sqlite3
+# ADMIN_API_KEY=change-me
+
+# Auth Mode (api_key or jwt); jwt accepts bearer tokens from an OIDC provider
+AUTH_MODE=api_key
+# JWT_JWKS=https://idp.example.com/.well-known/jwks.json
+# JWT_ISSUER=https://idp.example.com/
+# JWT_AUDIENCE=leaklens
+JWT_SCOPE_CLAIM=scope
+# JWT_SCOPE_MAP=leaklens.check=single,leaklens.batch=batch,leaklens.admin=admin
+JWT_LEEWAY_SECS=60
+
+# Logging (json or text); RUST_LOG takes directives like info,leaklens_api=debug
+LOG_FORMAT=json
+RUST_LOG=info
+# Usernames and hashes in logs (pseudonymize, mask or off)
+LOG_REDACTION=pseudonymize
+# Keeps pseudonyms stable across restarts; random per process when unset
+# LOG_PSEUDONYM_KEY=change-me
+
+# Trace export over OTLP/HTTP (http/json only), e.g. to a local collector
+# OTEL_EXPORTER_OTLP_ENDPOINT=http://localhost:4318
+OTEL_SERVICE_NAME=leaklens-api
 "#;
\ No newline at end of file
@@ -8,7 +8,6 @@ use thiserror::Error;
 use crate::models::response_models::ErrorResponse;
 
 #[derive(Debug, Error)]
-#[allow(dead_code)]
 pub enum ApiError {
     #[error("Authentication error: {0}")]
     Authentication(String),
@@ -22,6 +21,9 @@ pub enum ApiError {
     #[error("Resource not found: {0}")]
     NotFound(String),
 
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
     #[error("Rate limit exceeded: {0}")]
     RateLimited(String),
 
@@ -39,6 +41,7 @@ impl ApiError {
             ApiError::Authorization(_) => "AUTHORIZATION_ERROR",
             ApiError::InvalidInput(_) => "INVALID_INPUT",
             ApiError::NotFound(_) => "NOT_FOUND",
+            ApiError::Conflict(_) => "CONFLICT",
             ApiError::RateLimited(_) => "RATE_LIMITED",
             ApiError::ExternalService(_) => "EXTERNAL_SERVICE_ERROR",
             ApiError::Internal(_) => "INTERNAL_SERVER_ERROR",
@@ -51,6 +54,7 @@ impl ApiError {
             ApiError::Authorization(_) => StatusCode::FORBIDDEN,
             ApiError::InvalidInput(_) => StatusCode::BAD_REQUEST,
             ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
             ApiError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
             ApiError::ExternalService(_) => StatusCode::BAD_GATEWAY,
             ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
@@ -0,0 +1,256 @@
+//! Log output: one JSON object per line by default, or human readable text,
+//! filtered with `RUST_LOG` directives such as `info,leaklens_api=debug`.
+//!
+//! Every line carries the fields of the spans it was logged in, so lines
+//! logged while serving a request carry its `request_id`. Field values go
+//! through [`redact::field`] in both formats.
+
+use anyhow::{anyhow, Context as _, Result};
+use chrono::{SecondsFormat, Utc};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::field::MakeExt;
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::fmt::{format, MakeWriter};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::utils::config::LoggingConfig;
+use crate::utils::redact;
+use crate::utils::telemetry::OtlpLayer;
+
+/// Log line format, selected with `LOG_FORMAT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    Json,
+    Text,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "json" => Ok(LogFormat::Json),
+            "text" => Ok(LogFormat::Text),
+            other => Err(anyhow!("Unknown log format '{}'", other)),
+        }
+    }
+}
+
+/// Installs the global subscriber and the redaction policy, exporting spans
+/// through `traces` when given. `RUST_LOG` only filters log lines; spans are
+/// exported from info level up whatever it says.
+pub fn init(config: &LoggingConfig, traces: Option<OtlpLayer>) -> Result<()> {
+    redact::init(config.redaction, config.pseudonym_key.as_deref());
+
+    let filter: Targets = config
+        .filter
+        .parse()
+        .with_context(|| format!("Failed to parse RUST_LOG '{}'", config.filter))?;
+    let registry = tracing_subscriber::registry().with(traces.map(|traces| traces.with_filter(LevelFilter::INFO)));
+
+    match config.format {
+        LogFormat::Json => registry.with(JsonLayer::new(std::io::stdout).with_filter(filter)).try_init(),
+        LogFormat::Text => registry
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .fmt_fields(format::debug_fn(write_text_field).delimited(" "))
+                    .with_filter(filter),
+            )
+            .try_init(),
+    }
+    .context("Failed to install the log subscriber")
+}
+
+fn write_text_field(writer: &mut format::Writer<'_>, field: &Field, value: &dyn fmt::Debug) -> fmt::Result {
+    if field.name() == "message" {
+        return write!(writer, "{:?}", value);
+    }
+
+    let text = format!("{:?}", value);
+    match redact::field(field.name(), text.trim_matches('"')) {
+        Some(replacement) => write!(writer, "{}={}", field.name(), replacement),
+        None => write!(writer, "{}={}", field.name(), text),
+    }
+}
+
+/// Fields recorded on a span, kept in its extensions.
+struct SpanFields(Map<String, Value>);
+
+/// Writes every event as a JSON object with `timestamp`, `level`, `target`,
+/// the fields of its spans from the outermost in, `spans` (their names) and
+/// its own fields, `message` included.
+pub struct JsonLayer<W> {
+    make_writer: W,
+}
+
+impl<W> JsonLayer<W> {
+    pub fn new(make_writer: W) -> Self {
+        JsonLayer { make_writer }
+    }
+}
+
+impl<S, W> Layer<S> for JsonLayer<W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'a> MakeWriter<'a> + 'static,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = Map::new();
+        attrs.record(&mut JsonVisitor(&mut fields));
+        // Hints for trace export, not worth a place in every line.
+        fields.retain(|name, _| !name.starts_with("otel."));
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(fields));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let mut extensions = span.extensions_mut();
+        if let Some(SpanFields(fields)) = extensions.get_mut::<SpanFields>() {
+            values.record(&mut JsonVisitor(fields));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut line = Map::new();
+        line.insert("timestamp".to_string(), Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true).into());
+        line.insert("level".to_string(), metadata.level().as_str().into());
+        line.insert("target".to_string(), metadata.target().into());
+
+        if let Some(scope) = ctx.event_scope(event) {
+            let mut spans = Vec::new();
+            for span in scope.from_root() {
+                if let Some(SpanFields(fields)) = span.extensions().get::<SpanFields>() {
+                    line.extend(fields.clone());
+                }
+                spans.push(Value::from(span.name()));
+            }
+            line.insert("spans".to_string(), Value::Array(spans));
+        }
+
+        event.record(&mut JsonVisitor(&mut line));
+
+        let Ok(mut bytes) = serde_json::to_vec(&line) else { return };
+        bytes.push(b'\n');
+        let _ = self.make_writer.make_writer_for(metadata).write_all(&bytes);
+    }
+}
+
+/// Records fields as JSON values, redacted as [`redact::field`] requires.
+pub(crate) struct JsonVisitor<'a>(pub(crate) &'a mut Map<String, Value>);
+
+impl JsonVisitor<'_> {
+    fn insert(&mut self, field: &Field, value: Value) {
+        let value = match &value {
+            Value::String(text) => redact::field(field.name(), text).map_or(value, Value::from),
+            other => redact::field(field.name(), &other.to_string()).map_or(value, Value::from),
+        };
+        self.0.insert(field.name().to_string(), value);
+    }
+}
+
+impl Visit for JsonVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value.into());
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.insert(field, value.to_string().into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, format!("{:?}", value).into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn capture(f: impl FnOnce()) -> Vec<Value> {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::registry().with(JsonLayer::new(move || writer.clone()));
+        tracing::subscriber::with_default(subscriber, f);
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        output.lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_json_lines_carry_span_fields() {
+        let lines = capture(|| {
+            let request = tracing::info_span!("http", request_id = "req-1", method = "POST");
+            let _entered = request.enter();
+            let job = tracing::info_span!("batch_job", job_id = "job-7");
+            let _entered = job.enter();
+            tracing::warn!(attempt = 2, "Retrying {}", "lookup");
+        });
+
+        assert_eq!(lines.len(), 1);
+        let line = &lines[0];
+        assert_eq!(line["level"], "WARN");
+        assert_eq!(line["message"], "Retrying lookup");
+        assert_eq!(line["request_id"], "req-1");
+        assert_eq!(line["job_id"], "job-7");
+        assert_eq!(line["attempt"], 2);
+        assert_eq!(line["spans"], serde_json::json!(["http", "batch_job"]));
+        assert!(line["timestamp"].as_str().unwrap().ends_with('Z'));
+    }
+
+    #[test]
+    fn test_sensitive_fields_are_redacted() {
+        let lines = capture(|| {
+            tracing::info!(username = "alice@example.com", password = "hunter2", access_token = ?"ya29.abc", "Checked");
+        });
+
+        let line = &lines[0];
+        assert_eq!(line["password"], redact::REDACTED);
+        assert_eq!(line["access_token"], redact::REDACTED);
+        let username = line["username"].as_str().unwrap();
+        assert!(username.starts_with("user:") && !username.contains("alice"));
+        assert!(!lines[0].to_string().contains("hunter2"));
+    }
+}
@@ -0,0 +1,322 @@
+//! Process-wide counters and histograms, served on `/metrics` in the
+//! Prometheus text exposition format.
+//!
+//! Every metric is a static registered in `ALL_*`; call sites record into it
+//! directly. Gauges that describe current state, such as the number of
+//! running jobs, are computed when `/metrics` is scraped instead.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Buckets for request and upstream latencies, in seconds.
+const LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Buckets for hashing times, in seconds.
+const CPU_BUCKETS: &[f64] = &[0.0001, 0.0005, 0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+/// Buckets for the number of entries in a batch upload.
+const SIZE_BUCKETS: &[f64] = &[1.0, 10.0, 100.0, 1000.0, 10000.0, 100000.0];
+
+pub static CREDENTIAL_CHECKS: Counter = Counter::new(
+    "leaklens_credential_checks_total",
+    "Credentials checked, by route and outcome (leaked, not_leaked or error).",
+    &["route", "outcome"],
+);
+
+pub static SINGLE_CHECK_DURATION: Histogram = Histogram::new(
+    "leaklens_single_check_duration_seconds",
+    "Time taken by single credential checks, by outcome.",
+    &["outcome"],
+    LATENCY_BUCKETS,
+);
+
+pub static BATCH_UPLOAD_ENTRIES: Histogram = Histogram::new(
+    "leaklens_batch_upload_entries",
+    "Entries in each accepted batch upload.",
+    &[],
+    SIZE_BUCKETS,
+);
+
+pub static BATCH_JOBS_FINISHED: Counter = Counter::new(
+    "leaklens_batch_jobs_finished_total",
+    "Batch jobs that finished, by outcome (completed, failed or cancelled).",
+    &["outcome"],
+);
+
+pub static BATCH_JOBS_ABANDONED: Counter = Counter::new(
+    "leaklens_batch_jobs_abandoned_total",
+    "Times a batch job was abandoned because no client polled or streamed it.",
+    &[],
+);
+
+pub static UPSTREAM_REQUESTS: Counter = Counter::new(
+    "leaklens_upstream_requests_total",
+    "Lookups sent to Google's leak check API, by HTTP status (or error when no response arrived).",
+    &["status"],
+);
+
+pub static UPSTREAM_DURATION: Histogram = Histogram::new(
+    "leaklens_upstream_request_duration_seconds",
+    "Latency of lookups sent to Google's leak check API, by HTTP status.",
+    &["status"],
+    LATENCY_BUCKETS,
+);
+
+pub static UPSTREAM_RETRIES: Counter = Counter::new(
+    "leaklens_upstream_retries_total",
+    "Lookups sent to Google again after a transient failure, by the failed attempt's HTTP status (or error).",
+    &["status"],
+);
+
+pub static TOKEN_REFRESHES: Counter = Counter::new(
+    "leaklens_token_refreshes_total",
+    "OAuth access token refreshes, by result (success or failure).",
+    &["result"],
+);
+
+pub static HASHING_DURATION: Histogram = Histogram::new(
+    "leaklens_hashing_duration_seconds",
+    "CPU time spent in scrypt and hash_to_curve.",
+    &["operation"],
+    CPU_BUCKETS,
+);
+
+pub static RATE_LIMITED: Counter = Counter::new(
+    "leaklens_rate_limited_total",
+    "Requests rejected by a rate limit, by route.",
+    &["route"],
+);
+
+pub static RESULT_CACHE_LOOKUPS: Counter = Counter::new(
+    "leaklens_result_cache_lookups_total",
+    "Google lookups answered from the result cache (hit) or sent upstream (miss).",
+    &["result"],
+);
+
+pub static RESULT_CACHE_EVICTIONS: Counter = Counter::new(
+    "leaklens_result_cache_evictions_total",
+    "Entries dropped from the result cache, by reason (expired or capacity).",
+    &["reason"],
+);
+
+static ALL_COUNTERS: &[&Counter] = &[
+    &CREDENTIAL_CHECKS,
+    &BATCH_JOBS_FINISHED,
+    &BATCH_JOBS_ABANDONED,
+    &UPSTREAM_REQUESTS,
+    &UPSTREAM_RETRIES,
+    &TOKEN_REFRESHES,
+    &RATE_LIMITED,
+    &RESULT_CACHE_LOOKUPS,
+    &RESULT_CACHE_EVICTIONS,
+];
+
+static ALL_HISTOGRAMS: &[&Histogram] = &[
+    &SINGLE_CHECK_DURATION,
+    &BATCH_UPLOAD_ENTRIES,
+    &UPSTREAM_DURATION,
+    &HASHING_DURATION,
+];
+
+/// A monotonically increasing count per combination of label values.
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, u64>>,
+}
+
+impl Counter {
+    pub const fn new(name: &'static str, help: &'static str, labels: &'static [&'static str]) -> Self {
+        Counter { name, help, labels, values: Mutex::new(BTreeMap::new()) }
+    }
+
+    pub fn inc(&self, labels: &[&str]) {
+        self.inc_by(labels, 1);
+    }
+
+    pub fn inc_by(&self, labels: &[&str], n: u64) {
+        debug_assert_eq!(labels.len(), self.labels.len(), "wrong number of labels for {}", self.name);
+        let key = labels.iter().map(|value| value.to_string()).collect();
+        *self.values.lock().unwrap().entry(key).or_insert(0) += n;
+    }
+
+    fn render(&self, out: &mut String) {
+        write_header(out, self.name, self.help, "counter");
+        let values = self.values.lock().unwrap();
+        if values.is_empty() && self.labels.is_empty() {
+            let _ = writeln!(out, "{} 0", self.name);
+        }
+        for (key, value) in values.iter() {
+            let _ = writeln!(out, "{}{} {}", self.name, label_set(self.labels, key, None), value);
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Series {
+    /// Observations per bucket, not cumulative.
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+/// Observations sorted into fixed buckets per combination of label values.
+pub struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    bounds: &'static [f64],
+    series: Mutex<BTreeMap<Vec<String>, Series>>,
+}
+
+impl Histogram {
+    pub const fn new(
+        name: &'static str,
+        help: &'static str,
+        labels: &'static [&'static str],
+        bounds: &'static [f64],
+    ) -> Self {
+        Histogram { name, help, labels, bounds, series: Mutex::new(BTreeMap::new()) }
+    }
+
+    pub fn observe(&self, labels: &[&str], value: f64) {
+        debug_assert_eq!(labels.len(), self.labels.len(), "wrong number of labels for {}", self.name);
+        let key = labels.iter().map(|value| value.to_string()).collect();
+        let mut series = self.series.lock().unwrap();
+        let series = series.entry(key).or_insert_with(|| Series {
+            buckets: vec![0; self.bounds.len()],
+            sum: 0.0,
+            count: 0,
+        });
+
+        if let Some(bucket) = self.bounds.iter().position(|bound| value <= *bound) {
+            series.buckets[bucket] += 1;
+        }
+        series.sum += value;
+        series.count += 1;
+    }
+
+    /// Records the time since `start` in seconds.
+    pub fn observe_since(&self, labels: &[&str], start: Instant) {
+        self.observe(labels, start.elapsed().as_secs_f64());
+    }
+
+    /// Runs `f` and records how long it took.
+    pub fn time<T>(&self, labels: &[&str], f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.observe_since(labels, start);
+        result
+    }
+
+    fn render(&self, out: &mut String) {
+        write_header(out, self.name, self.help, "histogram");
+        for (key, series) in self.series.lock().unwrap().iter() {
+            let mut cumulative = 0;
+            for (bound, count) in self.bounds.iter().zip(&series.buckets) {
+                cumulative += count;
+                let le = bound.to_string();
+                let _ = writeln!(out, "{}_bucket{} {}", self.name, label_set(self.labels, key, Some(&le)), cumulative);
+            }
+            let _ = writeln!(out, "{}_bucket{} {}", self.name, label_set(self.labels, key, Some("+Inf")), series.count);
+            let _ = writeln!(out, "{}_sum{} {}", self.name, label_set(self.labels, key, None), series.sum);
+            let _ = writeln!(out, "{}_count{} {}", self.name, label_set(self.labels, key, None), series.count);
+        }
+    }
+}
+
+/// Appends a gauge computed at scrape time, one sample per label value.
+pub fn render_gauge(out: &mut String, name: &str, help: &str, label: &'static str, samples: &[(&str, u64)]) {
+    write_header(out, name, help, "gauge");
+    for (value, sample) in samples {
+        let _ = writeln!(out, "{}{} {}", name, label_set(&[label], &[value.to_string()], None), sample);
+    }
+}
+
+/// Every registered counter and histogram.
+pub fn render() -> String {
+    let mut out = String::new();
+    for counter in ALL_COUNTERS {
+        counter.render(&mut out);
+    }
+    for histogram in ALL_HISTOGRAMS {
+        histogram.render(&mut out);
+    }
+    out
+}
+
+fn write_header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// `{a="1",b="2"}`, with `le` appended for histogram buckets; empty when
+/// there are no labels at all.
+fn label_set(names: &[&str], values: &[String], le: Option<&str>) -> String {
+    let mut pairs: Vec<String> = names
+        .iter()
+        .zip(values)
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{}\"", le));
+    }
+
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counter_renders_each_label_set() {
+        let counter = Counter::new("test_total", "Test counter.", &["route", "outcome"]);
+        counter.inc(&["single", "leaked"]);
+        counter.inc_by(&["single", "leaked"], 2);
+        counter.inc(&["batch", "say \"hi\""]);
+
+        let mut out = String::new();
+        counter.render(&mut out);
+        assert_eq!(
+            out,
+            "# HELP test_total Test counter.\n\
+             # TYPE test_total counter\n\
+             test_total{route=\"batch\",outcome=\"say \\\"hi\\\"\"} 1\n\
+             test_total{route=\"single\",outcome=\"leaked\"} 3\n"
+        );
+
+        let unlabelled = Counter::new("idle_total", "Never incremented.", &[]);
+        let mut out = String::new();
+        unlabelled.render(&mut out);
+        assert!(out.ends_with("idle_total 0\n"));
+    }
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let histogram = Histogram::new("test_seconds", "Test histogram.", &[], &[0.1, 1.0]);
+        histogram.observe(&[], 0.05);
+        histogram.observe(&[], 0.5);
+        histogram.observe(&[], 0.5);
+        histogram.observe(&[], 5.0);
+
+        let mut out = String::new();
+        histogram.render(&mut out);
+        assert!(out.contains("test_seconds_bucket{le=\"0.1\"} 1\n"));
+        assert!(out.contains("test_seconds_bucket{le=\"1\"} 3\n"));
+        assert!(out.contains("test_seconds_bucket{le=\"+Inf\"} 4\n"));
+        assert!(out.contains("test_seconds_sum 6.05\n"));
+        assert!(out.contains("test_seconds_count 4\n"));
+    }
+}
@@ -1,3 +1,10 @@
 pub mod config;
 pub mod error;
-pub mod rate_limiter;
\ No newline at end of file
+pub mod logging;
+pub mod metrics;
+pub mod rate_limiter;
+pub mod redact;
+pub mod telemetry;
+#[cfg(test)]
+pub mod test_support;
+pub mod upload;
@@ -1,74 +1,507 @@
+//! Per-client rate limits.
+//!
+//! Every request first counts against a per-minute quota of its client IP,
+//! checked before authentication so failed attempts are limited as well. The
+//! credential check routes then have their own per-minute quota and an
+//! optional daily one, kept separately for each client: the API key or token
+//! subject of authenticated callers, otherwise the client IP. Behind a trusted
+//! proxy the IP is taken from `X-Forwarded-For`. Every response carries
+//! `X-RateLimit-Limit`, `X-RateLimit-Remaining` and `X-RateLimit-Reset` for
+//! the quota closest to running out; 429s add `Retry-After`.
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, HeaderValue, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
 use governor::{
-    clock::DefaultClock,
-    state::{InMemoryState, NotKeyed},
+    clock::{Clock, DefaultClock},
+    middleware::StateInformationMiddleware,
+    state::keyed::DefaultKeyedStateStore,
     Quota, RateLimiter as GovernorRateLimiter,
 };
+use serde::Deserialize;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
 use std::num::NonZeroU32;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::OnceLock;
 use std::time::Duration;
 
-use crate::utils::config;
+use crate::api::auth::Caller;
+use crate::utils::{config::{self, RateLimitConfig}, error::ApiError, metrics};
+
+pub const LIMIT_HEADER: &str = "x-ratelimit-limit";
+pub const REMAINING_HEADER: &str = "x-ratelimit-remaining";
+pub const RESET_HEADER: &str = "x-ratelimit-reset";
+
+const MINUTE: Duration = Duration::from_secs(60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Checks between two sweeps of clients whose quotas have fully recovered.
+const SWEEP_INTERVAL: u64 = 4096;
+
+/// A route with its own quotas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitedRoute {
+    /// Every route, keyed by client IP.
+    All,
+    Single,
+    Blinded,
+    Batch,
+}
+
+impl LimitedRoute {
+    /// The route's label in metrics.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LimitedRoute::All => "all",
+            LimitedRoute::Single => "single",
+            LimitedRoute::Blinded => "blinded",
+            LimitedRoute::Batch => "batch",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            LimitedRoute::All => "requests from this address",
+            LimitedRoute::Single => "single credential checks",
+            LimitedRoute::Blinded => "blinded credential checks",
+            LimitedRoute::Batch => "batch uploads",
+        }
+    }
+}
 
-struct RateLimitSettings {
-    single_credential_limit: NonZeroU32,
-    batch_credentials_limit: NonZeroU32,
+/// An address or CIDR block, e.g. `10.0.0.0/8`, listed in `TRUSTED_PROXIES`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix: u8,
 }
 
-impl RateLimitSettings {
-    fn new() -> Self {
-        let cfg = config::get();
-        
-        RateLimitSettings {
-            single_credential_limit: NonZeroU32::new(cfg.rate_limits.single_credential_rpm).unwrap_or(NonZeroU32::new(60).unwrap()),
-            batch_credentials_limit: NonZeroU32::new(cfg.rate_limits.batch_credential_rpm).unwrap_or(NonZeroU32::new(10).unwrap()),
+impl IpNetwork {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_matches(&net.octets(), &ip.octets(), self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_matches(&net.octets(), &ip.octets(), self.prefix)
+            }
+            (IpAddr::V4(_), IpAddr::V6(ip)) => {
+                ip.to_ipv4_mapped().is_some_and(|ip| self.contains(IpAddr::V4(ip)))
+            }
+            (IpAddr::V6(_), IpAddr::V4(_)) => false,
         }
     }
 }
 
-static SETTINGS: OnceLock<RateLimitSettings> = OnceLock::new();
+fn prefix_matches(net: &[u8], ip: &[u8], prefix: u8) -> bool {
+    let (bytes, bits) = ((prefix / 8) as usize, prefix % 8);
+    if net[..bytes] != ip[..bytes] {
+        return false;
+    }
+    bits == 0 || (net[bytes] ^ ip[bytes]) >> (8 - bits) == 0
+}
+
+impl FromStr for IpNetwork {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| anyhow::anyhow!("Invalid IP address '{}'", addr))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| anyhow::anyhow!("Invalid prefix length in '{}'", s.trim()))?,
+            None => max,
+        };
+        Ok(IpNetwork { addr, prefix })
+    }
+}
+
+impl TryFrom<String> for IpNetwork {
+    type Error = anyhow::Error;
 
-fn settings() -> &'static RateLimitSettings {
-    SETTINGS.get_or_init(RateLimitSettings::new)
+    fn try_from(s: String) -> anyhow::Result<Self> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Parses a comma separated `TRUSTED_PROXIES` list.
+pub fn parse_networks(list: &str) -> anyhow::Result<Vec<IpNetwork>> {
+    list.split(',').filter(|s| !s.trim().is_empty()).map(str::parse).collect()
+}
+
+type KeyedLimiter = GovernorRateLimiter<String, DefaultKeyedStateStore<String>, DefaultClock, StateInformationMiddleware>;
+
+struct RouteLimits {
+    per_minute: KeyedLimiter,
+    daily: Option<KeyedLimiter>,
+}
+
+impl RouteLimits {
+    /// A `per_minute` of 0 falls back to `default_rpm`; a `daily` of 0 means
+    /// no daily quota.
+    fn new(per_minute: u32, default_rpm: u32, daily: u32) -> Self {
+        let per_minute = NonZeroU32::new(per_minute).unwrap_or(NonZeroU32::new(default_rpm).unwrap());
+        RouteLimits {
+            per_minute: keyed(per_minute, MINUTE),
+            daily: NonZeroU32::new(daily).map(|daily| keyed(daily, DAY)),
+        }
+    }
+
+    fn limiters(&self) -> impl Iterator<Item = &KeyedLimiter> {
+        std::iter::once(&self.per_minute).chain(self.daily.as_ref())
+    }
+}
+
+/// `burst` requests at once, replenished evenly over `period`.
+fn keyed(burst: NonZeroU32, period: Duration) -> KeyedLimiter {
+    let quota = Quota::with_period(period / burst.get()).unwrap().allow_burst(burst);
+    GovernorRateLimiter::keyed(quota).with_middleware::<StateInformationMiddleware>()
+}
+
+/// Outcome of one check, as reported in the response headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimitStatus {
+    pub limit: u32,
+    pub remaining: u32,
+    /// Time until the quota is fully replenished.
+    pub reset: Duration,
+    /// Set when the request was rejected.
+    pub retry_after: Option<Duration>,
+}
+
+impl RateLimitStatus {
+    pub fn allowed(&self) -> bool {
+        self.retry_after.is_none()
+    }
+
+    /// The status to report of this and a route's quota: a rejection, else
+    /// the one with fewer requests left.
+    fn tightest(self, route: Option<RateLimitStatus>) -> RateLimitStatus {
+        match route {
+            Some(route) if !route.allowed() || route.remaining < self.remaining => route,
+            _ => self,
+        }
+    }
+
+    fn apply(&self, headers: &mut HeaderMap) {
+        headers.insert(LIMIT_HEADER, HeaderValue::from(self.limit));
+        headers.insert(REMAINING_HEADER, HeaderValue::from(self.remaining));
+        headers.insert(RESET_HEADER, HeaderValue::from(whole_seconds(self.reset)));
+        if let Some(retry_after) = self.retry_after {
+            headers.insert(axum::http::header::RETRY_AFTER, HeaderValue::from(whole_seconds(retry_after)));
+        }
+    }
+}
+
+fn whole_seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
 }
 
 pub struct RateLimiter {
-    single_credential_limiter: GovernorRateLimiter<NotKeyed, InMemoryState, DefaultClock>,
-    batch_credentials_limiter: GovernorRateLimiter<NotKeyed, InMemoryState, DefaultClock>,
+    all: RouteLimits,
+    single: RouteLimits,
+    blinded: RouteLimits,
+    batch: RouteLimits,
+    checks: AtomicU64,
 }
 
 impl RateLimiter {
-    pub fn new() -> Self {
-        let settings = settings();
+    pub fn new(limits: &RateLimitConfig) -> Self {
+        RateLimiter {
+            all: RouteLimits::new(limits.ip_rpm, 600, 0),
+            single: RouteLimits::new(limits.single_credential_rpm, 60, limits.single_credential_daily),
+            blinded: RouteLimits::new(limits.blinded_credential_rpm, 60, limits.blinded_credential_daily),
+            batch: RouteLimits::new(limits.batch_credential_rpm, 10, limits.batch_credential_daily),
+            checks: AtomicU64::new(0),
+        }
+    }
+
+    /// Counts a request of `client` against the route's quotas. A request
+    /// turned away by the daily quota still uses up per-minute capacity.
+    pub fn check(&self, route: LimitedRoute, client: &str) -> RateLimitStatus {
+        if self.checks.fetch_add(1, Ordering::Relaxed) % SWEEP_INTERVAL == SWEEP_INTERVAL - 1 {
+            self.sweep();
+        }
 
-        let single_credential_limiter = GovernorRateLimiter::direct(
-            Quota::with_period(Duration::from_secs(60))
-                .unwrap()
-                .allow_burst(settings.single_credential_limit),
-        );
+        let key = client.to_string();
+        let mut tightest: Option<RateLimitStatus> = None;
 
-        let batch_credentials_limiter = GovernorRateLimiter::direct(
-            Quota::with_period(Duration::from_secs(60))
-                .unwrap()
-                .allow_burst(settings.batch_credentials_limit),
-        );
+        for limiter in self.route(route).limiters() {
+            let status = match limiter.check_key(&key) {
+                Ok(snapshot) => {
+                    let quota = snapshot.quota();
+                    let remaining = snapshot.remaining_burst_capacity();
+                    RateLimitStatus {
+                        limit: quota.burst_size().get(),
+                        remaining,
+                        reset: quota.replenish_interval() * (quota.burst_size().get() - remaining),
+                        retry_after: None,
+                    }
+                }
+                Err(not_until) => {
+                    let quota = not_until.quota();
+                    let retry_after = not_until.wait_time_from(DefaultClock::default().now());
+                    return RateLimitStatus {
+                        limit: quota.burst_size().get(),
+                        remaining: 0,
+                        reset: quota.burst_size_replenished_in(),
+                        retry_after: Some(retry_after),
+                    };
+                }
+            };
 
-        RateLimiter {
-            single_credential_limiter,
-            batch_credentials_limiter,
+            if tightest.as_ref().map_or(true, |tightest| status.remaining < tightest.remaining) {
+                tightest = Some(status);
+            }
         }
+
+        tightest.expect("Every route has a per-minute quota")
     }
 
-    pub async fn check_single_credential_limit(&self) -> bool {
-        self.single_credential_limiter.check().is_ok()
+    fn route(&self, route: LimitedRoute) -> &RouteLimits {
+        match route {
+            LimitedRoute::All => &self.all,
+            LimitedRoute::Single => &self.single,
+            LimitedRoute::Blinded => &self.blinded,
+            LimitedRoute::Batch => &self.batch,
+        }
     }
 
-    pub async fn check_batch_credentials_limit(&self) -> bool {
-        self.batch_credentials_limiter.check().is_ok()
+    /// Forgets clients whose quotas have fully recovered.
+    fn sweep(&self) {
+        for route in [&self.all, &self.single, &self.blinded, &self.batch] {
+            for limiter in route.limiters() {
+                limiter.retain_recent();
+                limiter.shrink_to_fit();
+            }
+        }
     }
 }
 
 static RATE_LIMITER: OnceLock<RateLimiter> = OnceLock::new();
 
 pub fn get_rate_limiter() -> &'static RateLimiter {
-    RATE_LIMITER.get_or_init(RateLimiter::new)
-}
\ No newline at end of file
+    RATE_LIMITER.get_or_init(|| RateLimiter::new(&config::get().rate_limits))
+}
+
+/// Applies the per-address quota to every request. Must run before
+/// `authenticate`, so requests failing authentication count too, and around
+/// `rate_limit`, whose status it reports when that quota is tighter.
+pub async fn limit_address<B>(request: Request<B>, next: Next<B>) -> Response {
+    let client = request_client(&request);
+    let status = get_rate_limiter().check(LimitedRoute::All, &client);
+    if !status.allowed() {
+        return rejected(LimitedRoute::All, &status);
+    }
+
+    let mut response = next.run(request).await;
+    let route_status = response.extensions_mut().remove::<RateLimitStatus>();
+    status.tightest(route_status).apply(response.headers_mut());
+    response
+}
+
+/// Applies `route`'s quotas to the request's client. Must run after
+/// `authenticate` so authenticated callers are told apart by identity. The
+/// status is left in the response extensions for `limit_address` to report.
+pub async fn rate_limit<B>(
+    State(route): State<LimitedRoute>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let client = request_client(&request);
+    let status = get_rate_limiter().check(route, &client);
+    let mut response = if status.allowed() {
+        next.run(request).await
+    } else {
+        rejected(route, &status)
+    };
+
+    response.extensions_mut().insert(status);
+    response
+}
+
+fn rejected(route: LimitedRoute, status: &RateLimitStatus) -> Response {
+    metrics::RATE_LIMITED.inc(&[route.as_str()]);
+    let mut response = ApiError::RateLimited(format!("Rate limit exceeded for {}", route.description())).into_response();
+    status.apply(response.headers_mut());
+    response
+}
+
+fn request_client<B>(request: &Request<B>) -> String {
+    let peer = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|info| info.0.ip());
+    client_id(
+        request.extensions().get::<Caller>(),
+        peer,
+        request.headers(),
+        &config::get().rate_limits.trusted_proxies,
+    )
+}
+
+/// The key a client's quotas are kept under.
+fn client_id(caller: Option<&Caller>, peer: Option<IpAddr>, headers: &HeaderMap, trusted: &[IpNetwork]) -> String {
+    if let Some(id) = caller.and_then(Caller::id) {
+        return format!("caller:{}", id);
+    }
+
+    let forwarded_for = headers.get("x-forwarded-for").and_then(|v| v.to_str().ok());
+    match client_ip(peer, forwarded_for, trusted) {
+        Some(ip) => format!("ip:{}", ip),
+        None => "unknown".to_string(),
+    }
+}
+
+/// The peer address, or when the peer is a trusted proxy, the right-most
+/// `X-Forwarded-For` entry that is not. Entries left of an untrusted hop could
+/// have been written by the client itself, so they are never used.
+fn client_ip(peer: Option<IpAddr>, forwarded_for: Option<&str>, trusted: &[IpNetwork]) -> Option<IpAddr> {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|network| network.contains(ip));
+
+    let mut client = peer?;
+    if let Some(forwarded_for) = forwarded_for {
+        for hop in forwarded_for.rsplit(',') {
+            if !is_trusted(client) {
+                break;
+            }
+            match hop.trim().parse() {
+                Ok(ip) => client = ip,
+                Err(_) => break,
+            }
+        }
+    }
+    Some(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::auth::Caller;
+    use crate::services::jwt::Principal;
+
+    fn limits(single_rpm: u32, single_daily: u32) -> RateLimitConfig {
+        RateLimitConfig {
+            ip_rpm: 5,
+            single_credential_rpm: single_rpm,
+            batch_credential_rpm: 10,
+            blinded_credential_rpm: 60,
+            single_credential_daily: single_daily,
+            blinded_credential_daily: 0,
+            batch_credential_daily: 0,
+            max_batch_size: 10000,
+            max_upload_bytes: 1024,
+            trusted_proxies: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_ip_network_matching() {
+        let private: IpNetwork = "10.0.0.0/8".parse().unwrap();
+        assert!(private.contains("10.200.3.4".parse().unwrap()));
+        assert!(!private.contains("11.0.0.1".parse().unwrap()));
+        assert!(private.contains("::ffff:10.1.2.3".parse().unwrap()));
+
+        let odd: IpNetwork = "192.168.4.0/22".parse().unwrap();
+        assert!(odd.contains("192.168.7.255".parse().unwrap()));
+        assert!(!odd.contains("192.168.8.0".parse().unwrap()));
+
+        let host: IpNetwork = "::1".parse().unwrap();
+        assert!(host.contains("::1".parse().unwrap()));
+        assert!(!host.contains("127.0.0.1".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<IpNetwork>().is_err());
+        assert!("proxy.internal".parse::<IpNetwork>().is_err());
+        assert_eq!(parse_networks(" 127.0.0.1, 10.0.0.0/8 ,").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_forwarded_for_is_only_trusted_from_proxies() {
+        let trusted = parse_networks("10.0.0.0/8").unwrap();
+        let proxy = Some("10.0.0.5".parse().unwrap());
+        let direct = Some("203.0.113.9".parse().unwrap());
+        let ip = |s: &str| Some(s.parse::<IpAddr>().unwrap());
+
+        assert_eq!(client_ip(proxy, Some("198.51.100.7"), &trusted), ip("198.51.100.7"));
+        // A spoofed entry left of the real client is ignored.
+        assert_eq!(client_ip(proxy, Some("1.2.3.4, 198.51.100.7, 10.0.0.9"), &trusted), ip("198.51.100.7"));
+        assert_eq!(client_ip(direct, Some("198.51.100.7"), &trusted), direct);
+        assert_eq!(client_ip(proxy, Some("garbage"), &trusted), proxy);
+        assert_eq!(client_ip(proxy, None, &trusted), proxy);
+        assert_eq!(client_ip(None, Some("198.51.100.7"), &trusted), None);
+    }
+
+    #[test]
+    fn test_authenticated_callers_are_keyed_by_identity() {
+        let headers = HeaderMap::new();
+        let peer = Some("203.0.113.9".parse().unwrap());
+        let caller = Caller::Token(Principal { subject: "alice".to_string(), scopes: Vec::new() });
+
+        assert_eq!(client_id(Some(&caller), peer, &headers, &[]), "caller:alice");
+        assert_eq!(client_id(Some(&Caller::Anonymous), peer, &headers, &[]), "ip:203.0.113.9");
+        assert_eq!(client_id(None, None, &headers, &[]), "unknown");
+    }
+
+    #[test]
+    fn test_quotas_are_kept_per_client() {
+        let limiter = RateLimiter::new(&limits(2, 0));
+
+        let first = limiter.check(LimitedRoute::Single, "a");
+        assert_eq!((first.limit, first.remaining), (2, 1));
+        assert!(first.reset > Duration::ZERO && first.reset <= MINUTE);
+        assert!(limiter.check(LimitedRoute::Single, "a").allowed());
+
+        let rejected = limiter.check(LimitedRoute::Single, "a");
+        assert_eq!(rejected.remaining, 0);
+        assert!(rejected.retry_after.is_some_and(|wait| wait > Duration::ZERO && wait <= MINUTE));
+
+        assert!(limiter.check(LimitedRoute::Single, "b").allowed());
+        assert!(limiter.check(LimitedRoute::Blinded, "a").allowed());
+        assert_eq!(limiter.check(LimitedRoute::All, "a").limit, 5);
+    }
+
+    #[test]
+    fn test_daily_quota_reports_the_tightest_limit() {
+        let limiter = RateLimiter::new(&limits(10, 3));
+
+        let status = limiter.check(LimitedRoute::Single, "a");
+        assert_eq!((status.limit, status.remaining), (3, 2));
+        limiter.check(LimitedRoute::Single, "a");
+        limiter.check(LimitedRoute::Single, "a");
+
+        let rejected = limiter.check(LimitedRoute::Single, "a");
+        assert_eq!(rejected.limit, 3);
+        assert!(rejected.retry_after.is_some_and(|wait| wait > MINUTE));
+    }
+
+    #[test]
+    fn test_reported_status_is_the_tightest() {
+        let status = |remaining, rejected: bool| RateLimitStatus {
+            limit: 10,
+            remaining,
+            reset: MINUTE,
+            retry_after: rejected.then_some(MINUTE),
+        };
+
+        assert_eq!(status(5, false).tightest(None), status(5, false));
+        assert_eq!(status(5, false).tightest(Some(status(2, false))), status(2, false));
+        assert_eq!(status(2, false).tightest(Some(status(5, false))), status(2, false));
+        assert_eq!(status(0, false).tightest(Some(status(0, true))), status(0, true));
+    }
+}
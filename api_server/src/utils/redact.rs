@@ -0,0 +1,198 @@
+//! What may appear in logs about the credentials being checked.
+//!
+//! Usernames and hashes are logged through [`username`] and [`hash`], which
+//! by default print a keyed pseudonym: the same input always maps to the same
+//! pseudonym under one `LOG_PSEUDONYM_KEY`, so a user's requests can be
+//! followed through the logs without the logs revealing who they are.
+//! Passwords, tokens and keys have no such escape hatch: log fields named
+//! after them are always replaced, whatever the policy.
+
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use once_cell::sync::OnceCell;
+use rand::RngCore;
+use serde::Deserialize;
+use sha2::Sha256;
+use std::fmt;
+use std::str::FromStr;
+
+pub const REDACTED: &str = "<redacted>";
+
+/// Pseudonyms keep this many bytes of the HMAC, enough to tell the users of
+/// one deployment apart.
+const PSEUDONYM_BYTES: usize = 8;
+
+/// Log field names whose values are never written.
+const SECRET_FIELDS: &[&str] = &["password", "secret", "token", "api_key", "authorization", "private_key"];
+
+/// Log field names whose values are usernames.
+const USERNAME_FIELDS: &[&str] = &["username", "email"];
+
+static POLICY: OnceCell<Policy> = OnceCell::new();
+
+/// How usernames and hashes are logged, selected with `LOG_REDACTION`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Redaction {
+    /// Keyed pseudonyms, e.g. `user:3f9c0a71d2e4b856`.
+    Pseudonymize,
+    /// A fixed placeholder.
+    Mask,
+    /// The values themselves, for local debugging only.
+    Off,
+}
+
+impl FromStr for Redaction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "pseudonymize" => Ok(Redaction::Pseudonymize),
+            "mask" => Ok(Redaction::Mask),
+            "off" => Ok(Redaction::Off),
+            other => Err(anyhow!("Unknown redaction policy '{}'", other)),
+        }
+    }
+}
+
+struct Policy {
+    redaction: Redaction,
+    key: Vec<u8>,
+}
+
+impl Policy {
+    /// Without a key, pseudonyms are only stable until the process restarts.
+    fn new(redaction: Redaction, key: Option<&str>) -> Self {
+        let key = match key {
+            Some(key) => key.as_bytes().to_vec(),
+            None => {
+                let mut key = vec![0; 32];
+                rand::thread_rng().fill_bytes(&mut key);
+                key
+            }
+        };
+        Policy { redaction, key }
+    }
+
+    fn pseudonym(&self, kind: &str, value: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(kind.as_bytes());
+        mac.update(&[0]);
+        mac.update(value);
+        let digest = mac.finalize().into_bytes();
+        format!("{}:{}", kind, hex::encode(&digest[..PSEUDONYM_BYTES]))
+    }
+}
+
+/// Sets the policy used from now on. Only the first call has an effect;
+/// until then usernames and hashes are pseudonymized under a random key.
+pub fn init(redaction: Redaction, key: Option<&str>) {
+    let _ = POLICY.set(Policy::new(redaction, key));
+}
+
+fn policy() -> &'static Policy {
+    POLICY.get_or_init(|| Policy::new(Redaction::Pseudonymize, None))
+}
+
+/// A username or hash as the policy allows it to be logged.
+pub struct Redacted<'a> {
+    policy: &'a Policy,
+    kind: Kind<'a>,
+}
+
+enum Kind<'a> {
+    Username(&'a str),
+    Hash(&'a [u8]),
+}
+
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.policy.redaction, &self.kind) {
+            (Redaction::Off, Kind::Username(username)) => f.write_str(username),
+            (Redaction::Off, Kind::Hash(bytes)) => f.write_str(&hex::encode(bytes)),
+            (Redaction::Mask, _) => f.write_str(REDACTED),
+            (Redaction::Pseudonymize, Kind::Username(username)) => {
+                f.write_str(&self.policy.pseudonym("user", username.as_bytes()))
+            }
+            (Redaction::Pseudonymize, Kind::Hash(bytes)) => f.write_str(&self.policy.pseudonym("hash", bytes)),
+        }
+    }
+}
+
+impl fmt::Debug for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+pub fn username(username: &str) -> Redacted<'_> {
+    Redacted { policy: policy(), kind: Kind::Username(username) }
+}
+
+/// A hash, prefix or ciphertext derived from a credential.
+pub fn hash(bytes: &[u8]) -> Redacted<'_> {
+    Redacted { policy: policy(), kind: Kind::Hash(bytes) }
+}
+
+/// What a log field named `name` holding `value` is written as, when that is
+/// not `value` itself.
+pub fn field(name: &str, value: &str) -> Option<String> {
+    let name = name.to_lowercase();
+    if SECRET_FIELDS.iter().any(|secret| name.contains(secret)) {
+        Some(REDACTED.to_string())
+    } else if USERNAME_FIELDS.contains(&name.as_str()) {
+        Some(username(value).to_string())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn show(policy: &Policy, kind: Kind<'_>) -> String {
+        Redacted { policy, kind }.to_string()
+    }
+
+    #[test]
+    fn test_pseudonyms_are_keyed_and_stable() {
+        let policy = Policy::new(Redaction::Pseudonymize, Some("log-key"));
+        let alice = show(&policy, Kind::Username("alice@example.com"));
+        assert!(alice.starts_with("user:"));
+        assert_eq!(alice.len(), "user:".len() + 2 * PSEUDONYM_BYTES);
+        assert!(!alice.contains("alice"));
+        assert_eq!(alice, show(&policy, Kind::Username("alice@example.com")));
+        assert_ne!(alice, show(&policy, Kind::Username("bob@example.com")));
+
+        let other_key = Policy::new(Redaction::Pseudonymize, Some("other-key"));
+        assert_ne!(alice, show(&other_key, Kind::Username("alice@example.com")));
+
+        // A username and a hash of the same bytes do not share a pseudonym.
+        let hash = show(&policy, Kind::Hash(b"alice@example.com"));
+        assert!(hash.starts_with("hash:"));
+        assert_ne!(alice[5..], hash[5..]);
+    }
+
+    #[test]
+    fn test_mask_and_off_policies() {
+        let mask = Policy::new(Redaction::Mask, None);
+        assert_eq!(show(&mask, Kind::Username("alice")), REDACTED);
+        assert_eq!(show(&mask, Kind::Hash(&[0xab])), REDACTED);
+
+        let off = Policy::new(Redaction::Off, None);
+        assert_eq!(show(&off, Kind::Username("alice")), "alice");
+        assert_eq!(show(&off, Kind::Hash(&[0xab, 0x01])), "ab01");
+    }
+
+    #[test]
+    fn test_secret_fields_are_always_redacted() {
+        for name in ["password", "master_password", "access_token", "callback_secret", "Authorization"] {
+            assert_eq!(field(name, "hunter2").as_deref(), Some(REDACTED), "{}", name);
+        }
+        assert!(field("username", "alice").is_some_and(|value| value != "alice"));
+        assert_eq!(field("job_id", "1234"), None);
+        assert!("pseudonymize".parse::<Redaction>().is_ok());
+        assert!("plain".parse::<Redaction>().is_err());
+    }
+}
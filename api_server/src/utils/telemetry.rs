@@ -0,0 +1,446 @@
+//! Trace export over OTLP/HTTP with JSON encoding, which OpenTelemetry
+//! collectors accept on their HTTP port (4318).
+//!
+//! [`OtlpLayer`] turns this crate's spans into OTLP spans: a span inherits
+//! the trace of the nearest exported span above it, and a span without one
+//! starts a trace, or continues the one named by a `traceparent` field, which
+//! the router fills from the incoming header. Span fields become attributes,
+//! events inside a span become span events, and `otel.kind` sets the kind.
+//! Finished spans are batched and sent by a background task; when the
+//! collector falls behind, spans are dropped rather than queued without
+//! bound.
+
+use anyhow::{anyhow, Result};
+use rand::RngCore;
+use reqwest::Client;
+use serde_json::{json, Map, Value};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, oneshot};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::utils::config::TelemetryConfig;
+use crate::utils::logging::JsonVisitor;
+
+/// Only spans from this crate are exported, so the HTTP client sending the
+/// spans cannot feed its own spans back into the export.
+const CRATE_NAME: &str = env!("CARGO_CRATE_NAME");
+
+const QUEUE_SIZE: usize = 4096;
+const MAX_BATCH: usize = 512;
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Events kept per span; later ones are dropped.
+const MAX_EVENTS: usize = 32;
+
+// OTLP span kinds and status codes.
+const KIND_INTERNAL: u8 = 1;
+const KIND_SERVER: u8 = 2;
+const KIND_CLIENT: u8 = 3;
+const STATUS_ERROR: u8 = 2;
+
+/// A W3C trace context, as carried by the `traceparent` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceParent {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub sampled: bool,
+}
+
+impl TraceParent {
+    /// Parses `00-<trace id>-<parent span id>-<flags>`. Later versions may
+    /// append fields, which are ignored.
+    pub fn parse(header: &str) -> Option<Self> {
+        let mut parts = header.trim().split('-');
+        let version = parts.next().filter(|v| v.len() == 2 && *v != "ff")?;
+        let trace_id = decode_id::<16>(parts.next()?)?;
+        let span_id = decode_id::<8>(parts.next()?)?;
+        let flags = parts.next().filter(|f| f.len() == 2)?;
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        if version == "00" && parts.next().is_some() {
+            return None;
+        }
+        Some(TraceParent { trace_id, span_id, sampled: flags & 1 == 1 })
+    }
+}
+
+fn decode_id<const N: usize>(hex_id: &str) -> Option<[u8; N]> {
+    if hex_id.len() != 2 * N || hex_id.bytes().any(|b| b.is_ascii_uppercase()) {
+        return None;
+    }
+    let mut id = [0; N];
+    hex::decode_to_slice(hex_id, &mut id).ok()?;
+    // All-zero IDs are invalid.
+    id.iter().any(|b| *b != 0).then_some(id)
+}
+
+/// Everything known about an exported span until it closes.
+struct SpanData {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent_span_id: Option<[u8; 8]>,
+    sampled: bool,
+    kind: u8,
+    start: SystemTime,
+    attributes: Map<String, Value>,
+    events: Vec<Value>,
+    error: Option<String>,
+}
+
+enum Message {
+    Span(Value),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Builds the layer and starts the exporter when `OTEL_EXPORTER_OTLP_ENDPOINT`
+/// (or `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`) is set. Must be called from
+/// within the Tokio runtime.
+pub fn init(config: &TelemetryConfig) -> Option<(OtlpLayer, Exporter)> {
+    let url = config.traces_url()?;
+    let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+    let resource = json!({
+        "attributes": [attribute("service.name", &Value::from(config.service_name.as_str()))],
+    });
+    tokio::spawn(export_loop(receiver, url, resource));
+
+    Some((OtlpLayer { sender: sender.clone() }, Exporter { sender }))
+}
+
+/// Handle on the background exporter.
+pub struct Exporter {
+    sender: mpsc::Sender<Message>,
+}
+
+impl Exporter {
+    /// Sends every finished span now, e.g. before the process exits.
+    pub async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        if self.sender.send(Message::Flush(done)).await.is_ok() {
+            let _ = flushed.await;
+        }
+    }
+}
+
+pub struct OtlpLayer {
+    sender: mpsc::Sender<Message>,
+}
+
+impl<S> Layer<S> for OtlpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if !attrs.metadata().target().starts_with(CRATE_NAME) {
+            return;
+        }
+        let Some(span) = ctx.span(id) else { return };
+
+        let mut attributes = Map::new();
+        attrs.record(&mut JsonVisitor(&mut attributes));
+        let remote = attributes
+            .remove("traceparent")
+            .and_then(|header| header.as_str().and_then(TraceParent::parse));
+        let kind = match attributes.remove("otel.kind").as_ref().and_then(Value::as_str) {
+            Some("server") => KIND_SERVER,
+            Some("client") => KIND_CLIENT,
+            _ => KIND_INTERNAL,
+        };
+
+        let parent = span.scope().skip(1).find_map(|ancestor| {
+            ancestor
+                .extensions()
+                .get::<SpanData>()
+                .map(|data| TraceParent { trace_id: data.trace_id, span_id: data.span_id, sampled: data.sampled })
+        });
+        let (trace_id, parent_span_id, sampled) = match parent.or(remote) {
+            Some(parent) => (parent.trace_id, Some(parent.span_id), parent.sampled),
+            None => (random_id(), None, true),
+        };
+
+        span.extensions_mut().insert(SpanData {
+            trace_id,
+            span_id: random_id(),
+            parent_span_id,
+            sampled,
+            kind,
+            start: SystemTime::now(),
+            attributes,
+            events: Vec::new(),
+            error: None,
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let mut extensions = span.extensions_mut();
+        if let Some(data) = extensions.get_mut::<SpanData>() {
+            values.record(&mut JsonVisitor(&mut data.attributes));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.event_span(event) else { return };
+        let mut extensions = span.extensions_mut();
+        let Some(data) = extensions.get_mut::<SpanData>() else { return };
+
+        let mut fields = Map::new();
+        event.record(&mut JsonVisitor(&mut fields));
+        let name = fields.remove("message").and_then(|m| m.as_str().map(str::to_string)).unwrap_or_default();
+
+        if *event.metadata().level() == Level::ERROR && data.error.is_none() {
+            data.error = Some(name.clone());
+        }
+        if data.events.len() < MAX_EVENTS {
+            data.events.push(json!({
+                "timeUnixNano": unix_nanos(SystemTime::now()),
+                "name": name,
+                "attributes": attributes(&fields),
+            }));
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else { return };
+        let Some(data) = span.extensions_mut().remove::<SpanData>() else { return };
+        if !data.sampled {
+            return;
+        }
+
+        let server_error = data.attributes.get("http.status_code").and_then(Value::as_u64).is_some_and(|s| s >= 500);
+        let mut otlp = json!({
+            "traceId": hex::encode(data.trace_id),
+            "spanId": hex::encode(data.span_id),
+            "name": span.name(),
+            "kind": data.kind,
+            "startTimeUnixNano": unix_nanos(data.start),
+            "endTimeUnixNano": unix_nanos(SystemTime::now()),
+            "attributes": attributes(&data.attributes),
+            "events": data.events,
+        });
+        if let Some(parent) = data.parent_span_id {
+            otlp["parentSpanId"] = hex::encode(parent).into();
+        }
+        if data.error.is_some() || server_error {
+            otlp["status"] = json!({"code": STATUS_ERROR, "message": data.error.unwrap_or_default()});
+        }
+
+        // A full queue means the collector is not keeping up; drop the span.
+        let _ = self.sender.try_send(Message::Span(otlp));
+    }
+}
+
+async fn export_loop(mut receiver: mpsc::Receiver<Message>, url: String, resource: Value) {
+    let client = Client::builder().timeout(EXPORT_TIMEOUT).build().unwrap_or_default();
+    let mut batch = Vec::new();
+    let mut failing = false;
+    let mut ticker = tokio::time::interval(EXPORT_INTERVAL);
+
+    loop {
+        tokio::select! {
+            message = receiver.recv() => match message {
+                Some(Message::Span(span)) => {
+                    batch.push(span);
+                    if batch.len() >= MAX_BATCH {
+                        export(&client, &url, &resource, &mut batch, &mut failing).await;
+                    }
+                }
+                Some(Message::Flush(done)) => {
+                    export(&client, &url, &resource, &mut batch, &mut failing).await;
+                    let _ = done.send(());
+                }
+                None => {
+                    export(&client, &url, &resource, &mut batch, &mut failing).await;
+                    return;
+                }
+            },
+            _ = ticker.tick() => export(&client, &url, &resource, &mut batch, &mut failing).await,
+        }
+    }
+}
+
+/// Sends and clears `batch`. Failures are logged once until an export
+/// succeeds again; the spans of failed exports are dropped.
+async fn export(client: &Client, url: &str, resource: &Value, batch: &mut Vec<Value>, failing: &mut bool) {
+    if batch.is_empty() {
+        return;
+    }
+
+    let body = json!({
+        "resourceSpans": [{
+            "resource": resource,
+            "scopeSpans": [{"scope": {"name": CRATE_NAME}, "spans": std::mem::take(batch)}],
+        }],
+    });
+    let result = client
+        .post(url)
+        .json(&body)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+
+    match result {
+        Ok(_) if *failing => {
+            tracing::info!("Trace export to {} recovered", url);
+            *failing = false;
+        }
+        Ok(_) => {}
+        Err(e) if !*failing => {
+            tracing::warn!("Failed to export traces to {}: {}", url, e);
+            *failing = true;
+        }
+        Err(_) => {}
+    }
+}
+
+fn attributes(fields: &Map<String, Value>) -> Vec<Value> {
+    fields.iter().map(|(key, value)| attribute(key, value)).collect()
+}
+
+fn attribute(key: &str, value: &Value) -> Value {
+    let value = match value {
+        Value::Bool(b) => json!({"boolValue": b}),
+        Value::Number(n) if n.is_i64() || n.is_u64() => json!({"intValue": n.to_string()}),
+        Value::Number(n) => json!({"doubleValue": n.as_f64()}),
+        Value::String(s) => json!({"stringValue": s}),
+        other => json!({"stringValue": other.to_string()}),
+    };
+    json!({"key": key, "value": value})
+}
+
+/// OTLP/JSON carries 64-bit integers as strings.
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos().to_string()
+}
+
+fn random_id<const N: usize>() -> [u8; N] {
+    let mut id = [0; N];
+    while id.iter().all(|b| *b == 0) {
+        rand::thread_rng().fill_bytes(&mut id);
+    }
+    id
+}
+
+/// The OTLP protocols `OTEL_EXPORTER_OTLP_PROTOCOL` may name.
+pub fn check_protocol(protocol: &str) -> Result<()> {
+    match protocol.trim() {
+        "http/json" => Ok(()),
+        other => Err(anyhow!("Unsupported OTLP protocol '{}', only http/json is supported", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::prelude::*;
+
+    fn capture(f: impl FnOnce()) -> Vec<Value> {
+        let (sender, mut receiver) = mpsc::channel(QUEUE_SIZE);
+        let subscriber = tracing_subscriber::registry().with(OtlpLayer { sender });
+        tracing::subscriber::with_default(subscriber, f);
+
+        let mut spans = Vec::new();
+        while let Ok(Message::Span(span)) = receiver.try_recv() {
+            spans.push(span);
+        }
+        spans
+    }
+
+    fn find<'a>(spans: &'a [Value], name: &str) -> &'a Value {
+        spans.iter().find(|span| span["name"] == name).unwrap()
+    }
+
+    #[test]
+    fn test_parse_traceparent() {
+        let parent = TraceParent::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+        assert_eq!(hex::encode(parent.trace_id), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(hex::encode(parent.span_id), "00f067aa0ba902b7");
+        assert!(parent.sampled);
+        assert!(!TraceParent::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00").unwrap().sampled);
+
+        // Future versions may carry more fields.
+        assert!(TraceParent::parse("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra").is_some());
+
+        for invalid in [
+            "",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "00-4bf92f3577b34da6a3ce929d0e0e47-00f067aa0ba902b7-01",
+        ] {
+            assert_eq!(TraceParent::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_spans_form_a_trace_under_the_remote_parent() {
+        let spans = capture(|| {
+            let request = tracing::info_span!(
+                "http",
+                otel.kind = "server",
+                traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+                http.status_code = tracing::field::Empty,
+            );
+            let _entered = request.enter();
+            tracing::info_span!("scrypt").in_scope(|| {});
+            tracing::info_span!("upstream", otel.kind = "client", username = "alice@example.com").in_scope(|| {
+                tracing::error!(attempt = 1, "Lookup failed");
+            });
+            request.record("http.status_code", 502);
+        });
+
+        assert_eq!(spans.len(), 3);
+        let request = find(&spans, "http");
+        let scrypt = find(&spans, "scrypt");
+        let upstream = find(&spans, "upstream");
+
+        for span in &spans {
+            assert_eq!(span["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+        }
+        assert_eq!(request["parentSpanId"], "00f067aa0ba902b7");
+        assert_eq!(scrypt["parentSpanId"], request["spanId"]);
+        assert_eq!(upstream["parentSpanId"], request["spanId"]);
+
+        assert_eq!(request["kind"], KIND_SERVER);
+        assert_eq!(scrypt["kind"], KIND_INTERNAL);
+        assert_eq!(upstream["kind"], KIND_CLIENT);
+
+        // The 502 and the error event both mark their span as failed.
+        assert_eq!(request["status"]["code"], STATUS_ERROR);
+        assert_eq!(upstream["status"]["message"], "Lookup failed");
+        assert_eq!(upstream["events"][0]["name"], "Lookup failed");
+        assert!(scrypt.get("status").is_none());
+
+        // Attributes are redacted like log fields.
+        let username = &upstream["attributes"][0];
+        assert_eq!(username["key"], "username");
+        assert!(username["value"]["stringValue"].as_str().unwrap().starts_with("user:"));
+        assert!(request["attributes"].to_string().contains(r#""intValue":"502""#));
+    }
+
+    #[test]
+    fn test_unsampled_and_foreign_spans_are_not_exported() {
+        let spans = capture(|| {
+            let request = tracing::info_span!(
+                "http",
+                traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00",
+            );
+            request.in_scope(|| tracing::info_span!("scrypt").in_scope(|| {}));
+
+            tracing::info_span!(target: "hyper::client", "connect").in_scope(|| {});
+        });
+        assert!(spans.is_empty(), "{:?}", spans);
+
+        // Without a traceparent, each root span starts its own trace.
+        let spans = capture(|| {
+            tracing::info_span!("first").in_scope(|| {});
+            tracing::info_span!("second").in_scope(|| {});
+        });
+        assert_ne!(spans[0]["traceId"], spans[1]["traceId"]);
+        assert!(spans[0].get("parentSpanId").is_none());
+    }
+}
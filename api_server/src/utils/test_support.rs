@@ -0,0 +1,21 @@
+//! Helpers shared by unit tests across modules.
+
+use std::path::PathBuf;
+
+/// A SQLite file in the temp directory, removed along with its WAL files on drop.
+pub struct TempDatabase(pub PathBuf);
+
+impl TempDatabase {
+    /// A fresh path named `leaklens-<prefix>-<uuid>.sqlite3`.
+    pub fn new(prefix: &str) -> Self {
+        TempDatabase(std::env::temp_dir().join(format!("leaklens-{}-{}.sqlite3", prefix, uuid::Uuid::new_v4())))
+    }
+}
+
+impl Drop for TempDatabase {
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", self.0.display(), suffix));
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! Reading batch uploads: the multipart stream is consumed chunk by chunk
+//! under a byte cap, then gzip and zip archives are unpacked under the same cap.
+
+use anyhow::{anyhow, bail, Result};
+use axum::extract::multipart::Field;
+use std::io::{Cursor, Read};
+
+use crate::utils::error::ApiError;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZIP_MAGIC: [u8; 4] = *b"PK\x03\x04";
+
+/// Room left in the request body limit for the form fields around the file.
+pub const MULTIPART_OVERHEAD: usize = 64 * 1024;
+
+/// Reads a multipart field, failing as soon as it grows past `max_bytes`
+/// instead of buffering the whole upload first.
+pub async fn read_field(mut field: Field<'_>, max_bytes: usize) -> Result<Vec<u8>, ApiError> {
+    let mut bytes = Vec::new();
+
+    while let Some(chunk) = field.chunk().await
+        .map_err(|e| ApiError::InvalidInput(format!("Failed to read file: {}", e)))? {
+        if bytes.len() + chunk.len() > max_bytes {
+            return Err(ApiError::InvalidInput(format!(
+                "File exceeds the upload limit of {} bytes",
+                max_bytes
+            )));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(bytes)
+}
+
+/// Unpacks gzip and single-file zip uploads; anything else is returned as is.
+/// The unpacked size is held to `max_bytes` too, so a small archive cannot
+/// expand without bound.
+pub fn decompress(bytes: Vec<u8>, max_bytes: usize) -> Result<Vec<u8>> {
+    if bytes.starts_with(&GZIP_MAGIC) {
+        let decoder = flate2::read::MultiGzDecoder::new(bytes.as_slice());
+        return read_limited(decoder, max_bytes).map_err(|e| anyhow!("Invalid gzip upload: {}", e));
+    }
+
+    if bytes.starts_with(&ZIP_MAGIC) {
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes))
+            .map_err(|e| anyhow!("Invalid zip upload: {}", e))?;
+
+        let mut files = Vec::new();
+        for index in 0..archive.len() {
+            let entry = archive.by_index_raw(index)?;
+            // Skip folders and the resource forks macOS adds to archives.
+            if entry.is_file() && !entry.name().starts_with("__MACOSX/") {
+                files.push(index);
+            }
+        }
+
+        let [index] = files.as_slice() else {
+            bail!("Zip uploads must contain exactly one file, found {}", files.len());
+        };
+        let file = archive
+            .by_index(*index)
+            .map_err(|e| anyhow!("Invalid zip upload: {}", e))?;
+        return read_limited(file, max_bytes).map_err(|e| anyhow!("Invalid zip upload: {}", e));
+    }
+
+    Ok(bytes)
+}
+
+fn read_limited(reader: impl Read, max_bytes: usize) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(max_bytes as u64 + 1).read_to_end(&mut bytes)?;
+
+    if bytes.len() > max_bytes {
+        bail!("unpacked file exceeds the upload limit of {} bytes", max_bytes);
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+    use zip::write::{SimpleFileOptions, ZipWriter};
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            if name.ends_with('/') {
+                writer.add_directory(*name, SimpleFileOptions::default()).unwrap();
+            } else {
+                writer.start_file(*name, SimpleFileOptions::default()).unwrap();
+                writer.write_all(data).unwrap();
+            }
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_plain_uploads_pass_through() {
+        assert_eq!(decompress(b"alice:secret\n".to_vec(), 100).unwrap(), b"alice:secret\n");
+    }
+
+    #[test]
+    fn test_gzip_uploads() {
+        let data = b"alice:secret\nbob:hunter2\n";
+        assert_eq!(decompress(gzip(data), 100).unwrap(), data);
+
+        // Highly compressible input must not unpack past the limit.
+        let bomb = gzip(&vec![b'a'; 10_000]);
+        assert!(bomb.len() < 1_000);
+        assert!(decompress(bomb, 1_000).is_err());
+
+        let mut truncated = gzip(data);
+        truncated.truncate(12);
+        assert!(decompress(truncated, 100).is_err());
+    }
+
+    #[test]
+    fn test_zip_uploads() {
+        let data: &[u8] = b"alice:secret\n";
+        let archive = zip(&[("creds/", b""), ("__MACOSX/._creds.txt", b"junk"), ("creds/creds.txt", data)]);
+        assert_eq!(decompress(archive, 100).unwrap(), data);
+
+        let archive = zip(&[("a.txt", data), ("b.txt", data)]);
+        assert!(decompress(archive, 100).is_err());
+
+        let archive = zip(&[("big.txt", &[b'a'; 1_000])]);
+        assert!(decompress(archive, 100).is_err());
+    }
+}
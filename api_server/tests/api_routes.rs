@@ -0,0 +1,956 @@
+//! End-to-end route tests: the real router talks to the in-process fake
+//! Google upstream from `common`.
+
+mod common;
+
+use axum::http::StatusCode;
+use common::{
+    fake_google, multipart_body, multipart_bytes, spawn_app, spawn_app_with_store, Behavior, WebhookReceiver,
+    MAX_BATCH_SIZE, MAX_UPLOAD_BYTES,
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use leaklens_api::models::request_models::{CredentialCheckResult, JobState};
+use leaklens_api::services::batch_input::BatchEntry;
+use leaklens_api::services::job_store::{memory::MemoryJobStore, sqlite::SqliteJobStore, JobStore, NewJob};
+use serde_json::{json, Value};
+use futures::StreamExt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_tungstenite::tungstenite::Message;
+
+async fn check_single(app: &str, body: Value) -> (StatusCode, Value) {
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/v1/check/single", app))
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+    let status = StatusCode::from_u16(response.status().as_u16()).unwrap();
+    (status, response.json().await.unwrap())
+}
+
+async fn start_batch(app: &str, fields: &[(&str, &str)]) -> (StatusCode, Value) {
+    send_batch(app, multipart_body(fields)).await
+}
+
+async fn send_batch(app: &str, (content_type, body): (String, Vec<u8>)) -> (StatusCode, Value) {
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/v1/check/batch", app))
+        .header("content-type", content_type)
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+    let status = StatusCode::from_u16(response.status().as_u16()).unwrap();
+    (status, response.json().await.unwrap())
+}
+
+async fn batch_status(app: &str, job_id: &str) -> (StatusCode, Value) {
+    let response = reqwest::get(format!("{}/api/v1/check/batch/{}/status", app, job_id)).await.unwrap();
+    let status = StatusCode::from_u16(response.status().as_u16()).unwrap();
+    (status, response.json().await.unwrap())
+}
+
+async fn delete_batch(app: &str, job_id: &str) -> StatusCode {
+    let response = reqwest::Client::new()
+        .delete(format!("{}/api/v1/check/batch/{}", app, job_id))
+        .send()
+        .await
+        .unwrap();
+    StatusCode::from_u16(response.status().as_u16()).unwrap()
+}
+
+async fn wait_for_completion(app: &str, job_id: &str) -> Value {
+    let deadline = Instant::now() + Duration::from_secs(60);
+    loop {
+        let (status, body) = batch_status(app, job_id).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        if body["summary"]["completed"] == json!(true) {
+            return body;
+        }
+        assert!(Instant::now() < deadline, "batch job {} did not complete", job_id);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[tokio::test]
+async fn single_check_reports_leaked_and_clean_credentials() {
+    let app = spawn_app();
+    fake_google().leak("single-leaked@example.com", "hunter2");
+
+    let (status, body) = check_single(&app, json!({"username": "single-leaked@example.com", "password": "hunter2"})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["is_leaked"], json!(true));
+    assert_eq!(body["backend"], json!("google"));
+
+    let (status, body) = check_single(&app, json!({"username": "single-leaked@example.com", "password": "hunter3"})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["is_leaked"], json!(false));
+
+    assert!(fake_google().token_requests() >= 1);
+}
+
+#[tokio::test]
+async fn single_check_surfaces_upstream_failures() {
+    let app = spawn_app();
+    fake_google().script("single-failing", Behavior::Fail(StatusCode::SERVICE_UNAVAILABLE));
+    fake_google().script("single-malformed", Behavior::Malformed);
+
+    for username in ["single-failing", "single-malformed"] {
+        let (status, body) = check_single(&app, json!({"username": username, "password": "hunter2"})).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "{}", body);
+        assert_eq!(body["code"], json!("INTERNAL_SERVER_ERROR"));
+    }
+}
+
+#[tokio::test]
+async fn transient_upstream_failures_are_retried() {
+    let app = spawn_app();
+    fake_google().leak("flaky-leaked@example.com", "hunter2");
+    fake_google().script(
+        "flaky-leaked@example.com",
+        Behavior::Flaky { failures: 2, status: StatusCode::SERVICE_UNAVAILABLE, retry_after: None },
+    );
+
+    let (status, body) = check_single(&app, json!({"username": "flaky-leaked@example.com", "password": "hunter2"})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["is_leaked"], json!(true));
+
+    // A 429 is retried no sooner than its Retry-After.
+    fake_google().script(
+        "throttled@example.com",
+        Behavior::Flaky { failures: 1, status: StatusCode::TOO_MANY_REQUESTS, retry_after: Some(1) },
+    );
+    let started = Instant::now();
+    let (status, body) = check_single(&app, json!({"username": "throttled@example.com", "password": "hunter2"})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["is_leaked"], json!(false));
+    assert!(started.elapsed() >= Duration::from_secs(1));
+
+    let metrics = reqwest::get(format!("{}/metrics", app)).await.unwrap().text().await.unwrap();
+    assert!(metrics.contains("leaklens_upstream_retries_total{status=\"503\"}"), "{}", metrics);
+    assert!(metrics.contains("leaklens_upstream_retries_total{status=\"429\"}"), "{}", metrics);
+    assert!(metrics.contains("leaklens_upstream_concurrency{kind=\"limit\"}"), "{}", metrics);
+}
+
+#[tokio::test]
+async fn single_check_waits_for_slow_upstream() {
+    let app = spawn_app();
+    fake_google().leak("single-slow", "hunter2");
+    fake_google().script("single-slow", Behavior::Delay(Duration::from_millis(300)));
+
+    let started = Instant::now();
+    let (status, body) = check_single(&app, json!({"username": "single-slow", "password": "hunter2"})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["is_leaked"], json!(true));
+    assert!(started.elapsed() >= Duration::from_millis(300));
+}
+
+#[tokio::test]
+async fn repeated_checks_are_answered_from_the_result_cache() {
+    let app = spawn_app();
+    fake_google().leak("cached-leaked@example.com", "hunter2");
+
+    let credential = json!({"username": "cached-leaked@example.com", "password": "hunter2"});
+    let (status, body) = check_single(&app, credential.clone()).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["is_leaked"], json!(true));
+    assert_eq!(body["cached"], json!(false));
+
+    // Google is down now, so only the cache can answer.
+    fake_google().script("cached-leaked@example.com", Behavior::Fail(StatusCode::SERVICE_UNAVAILABLE));
+    let (status, body) = check_single(&app, credential).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["is_leaked"], json!(true));
+    assert_eq!(body["cached"], json!(true));
+
+    // Another password has another lookup hash, and misses the cache.
+    let (status, body) = check_single(&app, json!({"username": "cached-leaked@example.com", "password": "hunter3"})).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "{}", body);
+
+    let (job_status, body) = start_batch(&app, &[("file", "cached-leaked@example.com:hunter2\n")]).await;
+    assert_eq!(job_status, StatusCode::OK, "{}", body);
+    let body = wait_for_completion(&app, body["job_id"].as_str().unwrap()).await;
+    assert_eq!(body["results"][0]["is_leaked"], json!(true));
+    assert_eq!(body["results"][0]["cached"], json!(true));
+}
+
+#[tokio::test]
+async fn single_check_rejects_invalid_input() {
+    let app = spawn_app();
+
+    let (status, body) = check_single(&app, json!({"username": "", "password": "hunter2"})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+
+    let (status, body) = check_single(&app, json!({"username": "a", "password": "b", "backend": "nope"})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["code"], json!("INVALID_INPUT"));
+}
+
+#[tokio::test]
+async fn status_reports_upstream_connection() {
+    let app = spawn_app();
+
+    let body: Value = reqwest::get(format!("{}/api/v1/status", app)).await.unwrap().json().await.unwrap();
+    assert_eq!(body["status"], json!("healthy"));
+    assert_eq!(body["google_api_status"], json!("connected"));
+}
+
+#[tokio::test]
+async fn responses_carry_a_request_id() {
+    let app = spawn_app();
+    let client = reqwest::Client::new();
+
+    let response = client.get(format!("{}/health", app)).send().await.unwrap();
+    let generated = response.headers()["x-request-id"].to_str().unwrap().to_string();
+    assert_eq!(generated.len(), 36, "{}", generated);
+
+    let response = client.get(format!("{}/health", app)).header("x-request-id", "edge-42.a").send().await.unwrap();
+    assert_eq!(response.headers()["x-request-id"], "edge-42.a");
+
+    // Malformed IDs are replaced rather than echoed.
+    let response = client.get(format!("{}/health", app)).header("x-request-id", "a b\"c").send().await.unwrap();
+    let replaced = response.headers()["x-request-id"].to_str().unwrap();
+    assert_ne!(replaced, "a b\"c");
+    assert_ne!(replaced, generated);
+}
+
+#[tokio::test]
+async fn metrics_are_served_in_prometheus_format() {
+    let app = spawn_app();
+    fake_google().leak("metrics-leaked", "hunter2");
+
+    let (status, body) = check_single(&app, json!({"username": "metrics-leaked", "password": "hunter2"})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let response = reqwest::get(format!("{}/metrics", app)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()["content-type"].to_str().unwrap().starts_with("text/plain; version=0.0.4"));
+    let text = response.text().await.unwrap();
+
+    // Other tests in this binary check credentials too, so only presence is certain.
+    for line in [
+        "# TYPE leaklens_credential_checks_total counter",
+        "leaklens_credential_checks_total{route=\"single\",outcome=\"leaked\"}",
+        "leaklens_single_check_duration_seconds_count{outcome=\"leaked\"}",
+        "leaklens_upstream_requests_total{status=\"200\"}",
+        "leaklens_upstream_request_duration_seconds_bucket{status=\"200\",le=\"+Inf\"}",
+        "leaklens_hashing_duration_seconds_count{operation=\"scrypt\"}",
+        "leaklens_hashing_duration_seconds_count{operation=\"hash_to_curve\"}",
+        "leaklens_token_refreshes_total{result=\"success\"}",
+        "# TYPE leaklens_batch_jobs gauge",
+        "leaklens_batch_jobs{state=\"running\"}",
+        "leaklens_batch_jobs_abandoned_total",
+        "leaklens_result_cache_lookups_total{result=\"miss\"}",
+        "leaklens_result_cache_entries{verdict=\"leaked\"}",
+    ] {
+        assert!(text.contains(line), "missing {}:\n{}", line, text);
+    }
+}
+
+#[tokio::test]
+async fn batch_job_lifecycle() {
+    let app = spawn_app();
+    fake_google().leak("batch-leaked", "hunter2");
+    fake_google().script("batch-failing", Behavior::Fail(StatusCode::INTERNAL_SERVER_ERROR));
+
+    let file = "batch-leaked:hunter2\nbatch-clean:hunter2\nnot a credential\nbatch-failing:hunter2\n";
+    let (status, body) = start_batch(&app, &[("file", file)]).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let job_id = body["job_id"].as_str().unwrap().to_string();
+
+    let body = wait_for_completion(&app, &job_id).await;
+    let summary = &body["summary"];
+    assert_eq!(summary["total_processed"], json!(4));
+    assert_eq!(summary["total_leaked"], json!(1));
+    assert_eq!(summary["total_not_leaked"], json!(1));
+    assert_eq!(summary["total_errors"], json!(2));
+    assert_eq!(summary["progress_percentage"], json!(100));
+
+    let results = body["results"].as_array().unwrap();
+    let leaked = results.iter().find(|r| r["is_leaked"] == json!(true)).unwrap();
+    assert_eq!(leaked["credential"], json!("batch-leaked:••••••••"));
+    assert_eq!(leaked["backend"], json!("google"));
+    let failed = results.iter().find(|r| r["credential"] == json!("batch-failing:••••••••")).unwrap();
+    assert_eq!(failed["status"], json!("error"));
+
+    assert_eq!(delete_batch(&app, &job_id).await, StatusCode::OK);
+    assert_eq!(batch_status(&app, &job_id).await.0, StatusCode::NOT_FOUND);
+    assert_eq!(delete_batch(&app, &job_id).await, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn batch_accepts_rich_input_formats() {
+    let app = spawn_app();
+    fake_google().leak("format-csv", "pa:ss,word");
+    fake_google().leak("format-json", "hunter2");
+
+    // A Chrome export, sniffed from its header; the password holds a colon and a comma.
+    let chrome = "name,url,username,password,note\nExample,https://example.com/,format-csv,\"pa:ss,word\",\n";
+    let (status, body) = start_batch(&app, &[("file", chrome)]).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let body = wait_for_completion(&app, body["job_id"].as_str().unwrap()).await;
+    let result = &body["results"][0];
+    assert_eq!(result["is_leaked"], json!(true), "{}", body);
+    assert_eq!(result["line"], json!(2));
+    assert_eq!(result["url"], json!("https://example.com/"));
+    assert_eq!(result["title"], json!("Example"));
+
+    let ndjson = "{\"email\": \"format-json\", \"password\": \"hunter2\", \"url\": \"https://a.example\"}\n{\"email\": \"format-json\"}\n";
+    let (status, body) = start_batch(&app, &[("file", ndjson), ("input_type", "ndjson")]).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let body = wait_for_completion(&app, body["job_id"].as_str().unwrap()).await;
+    assert_eq!(body["summary"]["total_leaked"], json!(1), "{}", body);
+    assert_eq!(body["summary"]["total_errors"], json!(1));
+
+    // A password with a colon in a plain username:password file.
+    let (status, body) = start_batch(&app, &[("file", "format-csv:pa:ss,word\n")]).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let body = wait_for_completion(&app, body["job_id"].as_str().unwrap()).await;
+    assert_eq!(body["results"][0]["is_leaked"], json!(true), "{}", body);
+}
+
+fn kdbx_vault(master_password: &str) -> Vec<u8> {
+    use keepass::config::{DatabaseConfig, KdfConfig};
+    use keepass::db::{Entry, Group, Value as Field};
+    use keepass::{Database, DatabaseKey};
+
+    let config = DatabaseConfig {
+        kdf_config: KdfConfig::Aes { rounds: 1 },
+        ..DatabaseConfig::default()
+    };
+    let mut database = Database::new(config);
+    database.root.name = "Helpdesk".to_string();
+
+    let mut group = Group::new("Servers");
+    for (title, username, password) in [("Router", "kdbx-leaked", "hunter2"), ("NAS", "kdbx-clean", "hunter2")] {
+        let mut entry = Entry::new();
+        entry.fields.insert("Title".to_string(), Field::Unprotected(title.to_string()));
+        entry.fields.insert("UserName".to_string(), Field::Unprotected(username.to_string()));
+        entry.fields.insert("URL".to_string(), Field::Unprotected(format!("https://{}.example", title.to_lowercase())));
+        entry.fields.insert("Password".to_string(), Field::Protected(password.as_bytes().into()));
+        group.add_child(entry);
+    }
+    database.root.add_child(group);
+
+    let mut bytes = Vec::new();
+    database.save(&mut bytes, DatabaseKey::new().with_password(master_password)).unwrap();
+    bytes
+}
+
+#[tokio::test]
+async fn batch_audits_keepass_database() {
+    let store = Arc::new(MemoryJobStore::default());
+    let app = spawn_app_with_store(store.clone());
+    fake_google().leak("kdbx-leaked", "hunter2");
+    let vault = kdbx_vault("open sesame");
+
+    let (status, body) = send_batch(&app, multipart_bytes(&[("file", &vault), ("master_password", b"open sesame")])).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    // Decrypted entries are never handed to the job store.
+    let stored = store.load_jobs().await.unwrap();
+    assert!(!stored[0].resumable);
+    assert!(stored[0].pending.is_empty());
+    let body = wait_for_completion(&app, body["job_id"].as_str().unwrap()).await;
+
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results.len(), 2);
+    let router = results.iter().find(|r| r["title"] == json!("Router")).unwrap();
+    assert_eq!(router["is_leaked"], json!(true));
+    assert_eq!(router["group"], json!("Helpdesk/Servers"));
+    assert_eq!(router["url"], json!("https://router.example"));
+    assert_eq!(router["credential"], json!("kdbx-leaked:••••••••"));
+    assert!(!body.to_string().contains("hunter2"));
+
+    let (status, body) = send_batch(&app, multipart_bytes(&[("file", &vault), ("master_password", b"wrong")])).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    let (status, body) = send_batch(&app, multipart_bytes(&[("file", &vault)])).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    use std::io::Write;
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn zip_one(name: &str, data: &[u8]) -> Vec<u8> {
+    use std::io::Write;
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    writer.start_file(name, zip::write::SimpleFileOptions::default()).unwrap();
+    writer.write_all(data).unwrap();
+    writer.finish().unwrap().into_inner()
+}
+
+#[tokio::test]
+async fn batch_accepts_compressed_uploads() {
+    let app = spawn_app();
+    fake_google().leak("compressed-leaked", "hunter2");
+    let file = b"compressed-leaked:hunter2\ncompressed-clean:hunter2\n";
+
+    for archive in [gzip(file), zip_one("credentials.txt", file)] {
+        let (status, body) = send_batch(&app, multipart_bytes(&[("file", &archive)])).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let body = wait_for_completion(&app, body["job_id"].as_str().unwrap()).await;
+        assert_eq!(body["summary"]["total_leaked"], json!(1), "{}", body);
+        assert_eq!(body["summary"]["total_not_leaked"], json!(1));
+    }
+}
+
+#[tokio::test]
+async fn batch_enforces_upload_limits() {
+    let app = spawn_app();
+
+    let oversized = "a".repeat(MAX_UPLOAD_BYTES + 1);
+    let (status, body) = start_batch(&app, &[("file", &oversized)]).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["code"], json!("INVALID_INPUT"));
+    assert!(body["error"].as_str().unwrap().contains("upload limit"), "{}", body);
+
+    // Compresses to a few hundred bytes but unpacks past the limit.
+    let bomb = gzip(&vec![b'a'; MAX_UPLOAD_BYTES * 4]);
+    let (status, body) = send_batch(&app, multipart_bytes(&[("file", &bomb)])).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert!(body["error"].as_str().unwrap().contains("upload limit"), "{}", body);
+
+    let too_many: String = (0..=MAX_BATCH_SIZE).map(|i| format!("limit-{}:pw\n", i)).collect();
+    let (status, body) = start_batch(&app, &[("file", &too_many)]).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert!(body["error"].as_str().unwrap().contains(&format!("limit of {}", MAX_BATCH_SIZE)), "{}", body);
+}
+
+#[tokio::test]
+async fn batch_rejects_bad_uploads() {
+    let app = spawn_app();
+
+    let (status, body) = start_batch(&app, &[("input_type", "email_pass")]).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+
+    let (status, body) = start_batch(&app, &[("file", "\n\n")]).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+
+    let (status, body) = start_batch(&app, &[("file", "a:b"), ("backend", "nope")]).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+
+    let (status, body) = start_batch(&app, &[("file", "a:b"), ("input_type", "xml")]).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+
+    let (status, body) = start_batch(&app, &[("file", "[{\"username\": "), ("input_type", "json")]).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+
+    assert_eq!(batch_status(&app, "no-such-job").await.0, StatusCode::NOT_FOUND);
+}
+
+async fn start_streamed_batch(app: &str, prefix: &str) -> String {
+    fake_google().leak(&format!("{}-leaked", prefix), "hunter2");
+    // Slow the first lookup down so the stream is attached while work is live.
+    fake_google().script(&format!("{}-slow", prefix), Behavior::Delay(Duration::from_millis(300)));
+
+    let file = format!("{0}-slow:hunter2\n{0}-leaked:hunter2\n{0}-clean:hunter2\n", prefix);
+    let (status, body) = start_batch(app, &[("file", &file)]).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    body["job_id"].as_str().unwrap().to_string()
+}
+
+fn assert_streamed_job(events: &[Value]) {
+    let results: Vec<_> = events.iter().filter(|e| e["type"] == json!("result")).collect();
+    assert_eq!(results.len(), 3, "{:?}", events);
+    assert_eq!(results.iter().filter(|e| e["result"]["is_leaked"] == json!(true)).count(), 1);
+
+    let completed = events.last().unwrap();
+    assert_eq!(completed["type"], json!("completed"), "{:?}", events);
+    assert_eq!(completed["summary"]["total_processed"], json!(3));
+    assert_eq!(completed["summary"]["completed"], json!(true));
+    assert_eq!(completed["error"], Value::Null);
+}
+
+#[tokio::test]
+async fn batch_progress_over_sse() {
+    let app = spawn_app();
+    let job_id = start_streamed_batch(&app, "sse").await;
+
+    let mut response = reqwest::get(format!("{}/api/v1/check/batch/{}/events", app, job_id)).await.unwrap();
+    assert_eq!(response.headers()["content-type"], "text/event-stream");
+
+    let mut buffer = String::new();
+    let mut events = Vec::new();
+    while let Some(chunk) = tokio::time::timeout(Duration::from_secs(30), response.chunk()).await.unwrap().unwrap() {
+        buffer.push_str(&String::from_utf8_lossy(&chunk));
+        while let Some(end) = buffer.find("\n\n") {
+            let block: String = buffer.drain(..end + 2).collect();
+            let field = |name: &str| block.lines().find_map(|l| l.strip_prefix(name)).map(str::trim_start);
+            let name = field("event:").map(str::to_string);
+            if let Some(data) = field("data:") {
+                let event: Value = serde_json::from_str(data).unwrap();
+                assert_eq!(name.as_deref(), event["type"].as_str());
+                events.push(event);
+            }
+        }
+    }
+
+    assert_streamed_job(&events);
+    assert_eq!(
+        reqwest::get(format!("{}/api/v1/check/batch/missing/events", app)).await.unwrap().status(),
+        reqwest::StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn batch_progress_over_websocket() {
+    let app = spawn_app();
+    let job_id = start_streamed_batch(&app, "ws").await;
+
+    let url = format!("{}/api/v1/check/batch/{}/ws", app.replacen("http", "ws", 1), job_id);
+    let (mut socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+
+    let mut events = Vec::new();
+    while let Some(message) = tokio::time::timeout(Duration::from_secs(30), socket.next()).await.unwrap() {
+        match message.unwrap() {
+            Message::Text(text) => events.push(serde_json::from_str::<Value>(&text).unwrap()),
+            Message::Close(_) => break,
+            _ => {}
+        }
+    }
+
+    assert_streamed_job(&events);
+}
+
+/// A batch of `count` credentials whose every chunk of ten takes at least
+/// 300ms, leaving time to act on the job while it runs.
+fn slow_batch(prefix: &str, count: usize) -> String {
+    for i in (0..count).step_by(10) {
+        fake_google().script(&format!("{}-{}", prefix, i), Behavior::Delay(Duration::from_millis(300)));
+    }
+    (0..count).map(|i| format!("{}-{}:hunter2\n", prefix, i)).collect()
+}
+
+async fn control_batch(app: &str, job_id: &str, action: &str) -> (StatusCode, Value) {
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/v1/check/batch/{}/{}", app, job_id, action))
+        .send()
+        .await
+        .unwrap();
+    let status = StatusCode::from_u16(response.status().as_u16()).unwrap();
+    (status, response.json().await.unwrap())
+}
+
+#[tokio::test]
+async fn batch_jobs_pause_and_resume() {
+    let app = spawn_app();
+    let file = slow_batch("pausing", 40);
+    let (_, body) = start_batch(&app, &[("file", &file)]).await;
+    let job_id = body["job_id"].as_str().unwrap().to_string();
+
+    let (status, body) = control_batch(&app, &job_id, "pause").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["message"], json!("Job paused"));
+
+    // The chunk in flight when the pause arrived may still land.
+    tokio::time::sleep(Duration::from_millis(800)).await;
+    let (_, paused) = batch_status(&app, &job_id).await;
+    assert_eq!(paused["summary"]["state"], json!("paused"));
+    tokio::time::sleep(Duration::from_millis(800)).await;
+    let (_, still_paused) = batch_status(&app, &job_id).await;
+    assert_eq!(still_paused["summary"]["total_processed"], paused["summary"]["total_processed"]);
+    assert!(paused["summary"]["total_processed"].as_u64().unwrap() < 40);
+
+    let (status, body) = control_batch(&app, &job_id, "resume").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let body = wait_for_completion(&app, &job_id).await;
+    assert_eq!(body["summary"]["state"], json!("completed"));
+    assert_eq!(body["results"].as_array().unwrap().len(), 40);
+
+    assert_eq!(control_batch(&app, &job_id, "pause").await.0, StatusCode::CONFLICT);
+    assert_eq!(control_batch(&app, &job_id, "cancel").await.0, StatusCode::CONFLICT);
+    assert_eq!(control_batch(&app, "no-such-job", "resume").await.0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn batch_jobs_can_be_cancelled() {
+    let app = spawn_app();
+    let file = slow_batch("cancelling", 40);
+    let (_, body) = start_batch(&app, &[("file", &file)]).await;
+    let job_id = body["job_id"].as_str().unwrap().to_string();
+
+    let (status, body) = control_batch(&app, &job_id, "cancel").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let (_, body) = batch_status(&app, &job_id).await;
+    assert_eq!(body["summary"]["state"], json!("cancelled"));
+    assert_eq!(body["summary"]["completed"], json!(true));
+
+    tokio::time::sleep(Duration::from_millis(800)).await;
+    let (_, later) = batch_status(&app, &job_id).await;
+    assert_eq!(later["summary"]["total_processed"], body["summary"]["total_processed"]);
+    assert!(later["summary"]["total_processed"].as_u64().unwrap() < 40);
+
+    assert_eq!(control_batch(&app, &job_id, "resume").await.0, StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn abandoned_batch_jobs_resume_when_polled() {
+    let store = Arc::new(MemoryJobStore::default());
+    let app = spawn_app_with_store(store.clone());
+    let file = slow_batch("abandoning", 50);
+    let (_, body) = start_batch(&app, &[("file", &file), ("abandon_timeout", "1")]).await;
+    let job_id = body["job_id"].as_str().unwrap().to_string();
+
+    // Unattended, the job would finish in under two seconds.
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    assert_eq!(store.load_jobs().await.unwrap()[0].state, JobState::Abandoned);
+    let (_, body) = batch_status(&app, &job_id).await;
+    assert_eq!(body["summary"]["completed"], json!(false), "{}", body);
+    assert!(body["summary"]["total_processed"].as_u64().unwrap() < 50);
+
+    // The poll that resumed the job is saved, so a restart would not find it abandoned.
+    let stored = store.load_jobs().await.unwrap();
+    assert_eq!(stored[0].state, JobState::Running);
+    assert!(stored[0].version >= body["version"].as_u64().unwrap());
+
+    let body = wait_for_completion(&app, &job_id).await;
+    assert_eq!(body["summary"]["state"], json!("completed"));
+    assert_eq!(body["results"].as_array().unwrap().len(), 50);
+}
+
+#[tokio::test]
+async fn detached_batch_jobs_run_unattended() {
+    let app = spawn_app();
+    let file = slow_batch("detached", 30);
+    let fields = [("file", file.as_str()), ("abandon_timeout", "1"), ("detached", "true")];
+    let (_, body) = start_batch(&app, &fields).await;
+    let job_id = body["job_id"].as_str().unwrap().to_string();
+
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    let (_, body) = batch_status(&app, &job_id).await;
+    assert_eq!(body["summary"]["state"], json!("completed"), "{}", body);
+    assert_eq!(body["results"].as_array().unwrap().len(), 30);
+
+    for (field, value) in [("abandon_timeout", "0"), ("abandon_timeout", "soon"), ("detached", "maybe")] {
+        let (status, body) = start_batch(&app, &[("file", "a:b"), (field, value)]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    }
+}
+
+/// Checks a callback's `t=..,v1=..` signature against `secret`.
+fn assert_signed(webhook: &common::ReceivedWebhook, secret: &str) {
+    let header = webhook.headers["x-leaklens-signature"].to_str().unwrap();
+    let (timestamp, signature) = header
+        .strip_prefix("t=")
+        .and_then(|rest| rest.split_once(",v1="))
+        .unwrap_or_else(|| panic!("malformed signature header {}", header));
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(format!("{}.", timestamp).as_bytes());
+    mac.update(&webhook.body);
+    mac.verify_slice(&hex::decode(signature).unwrap()).expect("signature does not match");
+}
+
+async fn webhook_deliveries(app: &str, job_id: &str) -> Value {
+    reqwest::get(format!("{}/api/v1/check/batch/{}/webhooks", app, job_id))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn batch_completion_is_posted_to_signed_webhook() {
+    let app = spawn_app();
+    let receiver = WebhookReceiver::spawn(2, StatusCode::SERVICE_UNAVAILABLE);
+    fake_google().leak("webhook-leaked@example.com", "hunter2");
+
+    let fields = [
+        ("file", "webhook-leaked@example.com:hunter2\nwebhook-clean@example.com:hunter2\n"),
+        ("callback_url", receiver.url.as_str()),
+        ("callback_secret", "whsec_test"),
+        ("callback_include_results", "true"),
+    ];
+    let (status, body) = start_batch(&app, &fields).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let job_id = body["job_id"].as_str().unwrap().to_string();
+    wait_for_completion(&app, &job_id).await;
+
+    // Two refused attempts, then the delivery goes through.
+    let received = receiver.wait_for(3).await;
+    for webhook in &received {
+        assert_signed(webhook, "whsec_test");
+        assert_eq!(webhook.headers["x-leaklens-event"], "job.completed");
+    }
+    assert_eq!(received[0].headers["x-leaklens-delivery"], received[2].headers["x-leaklens-delivery"]);
+
+    let payload: Value = serde_json::from_slice(&received[2].body).unwrap();
+    assert_eq!(payload["event"], json!("job.completed"));
+    assert_eq!(payload["job_id"], json!(job_id));
+    assert_eq!(payload["summary"]["total_leaked"], json!(1));
+    assert_eq!(payload["summary"]["state"], json!("completed"));
+    assert_eq!(payload["results"].as_array().unwrap().len(), 2);
+    assert!(!String::from_utf8_lossy(&received[2].body).contains("hunter2"));
+
+    let log = webhook_deliveries(&app, &job_id).await;
+    assert_eq!(log["callback_url"], json!(receiver.url));
+    let attempts: Vec<_> = log["deliveries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| (d["attempt"].clone(), d["status_code"].clone(), d["delivered"].clone()))
+        .collect();
+    assert_eq!(attempts, vec![
+        (json!(1), json!(503), json!(false)),
+        (json!(2), json!(503), json!(false)),
+        (json!(3), json!(200), json!(true)),
+    ]);
+}
+
+#[tokio::test]
+async fn cancelled_batch_webhook_is_not_retried_after_client_errors() {
+    let app = spawn_app();
+    let receiver = WebhookReceiver::spawn(usize::MAX, StatusCode::GONE);
+    let file = slow_batch("webhook-cancel", 30);
+
+    let fields = [("file", file.as_str()), ("callback_url", receiver.url.as_str()), ("callback_secret", "s3cret")];
+    let (_, body) = start_batch(&app, &fields).await;
+    let job_id = body["job_id"].as_str().unwrap().to_string();
+    assert_eq!(control_batch(&app, &job_id, "cancel").await.0, StatusCode::OK);
+
+    let received = receiver.wait_for(1).await;
+    assert_signed(&received[0], "s3cret");
+    let payload: Value = serde_json::from_slice(&received[0].body).unwrap();
+    assert_eq!(payload["event"], json!("job.cancelled"));
+    assert_eq!(payload["error"], json!("Job cancelled"));
+    assert!(payload.get("results").is_none());
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(receiver.received().len(), 1);
+    let log = webhook_deliveries(&app, &job_id).await;
+    assert_eq!(log["deliveries"].as_array().unwrap().len(), 1);
+    assert_eq!(log["deliveries"][0]["status_code"], json!(410));
+}
+
+#[tokio::test]
+async fn batch_rejects_invalid_callbacks() {
+    let app = spawn_app();
+
+    let cases = [
+        vec![("file", "a:b"), ("callback_url", "http://127.0.0.1:9/hook")],
+        vec![("file", "a:b"), ("callback_url", "ftp://example.com/hook"), ("callback_secret", "s")],
+        vec![
+            ("file", "a:b"),
+            ("callback_url", "http://127.0.0.1:9/hook"),
+            ("callback_secret", "s"),
+            ("callback_include_results", "all"),
+        ],
+    ];
+    for fields in cases {
+        let (status, body) = start_batch(&app, &fields).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    }
+}
+
+#[tokio::test]
+async fn batch_status_pages_results_by_cursor() {
+    let app = spawn_app();
+    let file: String = (0..5).map(|i| format!("paged-{}:hunter2\n", i)).collect();
+    let (_, body) = start_batch(&app, &[("file", &file)]).await;
+    let job_id = body["job_id"].as_str().unwrap().to_string();
+    wait_for_completion(&app, &job_id).await;
+
+    let url = format!("{}/api/v1/check/batch/{}/status", app, job_id);
+    let page: Value = reqwest::get(format!("{}?limit=2", url)).await.unwrap().json().await.unwrap();
+    let indices: Vec<_> = page["results"].as_array().unwrap().iter().map(|r| r["index"].clone()).collect();
+    assert_eq!(indices, vec![json!(0), json!(1)]);
+    assert_eq!(page["next_cursor"], json!(2));
+    assert_eq!(page["has_more"], json!(true));
+    assert_eq!(page["summary"]["total_processed"], json!(5));
+
+    let page: Value = reqwest::get(format!("{}?since=2", url)).await.unwrap().json().await.unwrap();
+    let indices: Vec<_> = page["results"].as_array().unwrap().iter().map(|r| r["index"].clone()).collect();
+    assert_eq!(indices, vec![json!(2), json!(3), json!(4)]);
+    assert_eq!(page["has_more"], json!(false));
+
+    let page: Value = reqwest::get(format!("{}?cursor=5", url)).await.unwrap().json().await.unwrap();
+    assert_eq!(page["results"], json!([]));
+    assert_eq!(page["next_cursor"], json!(5));
+
+    let response = reqwest::get(&url).await.unwrap();
+    let etag = response.headers()["etag"].to_str().unwrap().to_string();
+    let response = reqwest::Client::new().get(&url).header("if-none-match", &etag).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers()["etag"].to_str().unwrap(), etag);
+
+    let response = reqwest::Client::new().get(&url).header("if-none-match", "\"stale\"").send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn batch_status_etags_differ_between_pages() {
+    let app = spawn_app();
+    let file: String = (0..4).map(|i| format!("etag-{}:hunter2\n", i)).collect();
+    let (_, body) = start_batch(&app, &[("file", &file)]).await;
+    let job_id = body["job_id"].as_str().unwrap().to_string();
+    wait_for_completion(&app, &job_id).await;
+
+    let url = format!("{}/api/v1/check/batch/{}/status", app, job_id);
+    let first = reqwest::get(format!("{}?limit=2", url)).await.unwrap();
+    let first_etag = first.headers()["etag"].to_str().unwrap().to_string();
+    let version = first.json::<Value>().await.unwrap()["version"].clone();
+
+    // Same job version, next page: the first page's ETag must not match.
+    let response = reqwest::Client::new()
+        .get(format!("{}?since=2&limit=2", url))
+        .header("if-none-match", &first_etag)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let second_etag = response.headers()["etag"].to_str().unwrap().to_string();
+    assert_ne!(second_etag, first_etag);
+    let page: Value = response.json().await.unwrap();
+    assert_eq!(page["version"], version);
+    let indices: Vec<_> = page["results"].as_array().unwrap().iter().map(|r| r["index"].clone()).collect();
+    assert_eq!(indices, vec![json!(2), json!(3)]);
+
+    // `since` and `cursor` name the same page.
+    let response = reqwest::Client::new()
+        .get(format!("{}?cursor=2&limit=2", url))
+        .header("if-none-match", &second_etag)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_MODIFIED);
+
+    let response = reqwest::Client::new().get(&url).header("if-none-match", &first_etag).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn batch_results_export() {
+    let app = spawn_app();
+    fake_google().leak("export-leaked", "hunter2");
+
+    // Blank lines are skipped but still count towards line numbers.
+    let file = "export-clean:hunter2\n\nexport-leaked:hunter2\nbroken line\n";
+    let (_, body) = start_batch(&app, &[("file", file)]).await;
+    let job_id = body["job_id"].as_str().unwrap().to_string();
+    wait_for_completion(&app, &job_id).await;
+
+    let url = format!("{}/api/v1/check/batch/{}/export", app, job_id);
+
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert!(response.headers()["content-type"].to_str().unwrap().starts_with("text/csv"));
+    assert!(response.headers()["content-disposition"].to_str().unwrap().contains(".csv"));
+    let csv = response.text().await.unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "line,credential,status,is_leaked,message,occurrences,url,title,group");
+    assert_eq!(lines[1], "1,export-clean:••••••••,checked,false,,,,,");
+    assert_eq!(lines[2], "3,export-leaked:••••••••,checked,true,,,,,");
+    assert!(lines[3].starts_with("4,line 4,error,,"));
+
+    let leaked: Value = reqwest::get(format!("{}?format=json&status=leaked", url)).await.unwrap().json().await.unwrap();
+    assert_eq!(leaked, json!([{
+        "line": 3,
+        "credential": "export-leaked:••••••••",
+        "status": "checked",
+        "is_leaked": true,
+        "message": null,
+        "occurrences": null,
+        "url": null,
+        "title": null,
+        "group": null,
+    }]));
+
+    let ndjson = reqwest::get(format!("{}?format=ndjson&status=errors", url)).await.unwrap().text().await.unwrap();
+    let rows: Vec<Value> = ndjson.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["line"], json!(4));
+
+    let response = reqwest::get(format!("{}?format=xml", url)).await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+    let response = reqwest::get(format!("{}/api/v1/check/batch/missing/export", app)).await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn batch_jobs_resume_after_restart() {
+    let path = std::env::temp_dir().join(format!("leaklens-resume-{}.sqlite3", std::process::id()));
+    let store = Arc::new(SqliteJobStore::open(&path, "test-key").unwrap());
+    fake_google().leak("resume-2@example.com", "hunter2");
+
+    // A job interrupted after checking the first of its three entries.
+    let entries: Vec<BatchEntry> = (0..3)
+        .map(|i| BatchEntry {
+            line: i + 1,
+            username: format!("resume-{}@example.com", i),
+            password: "hunter2".to_string(),
+            url: None,
+            title: None,
+            group: None,
+        })
+        .collect();
+    let job = NewJob {
+        id: "resumed-job".to_string(),
+        total: 3,
+        backend: "google".to_string(),
+        abandon_timeout: Some(15),
+        callback: None,
+        owner: None,
+        resumable: true,
+    };
+    let first = CredentialCheckResult {
+        index: 0,
+        line: 1,
+        credential: "resume-0@example.com:••••••••".to_string(),
+        is_leaked: Some(false),
+        status: "checked".to_string(),
+        message: None,
+        backend: Some("google".to_string()),
+        occurrences: None,
+        cached: false,
+        url: None,
+        title: None,
+        group: None,
+    };
+    store.create_job(&job, &entries).await.unwrap();
+    store.append_results("resumed-job", &[first], 1, 1).await.unwrap();
+    // A KeePass job, whose entries were kept in memory only.
+    let vault = NewJob { id: "vault-job".to_string(), resumable: false, ..job };
+    store.create_job(&vault, &entries).await.unwrap();
+
+    let app = spawn_app_with_store(store.clone());
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while batch_status(&app, "resumed-job").await.0 == StatusCode::NOT_FOUND {
+        assert!(Instant::now() < deadline, "stored job was not restored");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    let body = wait_for_completion(&app, "resumed-job").await;
+    let results = body["results"].as_array().unwrap();
+    let lines: Vec<_> = results.iter().map(|r| r["line"].clone()).collect();
+    assert_eq!(lines, vec![json!(1), json!(2), json!(3)]);
+    assert_eq!(results[2]["is_leaked"], json!(true));
+    assert_eq!(body["summary"]["total_processed"], json!(3));
+    assert_eq!(body["summary"]["progress_percentage"], json!(100));
+
+    let body = wait_for_completion(&app, "vault-job").await;
+    assert_eq!(body["results"], json!([]));
+
+    let mut stored = store.load_jobs().await.unwrap();
+    stored.sort_by(|a, b| a.id.cmp(&b.id));
+    assert_eq!(stored.len(), 2);
+    assert_eq!(stored[0].state, JobState::Completed);
+    assert_eq!(stored[0].results.len(), 3);
+    assert!(stored[0].pending.is_empty());
+    assert!(stored[1].error.as_deref().unwrap().contains("upload the database again"));
+    assert_eq!(delete_batch(&app, "vault-job").await, StatusCode::OK);
+
+    assert_eq!(delete_batch(&app, "resumed-job").await, StatusCode::OK);
+    assert!(store.load_jobs().await.unwrap().is_empty());
+
+    drop(store);
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+}
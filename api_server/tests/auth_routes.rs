@@ -0,0 +1,174 @@
+//! Route tests with API key authentication enabled. They run in their own
+//! binary because the config is installed once per test binary.
+
+mod common;
+
+use axum::http::StatusCode;
+use common::{call, fake_google, fake_google_with, spawn_app, start_batch, Credential::{self, ApiKey, Bearer}};
+use leaklens_api::utils::config::AppConfig;
+use reqwest::Method;
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+
+const ADMIN_KEY: &str = "test-admin-key";
+
+fn enable_auth(config: &mut AppConfig) {
+    config.auth.enabled = true;
+    config.auth.admin_key = Some(ADMIN_KEY.to_string());
+}
+
+fn spawn_authenticated_app() -> String {
+    fake_google_with(enable_auth);
+    spawn_app()
+}
+
+/// Issues a key through the admin API and returns its ID and secret.
+async fn issue_key(app: &str, scopes: Value) -> (String, String) {
+    let body = json!({"name": "test", "scopes": scopes});
+    let (status, body) = call(Method::POST, format!("{}/api/v1/admin/keys", app), Some(ApiKey(ADMIN_KEY)), Some(body)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    (body["key"]["id"].as_str().unwrap().to_string(), body["secret"].as_str().unwrap().to_string())
+}
+
+async fn check_single(app: &str, credential: Option<Credential<'_>>) -> (StatusCode, Value) {
+    let body = json!({"username": "auth-user@example.com", "password": "hunter2"});
+    call(Method::POST, format!("{}/api/v1/check/single", app), credential, Some(body)).await
+}
+
+async fn batch_status(app: &str, job_id: &str, key: &str) -> (StatusCode, Value) {
+    call(Method::GET, format!("{}/api/v1/check/batch/{}/status", app, job_id), Some(ApiKey(key)), None).await
+}
+
+#[tokio::test]
+async fn protected_routes_require_a_key() {
+    let app = spawn_authenticated_app();
+
+    let (status, _) = call(Method::GET, format!("{}/health", app), None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = call(Method::GET, format!("{}/api/v1/status", app), None, None).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let (status, body) = check_single(&app, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", body);
+    assert_eq!(body["code"], json!("AUTHENTICATION_ERROR"));
+
+    let (status, _) = check_single(&app, Some(ApiKey("llk_not-a-real-key"))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = call(Method::GET, format!("{}/api/v1/admin/keys", app), None, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = call(Method::GET, format!("{}/api/v1/check/batch/some-job/status", app), None, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn scopes_limit_what_a_key_can_call() {
+    let app = spawn_authenticated_app();
+    let (_, secret) = issue_key(&app, json!(["single"])).await;
+
+    let (status, body) = check_single(&app, Some(ApiKey(&secret))).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    // The bearer form is accepted as well.
+    let (status, body) = check_single(&app, Some(Bearer(&secret))).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let (status, body) = start_batch(&app, ApiKey(&secret), "auth-user:hunter2\n").await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+    assert_eq!(body["code"], json!("AUTHORIZATION_ERROR"));
+
+    let (status, _) = call(Method::GET, format!("{}/api/v1/admin/keys", app), Some(ApiKey(&secret)), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn keys_can_be_rotated_and_revoked() {
+    let app = spawn_authenticated_app();
+    let (key_id, secret) = issue_key(&app, json!(["single", "batch"])).await;
+
+    let (status, body) = call(Method::POST, format!("{}/api/v1/admin/keys/{}/rotate", app, key_id), Some(ApiKey(ADMIN_KEY)), None).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["key"]["rotated_from"], json!(key_id));
+    assert_eq!(body["key"]["scopes"], json!(["single", "batch"]));
+    let new_id = body["key"]["id"].as_str().unwrap().to_string();
+    let new_secret = body["secret"].as_str().unwrap().to_string();
+
+    assert_eq!(check_single(&app, Some(ApiKey(&secret))).await.0, StatusCode::UNAUTHORIZED);
+    assert_eq!(check_single(&app, Some(ApiKey(&new_secret))).await.0, StatusCode::OK);
+
+    let (status, body) = call(Method::POST, format!("{}/api/v1/admin/keys/{}/rotate", app, key_id), Some(ApiKey(ADMIN_KEY)), None).await;
+    assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+
+    let (status, body) = call(Method::DELETE, format!("{}/api/v1/admin/keys/{}", app, new_id), Some(ApiKey(ADMIN_KEY)), None).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(body["revoked_at"].is_string());
+    assert_eq!(check_single(&app, Some(ApiKey(&new_secret))).await.0, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = call(Method::DELETE, format!("{}/api/v1/admin/keys/missing", app), Some(ApiKey(ADMIN_KEY)), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, body) = call(Method::GET, format!("{}/api/v1/admin/keys", app), Some(ApiKey(ADMIN_KEY)), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let listed = body["keys"].as_array().unwrap().iter().find(|key| key["id"] == json!(key_id)).unwrap();
+    assert!(listed["revoked_at"].is_string());
+    assert!(listed.get("key_hash").is_none());
+}
+
+#[tokio::test]
+async fn issuing_validates_the_request() {
+    let app = spawn_authenticated_app();
+    let url = format!("{}/api/v1/admin/keys", app);
+
+    let (status, _) = call(Method::POST, url.clone(), Some(ApiKey(ADMIN_KEY)), Some(json!({"name": "x", "scopes": []}))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let body = json!({"name": "x", "scopes": ["batch"], "expires_in_days": 0});
+    let (status, _) = call(Method::POST, url.clone(), Some(ApiKey(ADMIN_KEY)), Some(body)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let body = json!({"name": "x", "scopes": ["batch"], "expires_in_days": 7});
+    let (status, body) = call(Method::POST, url, Some(ApiKey(ADMIN_KEY)), Some(body)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(body["key"]["expires_at"].is_string());
+    assert!(body["secret"].as_str().unwrap().starts_with(body["key"]["prefix"].as_str().unwrap()));
+}
+
+#[tokio::test]
+async fn jobs_are_private_to_the_key_that_created_them() {
+    let app = spawn_authenticated_app();
+    fake_google().leak("auth-batch", "hunter2");
+    let (owner_id, owner) = issue_key(&app, json!(["batch"])).await;
+    let (_, other) = issue_key(&app, json!(["batch"])).await;
+
+    let (status, body) = start_batch(&app, ApiKey(&owner), "auth-batch:hunter2\n").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let job_id = body["job_id"].as_str().unwrap().to_string();
+
+    let deadline = Instant::now() + Duration::from_secs(30);
+    loop {
+        let (status, body) = batch_status(&app, &job_id, &owner).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        if body["summary"]["completed"] == json!(true) {
+            assert_eq!(body["summary"]["total_leaked"], json!(1));
+            break;
+        }
+        assert!(Instant::now() < deadline, "batch job {} did not complete", job_id);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let (status, _) = batch_status(&app, &job_id, &other).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = call(Method::DELETE, format!("{}/api/v1/check/batch/{}", app, job_id), Some(ApiKey(&other)), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    assert_eq!(batch_status(&app, &job_id, ADMIN_KEY).await.0, StatusCode::OK);
+
+    // Jobs stay with their owner when the key is rotated.
+    let (status, body) = call(Method::POST, format!("{}/api/v1/admin/keys/{}/rotate", app, owner_id), Some(ApiKey(ADMIN_KEY)), None).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let rotated = body["secret"].as_str().unwrap().to_string();
+    assert_eq!(batch_status(&app, &job_id, &rotated).await.0, StatusCode::OK);
+    let (status, _) = call(Method::DELETE, format!("{}/api/v1/check/batch/{}", app, job_id), Some(ApiKey(&rotated)), None).await;
+    assert_eq!(status, StatusCode::OK);
+}
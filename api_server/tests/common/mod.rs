@@ -0,0 +1,382 @@
+//! In-process stand-ins for Google's OAuth token endpoint and the
+//! `leaks:lookupSingle` API, plus helpers to drive the real router against them.
+//!
+//! The global config can only be installed once per test binary, so a single
+//! fake upstream is shared by every test in it. Behaviour is scripted per
+//! username, which keeps concurrently running tests out of each other's way.
+
+#![allow(dead_code)]
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use leaklens_api::api::create_router;
+use leaklens_api::crypto::ecc_cipher::ECCommutativeCipher;
+use leaklens_api::crypto::hashing::{extract_username_from_email, username_hash_prefix};
+use leaklens_api::models::request_models::create_job_storage;
+use leaklens_api::proto::LookupSingleLeakRequest;
+use leaklens_api::services::api_keys::memory::MemoryApiKeyStore;
+use leaklens_api::services::job_store::memory::MemoryJobStore;
+use leaklens_api::services::job_store::JobStore;
+use leaklens_api::services::leak_database::LeakDatabase;
+use leaklens_api::utils::config::{self, AppConfig};
+use once_cell::sync::OnceCell;
+use prost::Message;
+use reqwest::{Client, Method, RequestBuilder};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+pub const ACCESS_TOKEN: &str = "fake-access-token";
+
+/// Upload limits installed for the tests, small enough to exceed cheaply.
+pub const MAX_BATCH_SIZE: usize = 100;
+pub const MAX_UPLOAD_BYTES: usize = 64 * 1024;
+
+/// How the fake upstream answers lookups for a given username.
+#[derive(Debug, Clone)]
+pub enum Behavior {
+    /// Answer from the leak database; the default.
+    Respond,
+    /// Fail with the given status code.
+    Fail(StatusCode),
+    /// Answer from the leak database after a delay.
+    Delay(Duration),
+    /// Answer 200 with a body that is not a valid protobuf response.
+    Malformed,
+    /// Fail the next `failures` lookups with `status`, sending `Retry-After`
+    /// when given, then answer from the leak database.
+    Flaky { failures: usize, status: StatusCode, retry_after: Option<u64> },
+}
+
+#[derive(Default)]
+struct Counters {
+    token_requests: AtomicUsize,
+    lookup_requests: AtomicUsize,
+}
+
+#[derive(Clone)]
+struct FakeState {
+    database: Arc<RwLock<LeakDatabase>>,
+    behaviors: Arc<Mutex<HashMap<Vec<u8>, Behavior>>>,
+    counters: Arc<Counters>,
+}
+
+pub struct FakeGoogle {
+    pub base_url: String,
+    state: FakeState,
+}
+
+impl FakeGoogle {
+    pub fn lookup_url(&self) -> String {
+        format!("{}/v1/leaks:lookupSingle", self.base_url)
+    }
+
+    pub fn token_url(&self) -> String {
+        format!("{}/token", self.base_url)
+    }
+
+    /// Adds a credential to the fake breach corpus.
+    pub fn leak(&self, username: &str, password: &str) {
+        self.state
+            .database
+            .write()
+            .unwrap()
+            .insert(username, password)
+            .expect("Failed to add leaked credential");
+    }
+
+    /// Scripts the response for every lookup in `username`'s bucket.
+    pub fn script(&self, username: &str, behavior: Behavior) {
+        let prefix = username_hash_prefix(&extract_username_from_email(username));
+        self.state.behaviors.lock().unwrap().insert(prefix, behavior);
+    }
+
+    pub fn token_requests(&self) -> usize {
+        self.state.counters.token_requests.load(Ordering::SeqCst)
+    }
+
+    pub fn lookup_requests(&self) -> usize {
+        self.state.counters.lookup_requests.load(Ordering::SeqCst)
+    }
+}
+
+/// Starts the fake upstream on its own runtime and installs a config pointing
+/// at it. Safe to call from every test.
+pub fn fake_google() -> &'static FakeGoogle {
+    fake_google_with(|_| {})
+}
+
+/// Like `fake_google`, letting the test binary adjust the config before it is
+/// installed. Only the first call in a binary configures anything, so every
+/// test in a binary must pass the same `configure`.
+pub fn fake_google_with(configure: fn(&mut AppConfig)) -> &'static FakeGoogle {
+    static FAKE: OnceCell<FakeGoogle> = OnceCell::new();
+    FAKE.get_or_init(|| {
+        let state = FakeState {
+            database: Arc::new(RwLock::new(LeakDatabase::new(ECCommutativeCipher::new(None)))),
+            behaviors: Arc::new(Mutex::new(HashMap::new())),
+            counters: Arc::new(Counters::default()),
+        };
+
+        let app = Router::new()
+            .route("/token", post(issue_token))
+            .route("/v1/leaks:lookupSingle", post(lookup_single_leak))
+            .with_state(state.clone());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+        std::thread::spawn(move || {
+            tokio::runtime::Runtime::new().unwrap().block_on(async move {
+                axum::Server::from_tcp(listener)
+                    .unwrap()
+                    .serve(app.into_make_service())
+                    .await
+                    .unwrap();
+            });
+        });
+
+        let fake = FakeGoogle { base_url, state };
+
+        let mut app_config = AppConfig::local(&fake.lookup_url(), &fake.token_url());
+        app_config.rate_limits.ip_rpm = 100_000;
+        app_config.rate_limits.single_credential_rpm = 100_000;
+        app_config.rate_limits.batch_credential_rpm = 100_000;
+        app_config.rate_limits.blinded_credential_rpm = 100_000;
+        app_config.rate_limits.max_batch_size = MAX_BATCH_SIZE;
+        app_config.rate_limits.max_upload_bytes = MAX_UPLOAD_BYTES;
+        app_config.webhooks.allow_private_targets = true;
+        app_config.webhooks.retry_base_delay_ms = 20;
+        app_config.upstream.retry_base_delay_ms = 20;
+        configure(&mut app_config);
+        config::init_with(app_config).expect("Config was installed before the fake upstream");
+
+        fake
+    })
+}
+
+/// Serves the real router on an ephemeral port and returns its base URL.
+pub fn spawn_app() -> String {
+    spawn_app_with_store(Arc::new(MemoryJobStore::default()))
+}
+
+/// Like `spawn_app`, but keeps jobs in `job_store`, resuming any unfinished
+/// ones it already holds.
+pub fn spawn_app_with_store(job_store: Arc<dyn JobStore>) -> String {
+    fake_google();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = create_router(create_job_storage(), job_store, Arc::new(MemoryApiKeyStore::default()))
+        .expect("The test config sets up every backend");
+    tokio::spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>()),
+    );
+    format!("http://{}", addr)
+}
+
+/// A callback received by a `WebhookReceiver`.
+#[derive(Debug, Clone)]
+pub struct ReceivedWebhook {
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+/// Local endpoint for batch job callbacks. Answers the first `failures`
+/// requests with `failure_status` and every later one with 200.
+pub struct WebhookReceiver {
+    pub url: String,
+    received: Arc<Mutex<Vec<ReceivedWebhook>>>,
+}
+
+impl WebhookReceiver {
+    pub fn spawn(failures: usize, failure_status: StatusCode) -> Self {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+
+        let app = Router::new().route(
+            "/hook",
+            post(move |headers: HeaderMap, body: Bytes| {
+                let log = log.clone();
+                async move {
+                    let mut log = log.lock().unwrap();
+                    log.push(ReceivedWebhook { headers, body });
+                    if log.len() <= failures { failure_status } else { StatusCode::OK }
+                }
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+
+        WebhookReceiver { url, received }
+    }
+
+    pub fn received(&self) -> Vec<ReceivedWebhook> {
+        self.received.lock().unwrap().clone()
+    }
+
+    /// Waits until at least `count` callbacks arrived and returns them all.
+    pub async fn wait_for(&self, count: usize) -> Vec<ReceivedWebhook> {
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        loop {
+            let received = self.received();
+            if received.len() >= count {
+                return received;
+            }
+            assert!(std::time::Instant::now() < deadline, "expected {} callbacks, got {}", count, received.len());
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+}
+
+/// How a request to the router authenticates.
+#[derive(Debug, Clone, Copy)]
+pub enum Credential<'a> {
+    /// An API key in the `X-API-Key` header.
+    ApiKey(&'a str),
+    /// An API key or token in `Authorization: Bearer`.
+    Bearer(&'a str),
+}
+
+impl Credential<'_> {
+    fn apply(self, request: RequestBuilder) -> RequestBuilder {
+        match self {
+            Credential::ApiKey(key) => request.header("X-API-Key", key),
+            Credential::Bearer(token) => request.bearer_auth(token),
+        }
+    }
+}
+
+/// Sends a request with an optional credential and JSON body. The response
+/// body is `null` when it is not JSON.
+pub async fn call(
+    method: Method,
+    url: String,
+    credential: Option<Credential<'_>>,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    let mut request = Client::new().request(method, url);
+    if let Some(credential) = credential {
+        request = credential.apply(request);
+    }
+    if let Some(body) = body {
+        request = request.json(&body);
+    }
+
+    let response = request.send().await.unwrap();
+    let status = StatusCode::from_u16(response.status().as_u16()).unwrap();
+    (status, response.json().await.unwrap_or(Value::Null))
+}
+
+/// Uploads `file` as a batch job on behalf of `credential`.
+pub async fn start_batch(app: &str, credential: Credential<'_>, file: &str) -> (StatusCode, Value) {
+    let (content_type, body) = multipart_body(&[("file", file)]);
+    let response = credential
+        .apply(Client::new().post(format!("{}/api/v1/check/batch", app)))
+        .header("content-type", content_type)
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+    let status = StatusCode::from_u16(response.status().as_u16()).unwrap();
+    (status, response.json().await.unwrap())
+}
+
+/// Builds a `multipart/form-data` body from text fields; a field named `file`
+/// is sent as a file part. Returns the content type and body.
+pub fn multipart_body(fields: &[(&str, &str)]) -> (String, Vec<u8>) {
+    let fields: Vec<(&str, &[u8])> = fields.iter().map(|(name, value)| (*name, value.as_bytes())).collect();
+    multipart_bytes(&fields)
+}
+
+/// Like `multipart_body`, for binary values such as a KeePass database.
+pub fn multipart_bytes(fields: &[(&str, &[u8])]) -> (String, Vec<u8>) {
+    let boundary = "leaklens-test-boundary";
+    let mut body = Vec::new();
+
+    for (name, value) in fields {
+        body.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+        if *name == "file" {
+            body.extend_from_slice(b"Content-Disposition: form-data; name=\"file\"; filename=\"credentials.txt\"\r\n");
+            body.extend_from_slice(b"Content-Type: application/octet-stream\r\n\r\n");
+        } else {
+            body.extend_from_slice(format!("Content-Disposition: form-data; name=\"{}\"\r\n\r\n", name).as_bytes());
+        }
+        body.extend_from_slice(value);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+
+    (format!("multipart/form-data; boundary={}", boundary), body)
+}
+
+async fn issue_token(State(state): State<FakeState>) -> Json<serde_json::Value> {
+    state.counters.token_requests.fetch_add(1, Ordering::SeqCst);
+    Json(serde_json::json!({
+        "access_token": ACCESS_TOKEN,
+        "expires_in": 3600,
+        "token_type": "Bearer",
+    }))
+}
+
+async fn lookup_single_leak(State(state): State<FakeState>, headers: HeaderMap, body: Bytes) -> Response {
+    state.counters.lookup_requests.fetch_add(1, Ordering::SeqCst);
+
+    let expected = format!("Bearer {}", ACCESS_TOKEN);
+    if headers.get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()) != Some(expected.as_str()) {
+        return (StatusCode::UNAUTHORIZED, "missing or invalid bearer token").into_response();
+    }
+
+    let request = match LookupSingleLeakRequest::decode(body) {
+        Ok(request) => request,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+
+    let behavior = {
+        let mut behaviors = state.behaviors.lock().unwrap();
+        let behavior = behaviors.get_mut(&request.username_hash_prefix);
+        let current = behavior.as_deref().cloned().unwrap_or(Behavior::Respond);
+        if let Some(Behavior::Flaky { failures, .. }) = behavior {
+            *failures = failures.saturating_sub(1);
+        }
+        current
+    };
+
+    match behavior {
+        Behavior::Respond => {}
+        Behavior::Fail(status) => return (status, "scripted failure").into_response(),
+        Behavior::Delay(delay) => tokio::time::sleep(delay).await,
+        Behavior::Malformed => return (StatusCode::OK, vec![0xff, 0xff, 0xff]).into_response(),
+        Behavior::Flaky { failures: 0, .. } => {}
+        Behavior::Flaky { status, retry_after, .. } => {
+            let mut response = (status, "scripted transient failure").into_response();
+            if let Some(seconds) = retry_after {
+                response.headers_mut().insert(header::RETRY_AFTER, seconds.into());
+            }
+            return response;
+        }
+    }
+
+    let result = state.database.read().unwrap().lookup(&request);
+    match result {
+        Ok(response) => (
+            [(header::CONTENT_TYPE, "application/x-protobuf")],
+            response.encode_to_vec(),
+        )
+            .into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
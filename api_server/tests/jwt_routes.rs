@@ -0,0 +1,250 @@
+//! Route tests with `AUTH_MODE=jwt`. Tokens are minted locally with an ES256
+//! key generated per run and the RS256 key in `tests/fixtures`; the public
+//! halves are served to the router from a JWKS file.
+
+mod common;
+
+use axum::http::StatusCode;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use common::{call, fake_google, fake_google_with, spawn_app, start_batch, Credential::Bearer};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use leaklens_api::services::jwt::{parse_scope_map, AuthMode};
+use leaklens_api::utils::config::{AppConfig, JwtConfig};
+use once_cell::sync::Lazy;
+use reqwest::Method;
+use ring::rand::SystemRandom;
+use ring::rsa::PublicKeyComponents;
+use ring::signature::{EcdsaKeyPair, KeyPair, RsaKeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const ISSUER: &str = "https://idp.test.example/";
+const AUDIENCE: &str = "leaklens";
+const ES256_KID: &str = "test-es256";
+const RS256_KID: &str = "test-rs256";
+const ADMIN_KEY: &str = "test-admin-key";
+
+struct TestKeys {
+    es256: EncodingKey,
+    rs256: EncodingKey,
+    /// An ES256 key that is not in the JWKS.
+    stranger: EncodingKey,
+    jwks_path: PathBuf,
+}
+
+static KEYS: Lazy<TestKeys> = Lazy::new(|| {
+    let rng = SystemRandom::new();
+    let generate_ec = || EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+
+    let es256_pkcs8 = generate_ec();
+    let es256 = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, es256_pkcs8.as_ref(), &rng).unwrap();
+    // Uncompressed point: 0x04 || x || y.
+    let point = es256.public_key().as_ref();
+
+    let rs256_pem = include_bytes!("fixtures/jwt_rs256.pem");
+    let rs256_der = pem_body(rs256_pem);
+    let rs256 = RsaKeyPair::from_pkcs8(&rs256_der).unwrap();
+    let rs256_public: PublicKeyComponents<Vec<u8>> = rs256.public().into();
+
+    let jwks = json!({"keys": [
+        {
+            "kty": "EC", "crv": "P-256", "use": "sig", "alg": "ES256", "kid": ES256_KID,
+            "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+            "y": URL_SAFE_NO_PAD.encode(&point[33..]),
+        },
+        {
+            "kty": "RSA", "use": "sig", "alg": "RS256", "kid": RS256_KID,
+            "n": URL_SAFE_NO_PAD.encode(&rs256_public.n),
+            "e": URL_SAFE_NO_PAD.encode(&rs256_public.e),
+        },
+    ]});
+    let jwks_path = std::env::temp_dir().join(format!("leaklens-jwks-{}.json", std::process::id()));
+    std::fs::write(&jwks_path, jwks.to_string()).unwrap();
+
+    TestKeys {
+        es256: EncodingKey::from_ec_der(es256_pkcs8.as_ref()),
+        rs256: EncodingKey::from_rsa_pem(rs256_pem).unwrap(),
+        stranger: EncodingKey::from_ec_der(generate_ec().as_ref()),
+        jwks_path,
+    }
+});
+
+/// DER contents of a single-block PEM file.
+fn pem_body(pem: &[u8]) -> Vec<u8> {
+    let base64: String = std::str::from_utf8(pem)
+        .unwrap()
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    base64::engine::general_purpose::STANDARD.decode(base64).unwrap()
+}
+
+fn enable_jwt(config: &mut AppConfig) {
+    config.auth.enabled = true;
+    config.auth.mode = AuthMode::Jwt;
+    config.auth.admin_key = Some(ADMIN_KEY.to_string());
+    config.auth.jwt = Some(JwtConfig {
+        jwks: KEYS.jwks_path.to_string_lossy().into_owned(),
+        issuer: ISSUER.to_string(),
+        audience: AUDIENCE.to_string(),
+        scope_claim: "realm_access.roles".to_string(),
+        scope_map: parse_scope_map("leaklens-check=single,leaklens-batch=batch,leaklens-ops=admin").unwrap(),
+        leeway_secs: 0,
+    });
+}
+
+fn spawn_jwt_app() -> String {
+    fake_google_with(enable_jwt);
+    spawn_app()
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+/// Claims of a valid token for `subject` with the given IdP roles.
+fn claims(subject: &str, roles: &[&str]) -> Value {
+    json!({
+        "iss": ISSUER,
+        "aud": AUDIENCE,
+        "sub": subject,
+        "iat": now(),
+        "exp": now() + 300,
+        "realm_access": {"roles": roles},
+    })
+}
+
+fn sign(alg: Algorithm, kid: Option<&str>, key: &EncodingKey, claims: &Value) -> String {
+    let mut header = Header::new(alg);
+    header.kid = kid.map(str::to_string);
+    encode(&header, claims, key).unwrap()
+}
+
+fn es256_token(claims: &Value) -> String {
+    sign(Algorithm::ES256, Some(ES256_KID), &KEYS.es256, claims)
+}
+
+async fn check_single(app: &str, token: Option<&str>) -> (StatusCode, Value) {
+    let body = json!({"username": "jwt-user@example.com", "password": "hunter2"});
+    call(Method::POST, format!("{}/api/v1/check/single", app), token.map(Bearer), Some(body)).await
+}
+
+#[tokio::test]
+async fn tokens_signed_by_the_jwks_keys_are_accepted() {
+    let app = spawn_jwt_app();
+    let claims = claims("alice", &["leaklens-check"]);
+
+    let (status, body) = check_single(&app, Some(&es256_token(&claims))).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let rs256 = sign(Algorithm::RS256, Some(RS256_KID), &KEYS.rs256, &claims);
+    let (status, body) = check_single(&app, Some(&rs256)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let (status, body) = check_single(&app, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", body);
+    assert_eq!(body["code"], json!("AUTHENTICATION_ERROR"));
+
+    let (status, _) = check_single(&app, Some("not-a-token")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // The break-glass admin key still works in JWT mode.
+    let (status, _) = call(Method::GET, format!("{}/api/v1/admin/keys", app), Some(Bearer(ADMIN_KEY)), None).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn forged_and_misdirected_tokens_are_rejected() {
+    let app = spawn_jwt_app();
+    let valid = claims("mallory", &["leaklens-check"]);
+
+    let mut rejected = vec![
+        // Signed by a key outside the JWKS, claiming a known and an unknown key ID.
+        sign(Algorithm::ES256, Some(ES256_KID), &KEYS.stranger, &valid),
+        sign(Algorithm::ES256, Some("unknown"), &KEYS.stranger, &valid),
+        // Without a key ID while the JWKS holds several keys.
+        sign(Algorithm::ES256, None, &KEYS.es256, &valid),
+        // RS256 header on the EC key.
+        sign(Algorithm::RS256, Some(ES256_KID), &KEYS.rs256, &valid),
+        // Symmetric algorithms are never accepted.
+        sign(Algorithm::HS256, Some(ES256_KID), &EncodingKey::from_secret(b"secret"), &valid),
+    ];
+
+    for (claim, value) in [
+        ("iss", json!("https://other-idp.example/")),
+        ("aud", json!("another-service")),
+        ("exp", json!(now() - 120)),
+        ("sub", json!("")),
+    ] {
+        let mut claims = valid.clone();
+        claims[claim] = value;
+        rejected.push(es256_token(&claims));
+    }
+
+    let mut missing_exp = valid.clone();
+    missing_exp.as_object_mut().unwrap().remove("exp");
+    rejected.push(es256_token(&missing_exp));
+
+    for (i, token) in rejected.iter().enumerate() {
+        let (status, body) = check_single(&app, Some(token)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "token {}: {}", i, body);
+    }
+}
+
+#[tokio::test]
+async fn identity_provider_roles_map_to_scopes() {
+    let app = spawn_jwt_app();
+
+    let check_only = es256_token(&claims("carol", &["leaklens-check", "offline_access"]));
+    assert_eq!(check_single(&app, Some(&check_only)).await.0, StatusCode::OK);
+    let (status, body) = start_batch(&app, Bearer(&check_only), "carol:hunter2\n").await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+    assert_eq!(body["code"], json!("AUTHORIZATION_ERROR"));
+
+    // Role names that happen to match a scope grant nothing once a map is set.
+    let unmapped = es256_token(&claims("carol", &["single", "admin"]));
+    assert_eq!(check_single(&app, Some(&unmapped)).await.0, StatusCode::FORBIDDEN);
+
+    let ops = es256_token(&claims("dave", &["leaklens-ops"]));
+    assert_eq!(check_single(&app, Some(&ops)).await.0, StatusCode::OK);
+    let (status, _) = call(Method::GET, format!("{}/api/v1/admin/keys", app), Some(Bearer(&ops)), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = call(Method::GET, format!("{}/api/v1/admin/keys", app), Some(Bearer(&check_only)), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn jobs_belong_to_the_token_subject() {
+    let app = spawn_jwt_app();
+    fake_google().leak("jwt-batch", "hunter2");
+
+    let alice = es256_token(&claims("alice", &["leaklens-batch"]));
+    let bob = es256_token(&claims("bob", &["leaklens-batch"]));
+
+    let (status, body) = start_batch(&app, Bearer(&alice), "jwt-batch:hunter2\n").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let job_id = body["job_id"].as_str().unwrap().to_string();
+    let status_url = format!("{}/api/v1/check/batch/{}/status", app, job_id);
+
+    let deadline = Instant::now() + Duration::from_secs(30);
+    loop {
+        let (status, body) = call(Method::GET, status_url.clone(), Some(Bearer(&alice)), None).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        if body["summary"]["completed"] == json!(true) {
+            assert_eq!(body["summary"]["total_leaked"], json!(1));
+            break;
+        }
+        assert!(Instant::now() < deadline, "batch job {} did not complete", job_id);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    assert_eq!(call(Method::GET, status_url.clone(), Some(Bearer(&bob)), None).await.0, StatusCode::NOT_FOUND);
+
+    // Ownership follows the subject, not the token or the key that signed it.
+    let alice_again = sign(Algorithm::RS256, Some(RS256_KID), &KEYS.rs256, &claims("alice", &["leaklens-batch"]));
+    assert_eq!(call(Method::GET, status_url.clone(), Some(Bearer(&alice_again)), None).await.0, StatusCode::OK);
+
+    let ops = es256_token(&claims("dave", &["leaklens-ops"]));
+    assert_eq!(call(Method::GET, status_url, Some(Bearer(&ops)), None).await.0, StatusCode::OK);
+}
@@ -0,0 +1,134 @@
+//! Known-answer tests for the leak lookup pipeline, driven by
+//! `tests/vectors/leak_detection.json`. Everything runs offline: the Google
+//! side of the protocol is played by a second cipher with a fixed key.
+
+use std::fs;
+use std::path::PathBuf;
+
+use leaklens_api::crypto::check_google_api::{check_credential_leaked, create_lookup_request, unblind_and_check};
+use leaklens_api::crypto::ecc_cipher::ECCommutativeCipher;
+use leaklens_api::crypto::hashing::{
+    extract_username_from_email, scrypt_hash_username_and_password, username_hash_prefix, USERNAME_SALT,
+};
+use leaklens_api::proto::LookupSingleLeakResponse;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Deserialize)]
+struct VectorFile {
+    username_hash: Vec<UsernameHashVector>,
+    lookup_hash: Vec<LookupHashVector>,
+    pipeline: Vec<PipelineVector>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UsernameHashVector {
+    source: String,
+    username: String,
+    hash: String,
+    prefix: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LookupHashVector {
+    source: String,
+    username: String,
+    password: String,
+    lookup_hash: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PipelineVector {
+    name: String,
+    username: String,
+    password: String,
+    canonical_username: String,
+    client_key: String,
+    server_key: String,
+    username_hash_prefix: String,
+    lookup_hash: String,
+    encrypted_lookup_hash: String,
+    reencrypted_lookup_hash: String,
+    decrypted_lookup_hash: String,
+    encrypted_leak_match_prefix: Vec<String>,
+    is_leaked: bool,
+}
+
+fn load_vectors() -> VectorFile {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/vectors/leak_detection.json");
+    let contents = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {}", path.display(), e));
+    serde_json::from_str(&contents).expect("Failed to parse vector file")
+}
+
+fn unhex(value: &str) -> Vec<u8> {
+    hex::decode(value).unwrap_or_else(|e| panic!("Invalid hex '{}': {}", value, e))
+}
+
+#[test]
+fn username_hash_vectors() {
+    for vector in load_vectors().username_hash {
+        let full_hash = Sha256::new()
+            .chain_update(vector.username.as_bytes())
+            .chain_update(USERNAME_SALT)
+            .finalize();
+        assert_eq!(hex::encode(full_hash), vector.hash, "[{}] hash of {}", vector.source, vector.username);
+
+        let prefix = username_hash_prefix(&vector.username);
+        assert_eq!(hex::encode(prefix), vector.prefix, "[{}] prefix of {}", vector.source, vector.username);
+    }
+}
+
+#[test]
+fn lookup_hash_vectors() {
+    for vector in load_vectors().lookup_hash {
+        let lookup_hash = scrypt_hash_username_and_password(&vector.username, &vector.password)
+            .expect("scrypt failed");
+        assert_eq!(
+            hex::encode(lookup_hash),
+            vector.lookup_hash,
+            "[{}] lookup hash of {}",
+            vector.source,
+            vector.username
+        );
+    }
+}
+
+#[test]
+fn pipeline_vectors() {
+    for vector in load_vectors().pipeline {
+        let name = vector.name.as_str();
+        let client = ECCommutativeCipher::new(Some(&unhex(&vector.client_key)));
+        let server = ECCommutativeCipher::new(Some(&unhex(&vector.server_key)));
+
+        assert_eq!(extract_username_from_email(&vector.username), vector.canonical_username, "{}", name);
+
+        let lookup_hash = scrypt_hash_username_and_password(&vector.username, &vector.password)
+            .expect("scrypt failed");
+        assert_eq!(hex::encode(&lookup_hash), vector.lookup_hash, "{}: lookup hash", name);
+
+        let request = create_lookup_request(&vector.username, &vector.password, &client)
+            .expect("Failed to build lookup request");
+        assert_eq!(hex::encode(&request.username_hash_prefix), vector.username_hash_prefix, "{}: prefix", name);
+        assert_eq!(request.username_hash_prefix_length, 26, "{}: prefix length", name);
+        assert_eq!(hex::encode(&request.encrypted_lookup_hash), vector.encrypted_lookup_hash, "{}: encrypted", name);
+
+        let reencrypted = server.re_encrypt(&request.encrypted_lookup_hash).expect("re-encryption failed");
+        assert_eq!(hex::encode(&reencrypted), vector.reencrypted_lookup_hash, "{}: re-encrypted", name);
+
+        let decrypted = client.decrypt(&reencrypted).expect("decryption failed");
+        assert_eq!(hex::encode(&decrypted), vector.decrypted_lookup_hash, "{}: decrypted", name);
+
+        // Peeling off the client layer must leave exactly what the server
+        // would have stored for this credential in its own breach database.
+        assert_eq!(decrypted, server.encrypt(&lookup_hash).expect("server encryption failed"), "{}", name);
+
+        let response = LookupSingleLeakResponse {
+            encrypted_leak_match_prefix: vector.encrypted_leak_match_prefix.iter().map(|p| unhex(p)).collect(),
+            reencrypted_lookup_hash: reencrypted,
+        };
+        let is_leaked = check_credential_leaked(&response, &decrypted).expect("leak check failed");
+        assert_eq!(is_leaked, vector.is_leaked, "{}: match decision", name);
+        assert_eq!(unblind_and_check(&response, &client).expect("unblinding failed"), vector.is_leaked, "{}", name);
+    }
+}
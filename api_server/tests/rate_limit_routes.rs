@@ -0,0 +1,136 @@
+//! Route tests with small rate limits. Every test passes its own client IP in
+//! `X-Forwarded-For`, which the router trusts from the loopback test client,
+//! so their quotas stay apart.
+
+mod common;
+
+use common::{fake_google_with, multipart_body, spawn_app};
+use leaklens_api::utils::config::AppConfig;
+use leaklens_api::utils::rate_limiter::parse_networks;
+use reqwest::{Client, RequestBuilder, Response};
+use serde_json::{json, Value};
+
+fn small_limits(config: &mut AppConfig) {
+    config.rate_limits.ip_rpm = 20;
+    config.rate_limits.single_credential_rpm = 3;
+    config.rate_limits.batch_credential_rpm = 10;
+    config.rate_limits.blinded_credential_rpm = 100;
+    config.rate_limits.blinded_credential_daily = 2;
+    config.rate_limits.trusted_proxies = parse_networks("127.0.0.1,::1").unwrap();
+}
+
+fn spawn_limited_app() -> String {
+    fake_google_with(small_limits);
+    spawn_app()
+}
+
+fn check_single(app: &str, client_ip: &str) -> RequestBuilder {
+    Client::new()
+        .post(format!("{}/api/v1/check/single", app))
+        .header("x-forwarded-for", client_ip)
+        .json(&json!({"username": "limited@example.com", "password": "hunter2"}))
+}
+
+fn header(response: &Response, name: &str) -> Option<u64> {
+    response.headers().get(name).map(|value| value.to_str().unwrap().parse().unwrap())
+}
+
+#[tokio::test]
+async fn quotas_are_reported_and_enforced_per_client() {
+    let app = spawn_limited_app();
+
+    for remaining in (0..3).rev() {
+        let response = check_single(&app, "198.51.100.1").send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(header(&response, "x-ratelimit-limit"), Some(3));
+        assert_eq!(header(&response, "x-ratelimit-remaining"), Some(remaining));
+        assert!(header(&response, "x-ratelimit-reset").is_some_and(|reset| (1..=60).contains(&reset)));
+        assert!(response.headers().get("retry-after").is_none());
+    }
+
+    let response = check_single(&app, "198.51.100.1").send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 429);
+    assert_eq!(header(&response, "x-ratelimit-remaining"), Some(0));
+    assert!(header(&response, "retry-after").is_some_and(|wait| (1..=60).contains(&wait)));
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["code"], json!("RATE_LIMITED"));
+
+    // Another client, and the same client on another route, are unaffected.
+    let response = check_single(&app, "198.51.100.2").send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let (content_type, body) = multipart_body(&[("file", "limited:hunter2\n")]);
+    let response = Client::new()
+        .post(format!("{}/api/v1/check/batch", app))
+        .header("x-forwarded-for", "198.51.100.1")
+        .header("content-type", content_type)
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(header(&response, "x-ratelimit-limit"), Some(10));
+
+    // Other routes report the per-address quota.
+    let response = Client::new()
+        .get(format!("{}/health", app))
+        .header("x-forwarded-for", "198.51.100.3")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(header(&response, "x-ratelimit-limit"), Some(20));
+    assert_eq!(header(&response, "x-ratelimit-remaining"), Some(19));
+}
+
+#[tokio::test]
+async fn failed_authentication_counts_against_the_address_quota() {
+    let app = spawn_limited_app();
+    let list_keys = |client_ip: &str| {
+        Client::new()
+            .get(format!("{}/api/v1/admin/keys", app))
+            .header("x-forwarded-for", client_ip.to_string())
+            .header("x-api-key", "llk_guess")
+    };
+
+    for remaining in (0..20).rev() {
+        let response = list_keys("192.0.2.10").send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(header(&response, "x-ratelimit-limit"), Some(20));
+        assert_eq!(header(&response, "x-ratelimit-remaining"), Some(remaining));
+    }
+
+    let response = list_keys("192.0.2.10").send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 429);
+    assert!(header(&response, "retry-after").is_some_and(|wait| (1..=60).contains(&wait)));
+
+    // The quota covers every route of the address, but no other address.
+    let response = check_single(&app, "192.0.2.10").send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 429);
+    let response = list_keys("192.0.2.11").send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn daily_quotas_apply_alongside_per_minute_ones() {
+    let app = spawn_limited_app();
+    let blinded = || {
+        Client::new()
+            .post(format!("{}/api/v1/check/blinded", app))
+            .header("x-forwarded-for", "2001:db8::7")
+            .json(&json!({"username_hash_prefix": "zz", "encrypted_lookup_hash": "zz"}))
+    };
+
+    // Rejected requests count too, and carry the headers of the tighter quota.
+    for remaining in [1, 0] {
+        let response = blinded().send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 400);
+        assert_eq!(header(&response, "x-ratelimit-limit"), Some(2));
+        assert_eq!(header(&response, "x-ratelimit-remaining"), Some(remaining));
+    }
+
+    let response = blinded().send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 429);
+    assert_eq!(header(&response, "x-ratelimit-limit"), Some(2));
+    assert!(header(&response, "retry-after").is_some_and(|wait| wait > 60));
+}
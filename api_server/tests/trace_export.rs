@@ -0,0 +1,115 @@
+//! Trace export to a local OTLP/HTTP collector. The tracing subscriber is
+//! process wide, so this binary holds only this test.
+
+mod common;
+
+use axum::{extract::State, routing::post, Json, Router};
+use common::{fake_google, spawn_app};
+use leaklens_api::utils::config::{LoggingConfig, TelemetryConfig};
+use leaklens_api::utils::logging::{self, LogFormat};
+use leaklens_api::utils::redact::Redaction;
+use leaklens_api::utils::telemetry;
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+const PARENT_SPAN_ID: &str = "00f067aa0ba902b7";
+
+type Exports = Arc<Mutex<Vec<Value>>>;
+
+async fn collect(State(exports): State<Exports>, Json(body): Json<Value>) {
+    exports.lock().unwrap().push(body);
+}
+
+fn spawn_collector() -> (String, Exports) {
+    let exports = Exports::default();
+    let app = Router::new().route("/v1/traces", post(collect)).with_state(exports.clone());
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+    (url, exports)
+}
+
+fn exported_spans(exports: &Exports) -> Vec<Value> {
+    let exports = exports.lock().unwrap();
+    exports
+        .iter()
+        .flat_map(|body| body["resourceSpans"].as_array().cloned().unwrap_or_default())
+        .flat_map(|resource| resource["scopeSpans"].as_array().cloned().unwrap_or_default())
+        .flat_map(|scope| scope["spans"].as_array().cloned().unwrap_or_default())
+        .collect()
+}
+
+#[tokio::test]
+async fn checks_are_traced_from_the_callers_parent_span() {
+    let (collector, exports) = spawn_collector();
+    let (layer, exporter) = telemetry::init(&TelemetryConfig {
+        otlp_endpoint: Some(collector),
+        otlp_traces_endpoint: None,
+        service_name: "leaklens-test".to_string(),
+    })
+    .expect("An endpoint enables export");
+    logging::init(
+        &LoggingConfig {
+            format: LogFormat::Text,
+            // Quietening the logs must not stop info spans from being exported.
+            filter: "warn".to_string(),
+            redaction: Redaction::Pseudonymize,
+            pseudonym_key: None,
+        },
+        Some(layer),
+    )
+    .unwrap();
+
+    fake_google().leak("traced@example.com", "hunter2");
+    let app = spawn_app();
+    let response = Client::new()
+        .post(format!("{}/api/v1/check/single", app))
+        .header("traceparent", format!("00-{}-{}-01", TRACE_ID, PARENT_SPAN_ID))
+        .json(&json!({"username": "traced@example.com", "password": "hunter2"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    // The request span ends as the response is written, so it may take a moment.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let spans = loop {
+        exporter.flush().await;
+        let spans = exported_spans(&exports);
+        if spans.iter().any(|span| span["name"] == "http") {
+            break spans;
+        }
+        assert!(Instant::now() < deadline, "the request span was not exported");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    };
+
+    // The fake upstream runs in this process, so its own spans show up in
+    // other traces.
+    let traced: Vec<&Value> = spans.iter().filter(|span| span["traceId"] == TRACE_ID).collect();
+    let span = |name: &str| {
+        *traced.iter().find(|span| span["name"] == name).unwrap_or_else(|| panic!("no {} span", name))
+    };
+    for name in ["check_credential", "scrypt", "encrypt", "hash_to_curve", "get_token", "unblind"] {
+        span(name);
+    }
+
+    let server = span("http");
+    assert_eq!(server["parentSpanId"], PARENT_SPAN_ID);
+    assert_eq!(server["kind"], 2);
+    assert_eq!(span("check_credential")["parentSpanId"], server["spanId"]);
+
+    let upstream = span("POST leaks:lookupSingle");
+    assert_eq!(upstream["kind"], 3);
+    assert!(upstream["attributes"].to_string().contains("http.status_code"));
+
+    // No span carries the credential being checked.
+    let exported = Value::Array(spans.clone()).to_string();
+    assert!(!exported.contains("hunter2"));
+    assert!(!exported.contains("traced@example.com"));
+    assert!(exports.lock().unwrap()[0].to_string().contains("leaklens-test"));
+}